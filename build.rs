@@ -1,15 +1,27 @@
 fn main() {
     linker_be_nice();
 
+    // Host builds of the library, for the simulator, need neither the C
+    // library nor the linker scripts
+    if std::env::var("CARGO_CFG_TARGET_ARCH").as_deref() != Ok("xtensa") {
+        return;
+    }
+
     // Link the esp_new_jpeg C library for JPEG decoding
+    if std::env::var_os("CARGO_FEATURE_ESP_NEW_JPEG").is_some() {
+        link_esp_new_jpeg();
+    }
+
+    // make sure linkall.x is the last linker script (otherwise might cause problems with flip-link)
+    println!("cargo:rustc-link-arg=-Tlinkall.x");
+}
+
+fn link_esp_new_jpeg() {
     println!(
         "cargo:rustc-link-search=native={}/esp-adf-libs/esp_new_jpeg/lib/esp32s3",
         std::env::var("CARGO_MANIFEST_DIR").unwrap()
     );
     println!("cargo:rustc-link-lib=static=esp_new_jpeg");
-
-    // make sure linkall.x is the last linker script (otherwise might cause problems with flip-link)
-    println!("cargo:rustc-link-arg=-Tlinkall.x");
 }
 
 fn linker_be_nice() {
@@ -46,6 +58,13 @@ fn linker_be_nice() {
                     );
                     eprintln!();
                 }
+                "jd_prepare" | "jd_decomp" => {
+                    eprintln!();
+                    eprintln!(
+                        "💡 The `rom-jpeg` feature needs the ROM's TJpgDec, which the `esp32s3.rom.ld` linker script provides"
+                    );
+                    eprintln!();
+                }
                 "free"
                 | "malloc"
                 | "calloc"
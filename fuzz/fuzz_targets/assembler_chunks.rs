@@ -0,0 +1,58 @@
+//! Cut a valid MJPEG stream wherever the input says and check that the
+//! assembler hands out the same frames as for the stream in one piece.
+//!
+//! Run it with `cargo fuzz run -s none assembler_chunks` from `fuzz/`. That
+//! builds on stable: the sanitizers need nightly, where the firmware's
+//! `build-std` setting would apply to this crate too.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rumble_rs::jpeg::markers::APP0;
+use rumble_rs::mjpeg::{FrameAssembler, FrameSource, Status};
+
+const FRAME: &[u8] = include_bytes!("../../sim/tests/data/baseline.jpg");
+
+/// Three frames, one with an EXIF thumbnail, with junk before and between.
+fn stream() -> (Vec<u8>, Vec<Vec<u8>>) {
+    let mut thumbnailed = FRAME[..2].to_vec();
+    thumbnailed.extend_from_slice(&[0xFF, APP0 + 1]);
+    thumbnailed.extend_from_slice(&(FRAME.len() as u16 + 8).to_be_bytes());
+    thumbnailed.extend_from_slice(b"Exif\0\0");
+    thumbnailed.extend_from_slice(FRAME);
+    thumbnailed.extend_from_slice(&FRAME[2..]);
+
+    let frames = vec![FRAME.to_vec(), thumbnailed, FRAME.to_vec()];
+    let mut stream = b"\xFF\xFFjunk\xFF".to_vec();
+    for frame in &frames {
+        stream.extend_from_slice(frame);
+        stream.extend_from_slice(b"\r\n--boundary\r\n\xFF");
+    }
+    (stream, frames)
+}
+
+fuzz_target!(|cuts: &[u8]| {
+    let (stream, expected) = stream();
+    let mut assembler = FrameAssembler::new(4096);
+    let mut frames = Vec::new();
+    let mut rest = &stream[..];
+    // Each input byte is the length of the next chunk, less one; whatever is
+    // left after the last goes in one piece
+    let mut cuts = cuts.iter().map(|&cut| cut as usize + 1);
+    while !rest.is_empty() {
+        let len = cuts.next().unwrap_or(rest.len()).min(rest.len());
+        let (mut input, tail) = rest.split_at(len);
+        rest = tail;
+        while !input.is_empty() {
+            match assembler.push(input) {
+                Status::Incomplete => break,
+                Status::Frame { consumed } => {
+                    frames.push(assembler.frame_mut().unwrap().to_vec());
+                    input = &input[consumed..];
+                }
+                Status::Dropped { reason, .. } => panic!("dropped: {reason:?}"),
+            }
+        }
+    }
+    assert_eq!(frames, expected);
+});
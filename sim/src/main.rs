@@ -0,0 +1,391 @@
+//! Plays a stream on the host the way the badge does, saving what the panel
+//! would show as images.
+//!
+//! Frames are assembled, laid out and drawn by the same library code as on
+//! the badge. Only the edges are swapped: std sockets for embassy-net,
+//! [`BaselineDecoder`] for ESP_NEW_JPEG, and [`Snapshots`] for the ST7789.
+//! Raw TCP streams are decoded while each frame is still arriving, as on a
+//! badge built with `baseline-jpeg` alone.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use embassy_futures::block_on;
+use rumble_rs::config::{Config, Transport};
+use rumble_rs::display::{ImageFormat, Snapshots};
+use rumble_rs::fit::{FitMode, Layout};
+use rumble_rs::http::{self, MultipartDemuxer};
+use rumble_rs::jpeg::baseline::{BaselineDecoder, BaselineStream};
+use rumble_rs::jpeg::{JpegBackend, JpegError, JpegPixelFormat, markers};
+use rumble_rs::mjpeg::{FrameSource, Status};
+use rumble_rs::pipeline::{DrawError, Incoming, IncomingError, Pipeline};
+use rumble_rs::rtp::Depacketizer;
+
+const USAGE: &str = "\
+usage: rumble-sim [options] <url>
+
+Plays an MJPEG stream like the badge does and saves each frame it shows.
+
+  <url>              tcp://host:port, http://host[:port][/path],
+                     tcp://@:port (accept pushed streams) or rtp://@:port
+  --size <WxH>       panel size (default 320x170)
+  --fit <mode>       fit, fill, center or stretch (default fill)
+  --out <dir>        where frames go (default frames)
+  --format <format>  png or ppm (default png)
+  --frames <n>       stop after n frames
+  --capacity <bytes> largest frame, or over raw TCP the input held while
+                     decoding (default 30720, as on the badge)
+";
+
+/// As on the badge.
+const PANEL_FORMAT: JpegPixelFormat = JpegPixelFormat::Rgb565Be;
+const MAX_DECODER_OUTPUT: usize = 32 * 1024;
+
+struct Options {
+    config: Config,
+    size: (u16, u16),
+    out: PathBuf,
+    format: ImageFormat,
+    frames: Option<u32>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut config = Config::default();
+        let mut size = (config.display.width, config.display.height);
+        let mut out = PathBuf::from("frames");
+        let mut format = ImageFormat::default();
+        let mut frames = None;
+        let mut url = None;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--size" => {
+                    size = value()?
+                        .split_once('x')
+                        .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                        .filter(|&(w, h)| w > 0 && h > 0)
+                        .ok_or("The size must look like 320x170.")?;
+                }
+                "--fit" => {
+                    config.display.fit = FitMode::parse(&value()?)
+                        .ok_or("The mode must be fit, fill, center or stretch.")?;
+                }
+                "--out" => out = value()?.into(),
+                "--format" => {
+                    format =
+                        ImageFormat::parse(&value()?).ok_or("The format must be png or ppm.")?;
+                }
+                "--frames" => {
+                    frames = Some(value()?.parse().map_err(|_| "Not a frame count.")?);
+                }
+                "--capacity" => {
+                    config.playback.frame_capacity =
+                        value()?.parse().map_err(|_| "Not a size in bytes.")?;
+                }
+                "-h" | "--help" => return Err(String::new()),
+                _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+                _ => url = Some(arg),
+            }
+        }
+        let url = url.ok_or("Which stream?")?;
+        let config = config.with_stream_url(&url).ok_or(
+            "The URL must be tcp://host:port, http://host/path, tcp://@:port or rtp://@:port.",
+        )?;
+        Ok(Self {
+            config,
+            size,
+            out,
+            format,
+            frames,
+        })
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("{}\n", e);
+            }
+            eprint!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(e) = std::fs::create_dir_all(&options.out) {
+        eprintln!("can't create {}: {}", options.out.display(), e);
+        return ExitCode::FAILURE;
+    }
+    match play(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn play(options: &Options) -> io::Result<()> {
+    let config = &options.config;
+    let capacity = config.playback.frame_capacity as usize;
+    let mut screen = Screen::new(options);
+    let mut buf = vec![0u8; 4096];
+
+    let server = config.server.as_deref();
+    match (&config.transport, server) {
+        (Transport::Tcp, Some(server)) => {
+            let mut socket = Blocking(connect(server)?);
+            let mut stream = BaselineStream::new(capacity);
+            screen.stream(&mut socket, &mut stream)
+        }
+        (Transport::Http { path }, Some(server)) => {
+            let socket = connect(server)?;
+            let mut socket = Blocking(socket);
+            let (head, pending) =
+                block_on(http::request_stream(&mut socket, server, path, &mut buf))
+                    .map_err(|e| io::Error::other(format!("HTTP error: {}", e)))?;
+            let mut demuxer = MultipartDemuxer::new(capacity);
+            demuxer.start(&head.boundary);
+            receive_frames(&mut socket.0, &mut demuxer, &mut buf, pending, &mut screen)
+        }
+        (Transport::Listen { port }, _) => {
+            let listener = TcpListener::bind(("0.0.0.0", *port))?;
+            println!("waiting for streams on TCP port {}", port);
+            let mut stream = BaselineStream::new(capacity);
+            while !screen.is_done() {
+                let (socket, peer) = listener.accept()?;
+                println!("streaming from {}", peer);
+                stream.reset();
+                screen.stream(&mut Blocking(socket), &mut stream)?;
+            }
+            Ok(())
+        }
+        (Transport::Rtp { port }, _) => {
+            let socket = UdpSocket::bind(("0.0.0.0", *port))?;
+            println!("listening for RTP/JPEG on UDP port {}", port);
+            let mut depacketizer = Depacketizer::new(capacity);
+            let mut packet = [0u8; 2048];
+            while !screen.is_done() {
+                let (n, _) = socket.recv_from(&mut packet)?;
+                feed(&mut depacketizer, &packet[..n], &mut screen)?;
+            }
+            Ok(())
+        }
+        _ => Err(io::Error::other(
+            "the simulator can't discover servers, give it a host",
+        )),
+    }
+}
+
+fn connect(server: &str) -> io::Result<TcpStream> {
+    println!("connecting to {}...", server);
+    let socket = TcpStream::connect(server)?;
+    println!("connected!");
+    Ok(socket)
+}
+
+/// Read frames until the stream ends or enough were shown. The first
+/// `pending` bytes of `buf` were already read.
+fn receive_frames(
+    socket: &mut TcpStream,
+    source: &mut impl FrameSource,
+    buf: &mut [u8],
+    mut pending: usize,
+    screen: &mut Screen,
+) -> io::Result<()> {
+    while !screen.is_done() {
+        let n = match core::mem::take(&mut pending) {
+            0 => socket.read(buf)?,
+            n => n,
+        };
+        if n == 0 {
+            println!("connection closed");
+            break;
+        }
+        feed(source, &buf[..n], screen)?;
+    }
+    Ok(())
+}
+
+/// Push `input` through `source`, showing every frame that completes.
+fn feed(source: &mut impl FrameSource, input: &[u8], screen: &mut Screen) -> io::Result<()> {
+    let mut used = 0;
+    while used < input.len() && !screen.is_done() {
+        match source.push(&input[used..]) {
+            Status::Incomplete => break,
+            Status::Dropped { consumed, reason } => {
+                println!("dropped frame: {:?}", reason);
+                used += consumed;
+            }
+            Status::Frame { consumed } => {
+                if let Some(jpeg) = source.frame_mut() {
+                    screen.show(jpeg)?;
+                }
+                used += consumed;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The simulated panel and how the stream is laid out on it.
+struct Screen {
+    decoder: BaselineDecoder,
+    sink: Snapshots,
+    pipeline: Pipeline,
+    size: (u16, u16),
+    fit: FitMode,
+    layout: Option<((u16, u16), Layout)>,
+    frames_left: Option<u32>,
+}
+
+impl Screen {
+    fn new(options: &Options) -> Self {
+        let (width, height) = options.size;
+        Self {
+            decoder: BaselineDecoder::new().expect("the default config is valid"),
+            sink: Snapshots::new(width, height, &options.out, options.format),
+            pipeline: Pipeline::new(width as usize * 16 * 2),
+            size: options.size,
+            fit: options.config.display.fit,
+            layout: None,
+            frames_left: options.frames,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.frames_left == Some(0)
+    }
+
+    /// How to show a stream of `size`. The layout is made again, with the
+    /// decoder and the bars to match, whenever the size changes.
+    fn lay_out(&mut self, size: (u16, u16)) -> io::Result<Layout> {
+        if let Some((laid_out, layout)) = self.layout
+            && laid_out == size
+        {
+            return Ok(layout);
+        }
+        let layout = Layout::new(self.fit, size, self.size, MAX_DECODER_OUTPUT);
+        println!("{}x{} stream, shown {:?}", size.0, size.1, layout.target);
+        let config = layout
+            .decoder_config(PANEL_FORMAT)
+            .map_err(|e| io::Error::other(format!("decoder config error: {}", e)))?;
+        if config != *self.decoder.config() {
+            self.decoder = BaselineDecoder::with_config(config)
+                .map_err(|e| io::Error::other(format!("decoder config error: {}", e)))?;
+        }
+        block_on(self.pipeline.clear(layout.bars(self.size), &mut self.sink))?;
+        self.layout = Some((size, layout));
+        Ok(layout)
+    }
+
+    /// Decode and draw one frame, then save it. Frames that don't decode are
+    /// skipped, as on the badge.
+    fn show(&mut self, jpeg: &mut [u8]) -> io::Result<()> {
+        let Ok(summary) = markers::parse(jpeg) else {
+            println!("not a JPEG frame");
+            return Ok(());
+        };
+        let layout = self.lay_out((summary.width, summary.height))?;
+
+        let mut session = match self.decoder.start_decode(jpeg) {
+            Ok(session) => session,
+            Err(e) => {
+                println!("decode error: {}", e);
+                return Ok(());
+            }
+        };
+        let path = self.sink.next_path();
+        match block_on(self.pipeline.draw(&layout, &mut session, &mut self.sink)) {
+            Ok(()) => println!("{}", path.display()),
+            Err(DrawError::Decode(e)) => {
+                println!("block decode error: {}", e);
+                return Ok(());
+            }
+            Err(DrawError::Sink(e)) => return Err(e),
+        }
+        if let Some(left) = &mut self.frames_left {
+            *left -= 1;
+        }
+        Ok(())
+    }
+
+    /// Decode and draw the raw frames coming from `socket` while they are
+    /// still arriving, saving each one, until the connection closes or
+    /// enough were shown.
+    fn stream(&mut self, socket: &mut Blocking, stream: &mut BaselineStream) -> io::Result<()> {
+        while !self.is_done() {
+            let info = match stream.header() {
+                Ok(info) => info,
+                Err(JpegError::NoMoreData) => {
+                    let n = socket.0.read(stream.spare())?;
+                    if n == 0 {
+                        println!("connection closed");
+                        break;
+                    }
+                    stream.commit(n);
+                    continue;
+                }
+                Err(e) => {
+                    println!("dropped frame: {}", e);
+                    continue;
+                }
+            };
+            let layout = self.lay_out((info.width, info.height))?;
+
+            let session = match self.decoder.start_stream(stream) {
+                Ok(session) => session,
+                Err(e) => {
+                    println!("decode error: {}", e);
+                    continue;
+                }
+            };
+            let path = self.sink.next_path();
+            let mut blocks = Incoming::new(session, socket);
+            match block_on(self.pipeline.draw(&layout, &mut blocks, &mut self.sink)) {
+                Ok(()) => println!("{}", path.display()),
+                Err(DrawError::Decode(IncomingError::Decode(e))) => {
+                    println!("block decode error: {}", e);
+                    continue;
+                }
+                Err(DrawError::Decode(IncomingError::Closed)) => {
+                    println!("connection closed");
+                    break;
+                }
+                Err(DrawError::Decode(IncomingError::Read(e)) | DrawError::Sink(e)) => {
+                    return Err(e);
+                }
+            }
+            if let Some(left) = &mut self.frames_left {
+                *left -= 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A std socket behind the async traits that [`http::request_stream`] takes.
+struct Blocking(TcpStream);
+
+impl embedded_io_async::ErrorType for Blocking {
+    type Error = io::Error;
+}
+
+impl embedded_io_async::Read for Blocking {
+    async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl embedded_io_async::Write for Blocking {
+    async fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
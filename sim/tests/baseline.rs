@@ -0,0 +1,209 @@
+//! The pure-Rust decoder against a reference decoder, for every sampling it
+//! supports, with and without restart intervals, and on tables no decoder
+//! should accept.
+
+use jpeg_encoder::{ColorType, Encoder, SamplingFactor};
+use rumble_rs::jpeg::baseline::{BaselineDecoder, BaselineStream};
+use rumble_rs::jpeg::markers::{DHT, MarkerError};
+use rumble_rs::jpeg::{JpegBackend, JpegDecoderConfig, JpegError, JpegPixelFormat};
+
+/// Most an 8-bit channel may be off after the trip through RGB565.
+const TOLERANCE: i16 = 11;
+
+/// Sizes on and off MCU boundaries.
+const SIZES: [(u16, u16); 5] = [(320, 170), (33, 17), (8, 8), (1, 1), (127, 61)];
+
+#[derive(Clone, Copy, Debug)]
+enum Sampling {
+    Yuv444,
+    Yuv422,
+    Yuv420,
+    Gray,
+}
+
+const SAMPLINGS: [Sampling; 4] = [
+    Sampling::Yuv444,
+    Sampling::Yuv422,
+    Sampling::Yuv420,
+    Sampling::Gray,
+];
+
+/// A test pattern that changes by at most a few levels per pixel, even in
+/// small images. The reference decoder upsamples chroma smoothly and this
+/// one doesn't, which only stays within the tolerance on gentle slopes.
+fn image(width: u16, height: u16) -> Vec<u8> {
+    let (w, h) = (width.max(128) as u32, height.max(128) as u32);
+    let mut rgb = Vec::new();
+    for y in 0..height as u32 {
+        for x in 0..width as u32 {
+            rgb.extend([
+                (x * 255 / w) as u8,
+                (y * 255 / h) as u8,
+                ((x + y) * 191 / (w + h) + 32) as u8,
+            ]);
+        }
+    }
+    rgb
+}
+
+fn encode(width: u16, height: u16, sampling: Sampling, restart_interval: u16) -> Vec<u8> {
+    let rgb = image(width, height);
+    let mut out = Vec::new();
+    let mut encoder = Encoder::new(&mut out, 90);
+    encoder.set_restart_interval(restart_interval);
+    match sampling {
+        Sampling::Gray => {
+            let luma: Vec<u8> = rgb.chunks_exact(3).map(|p| p[0] / 2 + p[1] / 2).collect();
+            encoder
+                .encode(&luma, width, height, ColorType::Luma)
+                .unwrap();
+        }
+        _ => {
+            encoder.set_sampling_factor(match sampling {
+                Sampling::Yuv444 => SamplingFactor::F_1_1,
+                Sampling::Yuv422 => SamplingFactor::F_2_1,
+                _ => SamplingFactor::F_2_2,
+            });
+            encoder.encode(&rgb, width, height, ColorType::Rgb).unwrap();
+        }
+    }
+    out
+}
+
+/// 8-bit RGB from the reference decoder.
+fn reference(jpeg: &[u8]) -> Vec<u8> {
+    let mut decoder = jpeg_decoder::Decoder::new(jpeg);
+    let pixels = decoder.decode().unwrap();
+    match decoder.info().unwrap().pixel_format {
+        jpeg_decoder::PixelFormat::L8 => pixels.iter().flat_map(|&l| [l, l, l]).collect(),
+        jpeg_decoder::PixelFormat::RGB24 => pixels,
+        format => panic!("{format:?}"),
+    }
+}
+
+/// Every block the library's decoder produces with `config`, joined.
+fn decode(jpeg: &[u8], config: JpegDecoderConfig) -> Result<Vec<u8>, JpegError> {
+    let mut decoder = BaselineDecoder::with_config(config)?;
+    let mut data = jpeg.to_vec();
+    let mut session = decoder.start_decode(&mut data)?;
+    let mut out = Vec::new();
+    for _ in 0..session.block_count() {
+        let (width, height) = session.decode_next_block()?;
+        let len = width as usize * height as usize * session.bytes_per_pixel();
+        assert_eq!(session.block_data().len(), len);
+        out.extend_from_slice(session.block_data());
+    }
+    assert_eq!(
+        session.decode_next_block().unwrap_err(),
+        JpegError::SessionExhausted
+    );
+    Ok(out)
+}
+
+/// RGB565, most significant byte first, widened back to 8 bits as the
+/// framebuffer does.
+fn widen(rgb565: &[u8]) -> Vec<u8> {
+    rgb565
+        .chunks_exact(2)
+        .flat_map(|p| {
+            let raw = u16::from_be_bytes([p[0], p[1]]);
+            let (r, g, b) = ((raw >> 11) as u8, (raw >> 5) as u8 & 0x3F, raw as u8 & 0x1F);
+            [
+                (r << 3) | (r >> 2),
+                (g << 2) | (g >> 4),
+                (b << 3) | (b >> 2),
+            ]
+        })
+        .collect()
+}
+
+fn rgb565() -> JpegDecoderConfig {
+    JpegDecoderConfig::builder()
+        .with_output_format(JpegPixelFormat::Rgb565Be)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn matches_the_reference_decoder() {
+    for (width, height) in SIZES {
+        for sampling in SAMPLINGS {
+            for restart_interval in [0, 1, 3] {
+                let case = format!("{width}x{height} {sampling:?} restart {restart_interval}");
+                let jpeg = encode(width, height, sampling, restart_interval);
+                let ours = widen(&decode(&jpeg, rgb565()).unwrap());
+                let theirs = reference(&jpeg);
+                assert_eq!(ours.len(), theirs.len(), "{case}");
+                for (i, (&a, &b)) in ours.iter().zip(&theirs).enumerate() {
+                    let (x, y) = (i / 3 % width as usize, i / 3 / width as usize);
+                    assert!(
+                        (a as i16 - b as i16).abs() <= TOLERANCE,
+                        "{case}: {a} vs {b} at {x},{y} channel {}",
+                        i % 3
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn restart_intervals_change_nothing() {
+    for (width, height) in SIZES {
+        for sampling in SAMPLINGS {
+            let plain = decode(&encode(width, height, sampling, 0), rgb565()).unwrap();
+            for restart_interval in [1, 2, 7] {
+                let jpeg = encode(width, height, sampling, restart_interval);
+                assert_eq!(
+                    decode(&jpeg, rgb565()).unwrap(),
+                    plain,
+                    "{width}x{height} {sampling:?} restart {restart_interval}"
+                );
+            }
+        }
+    }
+}
+
+/// `jpeg` with the counts of its first DHT table rearranged so that length 1
+/// has three codes, one more than fits. The number of values stays the same.
+fn overfull_dht(jpeg: &[u8]) -> Vec<u8> {
+    let mut jpeg = jpeg.to_vec();
+    let dht = jpeg.windows(2).position(|w| w == [0xFF, DHT]).unwrap();
+    let counts = &mut jpeg[dht + 5..dht + 21];
+    let total: u32 = counts.iter().map(|&n| n as u32).sum();
+    assert!(total >= 3);
+    counts.fill(0);
+    counts[0] = 3;
+    counts[15] = (total - 3) as u8;
+    jpeg
+}
+
+#[test]
+fn overfull_huffman_table_is_refused() {
+    let jpeg = overfull_dht(&encode(64, 48, Sampling::Yuv420, 0));
+    let refused = JpegError::InvalidStream(MarkerError::BadTableSpec(DHT));
+    assert_eq!(decode(&jpeg, rgb565()), Err(refused));
+
+    let mut stream = BaselineStream::new(4096);
+    assert_eq!(stream.push(&jpeg), jpeg.len());
+    assert_eq!(stream.header().unwrap_err(), refused);
+}
+
+#[test]
+fn corrupt_scans_do_not_panic() {
+    let jpeg = encode(64, 48, Sampling::Yuv420, 2);
+    let scan = jpeg.windows(2).position(|w| w == [0xFF, 0xDA]).unwrap() + 14;
+    let mut seed = 12345u32;
+    for _ in 0..2000 {
+        let mut broken = jpeg.clone();
+        for _ in 0..4 {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let at = scan + (seed as usize >> 8) % (broken.len() - scan - 2);
+            broken[at] = (seed >> 3) as u8;
+        }
+        let _ = decode(&broken, rgb565());
+    }
+    for cut in 0..jpeg.len() {
+        assert!(decode(&jpeg[..cut], rgb565()).is_err());
+    }
+}
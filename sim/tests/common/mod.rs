@@ -0,0 +1,30 @@
+//! Helpers shared by the host tests.
+
+#![allow(dead_code)]
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+/// A std socket behind the `embedded_io_async` traits the library takes,
+/// blocking in place of awaiting.
+pub struct Blocking(pub TcpStream);
+
+impl embedded_io_async::ErrorType for Blocking {
+    type Error = io::Error;
+}
+
+impl embedded_io_async::Read for Blocking {
+    async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl embedded_io_async::Write for Blocking {
+    async fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
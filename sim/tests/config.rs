@@ -0,0 +1,352 @@
+//! Config records through an in-memory flash that holds the store to the
+//! NorFlash alignment rules, and records written by older firmware.
+
+use crc::{CRC_32_ISO_HDLC, Crc};
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+use rumble_rs::config::{
+    Config, ConfigError, ConfigStore, DisplayConfig, MAX_NETWORKS, MAX_RECORD, PlaybackConfig,
+    Transport, VERSION, WifiConfig,
+};
+use rumble_rs::fit::FitMode;
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// NOR flash in memory: erase sets bytes to 0xFF, writes can only clear
+/// bits, and misaligned operations fail.
+struct Flash<const WRITE: usize, const ERASE: usize> {
+    data: Vec<u8>,
+    /// Every erase and write, as (is_erase, from, to).
+    ops: Vec<(bool, usize, usize)>,
+}
+
+impl<const WRITE: usize, const ERASE: usize> Flash<WRITE, ERASE> {
+    fn new(size: usize) -> Self {
+        Self {
+            data: vec![0xFF; size],
+            ops: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Error(NorFlashErrorKind);
+
+impl NorFlashError for Error {
+    fn kind(&self) -> NorFlashErrorKind {
+        self.0
+    }
+}
+
+impl<const WRITE: usize, const ERASE: usize> ErrorType for Flash<WRITE, ERASE> {
+    type Error = Error;
+}
+
+impl<const WRITE: usize, const ERASE: usize> ReadNorFlash for Flash<WRITE, ERASE> {
+    const READ_SIZE: usize = 4;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        let offset = offset as usize;
+        if !offset.is_multiple_of(Self::READ_SIZE) || !bytes.len().is_multiple_of(Self::READ_SIZE) {
+            return Err(Error(NorFlashErrorKind::NotAligned));
+        }
+        let src = self
+            .data
+            .get(offset..offset + bytes.len())
+            .ok_or(Error(NorFlashErrorKind::OutOfBounds))?;
+        bytes.copy_from_slice(src);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl<const WRITE: usize, const ERASE: usize> NorFlash for Flash<WRITE, ERASE> {
+    const WRITE_SIZE: usize = WRITE;
+    const ERASE_SIZE: usize = ERASE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        let (from, to) = (from as usize, to as usize);
+        if !from.is_multiple_of(ERASE) || !to.is_multiple_of(ERASE) {
+            return Err(Error(NorFlashErrorKind::NotAligned));
+        }
+        self.data
+            .get_mut(from..to)
+            .ok_or(Error(NorFlashErrorKind::OutOfBounds))?
+            .fill(0xFF);
+        self.ops.push((true, from, to));
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        let offset = offset as usize;
+        if !offset.is_multiple_of(WRITE) || !bytes.len().is_multiple_of(WRITE) {
+            return Err(Error(NorFlashErrorKind::NotAligned));
+        }
+        let dst = self
+            .data
+            .get_mut(offset..offset + bytes.len())
+            .ok_or(Error(NorFlashErrorKind::OutOfBounds))?;
+        for (dst, &src) in dst.iter_mut().zip(bytes) {
+            *dst &= src;
+        }
+        self.ops.push((false, offset, offset + bytes.len()));
+        Ok(())
+    }
+}
+
+/// A config with every field away from its default.
+fn changed() -> Config {
+    Config {
+        networks: vec![
+            WifiConfig::new("ääni".into(), "salasana".into()).unwrap(),
+            WifiConfig::new("open".into(), String::new())
+                .unwrap()
+                .with_priority(7),
+        ],
+        server: None,
+        transport: Transport::Http {
+            path: "/stream".into(),
+        },
+        display: DisplayConfig {
+            width: 240,
+            height: 135,
+            offset_x: 40,
+            offset_y: 53,
+            invert_colors: false,
+            fit: FitMode::Stretch,
+        },
+        playback: PlaybackConfig {
+            drop_stale_frames: false,
+            frame_capacity: 48 * 1024,
+        },
+    }
+}
+
+/// `record` cut down to its first `payload_len` payload bytes and labelled
+/// `version`, with the CRC redone, as older firmware would have written it.
+fn older(record: &[u8], version: u16, payload_len: usize) -> Vec<u8> {
+    let mut out = record[..8 + payload_len].to_vec();
+    out[4..6].copy_from_slice(&version.to_le_bytes());
+    out[6..8].copy_from_slice(&(payload_len as u16).to_le_bytes());
+    let crc = CRC.checksum(&out[4..]);
+    out.extend_from_slice(&crc.to_le_bytes());
+    out
+}
+
+fn decode(record: &[u8]) -> Result<Config, ConfigError<()>> {
+    Config::decode(record)
+}
+
+#[test]
+fn encode_decode_round_trip() {
+    let configs = [Config::default(), changed(), {
+        let mut config = changed();
+        config.networks.clear();
+        config.server = Some("camera.local:8080".into());
+        config.transport = Transport::Rtp { port: 5004 };
+        config
+    }];
+    for config in configs {
+        let record = config.encode();
+        assert_eq!(record[..4], *b"RBCF");
+        assert_eq!(record[4..6], VERSION.to_le_bytes());
+        assert_eq!(decode(&record).unwrap(), config);
+        // A whole sector, as the store reads it
+        let mut sector = record.clone();
+        sector.resize(4096, 0xFF);
+        assert_eq!(decode(&sector).unwrap(), config);
+    }
+}
+
+#[test]
+fn older_records_decode_with_defaults() {
+    let config = changed();
+    let record = config.encode();
+    let payload_len = u16::from_le_bytes([record[6], record[7]]) as usize;
+    let defaults = Config::default();
+
+    // Version 3 appended the fit mode
+    let v2 = decode(&older(&record, 2, payload_len - 1)).unwrap();
+    let mut expected = config.clone();
+    expected.display.fit = defaults.display.fit;
+    assert_eq!(v2, expected);
+
+    // Version 1: one network and no priorities
+    let others = 1 + config.networks[1..]
+        .iter()
+        .map(|n| 2 + n.ssid.len() + n.password.len() + 1)
+        .sum::<usize>();
+    let v1 = decode(&older(&record, 1, payload_len - 1 - others - 1)).unwrap();
+    assert_eq!(v1.networks, config.networks[..1]);
+    assert_eq!(v1.playback, config.playback);
+    assert_eq!(v1.display.height, config.display.height);
+
+    // Version 1 records from before the display and playback settings
+    let v1 = decode(&older(&record, 1, payload_len - 1 - others - 1 - 5)).unwrap();
+    assert_eq!(v1.playback, defaults.playback);
+    assert_eq!(v1.display.height, config.display.height);
+    let v1 = decode(&older(&record, 1, payload_len - 1 - others - 1 - 5 - 9)).unwrap();
+    assert_eq!(v1.networks, config.networks[..1]);
+    assert_eq!(v1.transport, config.transport);
+    assert_eq!(v1.display, defaults.display);
+    assert_eq!(v1.playback, defaults.playback);
+
+    // Cut inside a group of fields
+    assert!(matches!(
+        decode(&older(&record, 1, payload_len - 1 - others - 1 - 2)),
+        Err(ConfigError::Corrupt)
+    ));
+}
+
+#[test]
+fn flipped_bit_is_bad_crc() {
+    let record = changed().encode();
+    // Anywhere in the version, the payload or the CRC itself
+    for i in (4..6).chain(8..record.len()) {
+        for bit in 0..8 {
+            let mut flipped = record.clone();
+            flipped[i] ^= 1 << bit;
+            assert!(
+                matches!(decode(&flipped), Err(ConfigError::BadCrc)),
+                "byte {i} bit {bit}"
+            );
+        }
+    }
+}
+
+#[test]
+fn other_records_are_refused() {
+    let record = changed().encode();
+    assert!(matches!(decode(&[0xFF; 64]), Err(ConfigError::Empty)));
+    assert!(matches!(decode(&[0xFF; 4]), Err(ConfigError::Corrupt)));
+    assert!(matches!(
+        decode(&record[..record.len() - 1]),
+        Err(ConfigError::Corrupt)
+    ));
+    let mut other = record.clone();
+    other[..4].copy_from_slice(b"RBCG");
+    assert!(matches!(decode(&other), Err(ConfigError::BadMagic)));
+    let payload_len = record.len() - 12;
+    assert!(matches!(
+        decode(&older(&record, VERSION + 1, payload_len)),
+        Err(ConfigError::UnsupportedVersion(v)) if v == VERSION + 1
+    ));
+    assert!(matches!(
+        decode(&older(&record, 0, payload_len)),
+        Err(ConfigError::UnsupportedVersion(0))
+    ));
+    // A good CRC over a payload that doesn't parse
+    let mut bad_fit = record[..record.len() - 4].to_vec();
+    *bad_fit.last_mut().unwrap() = 9;
+    assert!(matches!(
+        decode(&older(&bad_fit, VERSION, payload_len)),
+        Err(ConfigError::Corrupt)
+    ));
+}
+
+#[test]
+fn erased_flash_is_empty() {
+    let mut flash = Flash::<4, 4096>::new(3 * 4096);
+    let mut store = ConfigStore::new(&mut flash, 4096, 4096);
+    assert!(matches!(store.load(), Err(ConfigError::Empty)));
+
+    store.save(&changed()).unwrap();
+    assert_eq!(store.load().unwrap(), changed());
+    store.clear().unwrap();
+    assert!(matches!(store.load(), Err(ConfigError::Empty)));
+    assert_eq!(flash.ops.last(), Some(&(true, 4096, 8192)));
+}
+
+#[test]
+fn save_pads_to_write_and_erase_size() {
+    fn check<const WRITE: usize, const ERASE: usize>(config: &Config) {
+        let record = config.encode();
+        let offset = 2 * ERASE.max(1024);
+        let mut flash = Flash::<WRITE, ERASE>::new(4 * offset);
+        // Something already there, past the record
+        flash.data.fill(0x00);
+        let mut store = ConfigStore::new(&mut flash, offset as u32, offset as u32);
+        store.save(config).unwrap();
+        assert_eq!(store.load().unwrap(), *config);
+
+        let written = record.len().next_multiple_of(WRITE);
+        let erased = written.next_multiple_of(ERASE);
+        assert_eq!(
+            flash.ops,
+            [
+                (true, offset, offset + erased),
+                (false, offset, offset + written)
+            ],
+            "WRITE_SIZE {WRITE}, ERASE_SIZE {ERASE}"
+        );
+        assert_eq!(flash.data[offset..][..record.len()], record);
+        // Padding and the rest of the erased blocks read as erased; nothing
+        // outside them was touched
+        assert!(
+            flash.data[offset + record.len()..offset + erased]
+                .iter()
+                .all(|&b| b == 0xFF)
+        );
+        assert!(flash.data[..offset].iter().all(|&b| b == 0x00));
+        assert!(flash.data[offset + erased..].iter().all(|&b| b == 0x00));
+    }
+
+    let mut full = changed();
+    for i in full.networks.len()..MAX_NETWORKS {
+        full.add_network(WifiConfig::new(format!("network {i}"), "x".repeat(63)).unwrap())
+            .unwrap();
+    }
+    // Several of the smaller erase blocks
+    assert!(full.encode().len() > 256);
+    for config in [Config::default(), changed(), full] {
+        check::<1, 4096>(&config);
+        check::<4, 4096>(&config);
+        check::<16, 4096>(&config);
+        check::<256, 4096>(&config);
+        check::<4, 256>(&config);
+        check::<32, 128>(&config);
+    }
+}
+
+#[test]
+fn save_refuses_what_does_not_fit() {
+    let mut config = changed();
+    config.server = Some("s".repeat(255));
+    config.transport = Transport::Http {
+        path: format!("/{}", "p".repeat(254)),
+    };
+    for i in config.networks.len()..MAX_NETWORKS {
+        config
+            .add_network(WifiConfig::new(format!("{i:0>32}"), "x".repeat(63)).unwrap())
+            .unwrap();
+    }
+    assert!(config.encode().len() > MAX_RECORD);
+    let mut flash = Flash::<4, 4096>::new(8192);
+    let mut store = ConfigStore::new(&mut flash, 0, 4096);
+    assert!(matches!(store.save(&config), Err(ConfigError::TooLarge)));
+
+    // Or the partition
+    let mut store = ConfigStore::new(&mut flash, 0, 256);
+    assert!(matches!(store.save(&changed()), Err(ConfigError::TooLarge)));
+    assert_eq!(flash.ops, []);
+}
+
+#[test]
+fn flash_errors_are_passed_on() {
+    // Not erase-block aligned, against the store's contract
+    let mut flash = Flash::<4, 4096>::new(8192);
+    let mut store = ConfigStore::new(&mut flash, 1024, 4096);
+    assert!(matches!(
+        store.save(&changed()),
+        Err(ConfigError::Flash(Error(NorFlashErrorKind::NotAligned)))
+    ));
+    let mut store = ConfigStore::new(&mut flash, 8192, 4096);
+    assert!(matches!(
+        store.load(),
+        Err(ConfigError::Flash(Error(NorFlashErrorKind::OutOfBounds)))
+    ));
+}
@@ -0,0 +1,292 @@
+//! Whole frames from stream bytes to a framebuffer: assembled, decoded by
+//! the pure-Rust decoder and drawn by the pipeline, with bars around them;
+//! and the framebuffer and snapshot sinks on their own.
+
+use embassy_futures::block_on;
+use jpeg_encoder::{ColorType, Encoder};
+use rumble_rs::display::{DisplaySink, Framebuffer, ImageFormat, OutOfBounds, Snapshots};
+use rumble_rs::fit::{FitMode, Layout, Rect};
+use rumble_rs::jpeg::baseline::BaselineDecoder;
+use rumble_rs::jpeg::markers;
+use rumble_rs::jpeg::{JpegBackend, JpegPixelFormat};
+use rumble_rs::mjpeg::{FrameAssembler, FrameSource, Status};
+use rumble_rs::pipeline::Pipeline;
+
+/// Most an 8-bit channel may be off after the decoder's rounding and the
+/// trip through RGB565.
+const TOLERANCE: i16 = 12;
+
+/// A smooth `width`×`height` test image as 8-bit RGB.
+fn image(width: u16, height: u16) -> Vec<u8> {
+    let mut rgb = Vec::new();
+    for y in 0..height as u32 {
+        for x in 0..width as u32 {
+            rgb.extend([
+                (x * 255 / width as u32) as u8,
+                (y * 255 / height as u32) as u8,
+                ((x + y) * 127 / (width + height) as u32 + 64) as u8,
+            ]);
+        }
+    }
+    rgb
+}
+
+fn encode(rgb: &[u8], width: u16, height: u16) -> Vec<u8> {
+    let mut out = Vec::new();
+    Encoder::new(&mut out, 95)
+        .encode(rgb, width, height, ColorType::Rgb)
+        .unwrap();
+    out
+}
+
+/// Every frame in `stream`, pushed `chunk` bytes at a time.
+fn assemble(stream: &[u8], chunk: usize) -> Vec<Vec<u8>> {
+    let mut assembler = FrameAssembler::new(64 * 1024);
+    let mut frames = Vec::new();
+    for mut input in stream.chunks(chunk) {
+        while !input.is_empty() {
+            match assembler.push(input) {
+                Status::Incomplete => break,
+                Status::Frame { consumed } => {
+                    frames.push(assembler.frame_mut().unwrap().to_vec());
+                    input = &input[consumed..];
+                }
+                Status::Dropped { reason, .. } => panic!("dropped: {reason:?}"),
+            }
+        }
+    }
+    frames
+}
+
+/// Lay `frame` out on a `panel` as the firmware does, and draw it.
+fn show(
+    frame: &mut [u8],
+    mode: FitMode,
+    panel: (u16, u16),
+    budget: usize,
+) -> (Layout, Framebuffer) {
+    let summary = markers::parse(frame).unwrap();
+    let layout = Layout::new(mode, (summary.width, summary.height), panel, budget);
+    let config = layout.decoder_config(JpegPixelFormat::Rgb565Be).unwrap();
+    let mut decoder = BaselineDecoder::with_config(config).unwrap();
+    let mut sink = Framebuffer::new(panel.0, panel.1);
+    // Stale pixels the bars have to paint over
+    block_on(sink.set_window(Rect::new(0, 0, panel.0, panel.1))).unwrap();
+    block_on(sink.write_pixels(&vec![0xAB; panel.0 as usize * panel.1 as usize * 2])).unwrap();
+
+    let mut pipeline = Pipeline::new(panel.0 as usize * 8 * 2);
+    block_on(pipeline.clear(layout.bars(panel), &mut sink)).unwrap();
+    let mut session = decoder.start_decode(frame).unwrap();
+    block_on(pipeline.draw(&layout, &mut session, &mut sink)).unwrap();
+    (layout, sink)
+}
+
+/// Check every panel pixel: black in the bars, and in the picture the
+/// image pixel that the decoder's scaling and then the layout pick.
+fn assert_drawn(layout: &Layout, sink: &Framebuffer, rgb: &[u8], image: (u16, u16)) {
+    let (iw, ih) = (image.0 as u32, image.1 as u32);
+    let (sw, sh) = match layout.scale {
+        (0, 0) => (iw, ih),
+        (w, h) => (w as u32, h as u32),
+    };
+    let (s, t) = (layout.source, layout.target);
+    let actual = sink.to_rgb888();
+    for y in 0..sink.height() {
+        for x in 0..sink.width() {
+            let at = (y as usize * sink.width() as usize + x as usize) * 3;
+            let pixel = &actual[at..at + 3];
+            let inside = (t.x..t.right()).contains(&x) && (t.y..t.bottom()).contains(&y);
+            if !inside {
+                assert_eq!(pixel, [0, 0, 0], "bar at {x},{y}");
+                continue;
+            }
+            let dx = s.x as u32 + (x - t.x) as u32 * s.width as u32 / t.width as u32;
+            let dy = s.y as u32 + (y - t.y) as u32 * s.height as u32 / t.height as u32;
+            let (ix, iy) = (dx * iw / sw, dy * ih / sh);
+            let at = ((iy * iw + ix) * 3) as usize;
+            for (channel, (&got, &want)) in pixel.iter().zip(&rgb[at..at + 3]).enumerate() {
+                assert!(
+                    (got as i16 - want as i16).abs() <= TOLERANCE,
+                    "{x},{y} (image {ix},{iy}) channel {channel}: {got} vs {want}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn stream_to_framebuffer() {
+    let rgb = image(96, 64);
+    let jpeg = encode(&rgb, 96, 64);
+    // Two frames with junk around them, as a raw TCP stream might have
+    let mut stream = b"\x00\xFF junk".to_vec();
+    stream.extend(&jpeg);
+    stream.extend(b"\r\n");
+    stream.extend(&jpeg);
+    for chunk in [1, 7, 4096] {
+        let frames = assemble(&stream, chunk);
+        assert_eq!(frames, [jpeg.clone(), jpeg.clone()], "chunks of {chunk}");
+    }
+
+    for (mode, panel) in [
+        // Bars on the left and right
+        (FitMode::Fit, (160, 80)),
+        // Bars all round
+        (FitMode::Center, (120, 90)),
+        // Cropped, and blown up
+        (FitMode::Fill, (200, 100)),
+        (FitMode::Stretch, (50, 170)),
+    ] {
+        let mut frame = jpeg.clone();
+        let (layout, sink) = show(&mut frame, mode, panel, 0);
+        assert_eq!(layout.scale, (0, 0));
+        let bars = layout.bars(panel).count();
+        match mode {
+            FitMode::Fit => assert_eq!(bars, 2),
+            FitMode::Center => assert_eq!(bars, 4),
+            _ => assert_eq!(bars, 0),
+        }
+        assert_drawn(&layout, &sink, &rgb, (96, 64));
+    }
+}
+
+#[test]
+fn decoder_scales_and_clips() {
+    let rgb = image(256, 128);
+    let jpeg = encode(&rgb, 256, 128);
+    for (mode, panel) in [(FitMode::Fit, (64, 48)), (FitMode::Fill, (48, 32))] {
+        let mut frame = assemble(&jpeg, 100).remove(0);
+        let (layout, sink) = show(&mut frame, mode, panel, 1 << 20);
+        assert_ne!(layout.scale, (0, 0), "{mode}");
+        assert_drawn(&layout, &sink, &rgb, (256, 128));
+    }
+}
+
+#[test]
+fn framebuffer_strips() {
+    let mut frame = Framebuffer::new(10, 5);
+    let mut write = |window: Rect, pixels: &[u8]| {
+        block_on(frame.set_window(window))?;
+        block_on(frame.write_pixels(pixels))
+    };
+    for (window, len) in [
+        (Rect::new(8, 0, 3, 1), 6),
+        (Rect::new(0, 4, 2, 2), 8),
+        // Too few pixels
+        (Rect::new(0, 0, 2, 2), 6),
+    ] {
+        assert_eq!(write(window, &vec![0; len]), Err(OutOfBounds { window }));
+    }
+    write(Rect::new(0, 0, 0, 0), &[]).unwrap();
+    // White and red over blue and green
+    write(
+        Rect::new(8, 3, 2, 2),
+        &[0xFF, 0xFF, 0xF8, 0, 0, 0x1F, 0x07, 0xE0],
+    )
+    .unwrap();
+
+    assert_eq!(frame.data()[(3 * 10 + 9) * 2..][..2], [0xF8, 0]);
+    assert_eq!(frame.data()[(4 * 10 + 9) * 2..][..2], [0x07, 0xE0]);
+    assert!(frame.pixel(9, 4).is_some());
+    assert_eq!(frame.pixel(10, 3), None);
+    let rgb = frame.to_rgb888();
+    let at = |x: usize, y: usize| &rgb[(y * 10 + x) * 3..][..3];
+    assert_eq!(at(8, 3), [255, 255, 255]);
+    assert_eq!(at(9, 3), [255, 0, 0]);
+    assert_eq!(at(8, 4), [0, 0, 255]);
+    assert_eq!(at(9, 4), [0, 255, 0]);
+    assert_eq!(at(0, 0), [0, 0, 0]);
+}
+
+/// The 8-bit RGB rows of a PNG written by [`Framebuffer::write_png`], whose
+/// deflate blocks are all stored.
+fn read_png(png: &[u8]) -> (u32, u32, Vec<u8>) {
+    const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+    assert_eq!(png[..8], *b"\x89PNG\r\n\x1a\n");
+    let (mut width, mut height, mut zlib) = (0, 0, Vec::new());
+    let mut rest = &png[8..];
+    while !rest.is_empty() {
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let (kind, data) = (&rest[4..8], &rest[8..8 + len]);
+        let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+        assert_eq!(CRC.checksum(&rest[4..8 + len]), crc);
+        match kind {
+            b"IHDR" => {
+                width = u32::from_be_bytes(data[..4].try_into().unwrap());
+                height = u32::from_be_bytes(data[4..8].try_into().unwrap());
+                assert_eq!(data[8..], [8, 2, 0, 0, 0]);
+            }
+            b"IDAT" => zlib.extend_from_slice(data),
+            _ => {}
+        }
+        rest = &rest[12 + len..];
+    }
+
+    let mut raw = Vec::new();
+    let mut blocks = &zlib[2..];
+    loop {
+        let last = blocks[0] == 1;
+        let len = u16::from_le_bytes([blocks[1], blocks[2]]) as usize;
+        assert_eq!(!u16::from_le_bytes([blocks[3], blocks[4]]) as usize, len);
+        raw.extend_from_slice(&blocks[5..5 + len]);
+        blocks = &blocks[5 + len..];
+        if last {
+            break;
+        }
+    }
+    assert_eq!(blocks.len(), 4, "Adler-32 at the end");
+
+    let stride = width as usize * 3 + 1;
+    assert_eq!(raw.len(), stride * height as usize);
+    let rows = raw.chunks_exact(stride).flat_map(|row| {
+        assert_eq!(row[0], 0, "no filter");
+        row[1..].iter().copied()
+    });
+    (width, height, rows.collect())
+}
+
+#[test]
+fn snapshots_save_every_frame() {
+    let dir = std::env::temp_dir().join(format!("rumble-snapshots-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let rgb = image(96, 64);
+    let mut frame = encode(&rgb, 96, 64);
+    let summary = markers::parse(&frame).unwrap();
+
+    // Over 64 KiB of rows, so the PNG takes several stored blocks
+    for (format, panel) in [
+        (ImageFormat::Png, (300, 250)),
+        (ImageFormat::Ppm, (160, 80)),
+    ] {
+        let mut sink = Snapshots::new(panel.0, panel.1, &dir, format);
+        let layout = Layout::new(FitMode::Fit, (summary.width, summary.height), panel, 0);
+        let config = layout.decoder_config(JpegPixelFormat::Rgb565Be).unwrap();
+        let mut decoder = BaselineDecoder::with_config(config).unwrap();
+        let mut pipeline = Pipeline::new(panel.0 as usize * 16);
+        for _ in 0..2 {
+            block_on(pipeline.clear(layout.bars(panel), &mut sink)).unwrap();
+            let mut session = decoder.start_decode(&mut frame).unwrap();
+            block_on(pipeline.draw(&layout, &mut session, &mut sink)).unwrap();
+        }
+        assert_eq!(sink.saved(), 2);
+        assert_drawn(&layout, sink.frame(), &rgb, (96, 64));
+
+        let name = format!("frame-00001.{}", format.extension());
+        let saved = std::fs::read(dir.join(name)).unwrap();
+        let (width, height, pixels) = match format {
+            ImageFormat::Png => read_png(&saved),
+            ImageFormat::Ppm => {
+                let header = format!("P6\n{} {}\n255\n", panel.0, panel.1);
+                assert!(saved.starts_with(header.as_bytes()));
+                (
+                    panel.0 as u32,
+                    panel.1 as u32,
+                    saved[header.len()..].to_vec(),
+                )
+            }
+        };
+        assert_eq!((width, height), (panel.0 as u32, panel.1 as u32));
+        assert_eq!(pixels, sink.frame().to_rgb888());
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
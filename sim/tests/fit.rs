@@ -0,0 +1,227 @@
+//! Layouts for every fit mode across image and panel sizes: where the picture
+//! goes, the bars around it, the rows each decoded block fills and the
+//! decoder settings.
+
+use rumble_rs::fit::{FitMode, Layout, Rect};
+use rumble_rs::jpeg::{JpegConfigError, JpegPixelFormat};
+
+const SIZES: [(u16, u16); 5] = [(640, 360), (240, 240), (1920, 1080), (320, 170), (16, 16)];
+const MODES: [FitMode; 4] = [
+    FitMode::Fit,
+    FitMode::Fill,
+    FitMode::Center,
+    FitMode::Stretch,
+];
+
+fn area(r: Rect) -> u64 {
+    r.width as u64 * r.height as u64
+}
+
+fn overlaps(a: Rect, b: Rect) -> bool {
+    a.x < b.right() && b.x < a.right() && a.y < b.bottom() && b.y < a.bottom()
+}
+
+/// Every layout the table covers, with what it was made from.
+fn layouts() -> impl Iterator<Item = (FitMode, (u16, u16), (u16, u16), usize, Layout)> {
+    MODES.into_iter().flat_map(|mode| {
+        SIZES.into_iter().flat_map(move |image| {
+            SIZES.into_iter().flat_map(move |panel| {
+                [0, 64 * 1024, 1 << 20].map(|budget| {
+                    let layout = Layout::new(mode, image, panel, budget);
+                    (mode, image, panel, budget, layout)
+                })
+            })
+        })
+    })
+}
+
+#[test]
+fn target_rect() {
+    for (mode, (iw, ih), (pw, ph), _, layout) in layouts() {
+        let (s, t) = (layout.source, layout.target);
+        let case = format!("{mode} {iw}x{ih} on {pw}x{ph}: {layout:?}");
+        assert!(
+            !t.is_empty() && t.right() <= pw && t.bottom() <= ph,
+            "{case}"
+        );
+        assert!(!s.is_empty(), "{case}");
+        match mode {
+            FitMode::Fill | FitMode::Stretch => assert_eq!(t, Rect::new(0, 0, pw, ph), "{case}"),
+            FitMode::Fit => {
+                assert!(t.width == pw || t.height == ph, "{case}");
+                assert_eq!(
+                    (t.x, t.y),
+                    ((pw - t.width) / 2, (ph - t.height) / 2),
+                    "{case}"
+                );
+                // The shorter side is rounded to the nearest pixel
+                let skew = (t.width as i64 * ih as i64 - t.height as i64 * iw as i64).abs();
+                assert!(skew <= iw.max(ih) as i64 / 2, "{case}");
+            }
+            FitMode::Center => {
+                let (w, h) = (iw.min(pw), ih.min(ph));
+                assert_eq!(t, Rect::new((pw - w) / 2, (ph - h) / 2, w, h), "{case}");
+            }
+        }
+        if layout.scale == (0, 0) {
+            assert!(s.right() <= iw && s.bottom() <= ih, "{case}");
+            if mode == FitMode::Center {
+                assert_eq!((s.width, s.height), (t.width, t.height), "{case}");
+            }
+        }
+    }
+}
+
+#[test]
+fn bars_cover_the_rest_of_the_panel() {
+    for (mode, image, (pw, ph), _, layout) in layouts() {
+        let case = format!("{mode} {image:?} on {pw}x{ph}: {layout:?}");
+        let mut rects: Vec<_> = layout.bars((pw, ph)).collect();
+        assert!(rects.iter().all(|r| !r.is_empty()), "{case}");
+        rects.push(layout.target);
+        for (i, a) in rects.iter().enumerate() {
+            assert!(a.right() <= pw && a.bottom() <= ph, "{case}: {a:?}");
+            for b in &rects[i + 1..] {
+                assert!(!overlaps(*a, *b), "{case}: {a:?} and {b:?}");
+            }
+        }
+        // Disjoint and inside the panel, so equal areas mean no gaps
+        let total: u64 = rects.iter().map(|&r| area(r)).sum();
+        assert_eq!(total, pw as u64 * ph as u64, "{case}");
+    }
+}
+
+#[test]
+fn rows_fill_the_target_once_in_order() {
+    for (mode, image, panel, _, layout) in layouts() {
+        let case = format!("{mode} {image:?} on {panel:?}: {layout:?}");
+        let config = layout.decoder_config(JpegPixelFormat::Rgb565Be).unwrap();
+        let (_, height) = config.output_size(image.0, image.1);
+        // Whole images when the decoder scales, blocks of 8 or 16 rows
+        // otherwise
+        let blocks: &[u16] = if config.block_mode() {
+            &[8, 16]
+        } else {
+            &[height]
+        };
+        for &block in blocks {
+            let mut next = layout.target.y;
+            let mut top = 0;
+            while top < height {
+                let rows = layout.rows(top, block.min(height - top));
+                assert_eq!(rows.start, next, "{case}, block at {top}");
+                assert!(rows.start <= rows.end, "{case}, block at {top}");
+                next = rows.end;
+                top += block;
+            }
+            assert_eq!(next, layout.target.bottom(), "{case}, blocks of {block}");
+        }
+    }
+}
+
+#[test]
+fn decoder_settings_are_multiples_of_8() {
+    for (mode, image, panel, budget, layout) in layouts() {
+        let case = format!("{mode} {image:?} on {panel:?} in {budget}: {layout:?}");
+        let config = layout.decoder_config(JpegPixelFormat::Rgb565Be).unwrap();
+        assert_eq!(config.output_format(), JpegPixelFormat::Rgb565Be, "{case}");
+        assert_eq!(config.scale(), layout.scale, "{case}");
+        assert_eq!(config.clipper(), layout.clipper, "{case}");
+        assert_eq!(config.block_mode(), layout.scale == (0, 0), "{case}");
+        let (sw, sh) = layout.scale;
+        let (cw, ch) = layout.clipper;
+        for n in [sw, sh, cw, ch] {
+            assert!(n.is_multiple_of(8), "{case}");
+        }
+        assert!(cw <= sw && ch <= sh, "{case}");
+
+        let (ow, oh) = config.output_size(image.0, image.1);
+        let source = layout.source;
+        assert!(source.right() <= ow && source.bottom() <= oh, "{case}");
+        if layout.scale != (0, 0) {
+            assert!(ow as usize * oh as usize * 2 <= budget, "{case}");
+            // Never shrinks below a pixel per target pixel
+            let target = layout.target;
+            assert!(source.width >= target.width, "{case}");
+            assert!(source.height >= target.height, "{case}");
+        }
+        if budget == 0 {
+            assert_eq!(layout.scale, (0, 0), "{case}");
+        }
+    }
+}
+
+#[test]
+fn exact_layouts() {
+    const PANEL: (u16, u16) = (320, 170);
+
+    // 240x240 fit: 170x170 in the middle, bars left and right
+    let layout = Layout::new(FitMode::Fit, (240, 240), PANEL, 0);
+    assert_eq!(layout.target, Rect::new(75, 0, 170, 170));
+    assert_eq!(
+        layout.bars(PANEL).collect::<Vec<_>>(),
+        [Rect::new(0, 0, 75, 170), Rect::new(245, 0, 75, 170)]
+    );
+
+    // 240x240 centered: rows 35..205 of it, bars of 40
+    let layout = Layout::new(FitMode::Center, (240, 240), PANEL, 0);
+    assert_eq!(layout.source, Rect::new(0, 35, 240, 170));
+    assert_eq!(layout.target, Rect::new(40, 0, 240, 170));
+
+    // 640x360 fit: 360 isn't a multiple of 16, so decoded in blocks
+    let layout = Layout::new(FitMode::Fit, (640, 360), PANEL, 1 << 20);
+    assert_eq!(layout.target, Rect::new(9, 0, 302, 170));
+    assert_eq!(layout.scale, (0, 0));
+
+    // 1280x720 fill with room: the decoder halves it and clips the bottom
+    // off, to the next multiple of 8
+    let layout = Layout::new(FitMode::Fill, (1280, 720), PANEL, 1 << 20);
+    assert_eq!(layout.scale, (640, 360));
+    assert_eq!(layout.source, Rect::new(0, 10, 640, 340));
+    assert_eq!(layout.clipper, (640, 352));
+    // and without, a full-size decode in blocks
+    let layout = Layout::new(FitMode::Fill, (1280, 720), PANEL, 64 * 1024);
+    assert_eq!(layout.scale, (0, 0));
+    assert_eq!(layout.source, Rect::new(0, 20, 1280, 680));
+    // 1080 isn't a multiple of 16, so 1920x1080 is never scaled
+    let layout = Layout::new(FitMode::Fill, (1920, 1080), PANEL, 1 << 20);
+    assert_eq!(layout.scale, (0, 0));
+    assert_eq!(layout.source, Rect::new(0, 30, 1920, 1020));
+
+    // A 16x16 image stretched: every panel row comes from one of its rows
+    let layout = Layout::new(FitMode::Stretch, (16, 16), PANEL, 1 << 20);
+    assert_eq!(layout.scale, (0, 0));
+    assert_eq!(layout.rows(0, 8), 0..85);
+    assert_eq!(layout.rows(8, 8), 85..170);
+}
+
+#[test]
+fn settings_the_decoder_refuses() {
+    let layout = Layout {
+        scale: (100, 60),
+        clipper: (0, 0),
+        source: Rect::new(0, 0, 100, 60),
+        target: Rect::new(0, 0, 100, 60),
+    };
+    assert_eq!(
+        layout.decoder_config(JpegPixelFormat::Rgb565Le),
+        Err(JpegConfigError::NotMultipleOf8)
+    );
+    let layout = Layout {
+        scale: (96, 64),
+        clipper: (104, 64),
+        ..layout
+    };
+    assert_eq!(
+        layout.decoder_config(JpegPixelFormat::Rgb565Le),
+        Err(JpegConfigError::ClipperExceedsScale)
+    );
+}
+
+#[test]
+fn parse_and_display() {
+    for mode in MODES {
+        assert_eq!(FitMode::parse(&mode.to_string()), Some(mode));
+    }
+    assert_eq!(FitMode::parse("zoom"), None);
+}
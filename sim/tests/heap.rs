@@ -0,0 +1,264 @@
+//! The codec's caps-aware heap over an allocator that counts what it is
+//! asked for, refuses regions on demand and keeps freed blocks around, so
+//! that double frees read memory that is still there.
+//!
+//! The installed allocator and the statistics are global, so the tests take
+//! turns.
+
+use std::alloc::Layout;
+use std::sync::{Mutex, MutexGuard};
+
+use rumble_rs::jpeg::heap::{
+    self, HeapStats, MALLOC_CAP_8BIT, MALLOC_CAP_32BIT, MALLOC_CAP_DEFAULT, MALLOC_CAP_DMA,
+    MALLOC_CAP_INTERNAL, MALLOC_CAP_SPIRAM, Region, RegionAllocator,
+};
+
+#[derive(Default)]
+struct State {
+    /// Regions that have no room.
+    full: Vec<Region>,
+    /// Every request, in order.
+    asked: Vec<Region>,
+    /// Blocks handed out and not freed yet.
+    live: Vec<(usize, Layout)>,
+    /// Freed blocks, kept until the test ends.
+    freed: Vec<(usize, Layout)>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    full: Vec::new(),
+    asked: Vec::new(),
+    live: Vec::new(),
+    freed: Vec::new(),
+});
+
+struct Counting;
+
+impl RegionAllocator for Counting {
+    unsafe fn alloc(&self, region: Region, layout: Layout) -> *mut u8 {
+        let mut state = STATE.lock().unwrap();
+        state.asked.push(region);
+        if state.full.contains(&region) {
+            return std::ptr::null_mut();
+        }
+        let raw = unsafe { std::alloc::alloc(layout) };
+        assert!(!raw.is_null());
+        // calloc has to clear what it hands out
+        unsafe { raw.write_bytes(0xAA, layout.size()) };
+        state.live.push((raw as usize, layout));
+        raw
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut state = STATE.lock().unwrap();
+        let at = state
+            .live
+            .iter()
+            .position(|&block| block == (ptr as usize, layout))
+            .expect("a live block, with the layout it was allocated with");
+        let block = state.live.remove(at);
+        state.freed.push(block);
+    }
+}
+
+static TURN: Mutex<()> = Mutex::new(());
+
+/// Install the counting allocator, with room everywhere, for one test.
+fn start() -> MutexGuard<'static, ()> {
+    let turn = TURN.lock().unwrap_or_else(|e| e.into_inner());
+    heap::set_allocator(&Counting);
+    let mut state = STATE.lock().unwrap();
+    state.full.clear();
+    state.asked.clear();
+    for (ptr, layout) in state.freed.drain(..) {
+        unsafe { std::alloc::dealloc(ptr as *mut u8, layout) };
+    }
+    turn
+}
+
+/// Leave no room in `regions`, and room in all others.
+fn fill(regions: &[Region]) {
+    STATE.lock().unwrap().full = regions.to_vec();
+}
+
+/// The regions asked for since the last call.
+fn asked() -> Vec<Region> {
+    std::mem::take(&mut STATE.lock().unwrap().asked)
+}
+
+/// Check that everything handed out was given back, to the heap and to the
+/// allocator.
+fn assert_all_freed() {
+    let stats = heap::stats();
+    assert_eq!((stats.live, stats.live_bytes), (0, 0));
+    assert_eq!(STATE.lock().unwrap().live, []);
+}
+
+/// What changed in the counters since `before`.
+fn since(before: HeapStats) -> (u32, u32) {
+    let now = heap::stats();
+    (now.failed - before.failed, now.bad_frees - before.bad_frees)
+}
+
+#[test]
+fn alignments_and_sizes() {
+    let _turn = start();
+    let before = heap::stats();
+    let mut blocks = Vec::new();
+    let mut bytes = 0;
+    for align in [1, 2, 4, 8, 16, 32, 64] {
+        for size in [1, 3, 17, 1000] {
+            let block = heap::calloc(size, 1, align, &[MALLOC_CAP_DMA | MALLOC_CAP_8BIT]);
+            assert!(!block.is_null());
+            assert!((block as usize).is_multiple_of(align), "{size} at {align}");
+            let data = unsafe { std::slice::from_raw_parts_mut(block, size) };
+            assert!(data.iter().all(|&b| b == 0), "{size} at {align}");
+            data.fill(0x55);
+            blocks.push(block);
+            bytes += size as u32;
+
+            let stats = heap::stats();
+            assert_eq!(stats.live, blocks.len() as u32);
+            assert_eq!(stats.live_bytes, bytes);
+            assert!(stats.peak_bytes >= bytes);
+        }
+    }
+    assert_eq!(asked(), [Region::Dma; 28]);
+    // Arrays of `n` elements count all of them
+    let array = heap::calloc(10, 12, 4, &[MALLOC_CAP_DEFAULT]);
+    assert_eq!(heap::stats().live_bytes, bytes + 120);
+    blocks.push(array);
+
+    for block in blocks {
+        unsafe { heap::free(block) };
+    }
+    assert_all_freed();
+    assert_eq!(since(before), (0, 0));
+}
+
+#[test]
+fn alignments_that_are_not_powers_of_two() {
+    let _turn = start();
+    let before = heap::stats();
+    for align in [24, 48, 96] {
+        assert!(heap::calloc(1, 8, align, &[MALLOC_CAP_DEFAULT]).is_null());
+    }
+    assert_eq!(asked(), []);
+    assert_eq!(since(before), (3, 0));
+}
+
+#[test]
+fn foreign_pointers_and_double_frees() {
+    let _turn = start();
+    let before = heap::stats();
+
+    // Not from calloc: the words before it don't make a header
+    let mut words = [0x1234usize; 16];
+    unsafe { heap::free(words.as_mut_ptr().add(8) as *mut u8) };
+    assert_eq!(since(before), (0, 1));
+
+    let block = heap::calloc(1, 64, 8, &[MALLOC_CAP_DEFAULT]);
+    unsafe { heap::free(block) };
+    assert_all_freed();
+    unsafe { heap::free(block) };
+    assert_eq!(since(before), (0, 2));
+    assert_eq!(STATE.lock().unwrap().freed.len(), 1);
+
+    // Null is no free at all
+    unsafe { heap::free(std::ptr::null_mut()) };
+    assert_eq!(since(before), (0, 2));
+    assert_all_freed();
+}
+
+#[test]
+fn psram_falls_back_to_the_default_heap() {
+    let _turn = start();
+    let before = heap::stats();
+    // PSRAM with room
+    let block = heap::calloc(1, 100, 4, &[MALLOC_CAP_SPIRAM]);
+    assert!(!block.is_null());
+    assert_eq!(asked(), [Region::External]);
+    unsafe { heap::free(block) };
+
+    // None, or full
+    fill(&[Region::External]);
+    let block = heap::calloc(1, 100, 4, &[MALLOC_CAP_SPIRAM | MALLOC_CAP_8BIT]);
+    assert!(!block.is_null());
+    assert_eq!(asked(), [Region::External, Region::Default]);
+    unsafe { heap::free(block) };
+
+    // Only PSRAM falls back
+    let block = heap::calloc(1, 100, 4, &[MALLOC_CAP_INTERNAL]);
+    assert!(!block.is_null());
+    assert_eq!(asked(), [Region::Internal]);
+    unsafe { heap::free(block) };
+
+    // and the default heap may be full too
+    fill(&[Region::External, Region::Default]);
+    assert!(heap::calloc(1, 100, 4, &[MALLOC_CAP_SPIRAM]).is_null());
+    assert_eq!(asked(), [Region::External, Region::Default]);
+    assert_eq!(since(before), (1, 0));
+    assert_all_freed();
+}
+
+#[test]
+fn regions_in_order_of_preference() {
+    assert_eq!(
+        Region::from_caps(MALLOC_CAP_DMA | MALLOC_CAP_SPIRAM),
+        Region::Dma
+    );
+    assert_eq!(
+        Region::from_caps(MALLOC_CAP_INTERNAL | MALLOC_CAP_SPIRAM),
+        Region::Internal
+    );
+    assert_eq!(
+        Region::from_caps(MALLOC_CAP_SPIRAM | MALLOC_CAP_8BIT),
+        Region::External
+    );
+    for caps in [0, MALLOC_CAP_8BIT | MALLOC_CAP_32BIT, MALLOC_CAP_DEFAULT] {
+        assert_eq!(Region::from_caps(caps), Region::Default);
+    }
+
+    let _turn = start();
+    let before = heap::stats();
+    fill(&[Region::Dma, Region::Internal]);
+    let caps = [MALLOC_CAP_DMA, MALLOC_CAP_INTERNAL, MALLOC_CAP_DEFAULT];
+    let block = heap::calloc(4, 4, 4, &caps);
+    assert!(!block.is_null());
+    assert_eq!(asked(), [Region::Dma, Region::Internal, Region::Default]);
+    unsafe { heap::free(block) };
+
+    // The first region with room wins
+    let block = heap::calloc(4, 4, 4, &[MALLOC_CAP_SPIRAM, MALLOC_CAP_DMA]);
+    assert!(!block.is_null());
+    assert_eq!(asked(), [Region::External]);
+    unsafe { heap::free(block) };
+
+    // Nowhere to try
+    assert!(heap::calloc(4, 4, 4, &[]).is_null());
+    assert!(heap::calloc(4, 4, 4, &[MALLOC_CAP_INTERNAL]).is_null());
+    assert_eq!(asked(), [Region::Internal]);
+    assert_eq!(since(before), (2, 0));
+    assert_all_freed();
+}
+
+#[test]
+fn sizes_that_overflow() {
+    let _turn = start();
+    let before = heap::stats();
+    let caps = [MALLOC_CAP_DEFAULT];
+    assert!(heap::calloc(usize::MAX, 2, 4, &caps).is_null());
+    assert!(heap::calloc(2, usize::MAX / 2 + 1, 4, &caps).is_null());
+    // Fits in usize, but not with the header
+    assert!(heap::calloc(1, usize::MAX - 4, 4, &caps).is_null());
+    // or not in a Layout
+    assert!(heap::calloc(1, isize::MAX as usize, 4, &caps).is_null());
+    assert_eq!(asked(), []);
+    assert_eq!(since(before), (4, 0));
+
+    // Nothing asked for isn't a failure
+    assert!(heap::calloc(0, 8, 4, &caps).is_null());
+    assert!(heap::calloc(8, 0, 4, &caps).is_null());
+    assert_eq!(since(before), (4, 0));
+    assert_all_freed();
+}
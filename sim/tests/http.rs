@@ -0,0 +1,246 @@
+//! The multipart client against a stand-in server on a loopback socket, and
+//! the demuxer against bodies cut at every byte.
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use common::Blocking;
+use embassy_futures::block_on;
+use rumble_rs::http::{HttpError, MultipartDemuxer, parse_response_head, request_stream};
+use rumble_rs::mjpeg::{DropReason, FrameSource, Status};
+
+const FRAME: &[u8] = include_bytes!("data/baseline.jpg");
+
+/// Accept one connection, read the request head and send `response` a piece
+/// at a time. The thread returns the request.
+fn serve(response: Vec<Vec<u8>>) -> (SocketAddr, JoinHandle<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        socket.set_nodelay(true).unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0u8; 256];
+        while !request.ends_with(b"\r\n\r\n") {
+            let n = socket.read(&mut buf).unwrap();
+            assert_ne!(n, 0, "request cut short");
+            request.extend_from_slice(&buf[..n]);
+        }
+        for piece in response {
+            socket.write_all(&piece).unwrap();
+            thread::sleep(Duration::from_millis(5));
+        }
+        request
+    });
+    (addr, server)
+}
+
+/// A multipart body with `frames` as parts, `Content-Length` on the ones
+/// `sized` says.
+fn body(boundary: &str, frames: &[&[u8]], sized: impl Fn(usize) -> bool) -> Vec<u8> {
+    let mut out = b"preamble\r\n".to_vec();
+    for (i, frame) in frames.iter().enumerate() {
+        out.extend_from_slice(format!("--{boundary}\r\nContent-Type: image/jpeg\r\n").as_bytes());
+        if sized(i) {
+            out.extend_from_slice(format!("Content-Length: {}\r\n", frame.len()).as_bytes());
+        }
+        out.extend_from_slice(b"\r\n");
+        out.extend_from_slice(frame);
+        out.extend_from_slice(b"\r\n");
+    }
+    out.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+    out
+}
+
+/// Push `input` through `demuxer`, collecting frames and drops.
+fn demux(
+    demuxer: &mut MultipartDemuxer,
+    mut input: &[u8],
+    out: &mut Vec<Result<Vec<u8>, DropReason>>,
+) {
+    while !input.is_empty() {
+        match demuxer.push(input) {
+            Status::Incomplete => break,
+            Status::Frame { consumed } => {
+                out.push(Ok(demuxer.frame_mut().unwrap().to_vec()));
+                input = &input[consumed..];
+            }
+            Status::Dropped { consumed, reason } => {
+                out.push(Err(reason));
+                input = &input[consumed..];
+            }
+        }
+    }
+}
+
+const HEAD: &[u8] =
+    b"HTTP/1.0 200 OK\r\nContent-Type: multipart/x-mixed-replace;boundary=ffmpeg\r\n\r\n";
+
+#[test]
+fn stream_from_stand_in_server() {
+    let other = [FRAME, b"\r\n--ffmpe"].concat();
+    let frames: [&[u8]; 3] = [FRAME, &other, FRAME];
+    let body = body("ffmpeg", &frames, |i| i != 1);
+    // The head and the start of the body in one piece, then cut inside a
+    // boundary
+    let cut = body.windows(8).rposition(|w| w == b"--ffmpeg").unwrap() + 4;
+    let first = [HEAD, &body[..100]].concat();
+    let (addr, server) = serve(vec![first, body[100..cut].to_vec(), body[cut..].to_vec()]);
+
+    let mut socket = Blocking(TcpStream::connect(addr).unwrap());
+    let host = addr.to_string();
+    let mut buf = vec![0u8; 1024];
+    let (head, pending) =
+        block_on(request_stream(&mut socket, &host, "/stream", &mut buf)).unwrap();
+    assert_eq!(head.status, 200);
+    assert_eq!(head.boundary, b"ffmpeg");
+
+    let mut demuxer = MultipartDemuxer::new(4096);
+    demuxer.start(&head.boundary);
+    let mut out = Vec::new();
+    demux(&mut demuxer, &buf[..pending], &mut out);
+    loop {
+        let n = socket.0.read(&mut buf).unwrap();
+        if n == 0 {
+            break;
+        }
+        demux(&mut demuxer, &buf[..n], &mut out);
+    }
+    let expected: Vec<_> = frames.iter().map(|f| Ok(f.to_vec())).collect();
+    assert_eq!(out, expected);
+    assert!(demuxer.is_closed());
+
+    let request = String::from_utf8(server.join().unwrap()).unwrap();
+    assert!(request.starts_with(&format!("GET /stream HTTP/1.1\r\nHost: {host}\r\n")));
+}
+
+#[test]
+fn error_status_from_stand_in_server() {
+    let (addr, server) = serve(vec![
+        b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec(),
+    ]);
+    let mut socket = Blocking(TcpStream::connect(addr).unwrap());
+    let mut buf = [0u8; 256];
+    let result = block_on(request_stream(&mut socket, "camera", "/", &mut buf));
+    assert!(matches!(result, Err(HttpError::Status(404))), "{result:?}");
+    server.join().unwrap();
+}
+
+#[test]
+fn head_too_large_or_cut_short() {
+    let (addr, server) = serve(vec![HEAD.to_vec()]);
+    let mut socket = Blocking(TcpStream::connect(addr).unwrap());
+    let mut buf = [0u8; 32];
+    let result = block_on(request_stream(&mut socket, "camera", "/", &mut buf));
+    assert!(matches!(result, Err(HttpError::HeadTooLarge)), "{result:?}");
+    server.join().unwrap();
+
+    let (addr, server) = serve(vec![HEAD[..20].to_vec()]);
+    let mut socket = Blocking(TcpStream::connect(addr).unwrap());
+    let mut buf = [0u8; 256];
+    let result = block_on(request_stream(&mut socket, "camera", "/", &mut buf));
+    assert!(matches!(result, Err(HttpError::Closed)), "{result:?}");
+    server.join().unwrap();
+}
+
+#[test]
+fn response_heads() {
+    let boundary = |content_type: &str| {
+        let head =
+            format!("HTTP/1.1 200 OK\r\nServer: cam\r\nContent-Type: {content_type}\r\n\r\n");
+        parse_response_head::<()>(head.as_bytes()).map(|head| head.boundary)
+    };
+    assert_eq!(
+        boundary("multipart/x-mixed-replace;boundary=ffmpeg").unwrap(),
+        b"ffmpeg"
+    );
+    assert_eq!(
+        boundary("multipart/x-mixed-replace; boundary=\"frame\"").unwrap(),
+        b"frame"
+    );
+    assert_eq!(
+        boundary("Multipart/X-Mixed-Replace; charset=utf-8; BOUNDARY = --myboundary").unwrap(),
+        b"--myboundary"
+    );
+    let longest = "b".repeat(70);
+    assert_eq!(
+        boundary(&format!("multipart/x-mixed-replace;boundary={longest}")).unwrap(),
+        longest.as_bytes()
+    );
+    for content_type in [
+        "image/jpeg",
+        "multipart/x-mixed-replace",
+        "multipart/x-mixed-replace; boundary=",
+        "multipart/x-mixed-replace; boundary=\"\"",
+        &format!("multipart/x-mixed-replace;boundary={longest}b"),
+    ] {
+        assert!(
+            matches!(boundary(content_type), Err(HttpError::NotMultipart)),
+            "{content_type}"
+        );
+    }
+
+    let parse = |head: &[u8]| parse_response_head::<()>(head);
+    assert!(matches!(
+        parse(b"HTTP/1.1 503 Busy\r\n\r\n"),
+        Err(HttpError::Status(503))
+    ));
+    assert!(matches!(
+        parse(b"ICY 200 OK\r\n\r\n"),
+        Err(HttpError::BadResponse)
+    ));
+    assert!(matches!(
+        parse(b"HTTP/1.1 OK\r\n\r\n"),
+        Err(HttpError::BadResponse)
+    ));
+    let chunked = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\
+        Content-Type: multipart/x-mixed-replace;boundary=x\r\n\r\n";
+    assert!(matches!(
+        parse(chunked),
+        Err(HttpError::UnsupportedEncoding)
+    ));
+}
+
+#[test]
+fn parts_with_and_without_content_length() {
+    // A delimiter-like line and a lone CR inside the unsized part
+    let tricky = [FRAME, b"\r\n--ffmpe\r\r\n-", FRAME].concat();
+    let frames: [&[u8]; 4] = [FRAME, &tricky, FRAME, &tricky];
+    for sized in [|_| true, |_| false, |i: usize| i.is_multiple_of(2)] {
+        let body = body("ffmpeg", &frames, sized);
+        // Every chunk size up to the longest line splits some boundary
+        for chunk in (1..40).chain([1000, body.len()]) {
+            let mut demuxer = MultipartDemuxer::new(4096);
+            demuxer.start(b"ffmpeg");
+            let mut out = Vec::new();
+            for input in body.chunks(chunk) {
+                demux(&mut demuxer, input, &mut out);
+            }
+            let expected: Vec<_> = frames.iter().map(|f| Ok(f.to_vec())).collect();
+            assert_eq!(out, expected, "{chunk}-byte chunks");
+            assert!(demuxer.is_closed());
+        }
+    }
+}
+
+#[test]
+fn oversized_part() {
+    let big = [FRAME, FRAME].concat();
+    for sized in [true, false] {
+        let body = body("ffmpeg", &[&big, FRAME], |_| sized);
+        for chunk in [1, 7, body.len()] {
+            let mut demuxer = MultipartDemuxer::new(FRAME.len());
+            demuxer.start(b"ffmpeg");
+            let mut out = Vec::new();
+            for input in body.chunks(chunk) {
+                demux(&mut demuxer, input, &mut out);
+            }
+            let too_large = Err(DropReason::TooLarge { size: big.len() });
+            assert_eq!(out, [too_large, Ok(FRAME.to_vec())], "{chunk}-byte chunks");
+        }
+    }
+}
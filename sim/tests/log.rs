@@ -0,0 +1,182 @@
+//! The codec's `printf` subset, conversion by conversion, and where 64-bit
+//! arguments are found among the argument words.
+
+use std::ffi::CStr;
+
+use rumble_rs::jpeg::log::{self, Args};
+
+/// `format` with `words` as its arguments.
+fn printf(format: &str, words: &[usize]) -> String {
+    let mut out = String::new();
+    unsafe { log::format(&mut out, format.as_bytes(), &mut Args::new(words)).unwrap() };
+    out
+}
+
+/// A C `int` or `long` argument as the word it is passed in.
+fn int(value: i64) -> usize {
+    value as usize
+}
+
+fn string(s: &CStr) -> usize {
+    s.as_ptr() as usize
+}
+
+fn assert_formats(cases: &[(&str, &[usize], &str)]) {
+    for &(format, words, expected) in cases {
+        assert_eq!(printf(format, words), expected, "{format:?}");
+    }
+}
+
+#[test]
+fn signed_lengths() {
+    assert_formats(&[
+        ("%d|%i", &[int(-42), int(-1)], "-42|-1"),
+        ("%hhd|%hhi", &[0xFF, 0x80], "-1|-128"),
+        // Only the low byte or half of the word counts
+        ("%hhd|%hd", &[0x1FF, 0x1_8000], "-1|-32768"),
+        ("%hd|%hi", &[0xFFFF, 0x7FFF], "-1|32767"),
+        (
+            "%hhu|%hu|%u",
+            &[0x1FF, 0x1_8000, int(-1)],
+            "255|32768|4294967295",
+        ),
+        ("%ld|%zu", &[int(-5), 7], "-5|7"),
+        ("%+d|% d|%+d", &[3, 3, int(-3)], "+3| 3|-3"),
+    ]);
+}
+
+#[test]
+fn flags_width_and_precision() {
+    assert_formats(&[
+        ("%5d|%-5d|%05d|", &[42, 42, int(-42)], "   42|42   |-0042|"),
+        // Left alignment and a precision both turn zero padding off
+        ("%-08.3x|", &[0xAB], "0ab     |"),
+        ("%08.3x|", &[0xAB], "     0ab|"),
+        ("%-05d|", &[3], "3    |"),
+        ("%#o|%#o|%o", &[8, 0, 8], "010|0|10"),
+        (
+            "%#x|%#X|%#x|%08x",
+            &[255, 255, 0, 0xAB],
+            "0xff|0XFF|0|000000ab",
+        ),
+        ("%.3d|%5.3d|%-5.2x|", &[7, int(-7), 10], "007| -007|0a   |"),
+        // No digits at all for zero
+        (
+            "[%.0d]|[%5.0d]|[%+.0d]|[%.0x]",
+            &[0, 0, 0, 0],
+            "[]|[     ]|[+]|[]",
+        ),
+        ("%.0d", &[5], "5"),
+        (
+            "%p|%c%c",
+            &[0x3FC8_1234, b'o' as usize, b'k' as usize],
+            "0x3fc81234|ok",
+        ),
+    ]);
+}
+
+#[test]
+fn widths_and_precisions_from_arguments() {
+    assert_formats(&[
+        ("%*d|", &[4, 1], "   1|"),
+        // A negative width aligns left
+        ("%*d|", &[int(-4), 1], "1   |"),
+        ("%-*d|", &[int(-4), 1], "1   |"),
+        ("%0*d|", &[int(-4), 1], "1   |"),
+        ("%.*d|", &[3, 1], "001|"),
+        // and a negative precision is none
+        ("%.*d|%0.*d|", &[int(-1), 7, int(-1), 7], "7|7|"),
+    ]);
+}
+
+#[test]
+fn strings() {
+    let hello = c"hello";
+    let p = string(hello);
+    assert_formats(&[
+        ("%s|%10s|%-10s|", &[p, p, p], "hello|     hello|hello     |"),
+        ("%.3s|%.0s|%.9s|", &[p, p, p], "hel||hello|"),
+        ("%*s|%-*s|%.*s|", &[7, p, 7, p, 2, p], "  hello|hello  |he|"),
+        ("%s|%8s|%-8s|", &[0, 0, 0], "(null)|  (null)|(null)  |"),
+    ]);
+    // The precision stops the read before a NUL is needed
+    let unterminated = *b"abc";
+    assert_eq!(printf("%.3s", &[unterminated.as_ptr() as usize]), "abc");
+    // Bytes that aren't UTF-8 are replaced
+    assert_eq!(printf("%s", &[string(c"a\xFFb")]), "a\u{FFFD}b");
+    let mut out = String::new();
+    unsafe { log::format(&mut out, b"x\xFE%d", &mut Args::new(&[1])).unwrap() };
+    assert_eq!(out, "x\u{FFFD}1");
+}
+
+#[test]
+fn percent_and_unknown_conversions() {
+    assert_formats(&[
+        ("100%%", &[], "100%"),
+        ("%%d|%d", &[5], "%d|5"),
+        // Copied as they are, without using an argument
+        ("%q|%f|%d", &[5], "%q|%f|5"),
+        ("trailing %", &[], "trailing %"),
+        ("%-5", &[], "%-5"),
+    ]);
+}
+
+#[test]
+fn missing_arguments() {
+    assert_formats(&[
+        ("%d %d", &[1], "1 ?"),
+        ("%s|%c|%x", &[], "?|?|?"),
+        ("%lld", &[], "?"),
+        // The width runs out first
+        ("%*d", &[], "?"),
+        ("%5d|%-3s|", &[], "?|?|"),
+    ]);
+}
+
+/// On 32-bit targets a 64-bit argument takes two words, starting at an even
+/// word of the whole call: what came before the format's arguments counts.
+#[cfg(target_pointer_width = "32")]
+#[test]
+fn wide_arguments_in_word_pairs() {
+    const PAD: usize = 0xDEAD_BEEF;
+    let (low, high) = (int(-5), int(-1));
+    let case = |position, words: &[usize]| {
+        let mut out = String::new();
+        let mut args = Args::after(position, words);
+        unsafe { log::format(&mut out, b"%d %lld %d", &mut args).unwrap() };
+        out
+    };
+    // After an even number of words the pair skips one
+    assert_eq!(case(0, &[7, PAD, low, high, 8]), "7 -5 8");
+    assert_eq!(case(2, &[7, PAD, low, high, 8]), "7 -5 8");
+    // after an odd number it doesn't
+    assert_eq!(case(1, &[7, low, high, 8]), "7 -5 8");
+    assert_eq!(case(3, &[7, low, high, 8]), "7 -5 8");
+
+    assert_eq!(
+        printf("%llu|%jd", &[1, 2, int(-1), 0x7FFF_FFFF]),
+        "8589934593|9223372036854775807"
+    );
+    assert_eq!(printf("%llx", &[1]), "?");
+}
+
+/// On 64-bit targets every argument is one word, wherever it falls.
+#[cfg(target_pointer_width = "64")]
+#[test]
+fn wide_arguments_in_single_words() {
+    let case = |position, words: &[usize]| {
+        let mut out = String::new();
+        let mut args = Args::after(position, words);
+        unsafe { log::format(&mut out, b"%d %lld %d", &mut args).unwrap() };
+        out
+    };
+    for position in 0..4 {
+        assert_eq!(case(position, &[7, int(-5), 8]), "7 -5 8");
+    }
+    assert_eq!(
+        printf("%llu|%jd|%llx", &[1 << 33, int(i64::MAX), usize::MAX]),
+        "8589934592|9223372036854775807|ffffffffffffffff"
+    );
+    // A `long` is the whole word too
+    assert_eq!(printf("%ld", &[int(-1 << 40)]), "-1099511627776");
+}
@@ -0,0 +1,255 @@
+//! The marker walker against the sample frames in `data/` and hand-made
+//! headers, one test per way a frame can be turned down.
+
+use rumble_rs::jpeg::JpegSubsampling;
+use rumble_rs::jpeg::markers::{
+    self, DHT, DNL, DQT, DRI, EOI, MarkerError, SOF0, SOF1, SOS, parse, parse_header,
+};
+
+const BASELINE: &[u8] = include_bytes!("data/baseline.jpg");
+const PROGRESSIVE: &[u8] = include_bytes!("data/progressive.jpg");
+const ARITHMETIC: &[u8] = include_bytes!("data/arithmetic.jpg");
+const TWELVE_BIT: &[u8] = include_bytes!("data/12bit.jpg");
+const TRUNCATED: &[u8] = include_bytes!("data/truncated.jpg");
+const BAD_LENGTH: &[u8] = include_bytes!("data/bad-length.jpg");
+
+/// Where the first `marker` segment starts in `data`.
+fn find(data: &[u8], marker: u8) -> usize {
+    data.windows(2)
+        .position(|w| w == [0xFF, marker])
+        .expect("marker")
+}
+
+/// A frame made of `segments` between SOI and EOI, with no scan data.
+fn frame(segments: &[(u8, &[u8])]) -> Vec<u8> {
+    let mut out = vec![0xFF, markers::SOI];
+    for &(marker, payload) in segments {
+        out.extend_from_slice(&[0xFF, marker]);
+        out.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+        out.extend_from_slice(payload);
+    }
+    out.extend_from_slice(&[0xFF, EOI]);
+    out
+}
+
+const DQT0: &[u8] = &[0x00; 65];
+/// 16×16, one component using quantization table 0.
+const SOF_GRAY: &[u8] = &[8, 0, 16, 0, 16, 1, 1, 0x11, 0];
+const SOS_GRAY: &[u8] = &[1, 1, 0x00, 0, 63, 0];
+
+#[test]
+fn baseline_frame() {
+    let summary = parse(BASELINE).unwrap();
+    assert_eq!((summary.width, summary.height), (16, 16));
+    assert_eq!(summary.sof, SOF0);
+    assert_eq!(summary.precision, 8);
+    assert_eq!(summary.subsampling(), Some(JpegSubsampling::Yuv420));
+    assert!(summary.has_huffman_tables());
+    assert_eq!(summary.scans, 1);
+    assert_eq!(summary.len, BASELINE.len());
+    assert_eq!(&BASELINE[summary.scan_offset - 14..][..2], [0xFF, SOS]);
+}
+
+#[test]
+fn bytes_after_eoi_are_left_out() {
+    let mut data = BASELINE.to_vec();
+    data.extend_from_slice(&[0xFF, markers::SOI, 1, 2, 3]);
+    assert_eq!(parse(&data).unwrap().len, BASELINE.len());
+}
+
+#[test]
+fn header_stops_at_the_scan() {
+    let full = parse(BASELINE).unwrap();
+    let header = parse_header(&BASELINE[..full.scan_offset]).unwrap();
+    assert_eq!((header.width, header.height), (16, 16));
+    assert_eq!(
+        (header.scan_offset, header.scans, header.len),
+        (full.scan_offset, 1, 0)
+    );
+    assert_eq!(
+        parse_header(&BASELINE[..full.scan_offset - 1]),
+        Err(MarkerError::Truncated)
+    );
+}
+
+#[test]
+fn every_prefix_is_truncated() {
+    for cut in 2..BASELINE.len() {
+        assert_eq!(
+            parse(&BASELINE[..cut]),
+            Err(MarkerError::Truncated),
+            "{cut}"
+        );
+    }
+}
+
+#[test]
+fn missing_soi() {
+    assert_eq!(parse(&[]), Err(MarkerError::MissingSoi));
+    assert_eq!(parse(&BASELINE[2..]), Err(MarkerError::MissingSoi));
+}
+
+#[test]
+fn truncated() {
+    assert_eq!(parse(TRUNCATED), Err(MarkerError::Truncated));
+}
+
+#[test]
+fn invalid_marker() {
+    // A stray byte between segments
+    let mut data = BASELINE.to_vec();
+    data.insert(find(BASELINE, DQT), 0x42);
+    assert_eq!(parse(&data), Err(MarkerError::InvalidMarker(0x42)));
+
+    // The reserved JPG marker
+    let mut data = BASELINE.to_vec();
+    data[find(BASELINE, DQT) + 1] = markers::JPG;
+    assert_eq!(parse(&data), Err(MarkerError::InvalidMarker(markers::JPG)));
+
+    // A second SOI
+    let data = frame(&[(markers::SOI, &[])]);
+    assert_eq!(parse(&data), Err(MarkerError::InvalidMarker(markers::SOI)));
+}
+
+#[test]
+fn bad_segment_length() {
+    assert_eq!(parse(BAD_LENGTH), Err(MarkerError::BadSegmentLength(DQT)));
+
+    let mut data = BASELINE.to_vec();
+    let dqt = find(BASELINE, DQT);
+    data[dqt + 2..dqt + 4].copy_from_slice(&1u16.to_be_bytes());
+    assert_eq!(parse(&data), Err(MarkerError::BadSegmentLength(DQT)));
+
+    let data = frame(&[(DRI, &[0, 1, 2])]);
+    assert_eq!(parse(&data), Err(MarkerError::BadSegmentLength(DRI)));
+    let data = frame(&[(DQT, DQT0), (SOF0, &SOF_GRAY[..8])]);
+    assert_eq!(parse(&data), Err(MarkerError::BadSegmentLength(SOF0)));
+    let data = frame(&[(DQT, DQT0), (SOF0, SOF_GRAY), (SOS, &SOS_GRAY[..5])]);
+    assert_eq!(parse(&data), Err(MarkerError::BadSegmentLength(SOS)));
+    let data = frame(&[(DHT, &[0x00, 1])]);
+    assert_eq!(parse(&data), Err(MarkerError::BadSegmentLength(DHT)));
+}
+
+#[test]
+fn progressive() {
+    assert_eq!(parse(PROGRESSIVE), Err(MarkerError::Progressive));
+}
+
+#[test]
+fn lossless() {
+    let mut data = BASELINE.to_vec();
+    data[find(BASELINE, SOF0) + 1] = markers::SOF3;
+    assert_eq!(parse(&data), Err(MarkerError::Lossless));
+}
+
+#[test]
+fn hierarchical() {
+    let mut data = BASELINE.to_vec();
+    data[find(BASELINE, SOF0) + 1] = 0xC5;
+    assert_eq!(parse(&data), Err(MarkerError::Hierarchical));
+
+    // Height deferred to a DNL segment
+    let mut sof = SOF_GRAY.to_vec();
+    sof[1..3].copy_from_slice(&[0, 0]);
+    let data = frame(&[(DQT, DQT0), (SOF0, &sof)]);
+    assert_eq!(parse(&data), Err(MarkerError::Hierarchical));
+    let data = frame(&[(DNL, &[0, 16])]);
+    assert_eq!(parse(&data), Err(MarkerError::Hierarchical));
+}
+
+#[test]
+fn arithmetic() {
+    assert_eq!(parse(ARITHMETIC), Err(MarkerError::Arithmetic));
+    let data = frame(&[(markers::DAC, &[0x00, 0x10])]);
+    assert_eq!(parse(&data), Err(MarkerError::Arithmetic));
+}
+
+#[test]
+fn unsupported_precision() {
+    assert_eq!(
+        parse(TWELVE_BIT),
+        Err(MarkerError::UnsupportedPrecision(12))
+    );
+    assert_eq!(
+        parse(&TWELVE_BIT[..find(TWELVE_BIT, SOF1) + 4]),
+        Err(MarkerError::Truncated)
+    );
+}
+
+#[test]
+fn unsupported_components() {
+    let sof = [8, 0, 16, 0, 16, 2, 1, 0x11, 0, 2, 0x11, 0];
+    let data = frame(&[(DQT, DQT0), (SOF0, &sof)]);
+    assert_eq!(parse(&data), Err(MarkerError::UnsupportedComponents(2)));
+}
+
+#[test]
+fn zero_size() {
+    let mut sof = SOF_GRAY.to_vec();
+    sof[3..5].copy_from_slice(&[0, 0]);
+    let data = frame(&[(DQT, DQT0), (SOF0, &sof)]);
+    assert_eq!(parse(&data), Err(MarkerError::ZeroSize));
+}
+
+#[test]
+fn duplicate_frame_header() {
+    let data = frame(&[(DQT, DQT0), (SOF0, SOF_GRAY), (SOF0, SOF_GRAY)]);
+    assert_eq!(parse(&data), Err(MarkerError::DuplicateFrameHeader));
+}
+
+#[test]
+fn scan_before_frame() {
+    let data = frame(&[(DQT, DQT0), (SOS, SOS_GRAY)]);
+    assert_eq!(parse(&data), Err(MarkerError::ScanBeforeFrame));
+}
+
+#[test]
+fn unknown_component() {
+    let sos = [1, 9, 0x00, 0, 63, 0];
+    let data = frame(&[(DQT, DQT0), (SOF0, SOF_GRAY), (SOS, &sos)]);
+    assert_eq!(parse(&data), Err(MarkerError::UnknownComponent(9)));
+}
+
+#[test]
+fn missing_quant_table() {
+    let mut sof = SOF_GRAY.to_vec();
+    sof[8] = 1;
+    let data = frame(&[(DQT, DQT0), (SOF0, &sof), (SOS, SOS_GRAY)]);
+    assert_eq!(parse(&data), Err(MarkerError::MissingQuantTable(1)));
+}
+
+#[test]
+fn bad_table_spec() {
+    let mut sof = SOF_GRAY.to_vec();
+    sof[7] = 0x51;
+    let data = frame(&[(DQT, DQT0), (SOF0, &sof)]);
+    assert_eq!(parse(&data), Err(MarkerError::BadTableSpec(SOF0)));
+
+    let data = frame(&[(DQT, &[0x04; 65])]);
+    assert_eq!(parse(&data), Err(MarkerError::BadTableSpec(DQT)));
+    let data = frame(&[(DHT, &[0x20; 17])]);
+    assert_eq!(parse(&data), Err(MarkerError::BadTableSpec(DHT)));
+    // More codes than a length has room for, given the shorter ones
+    for counts in [&[3][..], &[2, 1], &[1, 3]] {
+        let mut dht = vec![0x00];
+        dht.extend(counts);
+        dht.resize(17, 0);
+        dht.extend((0..counts.iter().sum()).collect::<Vec<u8>>());
+        let data = frame(&[(DHT, &dht)]);
+        assert_eq!(parse(&data), Err(MarkerError::BadTableSpec(DHT)));
+    }
+    // Every code of length 2 in use is fine
+    let mut dht = vec![0x00, 0, 4];
+    dht.resize(17 + 4, 0);
+    let data = frame(&[(DQT, DQT0), (DHT, &dht), (SOF0, SOF_GRAY), (SOS, SOS_GRAY)]);
+    assert_eq!(parse(&data).unwrap().huffman_tables, 1);
+    let sos = [1, 1, 0x40, 0, 63, 0];
+    let data = frame(&[(DQT, DQT0), (SOF0, SOF_GRAY), (SOS, &sos)]);
+    assert_eq!(parse(&data), Err(MarkerError::BadTableSpec(SOS)));
+}
+
+#[test]
+fn missing_scan() {
+    let data = frame(&[(DQT, DQT0), (SOF0, SOF_GRAY)]);
+    assert_eq!(parse(&data), Err(MarkerError::MissingScan));
+}
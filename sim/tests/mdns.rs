@@ -0,0 +1,428 @@
+//! Browsing for a service through PTR, SRV and A records, in one packet or
+//! many, and packets no browser should trust.
+
+use std::net::Ipv4Addr;
+
+use rumble_rs::mdns::{Browser, Service};
+
+const A: u16 = 1;
+const PTR: u16 = 12;
+const TXT: u16 = 16;
+const SRV: u16 = 33;
+/// IN, with the cache-flush bit responders set.
+const CLASS: u16 = 0x8001;
+const TTL: u32 = 120;
+
+const SERVICE: &str = "_rumble._tcp.local";
+const INSTANCE: &str = "My Laptop._rumble._tcp.local";
+const HOST: &str = "laptop.local";
+const ADDR: Ipv4Addr = Ipv4Addr::new(172, 20, 10, 2);
+const PORT: u16 = 3000;
+
+/// `dotted` in uncompressed wire format.
+fn name(dotted: &str) -> Vec<u8> {
+    let mut wire = Vec::new();
+    for label in dotted.split('.') {
+        wire.push(label.len() as u8);
+        wire.extend_from_slice(label.as_bytes());
+    }
+    wire.push(0);
+    wire
+}
+
+/// A compression pointer to `offset`.
+fn pointer(offset: usize) -> Vec<u8> {
+    vec![0xC0 | (offset >> 8) as u8, offset as u8]
+}
+
+fn srv_data(port: u16, target: &[u8]) -> Vec<u8> {
+    let mut rdata = vec![0, 0, 0, 0];
+    rdata.extend(port.to_be_bytes());
+    rdata.extend_from_slice(target);
+    rdata
+}
+
+/// A DNS message, built a section at a time.
+struct Packet(Vec<u8>);
+
+impl Packet {
+    /// An empty response to a standard query.
+    fn response() -> Self {
+        Self(vec![0, 0, 0x84, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+    }
+
+    /// An empty query, as other browsers on the network send.
+    fn query() -> Self {
+        Self(vec![0; 12])
+    }
+
+    /// Where the next byte goes, for pointers to it.
+    fn offset(&self) -> usize {
+        self.0.len()
+    }
+
+    fn count(&mut self, at: usize) {
+        let n = u16::from_be_bytes([self.0[at], self.0[at + 1]]) + 1;
+        self.0[at..at + 2].copy_from_slice(&n.to_be_bytes());
+    }
+
+    fn question(mut self, name: &[u8], qtype: u16) -> Self {
+        self.count(4);
+        self.0.extend_from_slice(name);
+        self.0.extend(qtype.to_be_bytes());
+        self.0.extend(1u16.to_be_bytes());
+        self
+    }
+
+    fn record(mut self, owner: &[u8], rtype: u16, ttl: u32, rdata: &[u8]) -> Self {
+        self.0.extend_from_slice(owner);
+        self.0.extend(rtype.to_be_bytes());
+        self.0.extend(CLASS.to_be_bytes());
+        self.0.extend(ttl.to_be_bytes());
+        self.0.extend((rdata.len() as u16).to_be_bytes());
+        self.0.extend_from_slice(rdata);
+        self
+    }
+
+    fn answer(mut self, owner: &[u8], rtype: u16, ttl: u32, rdata: &[u8]) -> Self {
+        self.count(6);
+        self.record(owner, rtype, ttl, rdata)
+    }
+
+    fn additional(mut self, owner: &[u8], rtype: u16, ttl: u32, rdata: &[u8]) -> Self {
+        self.count(10);
+        self.record(owner, rtype, ttl, rdata)
+    }
+
+    fn ptr(self, service: &str, instance: &str) -> Self {
+        self.answer(&name(service), PTR, TTL, &name(instance))
+    }
+
+    /// A PTR for the service, to `target` as it is.
+    fn ptr_raw(self, target: &[u8]) -> Self {
+        self.answer(&name(SERVICE), PTR, TTL, target)
+    }
+
+    fn srv(self, instance: &str, port: u16, host: &str) -> Self {
+        self.additional(&name(instance), SRV, TTL, &srv_data(port, &name(host)))
+    }
+
+    fn a(self, host: &str, addr: Ipv4Addr) -> Self {
+        self.additional(&name(host), A, TTL, &addr.octets())
+    }
+}
+
+fn found() -> Option<Service> {
+    Some(Service {
+        instance: INSTANCE.into(),
+        host: HOST.into(),
+        addr: ADDR,
+        port: PORT,
+    })
+}
+
+/// The questions of the query `browser` sends next, as names and types.
+fn questions(browser: &Browser) -> Vec<(String, u16)> {
+    let mut buf = [0; 512];
+    let len = browser.write_query(&mut buf).unwrap();
+    let query = &buf[..len];
+    // A standard query, with questions only
+    assert_eq!(query[..4], [0; 4]);
+    assert_eq!(query[6..12], [0; 6]);
+    let count = u16::from_be_bytes([query[4], query[5]]);
+
+    let mut pos = 12;
+    let mut found = Vec::new();
+    for _ in 0..count {
+        let mut labels = Vec::new();
+        while query[pos] != 0 {
+            let len = query[pos] as usize;
+            labels.push(String::from_utf8(query[pos + 1..pos + 1 + len].to_vec()).unwrap());
+            pos += 1 + len;
+        }
+        let qtype = u16::from_be_bytes([query[pos + 1], query[pos + 2]]);
+        // IN, asking for a unicast response
+        assert_eq!(query[pos + 3..pos + 5], [0x80, 1]);
+        found.push((labels.join("."), qtype));
+        pos += 5;
+    }
+    assert_eq!(pos, len);
+    found
+}
+
+#[test]
+fn everything_in_one_packet() {
+    // Records in the order a responder that puts its answer last might use
+    let packet = Packet::response()
+        .question(&name(SERVICE), PTR)
+        .a(HOST, ADDR)
+        .srv(INSTANCE, PORT, HOST)
+        .ptr(SERVICE, INSTANCE);
+    let mut browser = Browser::new(&[SERVICE]);
+    assert_eq!(browser.handle(&packet.0), found());
+}
+
+#[test]
+fn one_record_per_packet() {
+    let mut browser = Browser::new(&["_mjpeg._tcp.local", SERVICE]);
+    assert_eq!(
+        questions(&browser),
+        [("_mjpeg._tcp.local".into(), PTR), (SERVICE.into(), PTR)]
+    );
+
+    // An instance of another service, and a host nobody asked about yet
+    let other = Packet::response().ptr("_http._tcp.local", "Printer._http._tcp.local");
+    assert_eq!(browser.handle(&other.0), None);
+    let early = Packet::response().a(HOST, ADDR);
+    assert_eq!(browser.handle(&early.0), None);
+    assert_eq!(questions(&browser)[1], (SERVICE.into(), PTR));
+
+    assert_eq!(
+        browser.handle(&Packet::response().ptr(SERVICE, INSTANCE).0),
+        None
+    );
+    assert_eq!(questions(&browser), [(INSTANCE.into(), SRV)]);
+    // A second instance doesn't replace the first
+    let second = Packet::response().ptr(SERVICE, "Other._rumble._tcp.local");
+    assert_eq!(browser.handle(&second.0), None);
+    assert_eq!(questions(&browser), [(INSTANCE.into(), SRV)]);
+
+    // The address already came, so the SRV is enough
+    let srv = Packet::response().srv(INSTANCE, PORT, HOST);
+    assert_eq!(browser.handle(&srv.0), found());
+    assert_eq!(questions(&browser), [(HOST.into(), A)]);
+}
+
+#[test]
+fn addresses_after_the_srv() {
+    let mut browser = Browser::new(&[SERVICE]);
+    let packet = Packet::response()
+        .ptr(SERVICE, INSTANCE)
+        .srv(INSTANCE, PORT, HOST);
+    assert_eq!(browser.handle(&packet.0), None);
+    assert_eq!(questions(&browser), [(HOST.into(), A)]);
+
+    let elsewhere = Ipv4Addr::new(10, 0, 0, 9);
+    assert_eq!(
+        browser.handle(&Packet::response().a("other.local", elsewhere).0),
+        None
+    );
+    assert_eq!(browser.handle(&Packet::response().a(HOST, ADDR).0), found());
+    // The latest address wins
+    let moved = Packet::response().a(HOST, elsewhere);
+    assert_eq!(browser.handle(&moved.0).unwrap().addr, elsewhere);
+}
+
+#[test]
+fn only_the_latest_addresses_are_kept() {
+    let mut browser = Browser::new(&[SERVICE]);
+    let mut packet = Packet::response().a(HOST, ADDR);
+    for i in 0..8 {
+        packet = packet.a(&format!("host{i}.local"), Ipv4Addr::new(10, 0, 0, i));
+    }
+    assert_eq!(browser.handle(&packet.0), None);
+    let srv = Packet::response()
+        .ptr(SERVICE, INSTANCE)
+        .srv(INSTANCE, PORT, HOST);
+    assert_eq!(browser.handle(&srv.0), None);
+    assert_eq!(questions(&browser), [(HOST.into(), A)]);
+}
+
+#[test]
+fn names_match_in_any_case() {
+    let mut browser = Browser::new(&["_Rumble._TCP.local."]);
+    let packet = Packet::response()
+        .ptr("_rumble._tcp.LOCAL", INSTANCE)
+        .srv("MY LAPTOP._rumble._tcp.local", PORT, HOST)
+        .a("Laptop.Local", ADDR);
+    // Named as the records that introduced them say
+    assert_eq!(browser.handle(&packet.0), found());
+}
+
+#[test]
+fn compressed_names() {
+    let mut packet = Packet::response();
+    // _rumble._tcp.local in a record nobody reads
+    let service = packet.offset() + name("x.local").len() + 10;
+    packet = packet.answer(&name("x.local"), TXT, TTL, &name(SERVICE));
+    let local = service + 1 + 7 + 1 + 4;
+    // The PTR record's rdata starts two bytes of owner and ten of fixed
+    // fields after it
+    let instance = packet.offset() + 2 + 10;
+    let mut target = b"\x09My Laptop".to_vec();
+    target.extend(pointer(service));
+    packet = packet.answer(&pointer(service), PTR, TTL, &target);
+    let host = packet.offset() + 2 + 10 + 6;
+    let mut host_name = b"\x06laptop".to_vec();
+    host_name.extend(pointer(local));
+    packet = packet
+        .additional(&pointer(instance), SRV, TTL, &srv_data(PORT, &host_name))
+        .additional(&pointer(host), A, TTL, &ADDR.octets());
+
+    let mut browser = Browser::new(&[SERVICE]);
+    assert_eq!(browser.handle(&packet.0), found());
+}
+
+/// A response with `before(at)` as the data of a TXT record nobody reads,
+/// starting at `at`, then a PTR to the service's instance `target(at)`.
+fn ptr_into(before: impl Fn(usize) -> Vec<u8>, target: impl Fn(usize) -> Vec<u8>) -> Vec<u8> {
+    let mut packet = Packet::response();
+    let at = packet.offset() + name("x.local").len() + 10;
+    packet = packet.answer(&name("x.local"), TXT, TTL, &before(at));
+    packet.ptr_raw(&target(at)).0
+}
+
+#[test]
+fn pointer_chains_and_loops() {
+    // `jumps` pointers in a row, each to the one before, then the name
+    let chain = |jumps: usize| {
+        move |at: usize| {
+            let mut bytes = name(INSTANCE);
+            let mut previous = at;
+            for _ in 1..jumps {
+                let here = at + bytes.len();
+                bytes.extend(pointer(previous));
+                previous = here;
+            }
+            bytes
+        }
+    };
+    let last = |jumps: usize| {
+        move |at: usize| {
+            let len = name(INSTANCE).len() + 2 * (jumps - 1);
+            pointer(if jumps == 1 { at } else { at + len - 2 })
+        }
+    };
+    for (jumps, follows) in [(1, true), (16, true), (17, false), (40, false)] {
+        let mut browser = Browser::new(&[SERVICE]);
+        assert_eq!(browser.handle(&ptr_into(chain(jumps), last(jumps))), None);
+        let expected = if follows {
+            (INSTANCE, SRV)
+        } else {
+            (SERVICE, PTR)
+        };
+        assert_eq!(
+            questions(&browser),
+            [(expected.0.into(), expected.1)],
+            "{jumps} jumps"
+        );
+    }
+
+    let mut browser = Browser::new(&[SERVICE]);
+    // A pointer to itself
+    let packet = ptr_into(pointer, pointer);
+    assert_eq!(browser.handle(&packet), None);
+    // A label followed by a pointer back to the label, which would grow
+    // without end
+    let mut label = vec![63];
+    label.extend([b'a'; 63]);
+    let grows = ptr_into(
+        |at| {
+            let mut bytes = label.clone();
+            bytes.extend(pointer(at));
+            bytes
+        },
+        pointer,
+    );
+    assert_eq!(browser.handle(&grows), None);
+    // An owner name that loops makes the rest unreadable
+    let mut packet = Packet::response().ptr(SERVICE, INSTANCE);
+    let at = packet.offset();
+    packet = packet.answer(&pointer(at), A, TTL, &ADDR.octets());
+    assert_eq!(browser.handle(&packet.0), None);
+    assert_eq!(questions(&browser), [(SERVICE.into(), PTR)]);
+
+    // Labels of 64 and more that aren't pointers are not names
+    let mut packet = Packet::response().ptr_raw(b"\x40abc\x00");
+    packet.0[12] = 0x80;
+    assert_eq!(browser.handle(&packet.0), None);
+    assert_eq!(questions(&browser), [(SERVICE.into(), PTR)]);
+}
+
+#[test]
+fn truncated_packets() {
+    let packet = Packet::response()
+        .question(&name(SERVICE), PTR)
+        .ptr(SERVICE, INSTANCE)
+        .srv(INSTANCE, PORT, HOST)
+        .a(HOST, ADDR);
+    for cut in 0..packet.0.len() {
+        let mut browser = Browser::new(&[SERVICE]);
+        assert_eq!(browser.handle(&packet.0[..cut]), None, "cut at {cut}");
+        assert_eq!(questions(&browser), [(SERVICE.into(), PTR)], "cut at {cut}");
+    }
+
+    // Counts that promise more than there is
+    let mut packet = Packet::response().ptr(SERVICE, INSTANCE);
+    for at in [4, 6, 8, 10] {
+        let mut lying = packet.0.clone();
+        lying[at..at + 2].copy_from_slice(&u16::MAX.to_be_bytes());
+        let mut browser = Browser::new(&[SERVICE]);
+        assert_eq!(browser.handle(&lying), None);
+        assert_eq!(questions(&browser), [(SERVICE.into(), PTR)]);
+    }
+
+    // Records too short for their type are skipped, and the rest used. A
+    // name that runs on past its record's data is too short as well.
+    packet = Packet::response()
+        .answer(&name(SERVICE), PTR, TTL, &[])
+        .answer(&name(SERVICE), PTR, TTL, &name(INSTANCE)[..5])
+        .ptr(SERVICE, INSTANCE)
+        .additional(&name(INSTANCE), SRV, TTL, &[0, 0, 0, 0, 0x0B, 0xB8])
+        .additional(&name(HOST), A, TTL, &[172, 20, 10])
+        .srv(INSTANCE, PORT, HOST);
+    let mut browser = Browser::new(&[SERVICE]);
+    assert_eq!(browser.handle(&packet.0), None);
+    assert_eq!(questions(&browser), [(HOST.into(), A)]);
+}
+
+#[test]
+fn goodbyes_are_not_answers() {
+    let mut browser = Browser::new(&[SERVICE]);
+    let goodbye = Packet::response()
+        .answer(&name(SERVICE), PTR, 0, &name(INSTANCE))
+        .additional(&name(INSTANCE), SRV, 0, &srv_data(PORT, &name(HOST)))
+        .additional(&name(HOST), A, 0, &ADDR.octets());
+    assert_eq!(browser.handle(&goodbye.0), None);
+    assert_eq!(questions(&browser), [(SERVICE.into(), PTR)]);
+
+    // A goodbye for another instance next to a live one
+    let packet = Packet::response()
+        .answer(&name(SERVICE), PTR, 0, &name("Gone._rumble._tcp.local"))
+        .ptr(SERVICE, INSTANCE);
+    assert_eq!(browser.handle(&packet.0), None);
+    assert_eq!(questions(&browser), [(INSTANCE.into(), SRV)]);
+}
+
+#[test]
+fn queries_are_not_answers() {
+    let mut browser = Browser::new(&[SERVICE]);
+    // Known-answer lists in other browsers' queries carry records too
+    let mut packet = Packet::query()
+        .question(&name(SERVICE), PTR)
+        .ptr(SERVICE, INSTANCE)
+        .srv(INSTANCE, PORT, HOST)
+        .a(HOST, ADDR);
+    assert_eq!(browser.handle(&packet.0), None);
+    assert_eq!(questions(&browser), [(SERVICE.into(), PTR)]);
+
+    // and so do responses with another opcode
+    packet.0[2] = 0x84 | 2 << 3;
+    assert_eq!(browser.handle(&packet.0), None);
+    packet.0[2] = 0x84;
+    assert_eq!(browser.handle(&packet.0), found());
+}
+
+#[test]
+fn queries_that_do_not_fit() {
+    let browser = Browser::new(&[SERVICE, "_mjpeg._tcp.local"]);
+    let len = 12 + name(SERVICE).len() + 4 + name("_mjpeg._tcp.local").len() + 4;
+    let mut buf = vec![0; len];
+    assert_eq!(browser.write_query(&mut buf[..len - 1]), None);
+    assert_eq!(browser.write_query(&mut buf), Some(len));
+
+    // Names that can't be encoded are left out
+    let long = format!("{}.local", "x".repeat(64));
+    let browser = Browser::new(&["", "a..local", &long, SERVICE]);
+    assert_eq!(questions(&browser), [(SERVICE.into(), PTR)]);
+}
@@ -0,0 +1,126 @@
+//! Frame assembly from streams cut into chunks of every size.
+
+use rumble_rs::jpeg::markers::APP0;
+use rumble_rs::mjpeg::{DropReason, FrameAssembler, FrameSource, Stats, Status};
+
+const FRAME: &[u8] = include_bytes!("data/baseline.jpg");
+
+/// `frame` with an EXIF APP1 segment holding `thumbnail`, EOI and all.
+fn with_thumbnail(frame: &[u8], thumbnail: &[u8]) -> Vec<u8> {
+    let mut app1 = b"Exif\0\0".to_vec();
+    app1.extend_from_slice(thumbnail);
+    let mut out = frame[..2].to_vec();
+    out.extend_from_slice(&[0xFF, APP0 + 1]);
+    out.extend_from_slice(&(app1.len() as u16 + 2).to_be_bytes());
+    out.extend_from_slice(&app1);
+    out.extend_from_slice(&frame[2..]);
+    out
+}
+
+/// What comes out of `stream` pushed `chunk` bytes at a time.
+fn assemble(
+    assembler: &mut FrameAssembler,
+    stream: &[u8],
+    chunk: usize,
+) -> (Vec<Vec<u8>>, Vec<DropReason>) {
+    let mut frames = Vec::new();
+    let mut drops = Vec::new();
+    for mut input in stream.chunks(chunk) {
+        while !input.is_empty() {
+            match assembler.push(input) {
+                Status::Incomplete => break,
+                Status::Frame { consumed } => {
+                    frames.push(assembler.frame_mut().unwrap().to_vec());
+                    input = &input[consumed..];
+                }
+                Status::Dropped { consumed, reason } => {
+                    drops.push(reason);
+                    input = &input[consumed..];
+                }
+            }
+        }
+    }
+    (frames, drops)
+}
+
+#[test]
+fn markers_split_across_chunks() {
+    let thumbnailed = with_thumbnail(FRAME, FRAME);
+    let mut stream = b"\xFF\xFFjunk\xFF".to_vec();
+    for frame in [FRAME, &thumbnailed, FRAME] {
+        stream.extend_from_slice(frame);
+        stream.extend_from_slice(b"\r\n--boundary\r\n\xFF");
+    }
+    for chunk in [1, 2, 3, 5, 7, 64, 1000, stream.len()] {
+        let mut assembler = FrameAssembler::new(4096);
+        let (frames, drops) = assemble(&mut assembler, &stream, chunk);
+        assert_eq!(frames, [FRAME, &thumbnailed, FRAME], "{chunk}-byte chunks");
+        assert_eq!(drops, []);
+        assert_eq!(assembler.stats().frames, 3);
+    }
+}
+
+#[test]
+fn eoi_inside_app1_thumbnail() {
+    let frame = with_thumbnail(FRAME, FRAME);
+    let mut assembler = FrameAssembler::new(4096);
+    let (frames, drops) = assemble(&mut assembler, &frame, frame.len());
+    assert_eq!(frames, [frame]);
+    assert_eq!(drops, []);
+}
+
+#[test]
+fn oversized_frame_is_too_large() {
+    let big = with_thumbnail(FRAME, FRAME);
+    let mut stream = big.clone();
+    stream.extend_from_slice(FRAME);
+    for chunk in [1, 13, stream.len()] {
+        let mut assembler = FrameAssembler::new(FRAME.len());
+        let (frames, drops) = assemble(&mut assembler, &stream, chunk);
+        assert_eq!(frames, [FRAME]);
+        assert_eq!(drops, [DropReason::TooLarge { size: big.len() }]);
+        let stats = assembler.stats();
+        assert_eq!((stats.frames, stats.too_large), (1, 1));
+    }
+}
+
+#[test]
+fn new_soi_interrupts_the_frame() {
+    // Cut off in the middle of its scan
+    let mut stream = FRAME[..FRAME.len() - 10].to_vec();
+    stream.extend_from_slice(FRAME);
+    let mut assembler = FrameAssembler::new(4096);
+    let (frames, drops) = assemble(&mut assembler, &stream, 10);
+    assert_eq!(frames, [FRAME]);
+    assert_eq!(drops, [DropReason::Interrupted]);
+}
+
+#[test]
+fn stray_byte_between_segments_is_corrupt() {
+    let mut stream = FRAME[..2].to_vec();
+    stream.push(0x42);
+    stream.extend_from_slice(FRAME);
+    let mut assembler = FrameAssembler::new(4096);
+    let (frames, drops) = assemble(&mut assembler, &stream, 1);
+    assert_eq!(frames, [FRAME]);
+    assert_eq!(drops, [DropReason::Corrupt]);
+    assert_eq!(
+        assembler.stats(),
+        Stats {
+            frames: 1,
+            corrupt: 1,
+            ..Stats::default()
+        }
+    );
+}
+
+#[test]
+fn reset_forgets_the_partial_frame() {
+    let mut assembler = FrameAssembler::new(4096);
+    assert_eq!(assembler.push(&FRAME[..100]), Status::Incomplete);
+    assembler.reset();
+    let (frames, drops) = assemble(&mut assembler, &FRAME[100..], 64);
+    assert_eq!((frames.len(), drops.len()), (0, 0));
+    let (frames, _) = assemble(&mut assembler, FRAME, 64);
+    assert_eq!(frames, [FRAME]);
+}
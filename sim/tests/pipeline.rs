@@ -0,0 +1,215 @@
+//! Drawing frames through a sink that records when its transfers start and
+//! end, against blocks that take a while to decode.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use embassy_futures::{block_on, yield_now};
+use rumble_rs::display::DisplaySink;
+use rumble_rs::fit::{FitMode, Layout, Rect};
+use rumble_rs::pipeline::{BlockSource, DrawError, Pipeline};
+
+type Log = Rc<RefCell<Vec<String>>>;
+
+/// A `width`×`height` test image, `block_height` rows at a time. Each pixel
+/// is the low bytes of its row and column.
+struct Blocks {
+    width: u16,
+    height: u16,
+    block_height: u16,
+    next: u16,
+    data: Vec<u8>,
+    log: Log,
+    fail_at: Option<u16>,
+}
+
+impl Blocks {
+    fn new(image: (u16, u16), log: &Log) -> Self {
+        Self {
+            width: image.0,
+            height: image.1,
+            block_height: 16,
+            next: 0,
+            data: Vec::new(),
+            log: log.clone(),
+            fail_at: None,
+        }
+    }
+}
+
+impl BlockSource for Blocks {
+    type Error = &'static str;
+
+    async fn next_block(&mut self) -> Option<Result<(u16, u16), &'static str>> {
+        if self.next >= self.height {
+            return None;
+        }
+        if Some(self.next) == self.fail_at {
+            return Some(Err("corrupt"));
+        }
+        let top = self.next;
+        let rows = self.block_height.min(self.height - top);
+        self.log.borrow_mut().push(format!("decode {top}"));
+        // Decoding takes more than one poll
+        yield_now().await;
+        self.data = (top..top + rows)
+            .flat_map(|y| (0..self.width).flat_map(move |x| [y as u8, x as u8]))
+            .collect();
+        self.next += rows;
+        self.log.borrow_mut().push(format!("decoded {top}"));
+        Some(Ok((self.width, rows)))
+    }
+
+    fn block(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// A panel whose pixel writes work like a DMA transfer: they start on the
+/// first poll and finish on a later one.
+struct Sink {
+    log: Log,
+    width: u16,
+    window: Rect,
+    panel: Vec<Option<[u8; 2]>>,
+}
+
+impl Sink {
+    fn new(panel: (u16, u16), log: &Log) -> Self {
+        Self {
+            log: log.clone(),
+            width: panel.0,
+            window: Rect::default(),
+            panel: vec![None; panel.0 as usize * panel.1 as usize],
+        }
+    }
+
+    fn pixel(&self, x: u16, y: u16) -> Option<[u8; 2]> {
+        self.panel[y as usize * self.width as usize + x as usize]
+    }
+}
+
+impl DisplaySink for Sink {
+    type Error = ();
+
+    async fn set_window(&mut self, window: Rect) -> Result<(), ()> {
+        self.log.borrow_mut().push(format!("window {}", window.y));
+        self.window = window;
+        Ok(())
+    }
+
+    async fn write_pixels(&mut self, pixels: &[u8]) -> Result<(), ()> {
+        let window = self.window;
+        assert_eq!(
+            pixels.len(),
+            window.width as usize * window.height as usize * 2
+        );
+        self.log.borrow_mut().push(format!("start {}", window.y));
+        yield_now().await;
+        for (i, pixel) in pixels.chunks_exact(2).enumerate() {
+            let x = window.x as usize + i % window.width as usize;
+            let y = window.y as usize + i / window.width as usize;
+            self.panel[y * self.width as usize + x] = Some([pixel[0], pixel[1]]);
+        }
+        self.log.borrow_mut().push(format!("end {}", window.y));
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), ()> {
+        self.log.borrow_mut().push("flush".into());
+        Ok(())
+    }
+}
+
+#[test]
+fn pixels_go_out_while_the_next_block_decodes() {
+    let log = Log::default();
+    // Rows 8..40 of the image, one to one
+    let layout = Layout::new(FitMode::Fill, (320, 48), (320, 32), 0);
+    assert_eq!(layout.source, Rect::new(0, 8, 320, 32));
+    let mut blocks = Blocks::new((320, 48), &log);
+    let mut sink = Sink::new((320, 32), &log);
+    let mut pipeline = Pipeline::new(320 * 16 * 2);
+    block_on(pipeline.draw(&layout, &mut blocks, &mut sink)).unwrap();
+
+    // The window is set before the next block starts decoding, and only
+    // the pixel transfer runs alongside it
+    assert_eq!(
+        *log.borrow(),
+        [
+            "decode 0",
+            "decoded 0",
+            "window 0",
+            "start 0",
+            "decode 16",
+            "end 0",
+            "decoded 16",
+            "window 8",
+            "start 8",
+            "decode 32",
+            "end 8",
+            "decoded 32",
+            "window 24",
+            "start 24",
+            "end 24",
+            "flush",
+        ]
+    );
+    assert!(sink.panel.iter().all(|pixel| pixel.is_some()));
+}
+
+#[test]
+fn strips_bars_and_skipped_blocks() {
+    const PANEL: (u16, u16) = (320, 170);
+    // Bars around a small image, upscaled blocks split into several strips,
+    // cropped blocks that aren't shown and a one-row buffer
+    for (mode, image, buffer) in [
+        (FitMode::Fit, (96, 64), 320 * 2 * 5),
+        (FitMode::Center, (640, 480), 640 * 2 * 16),
+        (FitMode::Fill, (320, 176), 100),
+        (FitMode::Stretch, (33, 17), 1),
+    ] {
+        let log = Log::default();
+        let layout = Layout::new(mode, image, PANEL, 0);
+        let mut blocks = Blocks::new(image, &log);
+        let mut sink = Sink::new(PANEL, &log);
+        let mut pipeline = Pipeline::new(buffer);
+        block_on(pipeline.clear(layout.bars(PANEL), &mut sink)).unwrap();
+        block_on(pipeline.draw(&layout, &mut blocks, &mut sink)).unwrap();
+
+        let (s, t) = (layout.source, layout.target);
+        for y in 0..PANEL.1 {
+            for x in 0..PANEL.0 {
+                let inside = (t.x..t.right()).contains(&x) && (t.y..t.bottom()).contains(&y);
+                let expected = if inside {
+                    let sy = s.y as u32 + (y - t.y) as u32 * s.height as u32 / t.height as u32;
+                    let sx = s.x as u32 + (x - t.x) as u32 * s.width as u32 / t.width as u32;
+                    [sy as u8, sx as u8]
+                } else {
+                    [0, 0]
+                };
+                assert_eq!(sink.pixel(x, y), Some(expected), "{mode} at {x},{y}");
+            }
+        }
+        let log = log.borrow();
+        let starts = log.iter().filter(|l| l.starts_with("start")).count();
+        let ends = log.iter().filter(|l| l.starts_with("end")).count();
+        let windows = log.iter().filter(|l| l.starts_with("window")).count();
+        assert_eq!((starts, ends), (windows, windows), "{mode}");
+    }
+}
+
+#[test]
+fn decode_error_after_the_last_transfer() {
+    let log = Log::default();
+    let layout = Layout::new(FitMode::Fill, (320, 176), (320, 170), 0);
+    let mut blocks = Blocks {
+        fail_at: Some(48),
+        ..Blocks::new((320, 176), &log)
+    };
+    let mut sink = Sink::new((320, 170), &log);
+    let result = block_on(Pipeline::new(10240).draw(&layout, &mut blocks, &mut sink));
+    assert!(matches!(result, Err(DrawError::Decode("corrupt"))));
+    // The strip in flight still finishes; nothing is flushed
+    assert_eq!(log.borrow().last().unwrap(), "end 29");
+}
@@ -0,0 +1,547 @@
+//! The portal's servers over loopback sockets: a phone's DHCP handshake, its
+//! DNS lookups, and the form it posts a piece at a time.
+
+mod common;
+
+use std::convert::Infallible;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, Shutdown, TcpListener, TcpStream, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use common::Blocking;
+use embassy_futures::block_on;
+use rumble_rs::config::{Config, Transport};
+use rumble_rs::portal::dhcp::DhcpServer;
+use rumble_rs::portal::web::{self, Request, Route, WebError};
+use rumble_rs::portal::{PORTAL_ADDR, dns};
+use rumble_rs::wifi::Network;
+
+const POOL_START: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 2);
+
+// DHCP message types
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+const NAK: u8 = 6;
+const RELEASE: u8 = 7;
+const INFORM: u8 = 8;
+
+// DHCP options
+const SUBNET_MASK: u8 = 1;
+const ROUTER: u8 = 3;
+const DNS_SERVER: u8 = 6;
+const REQUESTED_IP: u8 = 50;
+const LEASE_TIME: u8 = 51;
+const MESSAGE_TYPE: u8 = 53;
+const SERVER_ID: u8 = 54;
+
+/// A client message from the phone with MAC `02:00:00:00:00:<client>`.
+fn dhcp_message(kind: u8, client: u8, options: &[(u8, &[u8])]) -> Vec<u8> {
+    let mut packet = vec![0u8; 240];
+    packet[..4].copy_from_slice(&[1, 1, 6, 0]);
+    packet[4..8].copy_from_slice(&[0xDE, 0xAD, kind, client]);
+    // Broadcast flag
+    packet[10] = 0x80;
+    packet[28..34].copy_from_slice(&[2, 0, 0, 0, 0, client]);
+    packet[236..240].copy_from_slice(&[99, 130, 83, 99]);
+    packet.extend([MESSAGE_TYPE, 1, kind]);
+    for &(code, value) in options {
+        packet.extend([code, value.len() as u8]);
+        packet.extend(value);
+    }
+    packet.push(255);
+    packet
+}
+
+/// The value of option `code` in a server reply.
+fn option(reply: &[u8], code: u8) -> Option<&[u8]> {
+    let mut pos = 240;
+    loop {
+        match *reply.get(pos)? {
+            0 => pos += 1,
+            255 => return None,
+            c => {
+                let len = reply[pos + 1] as usize;
+                if c == code {
+                    return Some(&reply[pos + 2..pos + 2 + len]);
+                }
+                pos += 2 + len;
+            }
+        }
+    }
+}
+
+/// A reply's `yiaddr`.
+fn offered(reply: &[u8]) -> Ipv4Addr {
+    Ipv4Addr::new(reply[16], reply[17], reply[18], reply[19])
+}
+
+/// Bind a UDP server on loopback that answers each datagram with `handle`
+/// until it is sent an empty one, and a client connected to it.
+fn udp_server(
+    mut handle: impl FnMut(&[u8], &mut [u8]) -> Option<usize> + Send + 'static,
+) -> (UdpSocket, thread::JoinHandle<()>) {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.connect(server.local_addr().unwrap()).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    let thread = thread::spawn(move || {
+        let mut request = [0u8; 1500];
+        let mut response = [0u8; 1500];
+        loop {
+            let (n, from) = server.recv_from(&mut request).unwrap();
+            if n == 0 {
+                break;
+            }
+            if let Some(len) = handle(&request[..n], &mut response) {
+                server.send_to(&response[..len], from).unwrap();
+            }
+        }
+    });
+    (client, thread)
+}
+
+/// Send `request` and wait for the reply, `None` if there is none.
+fn exchange(client: &UdpSocket, request: &[u8]) -> Option<Vec<u8>> {
+    client.send(request).unwrap();
+    let mut reply = [0u8; 1500];
+    match client.recv(&mut reply) {
+        Ok(n) => Some(reply[..n].to_vec()),
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => None,
+        Err(e) => panic!("{e}"),
+    }
+}
+
+#[test]
+fn dhcp_handshake() {
+    let mut server = DhcpServer::new(PORTAL_ADDR, POOL_START, 2).unwrap();
+    let (client, thread) = udp_server(move |request, response| server.handle(request, response));
+    let portal = PORTAL_ADDR.octets();
+
+    let offer = exchange(&client, &dhcp_message(DISCOVER, 1, &[])).unwrap();
+    assert_eq!(offer.len(), 300);
+    assert_eq!(offer[..4], [2, 1, 6, 0]);
+    assert_eq!(offer[4..8], [0xDE, 0xAD, DISCOVER, 1]);
+    assert_eq!(offer[10], 0x80);
+    assert_eq!(offer[28..34], [2, 0, 0, 0, 0, 1]);
+    assert_eq!(offered(&offer), POOL_START);
+    assert_eq!(option(&offer, MESSAGE_TYPE), Some(&[OFFER][..]));
+    assert_eq!(option(&offer, SERVER_ID), Some(&portal[..]));
+    assert_eq!(option(&offer, SUBNET_MASK), Some(&[255, 255, 255, 0][..]));
+    assert_eq!(option(&offer, ROUTER), Some(&portal[..]));
+    assert_eq!(option(&offer, DNS_SERVER), Some(&portal[..]));
+    assert_eq!(option(&offer, LEASE_TIME), Some(&3600u32.to_be_bytes()[..]));
+
+    let request = dhcp_message(
+        REQUEST,
+        1,
+        &[(REQUESTED_IP, &POOL_START.octets()), (SERVER_ID, &portal)],
+    );
+    let ack = exchange(&client, &request).unwrap();
+    assert_eq!(option(&ack, MESSAGE_TYPE), Some(&[ACK][..]));
+    assert_eq!(offered(&ack), POOL_START);
+
+    // Renewing from its address, without the requested-address option
+    let mut renew = dhcp_message(REQUEST, 1, &[]);
+    renew[12..16].copy_from_slice(&POOL_START.octets());
+    let ack = exchange(&client, &renew).unwrap();
+    assert_eq!(option(&ack, MESSAGE_TYPE), Some(&[ACK][..]));
+
+    // Another phone asking for the first one's address, or one outside
+    // the pool, is turned down
+    let offer = exchange(&client, &dhcp_message(DISCOVER, 2, &[])).unwrap();
+    assert_eq!(offered(&offer), Ipv4Addr::new(192, 168, 4, 3));
+    for wanted in [
+        POOL_START,
+        Ipv4Addr::new(192, 168, 4, 4),
+        Ipv4Addr::new(10, 0, 0, 3),
+    ] {
+        let request = dhcp_message(REQUEST, 2, &[(REQUESTED_IP, &wanted.octets())]);
+        let nak = exchange(&client, &request).unwrap();
+        assert_eq!(option(&nak, MESSAGE_TYPE), Some(&[NAK][..]), "{wanted}");
+        assert_eq!(offered(&nak), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(option(&nak, LEASE_TIME), None);
+        assert_eq!(option(&nak, ROUTER), None);
+    }
+
+    // It took another server's offer: no reply, and its address is free
+    let request = dhcp_message(
+        REQUEST,
+        2,
+        &[(REQUESTED_IP, &[10, 0, 0, 3]), (SERVER_ID, &[10, 0, 0, 1])],
+    );
+    assert_eq!(exchange(&client, &request), None);
+    let offer = exchange(&client, &dhcp_message(DISCOVER, 3, &[])).unwrap();
+    assert_eq!(offered(&offer), Ipv4Addr::new(192, 168, 4, 3));
+
+    // A full pool gives away the least recently seen lease
+    let offer = exchange(&client, &dhcp_message(DISCOVER, 4, &[])).unwrap();
+    assert_eq!(offered(&offer), POOL_START);
+
+    // Released addresses are handed out again, to whoever asks first
+    assert_eq!(exchange(&client, &dhcp_message(RELEASE, 4, &[])), None);
+    let request = [(REQUESTED_IP, &POOL_START.octets()[..])];
+    let offer = exchange(&client, &dhcp_message(DISCOVER, 5, &request)).unwrap();
+    assert_eq!(offered(&offer), POOL_START);
+
+    // INFORM from a phone that already has an address
+    let mut inform = dhcp_message(INFORM, 6, &[]);
+    inform[12..16].copy_from_slice(&[192, 168, 4, 9]);
+    let ack = exchange(&client, &inform).unwrap();
+    assert_eq!(option(&ack, MESSAGE_TYPE), Some(&[ACK][..]));
+    assert_eq!(ack[12..16], [192, 168, 4, 9]);
+    assert_eq!(offered(&ack), Ipv4Addr::UNSPECIFIED);
+    assert_eq!(option(&ack, LEASE_TIME), None);
+
+    // Not a BOOTP request at all
+    assert_eq!(exchange(&client, &[0u8; 100]), None);
+    let mut reply = dhcp_message(DISCOVER, 7, &[]);
+    reply[0] = 2;
+    assert_eq!(exchange(&client, &reply), None);
+
+    client.send(&[]).unwrap();
+    thread.join().unwrap();
+}
+
+#[test]
+fn dhcp_pool_stops_before_broadcast() {
+    let mut server = DhcpServer::new(PORTAL_ADDR, Ipv4Addr::new(192, 168, 4, 250), 20).unwrap();
+    let mut reply = [0u8; 576];
+    let mut addresses = Vec::new();
+    for client in 1..=6 {
+        server.handle(&dhcp_message(DISCOVER, client, &[]), &mut reply);
+        addresses.push(offered(&reply).octets()[3]);
+    }
+    assert_eq!(addresses, [250, 251, 252, 253, 254, 250]);
+    // Too small a reply buffer
+    assert_eq!(
+        server.handle(&dhcp_message(DISCOVER, 1, &[]), &mut reply[..299]),
+        None
+    );
+
+    // One address left before the broadcast address, and none
+    let mut server = DhcpServer::new(PORTAL_ADDR, Ipv4Addr::new(192, 168, 4, 254), 8).unwrap();
+    for client in 1..=3 {
+        server.handle(&dhcp_message(DISCOVER, client, &[]), &mut reply);
+        assert_eq!(offered(&reply), Ipv4Addr::new(192, 168, 4, 254));
+    }
+    assert!(DhcpServer::new(PORTAL_ADDR, Ipv4Addr::new(192, 168, 4, 255), 8).is_none());
+    assert!(DhcpServer::new(PORTAL_ADDR, Ipv4Addr::new(192, 168, 4, 255), 0).is_none());
+}
+
+/// A DNS query for `name` with record type `qtype`.
+fn dns_query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+    let mut query = id.to_be_bytes().to_vec();
+    // RD set, one question
+    query.extend([0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        query.push(label.len() as u8);
+        query.extend(label.as_bytes());
+    }
+    query.push(0);
+    query.extend(qtype.to_be_bytes());
+    query.extend(1u16.to_be_bytes());
+    query
+}
+
+#[test]
+fn dns_lookups() {
+    let (client, thread) = udp_server(|query, response| dns::answer(query, PORTAL_ADDR, response));
+
+    // A and ANY get the portal's address
+    for (id, qtype) in [(0x1234, 1), (0x4321, 255)] {
+        let query = dns_query(id, "connectivitycheck.gstatic.com", qtype);
+        let response = exchange(&client, &query).unwrap();
+        assert_eq!(response.len(), query.len() + 16);
+        assert_eq!(response[..2], id.to_be_bytes());
+        // QR, AA and RD set, NOERROR
+        assert_eq!(response[2..4], [0x85, 0x00]);
+        assert_eq!(response[4..12], [0, 1, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(response[12..query.len()], query[12..]);
+        let answer = &response[query.len()..];
+        // Name pointer to the question, type A, class IN, TTL, address
+        assert_eq!(answer[..4], [0xC0, 12, 0, 1]);
+        assert_eq!(answer[4..6], [0, 1]);
+        assert_eq!(answer[6..10], 60u32.to_be_bytes());
+        assert_eq!(answer[10..12], [0, 4]);
+        assert_eq!(answer[12..], PORTAL_ADDR.octets());
+    }
+
+    // AAAA gets an empty answer, so the phone falls back to IPv4
+    let query = dns_query(7, "apple.com", 28);
+    let response = exchange(&client, &query).unwrap();
+    assert_eq!(response.len(), query.len());
+    assert_eq!(response[2..12], [0x85, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+
+    // Responses, other opcodes and names that don't parse go unanswered
+    let mut response = dns_query(8, "a.b", 1);
+    response[2] |= 0x80;
+    assert_eq!(exchange(&client, &response), None);
+    let mut status = dns_query(9, "a.b", 1);
+    status[2] |= 2 << 3;
+    assert_eq!(exchange(&client, &status), None);
+    let mut compressed = dns_query(10, "a.b", 1);
+    compressed[12] = 0xC0;
+    assert_eq!(exchange(&client, &compressed), None);
+    let query = dns_query(11, "a.b", 1);
+    assert_eq!(exchange(&client, &query[..query.len() - 1]), None);
+
+    client.send(&[]).unwrap();
+    thread.join().unwrap();
+
+    // No room for the answer
+    let query = dns_query(12, "a.b", 1);
+    let mut response = [0u8; 64];
+    assert_eq!(
+        dns::answer(&query, PORTAL_ADDR, &mut response[..query.len() + 15]),
+        None
+    );
+}
+
+/// Reads `pieces` one per call, or as much of one as fits, the way a
+/// socket may hand them over.
+struct Pieces<'a>(Vec<&'a [u8]>);
+
+impl embedded_io_async::ErrorType for Pieces<'_> {
+    type Error = Infallible;
+}
+
+impl embedded_io_async::Read for Pieces<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+        let Some(piece) = self.0.first_mut() else {
+            return Ok(0);
+        };
+        let n = piece.len().min(buf.len());
+        buf[..n].copy_from_slice(&piece[..n]);
+        *piece = &piece[n..];
+        if piece.is_empty() {
+            self.0.remove(0);
+        }
+        Ok(n)
+    }
+}
+
+/// Method, path and body of the request `pieces` make up.
+fn read(
+    pieces: &[&[u8]],
+    buf: &mut [u8],
+) -> Result<(String, String, Vec<u8>), WebError<Infallible>> {
+    let request = block_on(web::read_request(&mut Pieces(pieces.to_vec()), buf))?;
+    Ok((
+        request.method.into(),
+        request.path.into(),
+        request.body.into(),
+    ))
+}
+
+const FORM: &[u8] = b"ssid=My+Net%C3%A4&password=hunter222&stream=http%3A%2F%2Fcam.local%2Fstream";
+
+fn post(body: &[u8]) -> Vec<u8> {
+    let mut request = format!(
+        "POST /save HTTP/1.1\r\nHost: 192.168.4.1\r\n\
+         Content-Type: application/x-www-form-urlencoded\r\ncontent-length: {}\r\n\r\n",
+        body.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(body);
+    request
+}
+
+#[test]
+fn post_split_across_reads() {
+    let request = post(FORM);
+    let expected = ("POST".to_string(), "/save".to_string(), FORM.to_vec());
+    // Cut in two at every byte, the head's blank line and the body included
+    for cut in 1..request.len() {
+        let mut buf = [0u8; 512];
+        let pieces = [&request[..cut], &request[cut..]];
+        assert_eq!(read(&pieces, &mut buf).unwrap(), expected, "cut at {cut}");
+    }
+    let pieces: Vec<_> = request.chunks(1).collect();
+    let mut buf = [0u8; 512];
+    assert_eq!(read(&pieces, &mut buf).unwrap(), expected);
+    // Exactly as large as the buffer
+    let mut buf = vec![0u8; request.len()];
+    assert_eq!(read(&pieces, &mut buf).unwrap(), expected);
+}
+
+#[test]
+fn bad_requests() {
+    let mut buf = [0u8; 64];
+    let too_large =
+        b"GET / HTTP/1.1\r\nUser-Agent: a user agent string that goes on and on and on\r\n\r\n";
+    assert!(matches!(
+        read(&[too_large], &mut buf),
+        Err(WebError::TooLarge)
+    ));
+    // A body that doesn't fit behind a head that does
+    let head = b"POST /save HTTP/1.1\r\nContent-Length: 60\r\n\r\n";
+    assert!(matches!(
+        read(&[head, &[b'x'; 60]], &mut buf),
+        Err(WebError::TooLarge)
+    ));
+    let huge = b"POST / HTTP/1.1\r\nContent-Length: 18446744073709551615\r\n\r\n";
+    assert!(matches!(read(&[huge], &mut buf), Err(WebError::TooLarge)));
+
+    let body_cut = b"POST /save HTTP/1.1\r\nContent-Length: 10\r\n\r\nssid=";
+    assert!(matches!(read(&[body_cut], &mut buf), Err(WebError::Closed)));
+    assert!(matches!(read(&[b"GET /"], &mut buf), Err(WebError::Closed)));
+
+    for bad in [
+        &b"GET /\r\n\r\n"[..],
+        b"GET / SPDY/3\r\n\r\n",
+        b"POST / HTTP/1.1\r\nContent-Length: ten\r\n\r\n",
+        b"GET /\xFF HTTP/1.1\r\n\r\n",
+    ] {
+        assert!(
+            matches!(read(&[bad], &mut buf), Err(WebError::BadRequest)),
+            "{}",
+            String::from_utf8_lossy(bad)
+        );
+    }
+}
+
+#[test]
+fn routes() {
+    let current = Config::default();
+    let route = |method: &str, path: &str, body: &[u8]| {
+        web::route(&Request { method, path, body }, &current)
+    };
+    assert_eq!(route("GET", "/", b""), Route::Page);
+    assert_eq!(route("GET", "/scan", b""), Route::Scan);
+    assert_eq!(route("GET", "/generate_204", b""), Route::Redirect);
+    assert_eq!(route("GET", "/save", b""), Route::Redirect);
+    assert_eq!(route("HEAD", "/", b""), Route::Redirect);
+
+    let Route::Save(config) = route("POST", "/save", FORM) else {
+        panic!("not saved");
+    };
+    let network = config.preferred_network().unwrap();
+    assert_eq!(
+        (network.ssid.as_str(), network.password.as_str()),
+        ("My Netä", "hunter222")
+    );
+    assert_eq!(config.networks.len(), current.networks.len() + 1);
+    assert_eq!(config.server.as_deref(), Some("cam.local:80"));
+    assert_eq!(
+        config.transport,
+        Transport::Http {
+            path: "/stream".into()
+        }
+    );
+
+    // An open network and mDNS discovery; fields in any order
+    let Route::Save(config) = route("POST", "/save", b"stream=&ssid=cafe%20wifi&password=") else {
+        panic!("not saved");
+    };
+    assert_eq!(config.preferred_network().unwrap().ssid, "cafe wifi");
+    assert_eq!((config.server, config.transport), (None, Transport::Tcp));
+
+    for (body, message) in [
+        (
+            &b"ssid=x&password=short"[..],
+            "The password must be 8 to 63 characters long.",
+        ),
+        (
+            b"password=hunter222",
+            "The network name must be 1 to 32 bytes long.",
+        ),
+        (b"ssid=x&stream=ftp://x", "The stream URL is not valid."),
+    ] {
+        assert_eq!(route("POST", "/save", body), Route::Invalid(message));
+    }
+}
+
+/// Serve one connection on loopback with `serve`, sending `request` to it a
+/// few bytes at a time. Returns the response.
+fn web_exchange(request: &[u8], serve: impl FnOnce(&mut Blocking) + Send + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (socket, _) = listener.accept().unwrap();
+        serve(&mut Blocking(socket));
+    });
+    let mut client = TcpStream::connect(addr).unwrap();
+    client.set_nodelay(true).unwrap();
+    for piece in request.chunks(16) {
+        client.write_all(piece).unwrap();
+        thread::sleep(Duration::from_millis(2));
+    }
+    client.shutdown(Shutdown::Write).unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    server.join().unwrap();
+    response
+}
+
+#[test]
+fn form_over_loopback() {
+    let response = web_exchange(&post(FORM), |socket| {
+        let mut buf = [0u8; 1024];
+        let request = block_on(web::read_request(socket, &mut buf)).unwrap();
+        let Route::Save(config) = web::route(&request, &Config::default()) else {
+            panic!("not saved");
+        };
+        let ssid = &config.preferred_network().unwrap().ssid;
+        block_on(web::write_saved(socket, ssid)).unwrap();
+    });
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.contains("joins <b>My Netä</b>"));
+
+    let response = web_exchange(
+        b"GET /hotspot-detect.html HTTP/1.1\r\nHost: captive.apple.com\r\n\r\n",
+        |socket| {
+            let mut buf = [0u8; 1024];
+            let request = block_on(web::read_request(socket, &mut buf)).unwrap();
+            assert_eq!(web::route(&request, &Config::default()), Route::Redirect);
+            block_on(web::write_redirect(socket, PORTAL_ADDR)).unwrap();
+        },
+    );
+    assert!(
+        response.starts_with("HTTP/1.1 302 Found\r\nLocation: http://192.168.4.1/\r\n"),
+        "{response}"
+    );
+
+    let response = web_exchange(b"GET /?refresh=1 HTTP/1.1\r\n\r\n", |socket| {
+        let mut buf = [0u8; 1024];
+        let request = block_on(web::read_request(socket, &mut buf)).unwrap();
+        assert_eq!(web::route(&request, &Config::default()), Route::Page);
+        let network = |ssid: &str, rssi, secure| Network {
+            ssid: ssid.into(),
+            rssi,
+            secure,
+        };
+        let networks = [
+            network("a<b", -70, true),
+            network("a<b", -40, true),
+            network("open", -50, false),
+            network("", -10, false),
+        ];
+        block_on(web::write_page(
+            socket,
+            &Config::default(),
+            &networks,
+            Some("hi & bye"),
+        ))
+        .unwrap();
+    });
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let len = head
+        .lines()
+        .find_map(|line| line.strip_prefix("Content-Length: "))
+        .unwrap();
+    assert_eq!(body.len(), len.parse::<usize>().unwrap());
+    assert!(body.contains("<p class=notice>hi &amp; bye</p>"));
+    // Strongest first, each name once, hidden networks left out
+    assert!(
+        body.contains(
+            "<datalist id=networks><option value=\"a&lt;b\">-40 dBm</option>\
+             <option value=\"open\">-50 dBm, open</option></datalist>"
+        ),
+        "{body}"
+    );
+}
@@ -0,0 +1,317 @@
+//! RTP/JPEG reassembly from scans packetized the way RFC 2435 senders do,
+//! delivered out of order, with losses and with in-band tables.
+
+use jpeg_encoder::{ColorType, Encoder, SamplingFactor};
+use rumble_rs::jpeg::JpegBackend;
+use rumble_rs::jpeg::baseline::BaselineDecoder;
+use rumble_rs::jpeg::markers::{self, DQT, EOI, SOS};
+use rumble_rs::mjpeg::{DropReason, FrameSource, Status};
+use rumble_rs::rtp::{Depacketizer, make_tables};
+
+const WIDTH: u16 = 80;
+const HEIGHT: u16 = 48;
+const MTU: usize = 97;
+
+/// A test pattern encoded at `quality`, different for every `seed`.
+fn encode(quality: u8, sampling: SamplingFactor, restart_interval: u16, seed: u32) -> Vec<u8> {
+    let mut image = Vec::new();
+    for y in 0..HEIGHT as u32 {
+        for x in 0..WIDTH as u32 {
+            image.extend([
+                (x * 3 + seed) as u8,
+                ((y * 5) ^ x) as u8,
+                (x * y + seed * 7) as u8,
+            ]);
+        }
+    }
+    let mut out = Vec::new();
+    let mut encoder = Encoder::new(&mut out, quality);
+    encoder.set_sampling_factor(sampling);
+    encoder.set_restart_interval(restart_interval);
+    encoder
+        .encode(&image, WIDTH, HEIGHT, ColorType::Rgb)
+        .unwrap();
+    out
+}
+
+/// The quantization tables, Pq/Tq bytes left out, and the entropy-coded scan
+/// of a baseline JPEG.
+fn split(jpeg: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut tables = Vec::new();
+    let mut i = 2;
+    loop {
+        let marker = jpeg[i + 1];
+        let len = u16::from_be_bytes([jpeg[i + 2], jpeg[i + 3]]) as usize;
+        if marker == DQT {
+            for table in jpeg[i + 4..i + 2 + len].chunks(65) {
+                tables.extend_from_slice(&table[1..]);
+            }
+        }
+        i += 2 + len;
+        if marker == SOS {
+            break;
+        }
+    }
+    assert_eq!(jpeg[jpeg.len() - 2..], [0xFF, EOI]);
+    (tables, jpeg[i..jpeg.len() - 2].to_vec())
+}
+
+/// How [`packetize`] fills in the JPEG and quantization table headers.
+#[derive(Clone, Copy)]
+struct Fragmenting {
+    kind: u8,
+    q: u8,
+    restart_interval: u16,
+    /// Send an empty table header, as a sender does when the tables didn't
+    /// change.
+    tables_cached: bool,
+}
+
+/// The RTP packets `jpeg`'s scan goes out in, `MTU` bytes of it at a time.
+fn packetize(jpeg: &[u8], timestamp: u32, how: Fragmenting) -> Vec<Vec<u8>> {
+    let (tables, scan) = split(jpeg);
+    let mut packets = Vec::new();
+    for (seq, offset) in (0..scan.len()).step_by(MTU).enumerate() {
+        let data = &scan[offset..scan.len().min(offset + MTU)];
+        let last = offset + data.len() == scan.len();
+        let mut packet = vec![0x80, if last { 0x80 | 26 } else { 26 }];
+        packet.extend((seq as u16).to_be_bytes());
+        packet.extend(timestamp.to_be_bytes());
+        packet.extend(0x1234_5678u32.to_be_bytes());
+        packet.push(0);
+        packet.extend(&(offset as u32).to_be_bytes()[1..]);
+        packet.extend([how.kind, how.q, (WIDTH / 8) as u8, (HEIGHT / 8) as u8]);
+        if how.kind >= 64 {
+            // Not fragmented on restart boundaries: F = L = 1, count 0x3FFF
+            packet.extend(how.restart_interval.to_be_bytes());
+            packet.extend([0xFF, 0xFF]);
+        }
+        if how.q >= 128 && offset == 0 {
+            let tables: &[u8] = if how.tables_cached { &[] } else { &tables };
+            packet.extend([0, 0]);
+            packet.extend((tables.len() as u16).to_be_bytes());
+            packet.extend(tables);
+        }
+        packet.extend(data);
+        packets.push(packet);
+    }
+    packets
+}
+
+/// Push every packet, collecting frames and drops.
+fn feed(depacketizer: &mut Depacketizer, packets: &[Vec<u8>]) -> (Vec<Vec<u8>>, Vec<DropReason>) {
+    let mut frames = Vec::new();
+    let mut drops = Vec::new();
+    for packet in packets {
+        let mut input = &packet[..];
+        while !input.is_empty() {
+            match depacketizer.push(input) {
+                Status::Incomplete => break,
+                Status::Frame { consumed } => {
+                    frames.push(depacketizer.frame_mut().unwrap().to_vec());
+                    input = &input[consumed..];
+                }
+                Status::Dropped { consumed, reason } => {
+                    drops.push(reason);
+                    input = &input[consumed..];
+                }
+            }
+        }
+    }
+    (frames, drops)
+}
+
+/// Pixels from the reference decoder.
+fn reference(jpeg: &[u8]) -> Vec<u8> {
+    jpeg_decoder::Decoder::new(jpeg).decode().unwrap()
+}
+
+/// Pixels from the library's own decoder.
+fn baseline(jpeg: &[u8]) -> Vec<u8> {
+    let mut decoder = BaselineDecoder::new().unwrap();
+    let mut out = Vec::new();
+    decoder
+        .decode(&mut jpeg.to_vec(), |_, _, _, data| {
+            out.extend_from_slice(data)
+        })
+        .unwrap();
+    out
+}
+
+/// Check that `frames` decode to the same pixels as the `sent` frames.
+fn assert_same_pixels(frames: &[Vec<u8>], sent: &[Vec<u8>]) {
+    assert_eq!(frames.len(), sent.len());
+    for (frame, sent) in frames.iter().zip(sent) {
+        markers::parse(frame).unwrap();
+        assert_eq!(reference(frame), reference(sent));
+        assert_eq!(baseline(frame), baseline(sent));
+    }
+}
+
+#[test]
+fn tables_match_ijg() {
+    for quality in [10, 50, 75, 90] {
+        let (tables, _) = split(&encode(quality, SamplingFactor::F_2_2, 0, 0));
+        let (luma, chroma) = make_tables(quality);
+        assert_eq!(tables[..64], luma, "quality {quality}");
+        assert_eq!(tables[64..], chroma, "quality {quality}");
+    }
+}
+
+#[test]
+fn fragments_out_of_order() {
+    for (sampling, kind) in [(SamplingFactor::F_2_1, 0), (SamplingFactor::F_2_2, 1)] {
+        for q in [75, 255] {
+            let how = Fragmenting {
+                kind,
+                q,
+                restart_interval: 0,
+                tables_cached: false,
+            };
+            let mut sent = Vec::new();
+            let mut packets = Vec::new();
+            for f in 0..4 {
+                let jpeg = encode(75, sampling, 0, f);
+                let mut fragments = packetize(&jpeg, 1000 + f * 3000, how);
+                match f {
+                    1 => fragments.reverse(),
+                    2 => {
+                        // The table header last, and one fragment twice
+                        let n = fragments.len();
+                        fragments.swap(0, n - 1);
+                        fragments.push(fragments[3].clone());
+                    }
+                    3 => fragments.swap(1, 4),
+                    _ => {}
+                }
+                sent.push(jpeg);
+                packets.extend(fragments);
+            }
+            let mut depacketizer = Depacketizer::new(20_000);
+            let (frames, drops) = feed(&mut depacketizer, &packets);
+            assert_eq!(drops, []);
+            assert_same_pixels(&frames, &sent);
+        }
+    }
+}
+
+#[test]
+fn dropped_middle_fragment_is_interrupted() {
+    let how = Fragmenting {
+        kind: 1,
+        q: 75,
+        restart_interval: 0,
+        tables_cached: false,
+    };
+    let first = encode(75, SamplingFactor::F_2_2, 0, 0);
+    let second = encode(75, SamplingFactor::F_2_2, 0, 1);
+    let a = packetize(&first, 10, how);
+    let b = packetize(&second, 20, how);
+    // a[3] is lost, then turns up late in the middle of the next frame
+    let mut packets = [&a[..3], &a[4..], &b[..2]].concat();
+    packets.push(a[3].clone());
+    packets.extend_from_slice(&b[2..]);
+
+    let mut depacketizer = Depacketizer::new(20_000);
+    let (frames, drops) = feed(&mut depacketizer, &packets);
+    assert_eq!(drops, [DropReason::Interrupted]);
+    assert_same_pixels(&frames, &[second]);
+}
+
+#[test]
+fn in_band_tables_cached_between_frames() {
+    let inline = Fragmenting {
+        kind: 1,
+        q: 200,
+        restart_interval: 0,
+        tables_cached: false,
+    };
+    let cached = Fragmenting {
+        tables_cached: true,
+        ..inline
+    };
+    // Tables the Q 1..=99 formula can't make
+    let sent: Vec<_> = (0..3)
+        .map(|f| encode(97, SamplingFactor::F_2_2, 0, f))
+        .collect();
+    let packets = [
+        packetize(&sent[0], 10, inline),
+        packetize(&sent[1], 20, cached),
+        packetize(&sent[2], 30, cached),
+    ]
+    .concat();
+    let mut depacketizer = Depacketizer::new(20_000);
+    let (frames, drops) = feed(&mut depacketizer, &packets);
+    assert_eq!(drops, []);
+    assert_same_pixels(&frames, &sent);
+
+    // Nothing cached yet, or cached for another Q
+    let mut depacketizer = Depacketizer::new(20_000);
+    let (frames, drops) = feed(&mut depacketizer, &packetize(&sent[1], 20, cached));
+    assert_eq!((frames.len(), drops), (0, vec![DropReason::Corrupt]));
+    let other_q = Fragmenting { q: 201, ..cached };
+    let packets = [
+        packetize(&sent[0], 10, inline),
+        packetize(&sent[1], 20, other_q),
+    ]
+    .concat();
+    let (frames, drops) = feed(&mut Depacketizer::new(20_000), &packets);
+    assert_eq!(frames.len(), 1);
+    assert_eq!(drops, [DropReason::Corrupt]);
+}
+
+#[test]
+fn restart_header_adds_dri() {
+    // Two MCU rows of 4:2:0 between restart markers
+    let restart_interval = 2 * WIDTH / 16;
+    for (sampling, kind) in [(SamplingFactor::F_2_1, 64), (SamplingFactor::F_2_2, 65)] {
+        let how = Fragmenting {
+            kind,
+            q: 128,
+            restart_interval,
+            tables_cached: false,
+        };
+        let sent: Vec<_> = (0..2)
+            .map(|f| encode(80, sampling, restart_interval, f))
+            .collect();
+        let mut packets = packetize(&sent[0], 10, how);
+        packets.reverse();
+        packets.extend(packetize(&sent[1], 20, how));
+        let mut depacketizer = Depacketizer::new(20_000);
+        let (frames, drops) = feed(&mut depacketizer, &packets);
+        assert_eq!(drops, []);
+        for frame in &frames {
+            assert_eq!(
+                markers::parse(frame).unwrap().restart_interval,
+                restart_interval
+            );
+        }
+        assert_same_pixels(&frames, &sent);
+    }
+}
+
+#[test]
+fn too_large_or_unknown_type() {
+    let how = Fragmenting {
+        kind: 1,
+        q: 75,
+        restart_interval: 0,
+        tables_cached: false,
+    };
+    let packets = packetize(&encode(75, SamplingFactor::F_2_2, 0, 0), 10, how);
+    let (frames, drops) = feed(&mut Depacketizer::new(500), &packets);
+    assert_eq!(frames.len(), 0);
+    assert!(
+        matches!(drops[..], [DropReason::TooLarge { size }] if size > 500),
+        "{drops:?}"
+    );
+
+    let packets = packetize(
+        &encode(75, SamplingFactor::F_2_2, 0, 0),
+        10,
+        Fragmenting { kind: 5, ..how },
+    );
+    let (frames, drops) = feed(&mut Depacketizer::new(20_000), &packets);
+    assert_eq!(frames.len(), 0);
+    assert_eq!(drops, [DropReason::Corrupt]);
+}
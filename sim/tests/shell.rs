@@ -0,0 +1,420 @@
+//! The console shell: line editing, argument parsing and every command run
+//! against a fake firmware.
+
+use std::net::Ipv4Addr;
+
+use rumble_rs::config::{Config, Transport, WifiConfig};
+use rumble_rs::fit::FitMode;
+use rumble_rs::jpeg::JpegDecoderConfig;
+use rumble_rs::jpeg::heap::HeapStats;
+use rumble_rs::shell::{Command, Edit, LineBuffer, ParseError, Stats, Target, execute};
+use rumble_rs::wifi::Candidate;
+
+fn wifi(ssid: &str, password: &str) -> WifiConfig {
+    WifiConfig::new(ssid.into(), password.into()).unwrap()
+}
+
+#[test]
+fn line_editing() {
+    let mut line = LineBuffer::new();
+    let edits: Vec<_> = "wifä\x7f\x7fi x\r\nhelp\n\x1b\x03"
+        .bytes()
+        .map(|b| line.push(b))
+        .collect();
+    let lines: Vec<_> = edits
+        .iter()
+        .filter_map(|edit| match edit {
+            Edit::Line(line) => Some(line.as_str()),
+            _ => None,
+        })
+        .collect();
+    // Backspace takes the two bytes of ä at once; CR LF is one line
+    assert_eq!(lines, ["wii x", "help", ""]);
+    assert_eq!(edits.iter().filter(|&e| *e == Edit::Erase).count(), 2);
+    assert_eq!(
+        edits[..3],
+        [Edit::Echo(b'w'), Edit::Echo(b'i'), Edit::Echo(b'f')]
+    );
+    assert_eq!(line.push(0x7F), Edit::None);
+    assert_eq!(line.push(0x1B), Edit::None);
+
+    // Input past the longest line is dropped
+    for _ in 0..256 {
+        assert_eq!(line.push(b'x'), Edit::Echo(b'x'));
+    }
+    assert_eq!(line.push(b'y'), Edit::None);
+    assert_eq!(line.push(b'\r'), Edit::Line("x".repeat(256)));
+}
+
+#[test]
+fn quoting_and_escapes() {
+    let set = |ssid, password| Ok(Command::WifiSet(wifi(ssid, password)));
+    assert_eq!(
+        Command::parse("wifi set \"My Net\" hunter222"),
+        set("My Net", "hunter222")
+    );
+    assert_eq!(Command::parse("  wifi   set My\\ Net  "), set("My Net", ""));
+    assert_eq!(Command::parse("wifi set My\" \"Net"), set("My Net", ""));
+    assert_eq!(Command::parse("wifi set x \"\""), set("x", ""));
+    assert_eq!(
+        Command::parse("wifi set \"say \\\"hi\\\"\""),
+        set("say \"hi\"", "")
+    );
+    assert_eq!(Command::parse("wifi set a\\\\b"), set("a\\b", ""));
+    // A trailing backslash stands for itself
+    assert_eq!(Command::parse("wifi set ab\\"), set("ab\\", ""));
+    assert_eq!(
+        Command::parse("wifi set \"x"),
+        Err(ParseError::UnterminatedQuote)
+    );
+    assert_eq!(
+        Command::parse("stream set \"tcp://h:1"),
+        Err(ParseError::UnterminatedQuote)
+    );
+    assert_eq!(
+        Command::parse("wifi add \"two words\" \"pass word\" 7"),
+        Ok(Command::WifiAdd(
+            wifi("two words", "pass word").with_priority(7)
+        ))
+    );
+}
+
+#[test]
+fn commands_and_usage() {
+    for (line, command) in [
+        ("help", Command::Help),
+        ("?", Command::Help),
+        ("wifi", Command::WifiShow),
+        ("wifi show", Command::WifiShow),
+        ("wifi add x", Command::WifiAdd(wifi("x", ""))),
+        (
+            "wifi add x password1",
+            Command::WifiAdd(wifi("x", "password1")),
+        ),
+        ("wifi remove x", Command::WifiRemove("x".into())),
+        ("stream", Command::StreamShow),
+        ("stream show", Command::StreamShow),
+        ("stream set", Command::StreamSet(String::new())),
+        (
+            "stream set tcp://h:1",
+            Command::StreamSet("tcp://h:1".into()),
+        ),
+        ("display fit center", Command::DisplayFit(FitMode::Center)),
+        ("stats", Command::Stats),
+        ("decoder", Command::DecoderInfo),
+        ("decoder info", Command::DecoderInfo),
+        ("config", Command::ConfigDump),
+        ("config dump", Command::ConfigDump),
+        ("config reset", Command::ConfigReset),
+        ("reboot", Command::Reboot),
+    ] {
+        assert_eq!(Command::parse(line), Ok(command), "{line}");
+    }
+
+    assert_eq!(Command::parse(""), Err(ParseError::Empty));
+    assert_eq!(Command::parse(" \t "), Err(ParseError::Empty));
+    for (line, usage) in [
+        ("wifi frob", "wifi show | wifi set"),
+        ("wifi set", "wifi show | wifi set"),
+        ("wifi remove", "wifi show | wifi set"),
+        ("wifi add a b 1 extra", "wifi show | wifi set"),
+        ("stream set a b", "stream show | stream set [url]"),
+        ("display", "display fit <mode>"),
+        ("display fit", "display fit <mode>"),
+        ("decoder reset", "decoder info"),
+        ("config load", "config dump | config reset"),
+    ] {
+        match Command::parse(line) {
+            Err(ParseError::Usage(u)) => assert!(u.starts_with(usage), "{line}: {u}"),
+            other => panic!("{line}: {other:?}"),
+        }
+    }
+    assert_eq!(
+        Command::parse("stream set a b").unwrap_err().to_string(),
+        "usage: stream show | stream set [url]"
+    );
+
+    for (line, reason) in [
+        (
+            "wifi set x short",
+            "The password must be 8 to 63 characters long.",
+        ),
+        (
+            "wifi set \"\"",
+            "The network name must be 1 to 32 bytes long.",
+        ),
+        ("wifi add x \"\" 256", "The priority must be 0 to 255."),
+        ("wifi add x \"\" -1", "The priority must be 0 to 255."),
+        (
+            "display fit zoom",
+            "The mode must be fit, fill, center or stretch.",
+        ),
+    ] {
+        assert_eq!(
+            Command::parse(line),
+            Err(ParseError::Invalid(reason)),
+            "{line}"
+        );
+    }
+
+    assert_eq!(
+        Command::parse("frob now"),
+        Err(ParseError::UnknownCommand("frob".into()))
+    );
+    assert_eq!(
+        Command::parse("frob").unwrap_err().to_string(),
+        "unknown command 'frob', try 'help'"
+    );
+}
+
+/// The firmware as far as the shell sees it, logging what is done to it.
+struct Fake {
+    config: Config,
+    fail_saves: bool,
+    calls: Vec<String>,
+    stats: Stats,
+    decoder: JpegDecoderConfig,
+    codec_heap: HeapStats,
+}
+
+impl Fake {
+    fn new() -> Self {
+        Self {
+            config: Config {
+                networks: vec![wifi("home", "password0")],
+                server: Some("10.0.0.2:3000".into()),
+                transport: Transport::Tcp,
+                ..Config::default()
+            },
+            fail_saves: false,
+            calls: Vec::new(),
+            stats: Stats::new(),
+            decoder: JpegDecoderConfig::default(),
+            codec_heap: HeapStats::default(),
+        }
+    }
+
+    /// Run `line` and return its output.
+    fn run(&mut self, line: &str) -> String {
+        let mut out = String::new();
+        execute(Command::parse(line).unwrap(), self, &mut out).unwrap();
+        out
+    }
+
+    fn calls(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.calls)
+    }
+}
+
+impl Target for Fake {
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn save(&mut self, config: Config) -> Result<(), String> {
+        self.calls.push("save".into());
+        if self.fail_saves {
+            return Err("flash error".into());
+        }
+        self.config = config;
+        Ok(())
+    }
+
+    fn reset_config(&mut self) -> Result<(), String> {
+        self.calls.push("reset_config".into());
+        if self.fail_saves {
+            return Err("flash error".into());
+        }
+        Ok(())
+    }
+
+    fn networks_changed(&mut self, reconnect: bool) {
+        self.calls.push(format!("networks_changed({reconnect})"));
+    }
+
+    fn network(&self) -> Option<Candidate> {
+        Some(Candidate {
+            network: self.config.networks.first()?.clone(),
+            rssi: Some(-52),
+        })
+    }
+
+    fn address(&self) -> Option<Ipv4Addr> {
+        Some(Ipv4Addr::new(10, 0, 0, 5))
+    }
+
+    fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    fn decoder_config(&self) -> JpegDecoderConfig {
+        self.decoder
+    }
+
+    fn heap(&self) -> (usize, usize) {
+        (1000, 2000)
+    }
+
+    fn codec_heap(&self) -> HeapStats {
+        self.codec_heap
+    }
+
+    fn uptime_ms(&self) -> u64 {
+        10_000
+    }
+
+    fn reboot(&mut self) {
+        self.calls.push("reboot".into());
+    }
+}
+
+#[test]
+fn wifi_commands() {
+    let mut fake = Fake::new();
+    assert_eq!(
+        fake.run("wifi"),
+        "ssid:    home (-52 dBm)\naddress: 10.0.0.5\nsaved:   home (priority 0)\n"
+    );
+    assert_eq!(fake.calls(), [""; 0]);
+
+    assert_eq!(fake.run("wifi set cafe password1"), "saved\njoining cafe\n");
+    assert_eq!(fake.calls(), ["save", "networks_changed(true)"]);
+    let preferred = fake.config.preferred_network().unwrap();
+    assert_eq!((preferred.ssid.as_str(), preferred.priority), ("cafe", 1));
+
+    assert_eq!(fake.run("wifi add phone \"\" 9"), "saved\n");
+    assert_eq!(fake.calls(), ["save", "networks_changed(false)"]);
+    assert!(fake.run("wifi").ends_with(
+        "saved:   phone (priority 9)\n         cafe (priority 1)\n         home (priority 0)\n"
+    ));
+
+    assert_eq!(fake.run("wifi remove zz"), "unknown network: zz\n");
+    assert_eq!(fake.calls(), [""; 0]);
+    assert_eq!(fake.run("wifi remove phone"), "saved\n");
+    assert_eq!(fake.calls(), ["save", "networks_changed(false)"]);
+    assert_eq!(fake.config.networks.len(), 2);
+
+    for i in 0..6 {
+        fake.run(&format!("wifi add n{i}"));
+    }
+    fake.calls();
+    assert_eq!(
+        fake.run("wifi add one-too-many"),
+        "Too many saved networks, remove one first.\n"
+    );
+    assert_eq!(
+        fake.run("wifi set one-too-many"),
+        "Too many saved networks, remove one first.\n"
+    );
+    assert_eq!(fake.calls(), [""; 0]);
+}
+
+#[test]
+fn failed_save_stops_the_command() {
+    let mut fake = Fake::new();
+    fake.fail_saves = true;
+    let before = fake.config.clone();
+    for line in [
+        "wifi set cafe password1",
+        "wifi add phone",
+        "wifi remove home",
+        "stream set rtp://@:5004",
+        "display fit stretch",
+    ] {
+        assert_eq!(fake.run(line), "save failed: flash error\n", "{line}");
+        assert_eq!(fake.calls(), ["save"], "{line}");
+    }
+    assert_eq!(fake.config, before);
+
+    assert_eq!(fake.run("config reset"), "erase failed: flash error\n");
+    fake.fail_saves = false;
+    assert_eq!(
+        fake.run("config reset"),
+        "saved settings erased, reboot to apply\n"
+    );
+}
+
+#[test]
+fn stream_and_display_commands() {
+    let mut fake = Fake::new();
+    assert_eq!(fake.run("stream"), "tcp://10.0.0.2:3000\n");
+    assert_eq!(
+        fake.run("stream set rtp://@:5004"),
+        "saved\nreboot to apply\n"
+    );
+    assert_eq!(fake.config.transport, Transport::Rtp { port: 5004 });
+    assert_eq!(fake.run("stream"), "rtp://@:5004\n");
+    assert_eq!(fake.run("stream set"), "saved\nreboot to apply\n");
+    assert_eq!(fake.run("stream show"), "discover with mDNS (tcp)\n");
+    assert_eq!(fake.calls(), ["save", "save"]);
+    assert_eq!(
+        fake.run("stream set ftp://x"),
+        "invalid stream URL: ftp://x\n"
+    );
+    assert_eq!(fake.calls(), [""; 0]);
+
+    assert_eq!(fake.run("display fit center"), "saved\nreboot to apply\n");
+    assert_eq!(fake.config.display.fit, FitMode::Center);
+}
+
+#[test]
+fn reports() {
+    let mut fake = Fake::new();
+    assert!(fake.run("help").contains("wifi set <ssid> [password]"));
+
+    for _ in 0..25 {
+        fake.stats.frame_shown(320, 176, 30_000);
+    }
+    fake.stats.frame_shown(320, 170, 45_000);
+    fake.stats.frame_dropped();
+    fake.stats.decode_failed();
+    fake.stats.decoder_reset();
+    fake.stats.received(4096 + 100);
+    fake.stats.connected();
+    assert_eq!(
+        fake.run("stats"),
+        "uptime:      10 s\n\
+         frames:      26 (2 fps average)\n\
+         dropped:     1\n\
+         bad frames:  1\n\
+         received:    4 KiB\n\
+         connections: 1\n\
+         heap:        1000 used, 2000 free\n"
+    );
+
+    let info = fake.run("decoder info");
+    assert!(info.contains("last frame:  320x170\n"), "{info}");
+    assert!(info.contains("frame time:  45000 us last, 45000 us max\n"));
+    assert!(info.contains("resets:      1\n"));
+    assert!(!info.contains("codec heap"));
+    fake.codec_heap = HeapStats {
+        live: 2,
+        live_bytes: 100,
+        peak_bytes: 300,
+        failed: 1,
+        bad_frees: 3,
+    };
+    let info = fake.run("decoder info");
+    assert!(
+        info.ends_with("codec heap:  100 B in 2 blocks, 300 B peak, 1 failed\nbad frees:   3\n")
+    );
+
+    fake.run("wifi add phone \"\" 4");
+    let dump = fake.run("config dump");
+    assert!(dump.starts_with(
+        "wifi.0.ssid:                home\n\
+         wifi.0.password:            (set)\n\
+         wifi.0.priority:            0\n\
+         wifi.1.ssid:                phone\n\
+         wifi.1.password:            (none)\n\
+         wifi.1.priority:            4\n\
+         server:                     10.0.0.2:3000\n"
+    ));
+    assert!(
+        dump.contains("display.fit:                fill\n"),
+        "{dump}"
+    );
+
+    assert_eq!(fake.run("reboot"), "rebooting\n");
+    assert_eq!(fake.calls().last().unwrap(), "reboot");
+}
@@ -0,0 +1,302 @@
+//! Frames decoded from a `BaselineStream` while their bytes are still
+//! arriving, against the same frames decoded whole.
+
+use embassy_futures::block_on;
+use jpeg_encoder::{ColorType, Encoder, SamplingFactor};
+use rumble_rs::display::Framebuffer;
+use rumble_rs::fit::{FitMode, Layout};
+use rumble_rs::jpeg::baseline::{BaselineDecoder, BaselineStream};
+use rumble_rs::jpeg::{JpegBackend, JpegDecoderConfig, JpegError, JpegPixelFormat};
+use rumble_rs::pipeline::{DrawError, Incoming, IncomingError, Pipeline};
+
+/// A noisy test pattern, so that the scans take some bytes.
+fn encode(width: u16, height: u16, sampling: Option<SamplingFactor>, restart: u16) -> Vec<u8> {
+    let mut seed = 0x9E37_79B9u32 ^ width as u32 ^ (height as u32) << 16;
+    let mut rgb = Vec::new();
+    for y in 0..height as u32 {
+        for x in 0..width as u32 {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            rgb.extend([
+                (x * 3 + (seed & 31)) as u8,
+                (y * 2) as u8,
+                (seed >> 8) as u8 & 0x7F,
+            ]);
+        }
+    }
+    let mut out = Vec::new();
+    let mut encoder = Encoder::new(&mut out, 80);
+    encoder.set_restart_interval(restart);
+    match sampling {
+        Some(sampling) => {
+            encoder.set_sampling_factor(sampling);
+            encoder.encode(&rgb, width, height, ColorType::Rgb).unwrap();
+        }
+        None => {
+            let luma: Vec<u8> = rgb.chunks_exact(3).map(|p| p[0] / 2 + p[1] / 2).collect();
+            encoder
+                .encode(&luma, width, height, ColorType::Luma)
+                .unwrap();
+        }
+    }
+    out
+}
+
+/// The blocks of `jpeg`, decoded with all of it at hand.
+fn whole(jpeg: &[u8], config: JpegDecoderConfig) -> Vec<Vec<u8>> {
+    let mut decoder = BaselineDecoder::with_config(config).unwrap();
+    let mut data = jpeg.to_vec();
+    let mut session = decoder.start_decode(&mut data).unwrap();
+    (0..session.block_count())
+        .map(|_| {
+            session.decode_next_block().unwrap();
+            session.block_data().to_vec()
+        })
+        .collect()
+}
+
+/// Input that arrives `chunk` bytes at a time.
+struct Arriving<'a> {
+    data: &'a [u8],
+    chunk: usize,
+    /// How much of `data` has been pushed.
+    pushed: usize,
+}
+
+impl Arriving<'_> {
+    /// Push the next chunk, or what's left of it, with `push`. False once
+    /// everything has been.
+    fn more(&mut self, push: impl FnOnce(&[u8]) -> usize) -> bool {
+        if self.pushed == self.data.len() {
+            return false;
+        }
+        let end = self.data.len().min(self.pushed + self.chunk);
+        self.pushed += push(&self.data[self.pushed..end]);
+        true
+    }
+}
+
+/// A decoded block, and how many bytes of the input had been pushed when it
+/// came out.
+type Block = (usize, Vec<u8>);
+
+/// Every frame of `input`, pushed `chunk` bytes at a time into a stream that
+/// holds `capacity`, and the errors of the frames that were skipped.
+fn streamed(
+    input: &[u8],
+    chunk: usize,
+    capacity: usize,
+    config: JpegDecoderConfig,
+) -> (Vec<Vec<Block>>, Vec<JpegError>) {
+    let mut decoder = BaselineDecoder::with_config(config).unwrap();
+    let mut stream = BaselineStream::new(capacity);
+    let mut input = Arriving {
+        data: input,
+        chunk,
+        pushed: 0,
+    };
+    let mut frames = Vec::new();
+    let mut errors = Vec::new();
+    loop {
+        match stream.header() {
+            Ok(_) => {}
+            Err(JpegError::NoMoreData) if input.more(|b| stream.push(b)) => continue,
+            Err(JpegError::NoMoreData) => return (frames, errors),
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        }
+        let mut session = match decoder.start_stream(&mut stream) {
+            Ok(session) => session,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        let mut blocks = Vec::new();
+        loop {
+            match session.decode_next_block() {
+                Ok(_) => blocks.push((input.pushed, session.block_data().to_vec())),
+                Err(JpegError::SessionExhausted) => break,
+                Err(JpegError::NoMoreData) if input.more(|b| session.push(b)) => {}
+                Err(e) => {
+                    errors.push(e);
+                    break;
+                }
+            }
+        }
+        frames.push(blocks);
+    }
+}
+
+fn pixels(frame: &[Block]) -> Vec<Vec<u8>> {
+    frame.iter().map(|(_, block)| block.clone()).collect()
+}
+
+#[test]
+fn one_byte_at_a_time_across_two_frames() {
+    let first = encode(320, 176, Some(SamplingFactor::F_2_2), 0);
+    let second = encode(240, 96, Some(SamplingFactor::F_1_1), 5);
+    // With junk before and between them, as a raw TCP stream may have
+    let mut input = b"junk\xFF\xFF".to_vec();
+    let starts = [input.len(), input.len() + first.len() + 12];
+    input.extend(&first);
+    input.extend(b"\r\n--frame\r\n\xFF");
+    input.extend(&second);
+
+    let config = JpegDecoderConfig::default();
+    let (frames, errors) = streamed(&input, 1, 1024, config);
+    assert_eq!(errors, []);
+    assert_eq!(frames.len(), 2);
+    for ((frame, jpeg), start) in frames.iter().zip([&first, &second]).zip(starts) {
+        assert!(pixels(frame) == whole(jpeg, config));
+        // Each block comes out as soon as its data is in, not at the end
+        let pushed: Vec<usize> = frame.iter().map(|&(pushed, _)| pushed).collect();
+        assert!(pushed.is_sorted_by(|a, b| a < b), "{pushed:?}");
+        assert!(pushed[0] < start + jpeg.len() / 4, "{pushed:?}");
+        assert!(pushed[pushed.len() - 1] <= start + jpeg.len());
+    }
+}
+
+#[test]
+fn any_chunks_decode_as_whole_frames() {
+    let configs = [
+        JpegDecoderConfig::default(),
+        JpegDecoderConfig::builder()
+            .with_output_format(JpegPixelFormat::Rgb565Be)
+            .with_block_mode(false)
+            .build()
+            .unwrap(),
+        JpegDecoderConfig::builder()
+            .with_block_mode(false)
+            .with_scale(64, 48)
+            .with_clipper(56, 40)
+            .build()
+            .unwrap(),
+    ];
+    for sampling in [
+        Some(SamplingFactor::F_2_2),
+        Some(SamplingFactor::F_2_1),
+        Some(SamplingFactor::F_1_1),
+        None,
+    ] {
+        for restart in [0, 1, 3] {
+            let jpeg = encode(96, 72, sampling, restart);
+            for config in configs {
+                let expected = whole(&jpeg, config);
+                for chunk in [2, 3, 13, 100, 4096] {
+                    let (frames, errors) = streamed(&jpeg, chunk, 2048, config);
+                    assert_eq!(errors, []);
+                    assert_eq!(frames.len(), 1);
+                    assert!(
+                        pixels(&frames[0]) == expected,
+                        "{sampling:?} restart {restart} chunk {chunk}"
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn frames_that_are_skipped() {
+    let jpeg = encode(64, 48, Some(SamplingFactor::F_2_2), 0);
+    // Headers bigger than the whole stream
+    let (frames, errors) = streamed(&jpeg, 64, 300, JpegDecoderConfig::default());
+    assert_eq!(errors, [JpegError::BufferTooSmall]);
+    assert_eq!(frames.len(), 0);
+
+    // A broken header, and the next frame still decodes
+    let mut input = jpeg.clone();
+    input[3] = 0x00;
+    input.extend(&jpeg);
+    let (frames, errors) = streamed(&input, 50, 2048, JpegDecoderConfig::default());
+    assert_eq!(errors.len(), 1, "{errors:?}");
+    assert_eq!(frames.len(), 1);
+    assert!(pixels(&frames[0]) == whole(&jpeg, JpegDecoderConfig::default()));
+}
+
+/// A connection that hands out `chunk` bytes per read.
+struct Trickle<'a> {
+    data: &'a [u8],
+    chunk: usize,
+}
+
+impl embedded_io_async::ErrorType for Trickle<'_> {
+    type Error = core::convert::Infallible;
+}
+
+impl embedded_io_async::Read for Trickle<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let n = buf.len().min(self.chunk).min(self.data.len());
+        buf[..n].copy_from_slice(&self.data[..n]);
+        self.data = &self.data[n..];
+        Ok(n)
+    }
+}
+
+/// Read from `socket` until the next frame's headers are in.
+fn next_frame<'a>(
+    decoder: &'a mut BaselineDecoder,
+    stream: &'a mut BaselineStream,
+    socket: &mut Trickle,
+) -> rumble_rs::jpeg::baseline::StreamSession<'a> {
+    loop {
+        if stream.header().is_ok() {
+            return decoder.start_stream(stream).unwrap();
+        }
+        let n = block_on(embedded_io_async::Read::read(socket, stream.spare())).unwrap();
+        assert!(n > 0);
+        stream.commit(n);
+    }
+}
+
+#[test]
+fn incoming_frames_draw_like_whole_ones() {
+    let first = encode(320, 176, Some(SamplingFactor::F_2_2), 0);
+    let second = encode(320, 176, Some(SamplingFactor::F_2_1), 4);
+    let mut input = first.clone();
+    input.extend(&second);
+    let layout = Layout::new(FitMode::Fill, (320, 176), (320, 170), 0);
+    let config = layout.decoder_config(JpegPixelFormat::Rgb565Be).unwrap();
+    let mut pipeline = Pipeline::new(320 * 16 * 2);
+
+    let mut decoder = BaselineDecoder::with_config(config).unwrap();
+    let mut stream = BaselineStream::new(4096);
+    let mut socket = Trickle {
+        data: &input,
+        chunk: 700,
+    };
+    for jpeg in [&first, &second] {
+        let mut expected = Framebuffer::new(320, 170);
+        let mut data = jpeg.clone();
+        let mut whole_decoder = BaselineDecoder::with_config(config).unwrap();
+        let mut session = whole_decoder.start_decode(&mut data).unwrap();
+        block_on(pipeline.draw(&layout, &mut session, &mut expected)).unwrap();
+
+        let session = next_frame(&mut decoder, &mut stream, &mut socket);
+        let mut blocks = Incoming::new(session, &mut socket);
+        assert_eq!(blocks.info().width, 320);
+        let mut frame = Framebuffer::new(320, 170);
+        block_on(pipeline.draw(&layout, &mut blocks, &mut frame)).unwrap();
+        assert!(blocks.received() > 0);
+        assert!(frame.data() == expected.data());
+    }
+    assert!(socket.data.len() < 700);
+
+    // The connection closes halfway through a frame
+    stream.reset();
+    let mut socket = Trickle {
+        data: &first[..first.len() / 2],
+        chunk: 300,
+    };
+    let session = next_frame(&mut decoder, &mut stream, &mut socket);
+    let mut blocks = Incoming::new(session, &mut socket);
+    let mut frame = Framebuffer::new(320, 170);
+    assert!(matches!(
+        block_on(pipeline.draw(&layout, &mut blocks, &mut frame)),
+        Err(DrawError::Decode(IncomingError::Closed))
+    ));
+}
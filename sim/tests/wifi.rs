@@ -0,0 +1,230 @@
+//! Network selection: the order networks are tried in, how often, and what
+//! a changed network list does to the current connection.
+
+use rumble_rs::config::WifiConfig;
+use rumble_rs::wifi::{Action, Candidate, Network, Selector};
+
+fn known(ssid: &str, priority: u8) -> WifiConfig {
+    WifiConfig::new(ssid.into(), "password".into())
+        .unwrap()
+        .with_priority(priority)
+}
+
+fn found(ssid: &str, rssi: i8) -> Network {
+    Network {
+        ssid: ssid.into(),
+        rssi,
+        secure: true,
+    }
+}
+
+fn join(ssid: &str, priority: u8, rssi: Option<i8>) -> Action {
+    Action::Join(Candidate {
+        network: known(ssid, priority),
+        rssi,
+    })
+}
+
+/// The networks `selector` tries, in order, if every join fails.
+fn order(selector: &mut Selector, scan: &[Network]) -> Vec<(String, Option<i8>)> {
+    let mut tried = Vec::new();
+    let mut action = selector.scanned(scan);
+    while let Action::Join(candidate) = action {
+        tried.push((candidate.network.ssid, candidate.rssi));
+        action = selector.failed();
+    }
+    assert_eq!(action, Action::Wait);
+    tried
+}
+
+fn tried(list: &[(&str, Option<i8>)]) -> Vec<(String, Option<i8>)> {
+    list.iter()
+        .map(|&(ssid, rssi)| (ssid.into(), rssi))
+        .collect()
+}
+
+#[test]
+fn nothing_to_join() {
+    let mut selector = Selector::new(Vec::new(), 3);
+    assert_eq!(selector.start(), Action::Wait);
+    assert_eq!(selector.scanned(&[found("cafe", -30)]), Action::Wait);
+
+    let mut selector = Selector::new(vec![known("home", 0)], 3);
+    assert_eq!(selector.start(), Action::Scan);
+    assert_eq!(order(&mut selector, &[]), tried(&[("home", None)]));
+}
+
+#[test]
+fn priority_then_signal() {
+    let mut selector = Selector::new(
+        vec![
+            known("home", 0),
+            known("office", 0),
+            known("phone", 5),
+            known("lab", 1),
+        ],
+        1,
+    );
+    assert_eq!(selector.start(), Action::Scan);
+    let scan = [
+        found("home", -70),
+        found("office", -50),
+        // A second access point of the same network
+        found("home", -40),
+        found("lab", -90),
+        found("neighbour", -10),
+        found("phone", -85),
+    ];
+    assert_eq!(
+        order(&mut selector, &scan),
+        tried(&[
+            ("phone", Some(-85)),
+            ("lab", Some(-90)),
+            ("home", Some(-40)),
+            ("office", Some(-50)),
+        ])
+    );
+}
+
+#[test]
+fn attempts_then_next_network() {
+    let mut selector = Selector::new(vec![known("home", 0), known("office", 1)], 3);
+    selector.start();
+    let scan = [found("home", -40), found("office", -60)];
+    assert_eq!(
+        order(&mut selector, &scan),
+        tried(&[
+            ("office", Some(-60)),
+            ("office", Some(-60)),
+            ("office", Some(-60)),
+            ("home", Some(-40)),
+            ("home", Some(-40)),
+            ("home", Some(-40)),
+        ])
+    );
+
+    // A new scan starts the count over
+    assert_eq!(selector.scanned(&scan), join("office", 1, Some(-60)));
+    assert_eq!(selector.failed(), join("office", 1, Some(-60)));
+    assert_eq!(selector.scanned(&scan), join("office", 1, Some(-60)));
+    assert_eq!(selector.failed(), join("office", 1, Some(-60)));
+    assert_eq!(selector.failed(), join("office", 1, Some(-60)));
+    assert_eq!(selector.failed(), join("home", 0, Some(-40)));
+
+    // No attempts at all is taken as one
+    let mut selector = Selector::new(vec![known("home", 0), known("office", 1)], 0);
+    selector.start();
+    assert_eq!(
+        order(&mut selector, &scan),
+        tried(&[("office", Some(-60)), ("home", Some(-40))])
+    );
+}
+
+#[test]
+fn unseen_networks_get_one_try_last() {
+    let mut selector = Selector::new(
+        vec![known("hidden", 9), known("home", 0), known("attic", 1)],
+        2,
+    );
+    selector.start();
+    assert_eq!(
+        order(&mut selector, &[found("home", -80)]),
+        tried(&[
+            ("home", Some(-80)),
+            ("home", Some(-80)),
+            // Then the unseen ones by priority, even when it is higher
+            // than a seen one's
+            ("hidden", None),
+            ("attic", None),
+        ])
+    );
+}
+
+#[test]
+fn joining_and_losing_a_network() {
+    let mut selector = Selector::new(vec![known("home", 0), known("office", 1)], 2);
+    assert_eq!(selector.current(), None);
+    selector.start();
+    assert_eq!(
+        selector.scanned(&[found("home", -40), found("office", -60)]),
+        join("office", 1, Some(-60))
+    );
+    assert_eq!(selector.failed(), join("office", 1, Some(-60)));
+    assert_eq!(selector.joined(), Action::Stay);
+    assert_eq!(
+        selector.current(),
+        Some(&Candidate {
+            network: known("office", 1),
+            rssi: Some(-60),
+        })
+    );
+
+    // Scans again rather than rejoining, and forgets the old network
+    assert_eq!(selector.disconnected(), Action::Scan);
+    assert_eq!(selector.current(), None);
+    assert_eq!(
+        selector.scanned(&[found("home", -40)]),
+        join("home", 0, Some(-40))
+    );
+}
+
+#[test]
+fn set_networks_keeps_or_drops_the_current_network() {
+    let connected = || {
+        let mut selector = Selector::new(vec![known("home", 0), known("phone", 2)], 2);
+        selector.start();
+        selector.scanned(&[found("home", -50), found("phone", -70)]);
+        assert_eq!(selector.joined(), Action::Stay);
+        assert_eq!(selector.current().unwrap().network.ssid, "phone");
+        selector
+    };
+
+    // Still known: stays, with the new settings
+    let mut selector = connected();
+    let networks = vec![known("phone", 7), known("cafe", 9)];
+    assert_eq!(selector.set_networks(networks, false), Action::Stay);
+    assert_eq!(
+        selector.current(),
+        Some(&Candidate {
+            network: known("phone", 7),
+            rssi: Some(-70),
+        })
+    );
+    // and picks from the new list next time
+    assert_eq!(selector.disconnected(), Action::Scan);
+    assert_eq!(
+        selector.scanned(&[found("cafe", -80), found("phone", -70)]),
+        join("cafe", 9, Some(-80))
+    );
+
+    // Asked to reconnect
+    let mut selector = connected();
+    let networks = vec![known("phone", 2), known("home", 0)];
+    assert_eq!(selector.set_networks(networks, true), Action::Scan);
+    assert_eq!(selector.current(), None);
+
+    // Forgotten
+    let mut selector = connected();
+    assert_eq!(
+        selector.set_networks(vec![known("home", 0)], false),
+        Action::Scan
+    );
+    assert_eq!(selector.current(), None);
+    assert_eq!(
+        selector.scanned(&[found("phone", -30)]),
+        join("home", 0, None)
+    );
+
+    // Nothing left
+    let mut selector = connected();
+    assert_eq!(selector.set_networks(Vec::new(), false), Action::Wait);
+
+    // Not connected: always starts over
+    let mut selector = Selector::new(vec![known("home", 0)], 2);
+    selector.start();
+    selector.scanned(&[]);
+    assert_eq!(
+        selector.set_networks(vec![known("home", 0)], false),
+        Action::Scan
+    );
+}
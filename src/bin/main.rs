@@ -7,29 +7,69 @@
 )]
 #![deny(clippy::large_stack_frames)]
 
+use core::alloc::GlobalAlloc;
+use core::cell::RefCell;
 use core::net::Ipv4Addr;
 
 use embassy_executor::Spawner;
-use embassy_net::{Runner, StackResources, tcp::TcpSocket};
-use embassy_time::{Duration, Timer};
-use embedded_graphics::pixelcolor::Rgb565;
-use embedded_graphics::pixelcolor::raw::RawU16;
+use embassy_futures::select::{Either, select};
+use embassy_net::dns::DnsQueryType;
+use embassy_net::tcp::{State, TcpSocket};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, Ipv4Cidr, Runner, Stack, StackResources, StaticConfigV4};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer, with_deadline, with_timeout};
+use embedded_hal_async::spi::SpiDevice;
 use embedded_hal_bus::spi::ExclusiveDevice;
+use esp_alloc::MemoryCapability;
+use esp_hal::Async;
 use esp_hal::clock::CpuClock;
+use esp_hal::delay::Delay;
 use esp_hal::dma::{DmaRxBuf, DmaTxBuf};
 use esp_hal::dma_buffers;
+use esp_hal::efuse::Efuse;
 use esp_hal::gpio::{Level, Output, OutputConfig};
 use esp_hal::rng::Rng;
-use esp_hal::spi::master::Spi;
+use esp_hal::spi::master::{Spi, SpiDmaBus};
 use esp_hal::time::Rate;
 use esp_hal::timer::timg::TimerGroup;
-use esp_println::println;
+use esp_hal::usb_serial_jtag::{UsbSerialJtag, UsbSerialJtagRx};
+use esp_println::{Printer, print, println};
 use esp_radio::{
     Controller,
-    wifi::{ClientConfig, ModeConfig, WifiController, WifiDevice, WifiEvent, WifiStaState},
+    wifi::{
+        AccessPointConfig, AuthMethod, ClientConfig, ModeConfig, ScanConfig, WifiController,
+        WifiDevice, WifiEvent, WifiStaState,
+    },
 };
+use esp_storage::FlashStorage;
 use mipidsi::interface::SpiInterface;
-use rumble_rs::jpeg::JpegDecoder;
+use rumble_rs::config::{Config, ConfigStore, PlaybackConfig, Transport, WifiConfig};
+use rumble_rs::display::DisplaySink;
+use rumble_rs::fit::{FitMode, Layout, Rect};
+use rumble_rs::http::{self, MultipartDemuxer};
+use rumble_rs::jpeg::heap::{self, HeapStats, Region, RegionAllocator};
+use rumble_rs::jpeg::log;
+use rumble_rs::jpeg::{JpegBackend, JpegDecoderConfig, JpegPixelFormat, JpegSession, markers};
+use rumble_rs::mdns::{Browser, MDNS_ADDR, MDNS_PORT, Service};
+use rumble_rs::mjpeg::{FrameSource, Status};
+use rumble_rs::pipeline::{DrawError, Pipeline};
+use rumble_rs::portal::dhcp::{self, DhcpServer};
+use rumble_rs::portal::web::{self, Route};
+use rumble_rs::portal::{PORTAL_ADDR, PORTAL_PREFIX_LEN, dns};
+use rumble_rs::rtp::Depacketizer;
+use rumble_rs::shell::{self, Command, Edit, LineBuffer, ParseError, Stats, Target};
+use rumble_rs::wifi::{Action, Candidate, Network, Selector};
+#[cfg(all(
+    feature = "baseline-jpeg",
+    not(any(feature = "esp-new-jpeg", feature = "rom-jpeg"))
+))]
+use rumble_rs::{
+    jpeg::JpegError,
+    pipeline::{Incoming, IncomingError},
+};
 
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
@@ -38,6 +78,8 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
 }
 
 extern crate alloc;
+use alloc::format;
+use alloc::string::{String, ToString};
 use alloc::vec;
 use alloc::vec::Vec;
 
@@ -52,15 +94,173 @@ macro_rules! mk_static {
 
 esp_bootloader_esp_idf::esp_app_desc!();
 
-/// Find a two-byte marker (e.g. SOI=0xFFD8, EOI=0xFFD9) in a byte slice.
-fn find_marker(data: &[u8], b0: u8, b1: u8) -> Option<usize> {
-    data.windows(2).position(|w| w[0] == b0 && w[1] == b1)
+/// The `config` partition in `partitions.csv`.
+const CONFIG_OFFSET: u32 = 0x31_0000;
+const CONFIG_SIZE: u32 = 0x1000;
+
+/// Tries to join a network in range before moving on to the next one.
+const JOIN_ATTEMPTS: u8 = 3;
+/// How long to wait before scanning again when no network could be joined.
+const RESCAN_DELAY: Duration = Duration::from_secs(10);
+
+/// The panel takes RGB565 most significant byte first.
+const PANEL_FORMAT: JpegPixelFormat = JpegPixelFormat::Rgb565Be;
+
+/// The JPEG decoder, picked by cargo feature: ESP_NEW_JPEG when it's there,
+/// then the ROM's TJpgDec, then the pure-Rust one.
+#[cfg(feature = "esp-new-jpeg")]
+type Decoder = rumble_rs::jpeg::JpegDecoder;
+#[cfg(all(feature = "rom-jpeg", not(feature = "esp-new-jpeg")))]
+type Decoder = rumble_rs::jpeg::rom::RomJpegDecoder;
+#[cfg(all(
+    feature = "baseline-jpeg",
+    not(any(feature = "esp-new-jpeg", feature = "rom-jpeg"))
+))]
+type Decoder = rumble_rs::jpeg::baseline::BaselineDecoder;
+#[cfg(not(any(
+    feature = "esp-new-jpeg",
+    feature = "rom-jpeg",
+    feature = "baseline-jpeg"
+)))]
+compile_error!("enable a JPEG decoder: esp-new-jpeg, rom-jpeg or baseline-jpeg");
+
+/// What raw TCP streams go through. The pure-Rust decoder starts on each
+/// frame while it is still arriving; the others need it whole, so it is
+/// assembled first.
+#[cfg(not(all(
+    feature = "baseline-jpeg",
+    not(any(feature = "esp-new-jpeg", feature = "rom-jpeg"))
+)))]
+type RawSource = rumble_rs::mjpeg::FrameAssembler;
+#[cfg(all(
+    feature = "baseline-jpeg",
+    not(any(feature = "esp-new-jpeg", feature = "rom-jpeg"))
+))]
+type RawSource = rumble_rs::jpeg::baseline::BaselineStream;
+
+/// Largest output the decoder may produce in one piece when it scales a
+/// stream down itself.
+const MAX_DECODER_OUTPUT: usize = 32 * 1024;
+
+/// DNS-SD service types that stream servers advertise.
+const SERVICES: [&str; 2] = ["_rumble._tcp.local", "_mjpeg._tcp.local"];
+
+/// Counters for the shell's `stats` and `decoder info`.
+static STATS: Stats = Stats::new();
+
+/// New networks for the `connection` task, from the shell.
+static WIFI_CHANGE: Signal<CriticalSectionRawMutex, NetworkChange> = Signal::new();
+
+/// The network the `connection` task is on, for the shell.
+static NETWORK: Mutex<CriticalSectionRawMutex, RefCell<Option<Candidate>>> =
+    Mutex::new(RefCell::new(None));
+
+/// The settings the decoder runs with, which change with the stream's size,
+/// for the shell.
+static DECODER: Mutex<CriticalSectionRawMutex, RefCell<Option<JpegDecoderConfig>>> =
+    Mutex::new(RefCell::new(None));
+
+struct NetworkChange {
+    networks: Vec<WifiConfig>,
+    /// Leave the current network and pick again.
+    reconnect: bool,
 }
 
-const SSID: &str = "ylikellotus";
-const PASSWORD: &str = "alakerta";
+type PanelSpi = ExclusiveDevice<SpiDmaBus<'static, Async>, Output<'static>, embassy_time::Delay>;
+
+/// Columns of the ST7789's frame memory, in its native orientation.
+const ST7789_COLUMNS: u16 = 240;
+
+/// The panel's SPI link. mipidsi sets the panel up; frames are then written
+/// here directly, so that pixels go out by DMA while the next block decodes.
+struct Panel {
+    spi: PanelSpi,
+    dc: Output<'static>,
+    /// Held so that the panel stays out of reset.
+    _reset: Output<'static>,
+    /// Where the visible area starts in frame memory, in rotated coordinates.
+    offset: (u16, u16),
+}
+
+impl Panel {
+    async fn command(
+        &mut self,
+        command: u8,
+        params: &[u8],
+    ) -> Result<(), <Self as DisplaySink>::Error> {
+        self.dc.set_low();
+        SpiDevice::write(&mut self.spi, &[command]).await?;
+        self.dc.set_high();
+        if !params.is_empty() {
+            SpiDevice::write(&mut self.spi, params).await?;
+        }
+        Ok(())
+    }
+}
 
-const DISPLAY_HEIGHT: u16 = 170;
+impl DisplaySink for Panel {
+    type Error = <PanelSpi as embedded_hal::spi::ErrorType>::Error;
+
+    async fn set_window(&mut self, window: Rect) -> Result<(), Self::Error> {
+        let x = window.x + self.offset.0;
+        let y = window.y + self.offset.1;
+        let range = |start: u16, len: u16| {
+            let [a, b] = start.to_be_bytes();
+            let [c, d] = (start + len - 1).to_be_bytes();
+            [a, b, c, d]
+        };
+        // CASET, RASET, RAMWR
+        self.command(0x2A, &range(x, window.width)).await?;
+        self.command(0x2B, &range(y, window.height)).await?;
+        self.command(0x2C, &[]).await
+    }
+
+    async fn write_pixels(&mut self, pixels: &[u8]) -> Result<(), Self::Error> {
+        SpiDevice::write(&mut self.spi, pixels).await
+    }
+}
+
+/// The panel, its visible size and how the current stream is laid out on
+/// it.
+struct Screen {
+    panel: Panel,
+    pipeline: Pipeline,
+    width: u16,
+    height: u16,
+    fit: FitMode,
+    /// The image size the layout was made for.
+    layout: Option<((u16, u16), Layout)>,
+}
+
+/// How frames arrive over the TCP connection.
+enum Source<'a> {
+    /// Raw concatenated JPEGs.
+    Raw(RawSource),
+    /// An HTTP multipart stream.
+    Http {
+        demuxer: MultipartDemuxer,
+        path: &'a str,
+    },
+}
+
+/// Places the C codec's buffers in the esp-alloc region its caps ask for.
+struct CodecHeap;
+
+impl RegionAllocator for CodecHeap {
+    unsafe fn alloc(&self, region: Region, layout: core::alloc::Layout) -> *mut u8 {
+        let caps = match region {
+            // All of the ESP32-S3's internal SRAM is DMA-capable
+            Region::Dma | Region::Internal => MemoryCapability::Internal,
+            Region::External => MemoryCapability::External,
+            Region::Default => return unsafe { esp_alloc::HEAP.alloc(layout) },
+        };
+        unsafe { esp_alloc::HEAP.alloc_caps(caps.into(), layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
+        unsafe { esp_alloc::HEAP.dealloc(ptr, layout) }
+    }
+}
 
 #[allow(
     clippy::large_stack_frames,
@@ -73,9 +273,28 @@ async fn main(spawner: Spawner) -> ! {
 
     esp_alloc::heap_allocator!(#[esp_hal::ram(reclaimed)] size: 64 * 1024);
     esp_alloc::heap_allocator!(size: 64 * 1024);
+    heap::set_allocator(&CodecHeap);
 
     let timg0 = TimerGroup::new(peripherals.TIMG0);
     esp_rtos::start(timg0.timer0);
+    log::set_output(|message| print!("{}", message));
+    log::set_clock(|| Instant::now().as_millis() as u32);
+
+    // -----------------------------------------------------------------------
+    // Configuration (flash record over build-time defaults)
+    // -----------------------------------------------------------------------
+    let mut store = ConfigStore::new(
+        FlashStorage::new(peripherals.FLASH),
+        CONFIG_OFFSET,
+        CONFIG_SIZE,
+    );
+    let config = match store.load() {
+        Ok(config) => config,
+        Err(e) => {
+            println!("using built-in defaults: {}", e);
+            Config::default()
+        }
+    };
 
     // -----------------------------------------------------------------------
     // Display init (ST7789 320×170 over SPI with DMA)
@@ -84,7 +303,7 @@ async fn main(spawner: Spawner) -> ! {
     let dma_rx_buf = DmaRxBuf::new(rx_descriptors, rx_buffer).unwrap();
     let dma_tx_buf = DmaTxBuf::new(tx_descriptors, tx_buffer).unwrap();
 
-    let mut delay = esp_hal::delay::Delay::new();
+    let mut delay = Delay::new();
 
     let dc = Output::new(peripherals.GPIO15, Level::Low, OutputConfig::default());
     let mut rst = Output::new(peripherals.GPIO7, Level::Low, OutputConfig::default());
@@ -103,19 +322,48 @@ async fn main(spawner: Spawner) -> ! {
     .into_async();
 
     let cs = Output::new(peripherals.GPIO6, Level::High, OutputConfig::default());
-    let spi_device = ExclusiveDevice::new(spi, cs, delay).unwrap();
+    let spi_device = ExclusiveDevice::new(spi, cs, embassy_time::Delay).unwrap();
 
-    let spi_buffer = mk_static!([u8; 10240], [0u8; 10240]);
+    // Only used while mipidsi sets the panel up
+    let spi_buffer = mk_static!([u8; 512], [0u8; 512]);
     let di = SpiInterface::new(spi_device, dc, spi_buffer);
 
-    let mut display = mipidsi::Builder::new(mipidsi::models::ST7789, di)
+    let inversion = if config.display.invert_colors {
+        mipidsi::options::ColorInversion::Inverted
+    } else {
+        mipidsi::options::ColorInversion::Normal
+    };
+    // The panel is mounted rotated, so its native size is height × width
+    let display = mipidsi::Builder::new(mipidsi::models::ST7789, di)
         .reset_pin(rst)
-        .display_size(170, 320)
-        .invert_colors(mipidsi::options::ColorInversion::Inverted)
+        .display_size(config.display.height, config.display.width)
+        .invert_colors(inversion)
         .orientation(mipidsi::options::Orientation::new().rotate(mipidsi::options::Rotation::Deg90))
-        .display_offset(35, 0)
+        .display_offset(config.display.offset_x, config.display.offset_y)
         .init(&mut delay)
         .unwrap();
+    // mipidsi's address offset for a 90° rotation: columns reversed, then
+    // rows and columns swapped
+    let (di, _, reset) = display.release();
+    let (spi, dc) = di.release();
+    let offset = (
+        config.display.offset_y,
+        ST7789_COLUMNS - config.display.height - config.display.offset_x,
+    );
+    let mut screen = Screen {
+        panel: Panel {
+            spi,
+            dc,
+            _reset: reset,
+            offset,
+        },
+        // 16 rows of the full width
+        pipeline: Pipeline::new(config.display.width as usize * 16 * 2),
+        width: config.display.width,
+        height: config.display.height,
+        fit: config.display.fit,
+        layout: None,
+    };
 
     println!("Display initialized");
 
@@ -124,26 +372,46 @@ async fn main(spawner: Spawner) -> ! {
     // -----------------------------------------------------------------------
     let esp_radio_ctrl = &*mk_static!(Controller<'static>, esp_radio::init().unwrap());
 
-    let (controller, interfaces) =
+    let (mut controller, interfaces) =
         esp_radio::wifi::new(esp_radio_ctrl, peripherals.WIFI, Default::default()).unwrap();
 
-    let wifi_interface = interfaces.sta;
-
-    let config = embassy_net::Config::dhcpv4(Default::default());
-
     let rng = Rng::new();
     let seed = (rng.random() as u64) << 32 | rng.random() as u64;
 
+    let mut selector = Selector::new(config.networks.clone(), JOIN_ATTEMPTS);
+    if config.networks.is_empty() || !join(&mut controller, &mut selector).await {
+        provision(spawner, controller, interfaces.ap, seed, store, config).await;
+    }
+
+    let wifi_interface = interfaces.sta;
+
+    let net_config = embassy_net::Config::dhcpv4(Default::default());
+
     let (stack, runner) = embassy_net::new(
         wifi_interface,
-        config,
-        mk_static!(StackResources<3>, StackResources::<3>::new()),
+        net_config,
+        mk_static!(StackResources<5>, StackResources::<5>::new()),
         seed,
     );
 
-    spawner.spawn(connection(controller)).ok();
+    spawner.spawn(connection(controller, selector)).ok();
     spawner.spawn(net_task(runner)).ok();
 
+    let decoder_config = JpegDecoderConfig::builder()
+        .with_output_format(PANEL_FORMAT)
+        .build()
+        .unwrap();
+    DECODER.lock(|decoder| *decoder.borrow_mut() = Some(decoder_config));
+    let (console_rx, _) = UsbSerialJtag::new(peripherals.USB_DEVICE)
+        .into_async()
+        .split();
+    let console = Console {
+        config: config.clone(),
+        store,
+        stack,
+    };
+    spawner.spawn(console_task(console_rx, console)).ok();
+
     // Wait for link
     loop {
         if stack.is_link_up() {
@@ -154,8 +422,8 @@ async fn main(spawner: Spawner) -> ! {
 
     println!("Waiting to get IP address...");
     loop {
-        if let Some(config) = stack.config_v4() {
-            println!("Got IP: {}", config.address);
+        if let Some(net_config) = stack.config_v4() {
+            println!("Got IP: {}", net_config.address);
             break;
         }
         Timer::after(Duration::from_millis(500)).await;
@@ -164,189 +432,1010 @@ async fn main(spawner: Spawner) -> ! {
     // -----------------------------------------------------------------------
     // MJPEG streaming loop
     // -----------------------------------------------------------------------
+    let mut decoder = Decoder::with_config(decoder_config).expect("failed to create JPEG decoder");
+    println!("JPEG decoder created");
+
+    let server = config.server.as_deref();
+    let playback = &config.playback;
+    match &config.transport {
+        Transport::Tcp => connect(stack, server, None, playback, &mut decoder, &mut screen).await,
+        Transport::Http { path } => {
+            connect(stack, server, Some(path.as_str()), playback, &mut decoder, &mut screen).await
+        }
+        Transport::Rtp { port } => {
+            receive_rtp(stack, *port, playback, &mut decoder, &mut screen).await
+        }
+        Transport::Listen { port } => {
+            listen(stack, *port, playback, &mut decoder, &mut screen).await
+        }
+    }
+}
+
+/// Connect to `server`, reconnecting whenever the stream ends. With
+/// `http_path` the stream is requested over HTTP.
+async fn connect(
+    stack: Stack<'static>,
+    server: Option<&str>,
+    http_path: Option<&str>,
+    playback: &PlaybackConfig,
+    decoder: &mut Decoder,
+    screen: &mut Screen,
+) -> ! {
     // Heap-allocated TCP buffers — larger RX = larger TCP window = better throughput
     let mut rx_buffer = vec![0u8; 16384];
     let mut tx_buffer = vec![0u8; 1024];
 
     // Frame accumulation buffer (~30KB on heap)
-    let mut frame_buf: Vec<u8> = vec![0u8; 30 * 1024];
-    let mut frame_len: usize = 0;
-    let mut in_frame = false;
+    let capacity = playback.frame_capacity as usize;
+    let mut source = match http_path {
+        Some(path) => Source::Http {
+            demuxer: MultipartDemuxer::new(capacity),
+            path,
+        },
+        None => Source::Raw(RawSource::new(capacity)),
+    };
 
-    let mut decoder = JpegDecoder::new().expect("failed to create JPEG decoder");
-    println!("JPEG decoder created");
+    let mut tcp_buf = vec![0u8; 4096];
 
     loop {
         Timer::after(Duration::from_millis(1_000)).await;
 
+        let Some((endpoint, host)) = find_server(stack, server).await else {
+            continue;
+        };
+
         let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
         socket.set_timeout(Some(Duration::from_secs(10)));
 
-        let remote_endpoint = (Ipv4Addr::new(172, 20, 10, 8), 3000);
-        println!("connecting to 172.20.10.8:3000...");
-        let r = socket.connect(remote_endpoint).await;
+        println!("connecting to {} ({})...", host, endpoint.0);
+        let r = socket.connect(endpoint).await;
         if let Err(e) = r {
             println!("connect error: {:?}", e);
             continue;
         }
         println!("connected!");
+        STATS.connected();
+
+        match &mut source {
+            Source::Raw(raw) => {
+                receive_raw(
+                    &mut socket,
+                    raw,
+                    &mut tcp_buf,
+                    playback.drop_stale_frames,
+                    decoder,
+                    screen,
+                )
+                .await;
+            }
+            Source::Http { demuxer, path } => match http::request_stream(
+                &mut socket,
+                &host,
+                path,
+                &mut tcp_buf,
+            )
+            .await
+            {
+                Ok((head, pending)) => {
+                    demuxer.start(&head.boundary);
+                    receive_frames(
+                        &mut socket,
+                        demuxer,
+                        &mut tcp_buf,
+                        pending,
+                        playback.drop_stale_frames,
+                        decoder,
+                        screen,
+                    )
+                    .await;
+                }
+                Err(e) => println!("HTTP error: {}", e),
+            },
+        }
+    }
+}
 
-        let mut tcp_buf = [0u8; 4096];
+/// Resolve `server`, or browse for a stream service if there is none.
+/// Returns the endpoint and the `host:port` to send as the HTTP `Host`.
+async fn find_server(
+    stack: Stack<'static>,
+    server: Option<&str>,
+) -> Option<((IpAddress, u16), String)> {
+    let Some(server) = server else {
+        let service = discover(stack).await?;
+        println!(
+            "found {} at {}:{}",
+            service.instance, service.addr, service.port
+        );
+        let host = format!("{}:{}", service.host, service.port);
+        return Some(((IpAddress::Ipv4(service.addr), service.port), host));
+    };
+
+    let Some((name, port)) = server
+        .rsplit_once(':')
+        .and_then(|(name, port)| Some((name, port.parse::<u16>().ok()?)))
+    else {
+        println!("invalid server address {}, expected host:port", server);
+        return None;
+    };
+    if let Ok(addr) = name.parse::<Ipv4Addr>() {
+        return Some(((IpAddress::Ipv4(addr), port), String::from(server)));
+    }
+    match stack.dns_query(name, DnsQueryType::A).await {
+        Ok(addrs) => {
+            let addr = *addrs.first()?;
+            Some(((addr, port), String::from(server)))
+        }
+        Err(e) => {
+            println!("DNS lookup of {} failed: {:?}", name, e);
+            None
+        }
+    }
+}
 
-        'recv: loop {
-            let n = match embedded_io_async::Read::read(&mut socket, &mut tcp_buf).await {
+/// Browse for one of `SERVICES` with mDNS, giving up after a few seconds.
+async fn discover(stack: Stack<'static>) -> Option<Service> {
+    if let Err(e) = stack.join_multicast_group(MDNS_ADDR) {
+        println!("mDNS join error: {:?}", e);
+    }
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = vec![0u8; 4096];
+    let mut tx_buffer = vec![0u8; 512];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(e) = socket.bind(MDNS_PORT) {
+        println!("mDNS bind error: {:?}", e);
+        return None;
+    }
+
+    println!("looking for {:?}...", SERVICES);
+    let mut browser = Browser::new(&SERVICES);
+    let mut packet = vec![0u8; 1500];
+    for _ in 0..5 {
+        let n = browser.write_query(&mut packet)?;
+        if let Err(e) = socket.send_to(&packet[..n], (MDNS_ADDR, MDNS_PORT)).await {
+            println!("mDNS send error: {:?}", e);
+        }
+        // Collect answers for a second before asking again
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while let Ok(Ok((n, _))) = with_deadline(deadline, socket.recv_from(&mut packet)).await {
+            if let Some(service) = browser.handle(&packet[..n]) {
+                return Some(service);
+            }
+        }
+    }
+    println!("no stream service found");
+    None
+}
+
+/// Accept raw MJPEG streams pushed to `port`. One socket streams while the
+/// other listens, so a new sender takes over as soon as it connects.
+async fn listen(
+    stack: Stack<'static>,
+    port: u16,
+    playback: &PlaybackConfig,
+    decoder: &mut Decoder,
+    screen: &mut Screen,
+) -> ! {
+    let [mut rx_a, mut rx_b] = [vec![0u8; 16384], vec![0u8; 16384]];
+    let [mut tx_a, mut tx_b] = [vec![0u8; 1024], vec![0u8; 1024]];
+    let mut sockets = [
+        TcpSocket::new(stack, &mut rx_a, &mut tx_a),
+        TcpSocket::new(stack, &mut rx_b, &mut tx_b),
+    ];
+    for socket in &mut sockets {
+        socket.set_timeout(Some(Duration::from_secs(10)));
+    }
+
+    let mut raw = RawSource::new(playback.frame_capacity as usize);
+    let mut tcp_buf = vec![0u8; 4096];
+
+    println!("listening on TCP port {}", port);
+    accept(&mut sockets[0], port).await;
+    let mut active = 0;
+
+    loop {
+        let [a, b] = &mut sockets;
+        let (current, next) = if active == 0 { (a, b) } else { (b, a) };
+        println!("accepted {:?}", current.remote_endpoint());
+        STATS.connected();
+
+        let receive = receive_raw(
+            current,
+            &mut raw,
+            &mut tcp_buf,
+            playback.drop_stale_frames,
+            decoder,
+            screen,
+        );
+        match select(receive, accept(next, port)).await {
+            Either::First(()) => {
+                current.abort();
+                let _ = with_timeout(Duration::from_secs(1), current.flush()).await;
+                accept(next, port).await;
+            }
+            Either::Second(()) => {
+                println!("new sender, dropping {:?}", current.remote_endpoint());
+                current.abort();
+                let _ = with_timeout(Duration::from_secs(1), current.flush()).await;
+            }
+        }
+        active ^= 1;
+    }
+}
+
+/// Wait until `socket` has a connection on `port`.
+async fn accept(socket: &mut TcpSocket<'_>, port: u16) {
+    loop {
+        match socket.state() {
+            // A cancelled accept can leave the handshake half done
+            State::SynSent | State::SynReceived => socket.abort(),
+            State::Closed | State::Listen => {}
+            _ => return,
+        }
+        match socket.accept(port).await {
+            Ok(()) => return,
+            Err(e) => {
+                println!("accept error: {:?}", e);
+                socket.abort();
+            }
+        }
+    }
+}
+
+/// Read the socket into `source` and show each completed frame until the
+/// connection ends. The first `pending` bytes of `tcp_buf` were already read.
+/// With `drop_stale`, whatever arrived while a frame was shown is skipped.
+async fn receive_frames(
+    socket: &mut TcpSocket<'_>,
+    source: &mut impl FrameSource,
+    tcp_buf: &mut [u8],
+    mut pending: usize,
+    drop_stale: bool,
+    decoder: &mut impl JpegBackend,
+    screen: &mut Screen,
+) {
+    'recv: loop {
+        let n = if pending > 0 {
+            core::mem::take(&mut pending)
+        } else {
+            match embedded_io_async::Read::read(socket, tcp_buf).await {
                 Ok(0) => {
                     println!("connection closed");
                     break;
                 }
-                Ok(n) => n,
+                Ok(n) => {
+                    STATS.received(n);
+                    n
+                }
                 Err(e) => {
                     println!("read error: {:?}", e);
                     break;
                 }
-            };
-
-            // Scan received chunk for JPEG SOI/EOI markers using bulk copy
-            let mut pos = 0;
-            while pos < n {
-                if !in_frame {
-                    if let Some(soi) = find_marker(&tcp_buf[pos..n], 0xFF, 0xD8) {
-                        frame_len = 0;
-                        in_frame = true;
-                        pos += soi;
-                    } else {
-                        break;
+            }
+        };
+
+        let mut input = &tcp_buf[..n];
+        while let Some(consumed) = feed(source, input, decoder, screen).await {
+            if !drop_stale {
+                input = &input[consumed..];
+                continue;
+            }
+
+            // --- Frame dropping: drain stale data from the socket ---
+            // After decode+display, the TCP buffer may have accumulated
+            // multiple frames. Drain them so we always show the latest.
+            source.reset();
+            if !drain(socket, tcp_buf).await {
+                break 'recv;
+            }
+            break; // back to main read loop with empty state
+        }
+    }
+}
+
+/// Read and discard whatever has queued up on `socket`. Returns `false` if
+/// the connection ended meanwhile.
+async fn drain(socket: &mut TcpSocket<'_>, tcp_buf: &mut [u8]) -> bool {
+    loop {
+        match with_timeout(
+            Duration::from_ticks(1),
+            embedded_io_async::Read::read(socket, tcp_buf),
+        )
+        .await
+        {
+            Ok(Ok(0)) => return false,      // connection closed
+            Ok(Ok(n)) => STATS.received(n), // discard stale data
+            Ok(Err(_)) => return false,
+            Err(_) => return true, // timeout = no more queued data
+        }
+    }
+}
+
+/// Show the frames of a raw MJPEG stream until the connection ends.
+#[cfg(not(all(
+    feature = "baseline-jpeg",
+    not(any(feature = "esp-new-jpeg", feature = "rom-jpeg"))
+)))]
+async fn receive_raw(
+    socket: &mut TcpSocket<'_>,
+    assembler: &mut RawSource,
+    tcp_buf: &mut [u8],
+    drop_stale: bool,
+    decoder: &mut Decoder,
+    screen: &mut Screen,
+) {
+    assembler.reset();
+    receive_frames(socket, assembler, tcp_buf, 0, drop_stale, decoder, screen).await;
+}
+
+/// Show the frames of a raw MJPEG stream until the connection ends, each
+/// block as soon as its data has arrived instead of once the whole frame
+/// has. With `drop_stale`, whatever queued up while a frame was shown is
+/// skipped.
+#[cfg(all(
+    feature = "baseline-jpeg",
+    not(any(feature = "esp-new-jpeg", feature = "rom-jpeg"))
+))]
+async fn receive_raw(
+    socket: &mut TcpSocket<'_>,
+    stream: &mut RawSource,
+    tcp_buf: &mut [u8],
+    drop_stale: bool,
+    decoder: &mut Decoder,
+    screen: &mut Screen,
+) {
+    stream.reset();
+    loop {
+        // --- Wait for the next frame's headers ---
+        let info = match stream.header() {
+            Ok(info) => info,
+            Err(JpegError::NoMoreData) => {
+                match embedded_io_async::Read::read(socket, stream.spare()).await {
+                    Ok(0) => {
+                        println!("connection closed");
+                        return;
                     }
+                    Ok(n) => {
+                        STATS.received(n);
+                        stream.commit(n);
+                    }
+                    Err(e) => {
+                        println!("read error: {:?}", e);
+                        return;
+                    }
+                }
+                continue;
+            }
+            Err(e) => {
+                STATS.frame_dropped();
+                println!("dropped frame: {}", e);
+                continue;
+            }
+        };
+        let layout = lay_out(decoder, screen, (info.width, info.height)).await;
+
+        // --- Decode and send each block as soon as its data is in ---
+        let start = Instant::now();
+        let session = match decoder.start_stream(stream) {
+            Ok(session) => session,
+            Err(e) => {
+                println!("decode error: {}", e);
+                STATS.decode_failed();
+                continue;
+            }
+        };
+        let mut blocks = Incoming::new(session, socket);
+        let drawn = screen
+            .pipeline
+            .draw(&layout, &mut blocks, &mut screen.panel)
+            .await;
+        STATS.received(blocks.received());
+        match drawn {
+            Ok(()) => {
+                let elapsed = start.elapsed().as_micros() as u32;
+                STATS.frame_shown(info.width, info.height, elapsed);
+            }
+            Err(DrawError::Decode(IncomingError::Decode(e))) => {
+                println!("block decode error: {}", e);
+                STATS.decode_failed();
+            }
+            Err(DrawError::Decode(e)) => {
+                println!("{}", e);
+                return;
+            }
+            Err(DrawError::Sink(e)) => println!("panel write error: {:?}", e),
+        }
+
+        if drop_stale {
+            stream.reset();
+            if !drain(socket, tcp_buf).await {
+                return;
+            }
+        }
+    }
+}
+
+/// Push `input` through `source` and show the first frame that completes.
+/// Returns how much of `input` was used up to the end of that frame, or
+/// `None` if all of it went in without completing one.
+async fn feed(
+    source: &mut impl FrameSource,
+    input: &[u8],
+    decoder: &mut impl JpegBackend,
+    screen: &mut Screen,
+) -> Option<usize> {
+    let mut used = 0;
+    while used < input.len() {
+        match source.push(&input[used..]) {
+            Status::Incomplete => break,
+            Status::Dropped { consumed, reason } => {
+                STATS.frame_dropped();
+                println!("dropped frame: {:?}", reason);
+                used += consumed;
+            }
+            Status::Frame { consumed } => {
+                if let Some(jpeg_data) = source.frame_mut() {
+                    show_frame(decoder, screen, jpeg_data).await;
                 }
+                return Some(used + consumed);
+            }
+        }
+    }
+    None
+}
+
+/// Receive RTP/JPEG on `port`. Unlike TCP there is no backlog to drain: a
+/// frame that loses packets is dropped and the next one is shown.
+async fn receive_rtp(
+    stack: Stack<'static>,
+    port: u16,
+    playback: &PlaybackConfig,
+    decoder: &mut impl JpegBackend,
+    screen: &mut Screen,
+) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 24];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = vec![0u8; 24 * 1024];
+    let mut tx_buffer = vec![0u8; 64];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(port).expect("failed to bind RTP port");
+    println!("listening for RTP/JPEG on UDP port {}", port);
+
+    let mut depacketizer = Depacketizer::new(playback.frame_capacity as usize);
+    let mut packet = [0u8; 2048];
+    loop {
+        match socket.recv_from(&mut packet).await {
+            Ok((n, _)) => {
+                STATS.received(n);
+                feed(&mut depacketizer, &packet[..n], decoder, screen).await;
+            }
+            Err(e) => println!("UDP receive error: {:?}", e),
+        }
+    }
+}
 
-                // Bulk copy remaining tcp data into frame_buf
-                let space = frame_buf.len() - frame_len;
-                let avail = n - pos;
-                let copy_len = avail.min(space);
-                if copy_len == 0 {
-                    in_frame = false;
-                    frame_len = 0;
-                    pos += 1;
-                    continue;
+/// How to show a stream of `size` on the panel. The layout is made again,
+/// with the decoder and the bars to match, whenever the size changes.
+async fn lay_out<D: JpegBackend>(decoder: &mut D, screen: &mut Screen, size: (u16, u16)) -> Layout {
+    if let Some((laid_out, layout)) = screen.layout
+        && laid_out == size
+    {
+        return layout;
+    }
+    let panel = (screen.width, screen.height);
+    let mut layout = Layout::new(screen.fit, size, panel, MAX_DECODER_OUTPUT);
+    println!("{}x{} stream, shown {:?}", size.0, size.1, layout.target);
+    let mut configured = configure(decoder, &layout);
+    if !configured && layout.scale != (0, 0) {
+        // Lay the stream out without the decoder's help instead
+        layout = Layout::new(screen.fit, size, panel, 0);
+        configured = configure(decoder, &layout);
+    }
+    DECODER.lock(|config| *config.borrow_mut() = Some(*decoder.config()));
+    let bars = layout.bars(panel);
+    if let Err(e) = screen.pipeline.clear(bars, &mut screen.panel).await {
+        println!("panel write error: {:?}", e);
+    }
+    // A layout the decoder isn't set up for is made again for the next frame
+    if configured {
+        screen.layout = Some((size, layout));
+    }
+    layout
+}
+
+/// Set `decoder` up for `layout`, unless it already is. False if it can't
+/// be.
+fn configure<D: JpegBackend>(decoder: &mut D, layout: &Layout) -> bool {
+    match layout.decoder_config(PANEL_FORMAT) {
+        Ok(config) if config == *decoder.config() => true,
+        Ok(config) => match decoder.reconfigure(config) {
+            Ok(()) => true,
+            Err(e) => {
+                println!("decoder reconfigure error: {}", e);
+                false
+            }
+        },
+        Err(e) => {
+            println!("decoder config error: {}", e);
+            false
+        }
+    }
+}
+
+/// Decode one frame onto the panel, sending each block while the next one
+/// decodes.
+async fn show_frame<D: JpegBackend>(decoder: &mut D, screen: &mut Screen, jpeg_data: &mut [u8]) {
+    // --- Lay out the stream, again whenever its size changes ---
+    let Ok(summary) = markers::parse(jpeg_data) else {
+        println!("not a JPEG frame");
+        STATS.decode_failed();
+        return;
+    };
+    let layout = lay_out(decoder, screen, (summary.width, summary.height)).await;
+
+    // --- Decode, sending each block while the next one decodes ---
+    let start = Instant::now();
+    let mut frame_error = None;
+    match decoder.start_decode(jpeg_data) {
+        Ok(mut session) => {
+            let info = *session.info();
+            let drawn = screen
+                .pipeline
+                .draw(&layout, &mut session, &mut screen.panel)
+                .await;
+            match drawn {
+                Ok(()) => {
+                    let elapsed = start.elapsed().as_micros() as u32;
+                    STATS.frame_shown(info.width, info.height, elapsed);
+                }
+                Err(DrawError::Decode(e)) => {
+                    println!("block decode error: {}", e);
+                    frame_error = Some(e);
                 }
+                Err(DrawError::Sink(e)) => println!("panel write error: {:?}", e),
+            }
+        }
+        Err(e) => {
+            println!("decode error: {}", e);
+            frame_error = Some(e);
+        }
+    }
+    if frame_error.is_some() {
+        STATS.decode_failed();
+    }
 
-                frame_buf[frame_len..frame_len + copy_len]
-                    .copy_from_slice(&tcp_buf[pos..pos + copy_len]);
-
-                let scan_start = if frame_len > 0 { frame_len - 1 } else { 0 };
-                frame_len += copy_len;
-                pos += copy_len;
-
-                if let Some(eoi) = find_marker(&frame_buf[scan_start..frame_len], 0xFF, 0xD9) {
-                    let eoi_end = scan_start + eoi + 2;
-
-                    // --- Decode with per-block yield ---
-                    let jpeg_data = &mut frame_buf[..eoi_end];
-                    match decoder.start_decode(jpeg_data) {
-                        Ok(mut session) => {
-                            for block_idx in 0..session.block_count() {
-                                let (block_width, block_height) = match session.decode_next_block()
-                                {
-                                    Ok(v) => v,
-                                    Err(e) => {
-                                        println!("block decode error: {}", e);
-                                        break;
-                                    }
-                                };
-
-                                let start_row = (block_idx as u16) * block_height;
-                                let visible_rows =
-                                    if start_row + block_height > DISPLAY_HEIGHT {
-                                        if start_row >= DISPLAY_HEIGHT {
-                                            continue;
-                                        }
-                                        DISPLAY_HEIGHT - start_row
-                                    } else {
-                                        block_height
-                                    };
-                                let end_row = start_row + visible_rows - 1;
-                                let pixel_count =
-                                    (block_width as usize) * (visible_rows as usize);
-
-                                let data = session.block_data();
-                                let pixels = unsafe {
-                                    core::slice::from_raw_parts(
-                                        data.as_ptr() as *const u16,
-                                        pixel_count,
-                                    )
-                                };
-                                let _ = display.set_pixels(
-                                    0,
-                                    start_row,
-                                    block_width - 1,
-                                    end_row,
-                                    pixels.iter().map(|&raw| Rgb565::from(RawU16::new(raw))),
-                                );
-
-                                // Yield to let the network task process TCP ACKs
-                                embassy_futures::yield_now().await;
-                            }
+    // Corrupt frames are simply skipped. Running out of memory says nothing
+    // about the frame, so release the decoder's internal buffers before
+    // trying the next one.
+    if let Some(e) = frame_error
+        && e.is_out_of_memory()
+    {
+        println!("reopening JPEG decoder");
+        STATS.decoder_reset();
+        if let Err(e) = decoder.reset() {
+            println!("decoder reset error: {}", e);
+        }
+    }
+}
+
+/// Keep the badge on the best known network, picking another one when it
+/// drops or the shell changes the list.
+#[embassy_executor::task]
+async fn connection(mut controller: WifiController<'static>, mut selector: Selector) {
+    println!("start connection task");
+    println!("Device capabilities: {:?}", controller.capabilities());
+    let mut action = match selector.current() {
+        Some(_) => Action::Stay,
+        None => selector.start(),
+    };
+    loop {
+        NETWORK.lock(|network| *network.borrow_mut() = selector.current().cloned());
+        action = match action {
+            Action::Scan => {
+                let found = scan(&mut controller).await;
+                selector.scanned(&found)
+            }
+            Action::Join(candidate) => {
+                if try_join(&mut controller, &candidate).await {
+                    selector.joined()
+                } else {
+                    selector.failed()
+                }
+            }
+            Action::Stay => {
+                if !matches!(esp_radio::wifi::sta_state(), WifiStaState::Connected) {
+                    selector.disconnected()
+                } else {
+                    let disconnected = controller.wait_for_event(WifiEvent::StaDisconnected);
+                    match select(disconnected, WIFI_CHANGE.wait()).await {
+                        Either::First(()) => {
+                            println!("Wifi disconnected");
+                            Timer::after(Duration::from_millis(5000)).await;
+                            selector.disconnected()
                         }
-                        Err(e) => {
-                            println!("decode error: {}", e);
+                        Either::Second(change) => {
+                            let next = selector.set_networks(change.networks, change.reconnect);
+                            if next != Action::Stay {
+                                let _ = controller.disconnect_async().await;
+                            }
+                            next
                         }
                     }
+                }
+            }
+            Action::Wait => {
+                println!("no known network could be joined");
+                match select(Timer::after(RESCAN_DELAY), WIFI_CHANGE.wait()).await {
+                    Either::First(()) => selector.start(),
+                    Either::Second(change) => selector.set_networks(change.networks, true),
+                }
+            }
+        };
+    }
+}
 
-                    // --- Frame dropping: drain stale data from the socket ---
-                    // After decode+display, the TCP buffer may have accumulated
-                    // multiple frames. Drain them so we always show the latest.
-                    frame_len = 0;
-                    in_frame = false;
-                    loop {
-                        match embassy_time::with_timeout(
-                            Duration::from_ticks(1),
-                            embedded_io_async::Read::read(&mut socket, &mut tcp_buf),
-                        )
-                        .await
-                        {
-                            Ok(Ok(0)) => break 'recv, // connection closed
-                            Ok(Ok(_)) => continue,    // discard stale data
-                            Ok(Err(_)) => break 'recv,
-                            Err(_) => break,           // timeout = no more queued data
-                        }
-                    }
-                    break; // back to main read loop with empty state
+fn client_config(wifi: &WifiConfig) -> ClientConfig {
+    ClientConfig::default()
+        .with_ssid(wifi.ssid.as_str().into())
+        .with_password(wifi.password.as_str().into())
+}
+
+/// Join one of the known networks at boot, giving up once each has been
+/// tried. Once joined, the `connection` task takes over.
+async fn join(controller: &mut WifiController<'static>, selector: &mut Selector) -> bool {
+    if let Err(e) = controller.set_config(&ModeConfig::Client(ClientConfig::default())) {
+        println!("wifi config error: {:?}", e);
+        return false;
+    }
+    println!("Starting wifi");
+    if let Err(e) = controller.start_async().await {
+        println!("wifi start error: {:?}", e);
+        return false;
+    }
+    let mut action = selector.start();
+    loop {
+        action = match action {
+            Action::Scan => {
+                let found = scan(controller).await;
+                selector.scanned(&found)
+            }
+            Action::Join(candidate) => {
+                if try_join(controller, &candidate).await {
+                    selector.joined()
+                } else {
+                    selector.failed()
                 }
             }
+            Action::Stay => return true,
+            Action::Wait => return false,
+        };
+    }
+}
+
+/// One attempt at joining `candidate`.
+async fn try_join(controller: &mut WifiController<'static>, candidate: &Candidate) -> bool {
+    let ssid = &candidate.network.ssid;
+    match candidate.rssi {
+        Some(rssi) => println!("joining {} ({} dBm)...", ssid, rssi),
+        None => println!("joining {} (not seen in scan)...", ssid),
+    }
+    let config = ModeConfig::Client(client_config(&candidate.network));
+    if let Err(e) = controller.set_config(&config) {
+        println!("wifi config error: {:?}", e);
+        return false;
+    }
+    match controller.connect_async().await {
+        Ok(_) => {
+            println!("Wifi connected to {}", ssid);
+            true
+        }
+        Err(e) => {
+            println!("Failed to connect to wifi: {e:?}");
+            Timer::after(Duration::from_millis(2000)).await;
+            false
         }
     }
 }
 
+// ---------------------------------------------------------------------------
+// Provisioning portal
+// ---------------------------------------------------------------------------
+
+/// Open an access point named after the badge and serve the setup page on
+/// it until a configuration is saved, then restart with it.
+async fn provision(
+    spawner: Spawner,
+    mut controller: WifiController<'static>,
+    device: WifiDevice<'static>,
+    seed: u64,
+    mut store: ConfigStore<FlashStorage<'static>>,
+    config: Config,
+) -> ! {
+    let mac = Efuse::mac_address();
+    let ssid = format!("rumble-{:02x}{:02x}", mac[4], mac[5]);
+    // AP+STA, since scanning needs the station interface
+    let _ = controller.stop_async().await;
+    let mode = ModeConfig::ApSta(
+        ClientConfig::default(),
+        AccessPointConfig::default().with_ssid(ssid.as_str().into()),
+    );
+    controller.set_config(&mode).unwrap();
+    controller.start_async().await.unwrap();
+    println!("no network, join {} and open http://{}/", ssid, PORTAL_ADDR);
+
+    let net_config = embassy_net::Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(PORTAL_ADDR, PORTAL_PREFIX_LEN),
+        gateway: None,
+        dns_servers: Default::default(),
+    });
+    let (stack, runner) = embassy_net::new(
+        device,
+        net_config,
+        mk_static!(StackResources<4>, StackResources::<4>::new()),
+        seed,
+    );
+    spawner.spawn(net_task(runner)).ok();
+    spawner.spawn(dhcp_server(stack)).ok();
+    spawner.spawn(dns_server(stack)).ok();
+
+    let mut networks = scan(&mut controller).await;
+    let mut rx_buffer = vec![0u8; 2048];
+    let mut tx_buffer = vec![0u8; 4096];
+    let mut request_buf = vec![0u8; 2048];
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+        if let Err(e) = socket.accept(web::HTTP_PORT).await {
+            println!("accept error: {:?}", e);
+            continue;
+        }
+
+        let result = match web::read_request(&mut socket, &mut request_buf).await {
+            Ok(request) => match web::route(&request, &config) {
+                Route::Page => web::write_page(&mut socket, &config, &networks, None).await,
+                Route::Scan => {
+                    networks = scan(&mut controller).await;
+                    web::write_page(&mut socket, &config, &networks, None).await
+                }
+                Route::Invalid(message) => {
+                    web::write_page(&mut socket, &config, &networks, Some(message)).await
+                }
+                Route::Redirect => web::write_redirect(&mut socket, PORTAL_ADDR).await,
+                Route::Save(new) => match store.save(&new) {
+                    Ok(()) => {
+                        let ssid = new.preferred_network().map_or("", |n| n.ssid.as_str());
+                        let _ = web::write_saved(&mut socket, ssid).await;
+                        socket.close();
+                        let _ = with_timeout(Duration::from_secs(1), socket.flush()).await;
+                        println!("configuration saved, restarting");
+                        Timer::after(Duration::from_millis(500)).await;
+                        esp_hal::system::software_reset();
+                    }
+                    Err(e) => {
+                        println!("config save error: {}", e);
+                        let notice = Some("Saving failed, please try again.");
+                        web::write_page(&mut socket, &config, &networks, notice).await
+                    }
+                },
+            },
+            Err(e) => {
+                println!("portal request error: {}", e);
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            println!("portal write error: {:?}", e);
+        }
+        socket.close();
+        let _ = with_timeout(Duration::from_secs(1), socket.flush()).await;
+    }
+}
+
+/// Networks in range.
+async fn scan(controller: &mut WifiController<'static>) -> Vec<Network> {
+    match controller
+        .scan_with_config_async(ScanConfig::default())
+        .await
+    {
+        Ok(found) => found
+            .into_iter()
+            .map(|ap| Network {
+                ssid: ap.ssid.as_str().into(),
+                rssi: ap.signal_strength,
+                secure: !matches!(ap.auth_method, None | Some(AuthMethod::None)),
+            })
+            .collect(),
+        Err(e) => {
+            println!("scan error: {:?}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Hand out addresses to phones joining the portal.
 #[embassy_executor::task]
-async fn connection(mut controller: WifiController<'static>) {
-    println!("start connection task");
-    println!("Device capabilities: {:?}", controller.capabilities());
+async fn dhcp_server(stack: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = vec![0u8; 2048];
+    let mut tx_buffer = vec![0u8; 2048];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket
+        .bind(dhcp::SERVER_PORT)
+        .expect("failed to bind DHCP port");
+
+    // The addresses after the badge's own
+    let mut server = DhcpServer::new(PORTAL_ADDR, Ipv4Addr::new(192, 168, 4, 2), 8)
+        .expect("no room for the DHCP pool");
+    let mut request = vec![0u8; 1024];
+    let mut response = vec![0u8; 576];
+    loop {
+        let n = match socket.recv_from(&mut request).await {
+            Ok((n, _)) => n,
+            Err(e) => {
+                println!("DHCP receive error: {:?}", e);
+                continue;
+            }
+        };
+        if let Some(len) = server.handle(&request[..n], &mut response) {
+            let to = (Ipv4Addr::BROADCAST, dhcp::CLIENT_PORT);
+            if let Err(e) = socket.send_to(&response[..len], to).await {
+                println!("DHCP send error: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Answer every DNS lookup with the portal's address.
+#[embassy_executor::task]
+async fn dns_server(stack: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 8];
+    let mut tx_meta = [PacketMetadata::EMPTY; 8];
+    let mut rx_buffer = vec![0u8; 2048];
+    let mut tx_buffer = vec![0u8; 2048];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(dns::DNS_PORT).expect("failed to bind DNS port");
+
+    let mut query = [0u8; 512];
+    let mut response = [0u8; 512];
+    loop {
+        let (n, from) = match socket.recv_from(&mut query).await {
+            Ok(received) => received,
+            Err(e) => {
+                println!("DNS receive error: {:?}", e);
+                continue;
+            }
+        };
+        if let Some(len) = dns::answer(&query[..n], PORTAL_ADDR, &mut response)
+            && let Err(e) = socket.send_to(&response[..len], from).await
+        {
+            println!("DNS send error: {:?}", e);
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Serial console
+// ---------------------------------------------------------------------------
+
+/// What the shell works on.
+struct Console {
+    config: Config,
+    store: ConfigStore<FlashStorage<'static>>,
+    stack: Stack<'static>,
+}
+
+impl Target for Console {
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn save(&mut self, config: Config) -> Result<(), String> {
+        self.store.save(&config).map_err(|e| e.to_string())?;
+        self.config = config;
+        Ok(())
+    }
+
+    fn reset_config(&mut self) -> Result<(), String> {
+        self.store.clear().map_err(|e| e.to_string())
+    }
+
+    fn networks_changed(&mut self, reconnect: bool) {
+        WIFI_CHANGE.signal(NetworkChange {
+            networks: self.config.networks.clone(),
+            reconnect,
+        });
+    }
+
+    fn network(&self) -> Option<Candidate> {
+        NETWORK.lock(|network| network.borrow().clone())
+    }
+
+    fn address(&self) -> Option<Ipv4Addr> {
+        self.stack.config_v4().map(|c| c.address.address())
+    }
+
+    fn stats(&self) -> &Stats {
+        &STATS
+    }
+
+    fn decoder_config(&self) -> JpegDecoderConfig {
+        DECODER.lock(|decoder| decoder.borrow().unwrap_or_default())
+    }
+
+    fn heap(&self) -> (usize, usize) {
+        (esp_alloc::HEAP.used(), esp_alloc::HEAP.free())
+    }
+
+    fn codec_heap(&self) -> HeapStats {
+        heap::stats()
+    }
+
+    fn uptime_ms(&self) -> u64 {
+        Instant::now().as_millis()
+    }
+
+    fn reboot(&mut self) {
+        esp_hal::system::software_reset();
+    }
+}
+
+/// Read commands from the USB serial/JTAG console and run them.
+#[embassy_executor::task]
+async fn console_task(mut rx: UsbSerialJtagRx<'static, Async>, mut console: Console) {
+    let mut line = LineBuffer::new();
+    let mut buf = [0u8; 64];
+    print!("> ");
     loop {
-        match esp_radio::wifi::sta_state() {
-            WifiStaState::Connected => {
-                controller.wait_for_event(WifiEvent::StaDisconnected).await;
-                Timer::after(Duration::from_millis(5000)).await
-            }
-            _ => {}
-        }
-        if !matches!(controller.is_started(), Ok(true)) {
-            let client_config = ModeConfig::Client(
-                ClientConfig::default()
-                    .with_ssid(SSID.into())
-                    .with_password(PASSWORD.into()),
-            );
-            controller.set_config(&client_config).unwrap();
-            println!("Starting wifi");
-            controller.start_async().await.unwrap();
-            println!("Wifi started!");
-        }
-        println!("About to connect...");
-
-        match controller.connect_async().await {
-            Ok(_) => println!("Wifi connected!"),
+        let n = match embedded_io_async::Read::read(&mut rx, &mut buf).await {
+            Ok(n) => n,
             Err(e) => {
-                println!("Failed to connect to wifi: {e:?}");
-                Timer::after(Duration::from_millis(5000)).await
+                println!("console read error: {:?}", e);
+                continue;
+            }
+        };
+        for &byte in &buf[..n] {
+            match line.push(byte) {
+                Edit::None => {}
+                Edit::Echo(byte) => Printer::write_bytes(&[byte]),
+                Edit::Erase => print!("\x08 \x08"),
+                Edit::Line(text) => {
+                    println!();
+                    match Command::parse(&text) {
+                        Ok(command) => {
+                            let _ = shell::execute(command, &mut console, &mut Printer);
+                        }
+                        Err(ParseError::Empty) => {}
+                        Err(e) => println!("{}", e),
+                    }
+                    print!("> ");
+                }
             }
         }
     }
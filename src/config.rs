@@ -0,0 +1,631 @@
+//! Runtime configuration, persisted in a flash partition.
+//!
+//! [`Config::default`] takes its values from `RUMBLE_*` environment variables
+//! at build time, falling back to the values the firmware always had.
+//! [`ConfigStore`] keeps one record in flash that overrides them:
+//!
+//! ```text
+//! "RBCF" | version: u16 LE | len: u16 LE | payload: len bytes | CRC-32 LE
+//! ```
+//!
+//! The CRC (IEEE, as in zlib) covers version, length and payload. Each
+//! version only appends fields to the payload, so a record written by older
+//! firmware decodes with defaults for whatever it lacks.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crc::{CRC_32_ISO_HDLC, Crc};
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::fit::FitMode;
+
+const MAGIC: [u8; 4] = *b"RBCF";
+/// Current record layout version.
+pub const VERSION: u16 = 3;
+const HEADER_LEN: usize = 8;
+const CRC_LEN: usize = 4;
+/// Largest record the store reads or writes.
+pub const MAX_RECORD: usize = 1024;
+/// Known networks kept, so that a full list still fits in a record.
+pub const MAX_NETWORKS: usize = 8;
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+// ---------------------------------------------------------------------------
+// Configuration
+// ---------------------------------------------------------------------------
+
+/// How frames reach the badge.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Transport {
+    /// Connect to the server and read raw concatenated JPEGs.
+    Tcp,
+    /// Connect to the server and GET a `multipart/x-mixed-replace` stream.
+    Http { path: String },
+    /// Receive RTP/JPEG (RFC 2435) on a UDP port.
+    Rtp { port: u16 },
+    /// Accept raw MJPEG streams pushed to a TCP port. A newer connection
+    /// replaces the current one.
+    Listen { port: u16 },
+}
+
+impl Transport {
+    /// Parse `tcp`, `http:<path>`, `rtp:<port>` or `listen:<port>`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (kind, arg) = match s.split_once(':') {
+            Some((kind, arg)) => (kind, Some(arg)),
+            None => (s, None),
+        };
+        match (kind, arg) {
+            ("tcp", None) => Some(Self::Tcp),
+            ("http", None) => Some(Self::Http {
+                path: "/".to_string(),
+            }),
+            ("http", Some(path)) if path.starts_with('/') => Some(Self::Http {
+                path: path.to_string(),
+            }),
+            ("rtp", Some(port)) => Some(Self::Rtp {
+                port: port.parse().ok()?,
+            }),
+            ("listen", Some(port)) => Some(Self::Listen {
+                port: port.parse().ok()?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl core::fmt::Display for Transport {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Tcp => f.write_str("tcp"),
+            Self::Http { path } => write!(f, "http:{}", path),
+            Self::Rtp { port } => write!(f, "rtp:{}", port),
+            Self::Listen { port } => write!(f, "listen:{}", port),
+        }
+    }
+}
+
+/// A known Wi-Fi network.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WifiConfig {
+    pub ssid: String,
+    pub password: String,
+    /// Networks with a higher priority are tried first, whatever their
+    /// signal strength.
+    pub priority: u8,
+}
+
+impl WifiConfig {
+    /// Check the lengths the radio accepts. WPA passphrases are 8 to 63
+    /// characters; open networks have none.
+    pub fn new(ssid: String, password: String) -> Result<Self, &'static str> {
+        if ssid.is_empty() || ssid.len() > 32 {
+            return Err("The network name must be 1 to 32 bytes long.");
+        }
+        if !password.is_empty() && !(8..=63).contains(&password.len()) {
+            return Err("The password must be 8 to 63 characters long.");
+        }
+        Ok(Self {
+            ssid,
+            password,
+            priority: 0,
+        })
+    }
+
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+}
+
+/// Panel geometry as seen after rotation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DisplayConfig {
+    pub width: u16,
+    pub height: u16,
+    /// Offset of the visible area in panel RAM, in the panel's native
+    /// orientation.
+    pub offset_x: u16,
+    pub offset_y: u16,
+    pub invert_colors: bool,
+    /// How streams of another size are shown.
+    pub fit: FitMode,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlaybackConfig {
+    /// After showing a frame over TCP, discard whatever queued up meanwhile
+    /// so the picture stays live instead of falling behind.
+    pub drop_stale_frames: bool,
+    /// Largest JPEG frame accepted, in bytes.
+    /// A decoder that starts on raw TCP frames before they are complete
+    /// holds at most this much of the stream instead.
+    pub frame_capacity: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Known networks, at most [`MAX_NETWORKS`]. Empty opens the portal.
+    pub networks: Vec<WifiConfig>,
+    /// `host:port` of the stream server, or `None` to discover one with mDNS.
+    pub server: Option<String>,
+    pub transport: Transport,
+    pub display: DisplayConfig,
+    pub playback: PlaybackConfig,
+}
+
+impl Default for Config {
+    /// Build-time defaults. `RUMBLE_SSID` and `RUMBLE_SERVER` may be empty
+    /// for no network and mDNS discovery; unparsable values fall back to the
+    /// built-in ones.
+    fn default() -> Self {
+        let ssid = option_env!("RUMBLE_SSID").unwrap_or("ylikellotus");
+        let password = option_env!("RUMBLE_PASSWORD").unwrap_or("alakerta");
+        let server = option_env!("RUMBLE_SERVER").unwrap_or("172.20.10.8:3000");
+        Self {
+            networks: WifiConfig::new(ssid.to_string(), password.to_string())
+                .into_iter()
+                .collect(),
+            server: (!server.is_empty()).then(|| server.to_string()),
+            transport: option_env!("RUMBLE_TRANSPORT")
+                .and_then(Transport::parse)
+                .unwrap_or(Transport::Tcp),
+            display: DisplayConfig {
+                width: 320,
+                height: 170,
+                offset_x: 35,
+                offset_y: 0,
+                invert_colors: true,
+                fit: option_env!("RUMBLE_FIT")
+                    .and_then(FitMode::parse)
+                    .unwrap_or_default(),
+            },
+            playback: PlaybackConfig {
+                drop_stale_frames: true,
+                frame_capacity: 30 * 1024,
+            },
+        }
+    }
+}
+
+impl Config {
+    /// Add `network`, replacing a saved one with the same name.
+    pub fn add_network(&mut self, network: WifiConfig) -> Result<(), &'static str> {
+        match self.networks.iter().position(|n| n.ssid == network.ssid) {
+            Some(i) => self.networks[i] = network,
+            None if self.networks.len() == MAX_NETWORKS => {
+                return Err("Too many saved networks, remove one first.");
+            }
+            None => self.networks.push(network),
+        }
+        Ok(())
+    }
+
+    /// Add `network` with a priority above all the others.
+    pub fn add_preferred_network(&mut self, network: WifiConfig) -> Result<(), &'static str> {
+        let top = self
+            .networks
+            .iter()
+            .filter(|n| n.ssid != network.ssid)
+            .map(|n| n.priority.saturating_add(1))
+            .max()
+            .unwrap_or(0);
+        self.add_network(network.with_priority(top))
+    }
+
+    /// Forget the network called `ssid`. Returns whether it was known.
+    pub fn remove_network(&mut self, ssid: &str) -> bool {
+        let len = self.networks.len();
+        self.networks.retain(|n| n.ssid != ssid);
+        self.networks.len() != len
+    }
+
+    /// The network tried first when all are in range.
+    pub fn preferred_network(&self) -> Option<&WifiConfig> {
+        // The first of equals, as the list is in the order they were added
+        self.networks.iter().rev().max_by_key(|n| n.priority)
+    }
+
+    /// Server and transport as one URL, as accepted by
+    /// [`Config::with_stream_url`].
+    pub fn stream_url(&self) -> String {
+        let server = self.server.as_deref().unwrap_or("");
+        match &self.transport {
+            Transport::Tcp if server.is_empty() => String::new(),
+            Transport::Tcp => format!("tcp://{}", server),
+            Transport::Http { path } => format!("http://{}{}", server, path),
+            Transport::Rtp { port } => format!("rtp://@:{}", port),
+            Transport::Listen { port } => format!("tcp://@:{}", port),
+        }
+    }
+
+    /// Set server and transport from a URL, `@` meaning the badge itself:
+    ///
+    /// - `tcp://host:port`, `http://host[:port][/path]`: connect to a server
+    /// - `tcp://@:port`: accept pushed streams
+    /// - `rtp://@:port`: receive RTP/JPEG
+    ///
+    /// Leaving out the host, or the whole URL, discovers the server with
+    /// mDNS.
+    pub fn with_stream_url(mut self, url: &str) -> Option<Self> {
+        let url = url.trim();
+        let (scheme, rest) = url.split_once("://").unwrap_or(("tcp", url));
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+        let local_port = authority
+            .strip_prefix('@')
+            .unwrap_or(authority)
+            .strip_prefix(':')
+            .and_then(|port| port.parse().ok());
+
+        let (server, transport) = match scheme.to_ascii_lowercase().as_str() {
+            "tcp" if !path.is_empty() => return None,
+            "tcp" if authority.starts_with(['@', ':']) => {
+                (None, Transport::Listen { port: local_port? })
+            }
+            "tcp" => (Some(authority.to_string()), Transport::Tcp),
+            "rtp" => (None, Transport::Rtp { port: local_port? }),
+            "http" => {
+                let server = if authority.contains(':') {
+                    authority.to_string()
+                } else {
+                    format!("{}:80", authority)
+                };
+                let path = if path.is_empty() { "/" } else { path };
+                let transport = Transport::Http {
+                    path: path.to_string(),
+                };
+                (Some(server), transport)
+            }
+            _ => return None,
+        };
+        // No host means discovery; a host needs a port
+        self.server = match server {
+            Some(server) if server.starts_with(':') || server.is_empty() => None,
+            Some(server) => {
+                let (host, port) = server.rsplit_once(':')?;
+                if host.is_empty() || port.parse::<u16>().is_err() {
+                    return None;
+                }
+                Some(server)
+            }
+            None => None,
+        };
+        self.transport = transport;
+        Some(self)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Errors
+// ---------------------------------------------------------------------------
+
+#[derive(Debug)]
+pub enum ConfigError<E> {
+    Flash(E),
+    /// Nothing has been saved yet (erased flash).
+    Empty,
+    /// The partition holds something that isn't a config record.
+    BadMagic,
+    /// Written by newer firmware.
+    UnsupportedVersion(u16),
+    BadCrc,
+    /// The CRC matched but the payload didn't decode.
+    Corrupt,
+    /// The record would not fit in [`MAX_RECORD`] or the partition.
+    TooLarge,
+}
+
+impl<E: core::fmt::Debug> core::fmt::Display for ConfigError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Flash(e) => write!(f, "flash error: {:?}", e),
+            Self::Empty => f.write_str("no saved configuration"),
+            Self::BadMagic => f.write_str("not a configuration record"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported configuration version {}", v),
+            Self::BadCrc => f.write_str("configuration CRC mismatch"),
+            Self::Corrupt => f.write_str("configuration record is corrupt"),
+            Self::TooLarge => f.write_str("configuration too large"),
+        }
+    }
+}
+
+impl<E: core::fmt::Debug> core::error::Error for ConfigError<E> {}
+
+// ---------------------------------------------------------------------------
+// Serialization
+// ---------------------------------------------------------------------------
+
+impl Config {
+    /// Serialize to a complete record, header and CRC included.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer(Vec::new());
+        // Version 1 had exactly one network here; the rest follow playback
+        let (first, others) = match self.networks.split_first() {
+            Some((first, others)) => (Some(first), others),
+            None => (None, &[][..]),
+        };
+        w.str(first.map_or("", |n| &n.ssid));
+        w.str(first.map_or("", |n| &n.password));
+        match &self.server {
+            Some(server) => {
+                w.u8(1);
+                w.str(server);
+            }
+            None => w.u8(0),
+        }
+        match &self.transport {
+            Transport::Tcp => w.u8(0),
+            Transport::Http { path } => {
+                w.u8(1);
+                w.str(path);
+            }
+            Transport::Rtp { port } => {
+                w.u8(2);
+                w.u16(*port);
+            }
+            Transport::Listen { port } => {
+                w.u8(3);
+                w.u16(*port);
+            }
+        }
+        w.u16(self.display.width);
+        w.u16(self.display.height);
+        w.u16(self.display.offset_x);
+        w.u16(self.display.offset_y);
+        w.u8(self.display.invert_colors as u8);
+        w.u8(self.playback.drop_stale_frames as u8);
+        w.u32(self.playback.frame_capacity);
+        w.u8(first.map_or(0, |n| n.priority));
+        let others = &others[..others.len().min(MAX_NETWORKS - 1)];
+        w.u8(others.len() as u8);
+        for network in others {
+            w.str(&network.ssid);
+            w.str(&network.password);
+            w.u8(network.priority);
+        }
+        w.u8(match self.display.fit {
+            FitMode::Fit => 0,
+            FitMode::Fill => 1,
+            FitMode::Center => 2,
+            FitMode::Stretch => 3,
+        });
+        let payload = w.0;
+
+        let mut record = Vec::with_capacity(HEADER_LEN + payload.len() + CRC_LEN);
+        record.extend_from_slice(&MAGIC);
+        record.extend_from_slice(&VERSION.to_le_bytes());
+        record.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        record.extend_from_slice(&payload);
+        let crc = CRC.checksum(&record[MAGIC.len()..]);
+        record.extend_from_slice(&crc.to_le_bytes());
+        record
+    }
+
+    /// Parse a record. Trailing bytes after the CRC are ignored, so this can
+    /// be handed a whole flash sector.
+    pub fn decode<E>(record: &[u8]) -> Result<Self, ConfigError<E>> {
+        if record.len() < HEADER_LEN {
+            return Err(ConfigError::Corrupt);
+        }
+        if record[..MAGIC.len()].iter().all(|&b| b == 0xFF) {
+            return Err(ConfigError::Empty);
+        }
+        if record[..MAGIC.len()] != MAGIC {
+            return Err(ConfigError::BadMagic);
+        }
+        let version = u16::from_le_bytes([record[4], record[5]]);
+        let len = u16::from_le_bytes([record[6], record[7]]) as usize;
+        let Some(crc) = record.get(HEADER_LEN + len..HEADER_LEN + len + CRC_LEN) else {
+            return Err(ConfigError::Corrupt);
+        };
+        if CRC.checksum(&record[MAGIC.len()..HEADER_LEN + len])
+            != u32::from_le_bytes(crc.try_into().unwrap())
+        {
+            return Err(ConfigError::BadCrc);
+        }
+        if version == 0 || version > VERSION {
+            return Err(ConfigError::UnsupportedVersion(version));
+        }
+        Self::decode_payload(&record[HEADER_LEN..HEADER_LEN + len]).ok_or(ConfigError::Corrupt)
+    }
+
+    /// Read fields in order until the payload ends; the rest keep their
+    /// defaults. This is the whole migration story while fields are only
+    /// ever appended. A change to an existing field's meaning needs a
+    /// version bump and a step here that looks at the version.
+    fn decode_payload(payload: &[u8]) -> Option<Self> {
+        let mut config = Self::default();
+        let mut r = Reader(payload);
+
+        let ssid = r.str()?;
+        let password = r.str()?;
+        config.networks.clear();
+        if !ssid.is_empty() {
+            config.networks.push(WifiConfig {
+                ssid,
+                password,
+                priority: 0,
+            });
+        }
+        config.server = match r.u8()? {
+            0 => None,
+            1 => Some(r.str()?),
+            _ => return None,
+        };
+        config.transport = match r.u8()? {
+            0 => Transport::Tcp,
+            1 => Transport::Http { path: r.str()? },
+            2 => Transport::Rtp { port: r.u16()? },
+            3 => Transport::Listen { port: r.u16()? },
+            _ => return None,
+        };
+        if r.is_empty() {
+            return Some(config);
+        }
+
+        config.display = DisplayConfig {
+            width: r.u16()?,
+            height: r.u16()?,
+            offset_x: r.u16()?,
+            offset_y: r.u16()?,
+            invert_colors: r.u8()? != 0,
+            ..config.display
+        };
+        if r.is_empty() {
+            return Some(config);
+        }
+
+        config.playback = PlaybackConfig {
+            drop_stale_frames: r.u8()? != 0,
+            frame_capacity: r.u32()?,
+        };
+        if r.is_empty() {
+            return Some(config);
+        }
+
+        // Version 2: more networks
+        let priority = r.u8()?;
+        if let Some(first) = config.networks.first_mut() {
+            first.priority = priority;
+        }
+        for _ in 0..r.u8()? {
+            config.networks.push(WifiConfig {
+                ssid: r.str()?,
+                password: r.str()?,
+                priority: r.u8()?,
+            });
+        }
+        if r.is_empty() {
+            return Some(config);
+        }
+
+        config.display.fit = match r.u8()? {
+            0 => FitMode::Fit,
+            1 => FitMode::Fill,
+            2 => FitMode::Center,
+            3 => FitMode::Stretch,
+            _ => return None,
+        };
+        Some(config)
+    }
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, v: u8) {
+        self.0.push(v);
+    }
+
+    fn u16(&mut self, v: u16) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    /// Length-prefixed; longer strings are cut at a character boundary.
+    fn str(&mut self, s: &str) {
+        let mut len = s.len().min(u8::MAX as usize);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.u8(len as u8);
+        self.0.extend_from_slice(&s.as_bytes()[..len]);
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn bytes(&mut self, n: usize) -> Option<&[u8]> {
+        let (head, rest) = self.0.split_at_checked(n)?;
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn str(&mut self) -> Option<String> {
+        let len = self.u8()? as usize;
+        let bytes = self.bytes(len)?;
+        core::str::from_utf8(bytes).ok().map(String::from)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Flash storage
+// ---------------------------------------------------------------------------
+
+/// The config record at the start of a flash region of `size` bytes.
+pub struct ConfigStore<F> {
+    flash: F,
+    offset: u32,
+    size: u32,
+}
+
+impl<F: NorFlash> ConfigStore<F> {
+    /// `offset` must be erase-block aligned, e.g. the start of a partition.
+    pub fn new(flash: F, offset: u32, size: u32) -> Self {
+        Self {
+            flash,
+            offset,
+            size,
+        }
+    }
+
+    pub fn load(&mut self) -> Result<Config, ConfigError<F::Error>> {
+        let len = MAX_RECORD.min(self.size as usize) / F::READ_SIZE * F::READ_SIZE;
+        let mut record = vec![0u8; len];
+        self.flash
+            .read(self.offset, &mut record)
+            .map_err(ConfigError::Flash)?;
+        Config::decode(&record)
+    }
+
+    pub fn save(&mut self, config: &Config) -> Result<(), ConfigError<F::Error>> {
+        let mut record = config.encode();
+        // Erased flash reads as 0xFF, so pad with that
+        record.resize(record.len().next_multiple_of(F::WRITE_SIZE), 0xFF);
+        let erase_len = record.len().next_multiple_of(F::ERASE_SIZE);
+        if record.len() > MAX_RECORD || erase_len > self.size as usize {
+            return Err(ConfigError::TooLarge);
+        }
+        self.flash
+            .erase(self.offset, self.offset + erase_len as u32)
+            .map_err(ConfigError::Flash)?;
+        self.flash
+            .write(self.offset, &record)
+            .map_err(ConfigError::Flash)
+    }
+
+    /// Forget the saved config so the defaults apply again.
+    pub fn clear(&mut self) -> Result<(), ConfigError<F::Error>> {
+        let erase_len = (F::ERASE_SIZE as u32).min(self.size);
+        self.flash
+            .erase(self.offset, self.offset + erase_len)
+            .map_err(ConfigError::Flash)
+    }
+}
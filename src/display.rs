@@ -0,0 +1,367 @@
+//! Where drawn frames end up.
+//!
+//! [`crate::pipeline::Pipeline`] hands each frame to a [`DisplaySink`] as
+//! positioned strips of RGB565, most significant byte first. The firmware's
+//! panel driver sends them out by SPI DMA; [`mipidsi::Display`] works as a
+//! (blocking) sink for any other panel, a [`Framebuffer`] keeps the frame in
+//! RAM, and with the `std` feature [`Snapshots`] saves every frame as an
+//! image, so the whole stream pipeline can run off the device.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::pixelcolor::raw::RawU16;
+
+use crate::fit::Rect;
+
+/// Receives frames strip by strip.
+#[allow(async_fn_in_trait)]
+pub trait DisplaySink {
+    type Error;
+    /// Address `window`: the next [`DisplaySink::write_pixels`] fills it.
+    async fn set_window(&mut self, window: Rect) -> Result<(), Self::Error>;
+
+    /// Fill the window set last row by row from `pixels`, two bytes each.
+    /// The transfer should start on the first poll, so that the next block
+    /// can be decoded while it runs.
+    async fn write_pixels(&mut self, pixels: &[u8]) -> Result<(), Self::Error>;
+
+    /// The frame is complete.
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<DI, M, RST> DisplaySink for mipidsi::Display<DI, M, RST>
+where
+    DI: mipidsi::interface::Interface,
+    M: mipidsi::models::Model<ColorFormat = Rgb565>,
+    Rgb565: mipidsi::interface::InterfacePixelFormat<DI::Word>,
+    RST: embedded_hal::digital::OutputPin,
+{
+    type Error = DI::Error;
+
+    async fn set_window(&mut self, window: Rect) -> Result<(), Self::Error> {
+        if window.is_empty() {
+            return Ok(());
+        }
+        // Sets the address window and starts the memory write, no pixels yet
+        self.set_pixels(
+            window.x,
+            window.y,
+            window.right() - 1,
+            window.bottom() - 1,
+            core::iter::empty(),
+        )
+    }
+
+    async fn write_pixels(&mut self, pixels: &[u8]) -> Result<(), Self::Error> {
+        let colors = pixels
+            .chunks_exact(2)
+            .map(|p| Rgb565::from(RawU16::new(u16::from_be_bytes([p[0], p[1]]))));
+        // SAFETY: only pixel data goes out, continuing the memory write that
+        // set_window started; no controller state changes.
+        let di = unsafe { self.dcs() };
+        <Rgb565 as mipidsi::interface::InterfacePixelFormat<DI::Word>>::send_pixels(di, colors)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Framebuffer
+// ---------------------------------------------------------------------------
+
+/// A strip that doesn't fit the framebuffer, or has too few pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfBounds {
+    pub window: Rect,
+}
+
+impl core::fmt::Display for OutOfBounds {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let w = self.window;
+        write!(
+            f,
+            "{}x{} strip at ({}, {}) doesn't fit the framebuffer",
+            w.width, w.height, w.x, w.y
+        )
+    }
+}
+
+impl core::error::Error for OutOfBounds {}
+
+/// A frame in RAM, RGB565 most significant byte first.
+pub struct Framebuffer {
+    width: u16,
+    height: u16,
+    data: Vec<u8>,
+    /// Where [`DisplaySink::write_pixels`] goes.
+    window: Rect,
+}
+
+impl Framebuffer {
+    /// A black frame.
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0; width as usize * height as usize * 2],
+            window: Rect::default(),
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// Rows top to bottom, two bytes per pixel.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn pixel(&self, x: u16, y: u16) -> Option<Rgb565> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let at = (y as usize * self.width as usize + x as usize) * 2;
+        let raw = u16::from_be_bytes([self.data[at], self.data[at + 1]]);
+        Some(Rgb565::from(RawU16::new(raw)))
+    }
+
+    /// The frame as 8-bit RGB, rows top to bottom.
+    pub fn to_rgb888(&self) -> Vec<u8> {
+        self.data
+            .chunks_exact(2)
+            .flat_map(|p| rgb888(u16::from_be_bytes([p[0], p[1]])))
+            .collect()
+    }
+
+    /// Copy a strip in, as a [`DisplaySink`] would.
+    pub fn blit(&mut self, window: Rect, pixels: &[u8]) -> Result<(), OutOfBounds> {
+        let row_len = window.width as usize * 2;
+        if window.x as u32 + window.width as u32 > self.width as u32
+            || window.y as u32 + window.height as u32 > self.height as u32
+            || pixels.len() < row_len * window.height as usize
+        {
+            return Err(OutOfBounds { window });
+        }
+        if row_len == 0 {
+            return Ok(());
+        }
+        for (y, row) in (window.y..).zip(pixels.chunks_exact(row_len)) {
+            let at = (y as usize * self.width as usize + window.x as usize) * 2;
+            self.data[at..at + row_len].copy_from_slice(row);
+        }
+        Ok(())
+    }
+}
+
+impl DisplaySink for Framebuffer {
+    type Error = OutOfBounds;
+
+    async fn set_window(&mut self, window: Rect) -> Result<(), OutOfBounds> {
+        self.window = window;
+        Ok(())
+    }
+
+    async fn write_pixels(&mut self, pixels: &[u8]) -> Result<(), OutOfBounds> {
+        self.blit(self.window, pixels)
+    }
+}
+
+/// Widen RGB565 to 8 bits per channel, repeating the top bits so that full
+/// intensity stays 255.
+fn rgb888(raw: u16) -> [u8; 3] {
+    let r = (raw >> 11) as u8 & 0x1F;
+    let g = (raw >> 5) as u8 & 0x3F;
+    let b = raw as u8 & 0x1F;
+    [
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+    ]
+}
+
+// ---------------------------------------------------------------------------
+// Image snapshots (host only)
+// ---------------------------------------------------------------------------
+
+#[cfg(feature = "std")]
+pub use snapshot::{ImageFormat, Snapshots};
+
+#[cfg(feature = "std")]
+mod snapshot {
+    use std::fs::File;
+    use std::io::{self, BufWriter, Write};
+    use std::path::PathBuf;
+
+    use alloc::vec::Vec;
+
+    use super::{DisplaySink, Framebuffer, OutOfBounds};
+    use crate::fit::Rect;
+
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum ImageFormat {
+        /// Binary PPM (P6), readable by most image tools.
+        Ppm,
+        /// Uncompressed PNG.
+        #[default]
+        Png,
+    }
+
+    impl ImageFormat {
+        pub fn parse(s: &str) -> Option<Self> {
+            match s {
+                "ppm" => Some(Self::Ppm),
+                "png" => Some(Self::Png),
+                _ => None,
+            }
+        }
+
+        pub fn extension(&self) -> &'static str {
+            match self {
+                Self::Ppm => "ppm",
+                Self::Png => "png",
+            }
+        }
+    }
+
+    impl Framebuffer {
+        pub fn write_ppm<W: Write>(&self, mut w: W) -> io::Result<()> {
+            write!(w, "P6\n{} {}\n255\n", self.width, self.height)?;
+            w.write_all(&self.to_rgb888())
+        }
+
+        /// Write an 8-bit RGB PNG, stored without compression.
+        pub fn write_png<W: Write>(&self, mut w: W) -> io::Result<()> {
+            const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+            let chunk = |w: &mut W, kind: &[u8; 4], data: &[u8]| -> io::Result<()> {
+                let mut digest = CRC.digest();
+                digest.update(kind);
+                digest.update(data);
+                w.write_all(&(data.len() as u32).to_be_bytes())?;
+                w.write_all(kind)?;
+                w.write_all(data)?;
+                w.write_all(&digest.finalize().to_be_bytes())
+            };
+
+            let mut header = Vec::with_capacity(13);
+            header.extend_from_slice(&(self.width as u32).to_be_bytes());
+            header.extend_from_slice(&(self.height as u32).to_be_bytes());
+            // 8 bits per channel, RGB, deflate, no filter, no interlace
+            header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+            // Each row starts with its filter type, 0 for none
+            let rgb = self.to_rgb888();
+            let mut raw = Vec::with_capacity(rgb.len() + self.height as usize);
+            for row in rgb.chunks_exact((self.width as usize * 3).max(1)) {
+                raw.push(0);
+                raw.extend_from_slice(row);
+            }
+
+            w.write_all(b"\x89PNG\r\n\x1a\n")?;
+            chunk(&mut w, b"IHDR", &header)?;
+            chunk(&mut w, b"IDAT", &zlib_stored(&raw))?;
+            chunk(&mut w, b"IEND", &[])
+        }
+    }
+
+    /// A zlib stream of `data` in stored (uncompressed) deflate blocks.
+    fn zlib_stored(data: &[u8]) -> Vec<u8> {
+        const MAX_BLOCK: usize = u16::MAX as usize;
+        let mut out = Vec::with_capacity(data.len() + data.len() / MAX_BLOCK * 5 + 16);
+        out.extend_from_slice(&[0x78, 0x01]);
+        let mut blocks = data.chunks(MAX_BLOCK).peekable();
+        if blocks.peek().is_none() {
+            out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+        }
+        while let Some(block) = blocks.next() {
+            let last = blocks.peek().is_none();
+            let len = block.len() as u16;
+            out.push(last as u8);
+            out.extend_from_slice(&len.to_le_bytes());
+            out.extend_from_slice(&(!len).to_le_bytes());
+            out.extend_from_slice(block);
+        }
+        let (mut a, mut b) = (1u32, 0u32);
+        for &byte in data {
+            a = (a + byte as u32) % 65521;
+            b = (b + a) % 65521;
+        }
+        out.extend_from_slice(&((b << 16) | a).to_be_bytes());
+        out
+    }
+
+    /// Keeps frames in a [`Framebuffer`] and saves each one as
+    /// `frame-00000.png` (or `.ppm`) and so on in a directory.
+    pub struct Snapshots {
+        frame: Framebuffer,
+        dir: PathBuf,
+        format: ImageFormat,
+        saved: u32,
+    }
+
+    impl Snapshots {
+        pub fn new(width: u16, height: u16, dir: impl Into<PathBuf>, format: ImageFormat) -> Self {
+            Self {
+                frame: Framebuffer::new(width, height),
+                dir: dir.into(),
+                format,
+                saved: 0,
+            }
+        }
+
+        /// The frame being drawn, or the last one saved.
+        pub fn frame(&self) -> &Framebuffer {
+            &self.frame
+        }
+
+        /// Frames saved so far.
+        pub fn saved(&self) -> u32 {
+            self.saved
+        }
+
+        /// Where the next frame goes.
+        pub fn next_path(&self) -> PathBuf {
+            self.dir.join(format!(
+                "frame-{:05}.{}",
+                self.saved,
+                self.format.extension()
+            ))
+        }
+
+        fn save(&mut self) -> io::Result<()> {
+            let mut w = BufWriter::new(File::create(self.next_path())?);
+            match self.format {
+                ImageFormat::Ppm => self.frame.write_ppm(&mut w)?,
+                ImageFormat::Png => self.frame.write_png(&mut w)?,
+            }
+            w.flush()?;
+            self.saved += 1;
+            Ok(())
+        }
+    }
+
+    impl DisplaySink for Snapshots {
+        type Error = io::Error;
+
+        async fn set_window(&mut self, window: Rect) -> io::Result<()> {
+            self.frame.window = window;
+            Ok(())
+        }
+
+        async fn write_pixels(&mut self, pixels: &[u8]) -> io::Result<()> {
+            self.frame
+                .blit(self.frame.window, pixels)
+                .map_err(|e: OutOfBounds| io::Error::new(io::ErrorKind::InvalidInput, e))
+        }
+
+        async fn flush(&mut self) -> io::Result<()> {
+            self.save()
+        }
+    }
+}
@@ -0,0 +1,282 @@
+//! Placing a stream of any size on the panel.
+//!
+//! [`Layout::new`] works out which part of the decoded image is shown and
+//! where it goes, and [`Layout::copy_rows`] resamples a decoded block into
+//! that place with nearest-neighbour sampling. When an image divides evenly, the
+//! decoder's own scaler and clipper do most of the shrinking, as long as the
+//! whole output (these features don't work block by block) fits the budget.
+//! Everything else is done per block, and the panel outside the picture is
+//! painted black once per layout.
+
+use core::ops::Range;
+
+use crate::jpeg::{JpegConfigError, JpegDecoderConfig, JpegPixelFormat};
+
+/// How an image that isn't the panel's size is shown.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FitMode {
+    /// Scale to fit inside the panel, keeping the aspect ratio, with bars.
+    Fit,
+    /// Scale to cover the panel, keeping the aspect ratio, and crop the
+    /// middle.
+    #[default]
+    Fill,
+    /// Show at native size in the middle, cropped or with bars.
+    Center,
+    /// Scale to the panel's size regardless of the aspect ratio.
+    Stretch,
+}
+
+impl FitMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "fit" => Some(Self::Fit),
+            "fill" => Some(Self::Fill),
+            "center" => Some(Self::Center),
+            "stretch" => Some(Self::Stretch),
+            _ => None,
+        }
+    }
+}
+
+impl core::fmt::Display for FitMode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Self::Fit => "fit",
+            Self::Fill => "fill",
+            Self::Center => "center",
+            Self::Stretch => "stretch",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Rect {
+    pub const fn new(x: u16, y: u16, width: u16, height: u16) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub fn right(&self) -> u16 {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> u16 {
+        self.y + self.height
+    }
+}
+
+/// Where a decoded image goes on the panel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Layout {
+    /// Decoder scale, (0, 0) for none.
+    pub scale: (u16, u16),
+    /// Decoder clipper, (0, 0) for none.
+    pub clipper: (u16, u16),
+    /// The part of the decoder's output that is shown.
+    pub source: Rect,
+    /// Where `source` goes on the panel, resampled to this size.
+    pub target: Rect,
+}
+
+impl Layout {
+    /// Lay out an `image` (width, height) on a `panel` of the given size.
+    /// The decoder may scale and clip if its whole output fits in
+    /// `max_output` bytes of RGB565.
+    pub fn new(mode: FitMode, image: (u16, u16), panel: (u16, u16), max_output: usize) -> Self {
+        let (iw, ih) = (image.0.max(1), image.1.max(1));
+        let (pw, ph) = (panel.0.max(1), panel.1.max(1));
+        let full = Rect::new(0, 0, iw, ih);
+        let (source, target) = match mode {
+            FitMode::Stretch => (full, Rect::new(0, 0, pw, ph)),
+            FitMode::Center => {
+                let (w, h) = (iw.min(pw), ih.min(ph));
+                let source = Rect::new((iw - w) / 2, (ih - h) / 2, w, h);
+                (source, centered(w, h, pw, ph))
+            }
+            FitMode::Fit => {
+                // Whichever side is relatively longer spans the panel
+                let (w, h) = if iw as u32 * ph as u32 >= ih as u32 * pw as u32 {
+                    (pw, ratio(ih, pw, iw).clamp(1, ph))
+                } else {
+                    (ratio(iw, ph, ih).clamp(1, pw), ph)
+                };
+                (full, centered(w, h, pw, ph))
+            }
+            FitMode::Fill => {
+                // Crop whichever side is relatively longer
+                let (w, h) = if iw as u32 * ph as u32 >= ih as u32 * pw as u32 {
+                    (ratio(pw, ih, ph).clamp(1, iw), ih)
+                } else {
+                    (iw, ratio(ph, iw, pw).clamp(1, ih))
+                };
+                let source = Rect::new((iw - w) / 2, (ih - h) / 2, w, h);
+                (source, Rect::new(0, 0, pw, ph))
+            }
+        };
+        let layout = Self {
+            scale: (0, 0),
+            clipper: (0, 0),
+            source,
+            target,
+        };
+        layout.with_decoder_scaling(iw, ih, max_output)
+    }
+
+    /// Let the decoder shrink the image by the largest power of two that
+    /// still leaves at least a pixel per target pixel.
+    fn with_decoder_scaling(self, iw: u16, ih: u16, max_output: usize) -> Self {
+        for k in [8, 4, 2] {
+            if !(iw.is_multiple_of(8 * k) && ih.is_multiple_of(8 * k)) {
+                continue;
+            }
+            if self.source.width / k < self.target.width
+                || self.source.height / k < self.target.height
+            {
+                continue;
+            }
+            let scale = (iw / k, ih / k);
+            let source = Rect::new(
+                self.source.x / k,
+                self.source.y / k,
+                self.source.width / k,
+                self.source.height / k,
+            );
+            // Only the top-left corner can be kept, in multiples of 8
+            let clipper = (
+                source.right().next_multiple_of(8).min(scale.0),
+                source.bottom().next_multiple_of(8).min(scale.1),
+            );
+            if clipper.0 as usize * clipper.1 as usize * 2 > max_output {
+                continue;
+            }
+            let clipper = if clipper == scale { (0, 0) } else { clipper };
+            let scaled = Self {
+                scale,
+                clipper,
+                source,
+                ..self
+            };
+            // Anything the decoder would turn down is laid out without it
+            if scaled.decoder_config(JpegPixelFormat::Rgb565Le).is_ok() {
+                return scaled;
+            }
+        }
+        self
+    }
+
+    /// Decoder settings for this layout, producing `format` (two bytes per
+    /// pixel): block mode unless the decoder scales. Layouts from
+    /// [`Layout::new`] always have them; one put together by hand may not.
+    pub fn decoder_config(
+        &self,
+        format: JpegPixelFormat,
+    ) -> Result<JpegDecoderConfig, JpegConfigError> {
+        let builder = JpegDecoderConfig::builder().with_output_format(format);
+        let builder = if self.scale == (0, 0) {
+            builder
+        } else {
+            builder
+                .with_block_mode(false)
+                .with_scale(self.scale.0, self.scale.1)
+                .with_clipper(self.clipper.0, self.clipper.1)
+        };
+        builder.build()
+    }
+
+    /// The parts of a `panel` (width, height) outside the picture.
+    pub fn bars(&self, panel: (u16, u16)) -> impl Iterator<Item = Rect> {
+        let (pw, ph) = panel;
+        let t = self.target;
+        [
+            Rect::new(0, 0, pw, t.y),
+            Rect::new(0, t.bottom(), pw, ph.saturating_sub(t.bottom())),
+            Rect::new(0, t.y, t.x, t.height),
+            Rect::new(t.right(), t.y, pw.saturating_sub(t.right()), t.height),
+        ]
+        .into_iter()
+        .filter(|r| !r.is_empty())
+    }
+
+    /// Panel rows drawn from the decoded rows `top..top + height`.
+    pub fn rows(&self, top: u16, height: u16) -> Range<u16> {
+        let (s, t) = (self.source, self.target);
+        // First and one-past-last target rows whose source row is in range;
+        // the mapping is monotonic, so they are contiguous.
+        let first = |row: u16| {
+            let into = row.saturating_sub(s.y) as u32;
+            let n = (into * t.height as u32).div_ceil(s.height.max(1) as u32);
+            t.y + n.min(t.height as u32) as u16
+        };
+        first(top)..first(top.saturating_add(height))
+    }
+
+    /// Fill `out` with the panel `rows` (from [`Layout::rows`]) across the
+    /// target's columns, taken from a decoded `block` of `width` two-byte
+    /// pixels per row whose first row is `top`. Returns the bytes written.
+    pub fn copy_rows(
+        &self,
+        rows: Range<u16>,
+        top: u16,
+        width: u16,
+        block: &[u8],
+        out: &mut [u8],
+    ) -> usize {
+        let t = self.target;
+        let row_len = t.width as usize * 2;
+        let mut len = 0;
+        for y in rows {
+            let src = (self.source_row(y) - top) as usize * width as usize * 2;
+            let dst = &mut out[len..len + row_len];
+            if self.source.width == t.width {
+                // One to one: a row is a single copy
+                let start = src + self.source.x as usize * 2;
+                match block.get(start..start + row_len) {
+                    Some(row) => dst.copy_from_slice(row),
+                    None => dst.fill(0),
+                }
+            } else {
+                for (x, pixel) in (t.x..).zip(dst.chunks_exact_mut(2)) {
+                    let at = src + self.source_col(x) as usize * 2;
+                    pixel.copy_from_slice(block.get(at..at + 2).unwrap_or(&[0, 0]));
+                }
+            }
+            len += row_len;
+        }
+        len
+    }
+
+    fn source_row(&self, y: u16) -> u16 {
+        let (s, t) = (self.source, self.target);
+        s.y + ((y - t.y) as u32 * s.height as u32 / t.height.max(1) as u32) as u16
+    }
+
+    fn source_col(&self, x: u16) -> u16 {
+        let (s, t) = (self.source, self.target);
+        s.x + ((x - t.x) as u32 * s.width as u32 / t.width.max(1) as u32) as u16
+    }
+}
+
+/// `a * b / c`, rounded.
+fn ratio(a: u16, b: u16, c: u16) -> u16 {
+    ((a as u32 * b as u32 + c as u32 / 2) / c as u32) as u16
+}
+
+fn centered(w: u16, h: u16, pw: u16, ph: u16) -> Rect {
+    Rect::new((pw - w) / 2, (ph - h) / 2, w, h)
+}
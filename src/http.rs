@@ -0,0 +1,449 @@
+//! HTTP/1.1 client for `multipart/x-mixed-replace` MJPEG streams.
+//!
+//! This is what IP cameras, `ffmpeg -f mpjpeg`, motion and OctoPrint webcams
+//! serve. [`request_stream`] sends the GET and parses the response head over
+//! any `embedded_io_async` transport (an `embassy_net` `TcpSocket` on the
+//! badge, a std socket in tests), and [`MultipartDemuxer`] splits the body
+//! into JPEG frames using each part's `Content-Length`.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use embedded_io_async::{Read, Write};
+
+use crate::mjpeg::{DropReason, FrameSource, Status};
+
+/// Longest boundary RFC 2046 allows.
+const MAX_BOUNDARY: usize = 70;
+/// Part header lines longer than this are ignored.
+const MAX_LINE: usize = 128;
+
+#[derive(Debug)]
+pub enum HttpError<E> {
+    Io(E),
+    /// The connection closed before the response head was complete.
+    Closed,
+    /// The response head did not fit in the buffer.
+    HeadTooLarge,
+    /// The status line or headers could not be parsed.
+    BadResponse,
+    /// The server answered with a non-2xx status.
+    Status(u16),
+    /// The response is not `multipart/x-mixed-replace` with a boundary.
+    NotMultipart,
+    /// `Transfer-Encoding` other than identity.
+    UnsupportedEncoding,
+}
+
+impl<E: core::fmt::Debug> core::fmt::Display for HttpError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {:?}", e),
+            Self::Closed => f.write_str("connection closed"),
+            Self::HeadTooLarge => f.write_str("response head too large"),
+            Self::BadResponse => f.write_str("malformed HTTP response"),
+            Self::Status(code) => write!(f, "HTTP status {}", code),
+            Self::NotMultipart => f.write_str("not a multipart/x-mixed-replace stream"),
+            Self::UnsupportedEncoding => f.write_str("unsupported transfer encoding"),
+        }
+    }
+}
+
+impl<E: core::fmt::Debug> core::error::Error for HttpError<E> {}
+
+/// The parts of a response head the stream client cares about.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResponseHead {
+    pub status: u16,
+    pub boundary: Vec<u8>,
+}
+
+/// Send `GET path` to `host` (`"name"` or `"name:port"`, used for the `Host`
+/// header) and read the response head into `buf`.
+///
+/// On success returns the head and the number of body bytes that were read
+/// along with it; they are at the start of `buf` and must be fed to the
+/// demuxer before anything else.
+pub async fn request_stream<S: Read + Write>(
+    socket: &mut S,
+    host: &str,
+    path: &str,
+    buf: &mut [u8],
+) -> Result<(ResponseHead, usize), HttpError<S::Error>> {
+    for part in [
+        "GET ",
+        path,
+        " HTTP/1.1\r\nHost: ",
+        host,
+        "\r\nUser-Agent: rumble-rs\r\n\
+         Accept: multipart/x-mixed-replace, image/jpeg\r\n\
+         Connection: close\r\n\r\n",
+    ] {
+        socket
+            .write_all(part.as_bytes())
+            .await
+            .map_err(HttpError::Io)?;
+    }
+    socket.flush().await.map_err(HttpError::Io)?;
+
+    let mut filled = 0;
+    let head_len = loop {
+        if let Some(end) = find(&buf[..filled], b"\r\n\r\n") {
+            break end + 4;
+        }
+        if filled == buf.len() {
+            return Err(HttpError::HeadTooLarge);
+        }
+        match socket.read(&mut buf[filled..]).await.map_err(HttpError::Io)? {
+            0 => return Err(HttpError::Closed),
+            n => filled += n,
+        }
+    };
+
+    let head = parse_response_head(&buf[..head_len])?;
+    buf.copy_within(head_len..filled, 0);
+    Ok((head, filled - head_len))
+}
+
+/// Parse a response head (status line and headers, through the blank line).
+pub fn parse_response_head<E>(head: &[u8]) -> Result<ResponseHead, HttpError<E>> {
+    let mut lines = head.split(|&b| b == b'\n').map(trim);
+
+    // HTTP/1.x 200 OK
+    let status_line = lines.next().ok_or(HttpError::BadResponse)?;
+    let mut fields = status_line.split(|&b| b == b' ').filter(|f| !f.is_empty());
+    let version = fields.next().ok_or(HttpError::BadResponse)?;
+    if !version.starts_with(b"HTTP/1.") {
+        return Err(HttpError::BadResponse);
+    }
+    let status = fields
+        .next()
+        .and_then(parse_decimal)
+        .and_then(|s| u16::try_from(s).ok())
+        .ok_or(HttpError::BadResponse)?;
+    if !(200..300).contains(&status) {
+        return Err(HttpError::Status(status));
+    }
+
+    let mut boundary = None;
+    for line in lines {
+        let Some((name, value)) = split_header(line) else {
+            continue;
+        };
+        if name.eq_ignore_ascii_case(b"content-type") {
+            boundary = multipart_boundary(value);
+        } else if name.eq_ignore_ascii_case(b"transfer-encoding")
+            && !value.eq_ignore_ascii_case(b"identity")
+        {
+            return Err(HttpError::UnsupportedEncoding);
+        }
+    }
+
+    let boundary = boundary.ok_or(HttpError::NotMultipart)?;
+    Ok(ResponseHead {
+        status,
+        boundary: boundary.to_vec(),
+    })
+}
+
+/// Extract the boundary from a `multipart/x-mixed-replace` content type.
+fn multipart_boundary(content_type: &[u8]) -> Option<&[u8]> {
+    let mut params = content_type.split(|&b| b == b';').map(trim);
+    let mime = params.next()?;
+    if !mime.eq_ignore_ascii_case(b"multipart/x-mixed-replace") {
+        return None;
+    }
+    params.find_map(|param| {
+        let (name, value) = split_once(param, b'=')?;
+        if !trim(name).eq_ignore_ascii_case(b"boundary") {
+            return None;
+        }
+        let value = trim(value);
+        let value = value
+            .strip_prefix(b"\"")
+            .and_then(|v| v.strip_suffix(b"\""))
+            .unwrap_or(value);
+        (!value.is_empty() && value.len() <= MAX_BOUNDARY).then_some(value)
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Looking for a `--boundary` line.
+    Boundary,
+    /// Reading part headers up to the blank line.
+    Headers,
+    /// Copying a part body of known length.
+    Body { remaining: usize },
+    /// Copying a part body until the next `\r\n--boundary`.
+    Delimited,
+    /// Saw the closing `--boundary--`.
+    Closed,
+}
+
+/// Splits a `multipart/x-mixed-replace` body into JPEG frames.
+///
+/// Parts with a `Content-Length` are copied verbatim without scanning; parts
+/// without one end at the next boundary delimiter.
+pub struct MultipartDemuxer {
+    buf: Vec<u8>,
+    len: usize,
+    size: usize,
+    /// `\r\n--` followed by the boundary.
+    delimiter: Vec<u8>,
+    /// Bytes of `delimiter` matched so far in `Delimited` state.
+    matched: usize,
+    line: [u8; MAX_LINE],
+    line_len: usize,
+    line_overflow: bool,
+    content_length: Option<usize>,
+    state: State,
+    ready: bool,
+}
+
+impl MultipartDemuxer {
+    /// Create a demuxer that holds frames of up to `capacity` bytes.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            buf: vec![0u8; capacity],
+            len: 0,
+            size: 0,
+            delimiter: Vec::new(),
+            matched: 0,
+            line: [0u8; MAX_LINE],
+            line_len: 0,
+            line_overflow: false,
+            content_length: None,
+            state: State::Boundary,
+            ready: false,
+        }
+    }
+
+    /// Start a new response body with the boundary from its head.
+    pub fn start(&mut self, boundary: &[u8]) {
+        self.delimiter.clear();
+        self.delimiter.extend_from_slice(b"\r\n--");
+        self.delimiter.extend_from_slice(boundary);
+        self.reset();
+    }
+
+    /// True once the closing boundary has been seen.
+    pub fn is_closed(&self) -> bool {
+        self.state == State::Closed
+    }
+
+    fn boundary(&self) -> &[u8] {
+        &self.delimiter[4..]
+    }
+
+    /// Accumulate one line; returns it once `\n` arrives.
+    fn take_line(&mut self, input: &[u8], pos: &mut usize) -> Option<(usize, bool)> {
+        while *pos < input.len() {
+            let b = input[*pos];
+            *pos += 1;
+            if b == b'\n' {
+                let line = (self.line_len, self.line_overflow);
+                self.line_len = 0;
+                self.line_overflow = false;
+                return Some(line);
+            }
+            if self.line_len < MAX_LINE {
+                self.line[self.line_len] = b;
+                self.line_len += 1;
+            } else {
+                self.line_overflow = true;
+            }
+        }
+        None
+    }
+
+    /// Is `line` a delimiter? Returns `Some(true)` for the closing one.
+    /// Some servers declare the boundary with its leading dashes, so the
+    /// bare boundary is accepted as well.
+    fn match_boundary(&self, line: &[u8]) -> Option<bool> {
+        let boundary = self.boundary();
+        let rest = line
+            .strip_prefix(b"--")
+            .and_then(|l| l.strip_prefix(boundary))
+            .or_else(|| line.strip_prefix(boundary))?;
+        match trim(rest) {
+            b"" => Some(false),
+            b"--" => Some(true),
+            _ => None,
+        }
+    }
+
+    fn begin_body(&mut self) {
+        self.len = 0;
+        self.size = 0;
+        self.matched = 0;
+        self.state = match self.content_length {
+            Some(remaining) => State::Body { remaining },
+            None => State::Delimited,
+        };
+    }
+
+    fn finish_part(&mut self, consumed: usize) -> Status {
+        if self.size > self.buf.len() {
+            let size = self.size;
+            self.len = 0;
+            return Status::Dropped {
+                consumed,
+                reason: DropReason::TooLarge { size },
+            };
+        }
+        self.ready = true;
+        Status::Frame { consumed }
+    }
+
+    fn store(&mut self, bytes: &[u8]) {
+        self.size += bytes.len();
+        if self.size <= self.buf.len() {
+            self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+            self.len += bytes.len();
+        }
+    }
+}
+
+impl FrameSource for MultipartDemuxer {
+    fn push(&mut self, input: &[u8]) -> Status {
+        if self.ready {
+            self.ready = false;
+            self.len = 0;
+            self.size = 0;
+        }
+
+        let mut pos = 0;
+        while pos < input.len() {
+            match self.state {
+                State::Boundary => {
+                    let Some((len, overflow)) = self.take_line(input, &mut pos) else {
+                        return Status::Incomplete;
+                    };
+                    if overflow {
+                        continue;
+                    }
+                    let line = self.line;
+                    match self.match_boundary(trim(&line[..len])) {
+                        Some(true) => self.state = State::Closed,
+                        Some(false) => {
+                            self.content_length = None;
+                            self.state = State::Headers;
+                        }
+                        // Preamble or the CRLF that ends the previous part
+                        None => {}
+                    }
+                }
+                State::Headers => {
+                    let Some((len, overflow)) = self.take_line(input, &mut pos) else {
+                        return Status::Incomplete;
+                    };
+                    if overflow {
+                        continue;
+                    }
+                    let line = self.line;
+                    let line = trim(&line[..len]);
+                    if line.is_empty() {
+                        self.begin_body();
+                        continue;
+                    }
+                    if let Some((name, value)) = split_header(line)
+                        && name.eq_ignore_ascii_case(b"content-length")
+                    {
+                        match parse_decimal(value) {
+                            Some(n) => self.content_length = Some(n),
+                            None => {
+                                self.state = State::Boundary;
+                                return Status::Dropped {
+                                    consumed: pos,
+                                    reason: DropReason::Corrupt,
+                                };
+                            }
+                        }
+                    }
+                }
+                State::Body { remaining } => {
+                    let take = remaining.min(input.len() - pos);
+                    self.store(&input[pos..pos + take]);
+                    pos += take;
+                    let remaining = remaining - take;
+                    if remaining > 0 {
+                        self.state = State::Body { remaining };
+                    } else {
+                        self.state = State::Boundary;
+                        return self.finish_part(pos);
+                    }
+                }
+                State::Delimited => {
+                    let b = input[pos];
+                    pos += 1;
+                    self.store(&[b]);
+                    if b == self.delimiter[self.matched] {
+                        self.matched += 1;
+                    } else {
+                        // The delimiter starts with the only '\r' in it
+                        self.matched = usize::from(b == b'\r');
+                    }
+                    if self.matched == self.delimiter.len() {
+                        // Un-store the delimiter and parse the rest of its line
+                        let n = self.delimiter.len();
+                        self.size -= n;
+                        self.len = self.len.min(self.size);
+                        self.line[..n - 2].copy_from_slice(&self.delimiter[2..]);
+                        self.line_len = n - 2;
+                        self.state = State::Boundary;
+                        return self.finish_part(pos);
+                    }
+                }
+                State::Closed => return Status::Incomplete,
+            }
+        }
+        Status::Incomplete
+    }
+
+    fn frame_mut(&mut self) -> Option<&mut [u8]> {
+        if self.ready {
+            Some(&mut self.buf[..self.len])
+        } else {
+            None
+        }
+    }
+
+    fn reset(&mut self) {
+        self.len = 0;
+        self.size = 0;
+        self.matched = 0;
+        self.line_len = 0;
+        self.line_overflow = false;
+        self.content_length = None;
+        self.state = State::Boundary;
+        self.ready = false;
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn trim(s: &[u8]) -> &[u8] {
+    s.trim_ascii()
+}
+
+fn split_once(s: &[u8], sep: u8) -> Option<(&[u8], &[u8])> {
+    let i = s.iter().position(|&b| b == sep)?;
+    Some((&s[..i], &s[i + 1..]))
+}
+
+fn split_header(line: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (name, value) = split_once(line, b':')?;
+    Some((trim(name), trim(value)))
+}
+
+fn parse_decimal(s: &[u8]) -> Option<usize> {
+    if s.is_empty() || s.len() > 9 {
+        return None;
+    }
+    s.iter().try_fold(0usize, |acc, &b| {
+        b.is_ascii_digit().then(|| acc * 10 + (b - b'0') as usize)
+    })
+}
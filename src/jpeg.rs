@@ -1,59 +1,249 @@
 //! FFI bindings and safe wrapper for esp_new_jpeg block-mode decoder.
-
-use alloc::alloc::{Layout, alloc, dealloc};
+//!
+//! The encoder half of the library lives in [`encoder`]. The memory both
+//! halves ask for comes from [`heap`], and what they log goes through
+//! [`log`]. Every frame is checked by the pure-Rust [`markers`] walker
+//! before the C decoder sees it.
+//!
+//! The prebuilt library only exists for the ESP32-S3, so it sits behind the
+//! default `esp-new-jpeg` feature. The `baseline-jpeg` feature adds
+//! [`baseline::BaselineDecoder`], which runs anywhere, and `rom-jpeg` adds
+//! [`rom::RomJpegDecoder`], which uses the decoder in the chip's ROM; code
+//! that only needs to decode can take any of them through [`JpegBackend`].
+
+mod backend;
+#[cfg(feature = "baseline-jpeg")]
+pub mod baseline;
+#[cfg(feature = "esp-new-jpeg")]
+pub mod encoder;
+pub mod heap;
+pub mod log;
+pub mod markers;
+#[cfg(feature = "rom-jpeg")]
+pub mod rom;
+
+pub use backend::{JpegBackend, JpegSession};
+#[cfg(feature = "esp-new-jpeg")]
+pub use encoder::{EncodeSession, JpegEncoder, JpegEncoderConfig, JpegSourceFormat};
+
+#[cfg(feature = "esp-new-jpeg")]
 use core::ffi::c_void;
+#[cfg(feature = "esp-new-jpeg")]
+use core::marker::PhantomData;
+use core::mem::offset_of;
+#[cfg(feature = "esp-new-jpeg")]
 use core::ptr;
 
 // ---------------------------------------------------------------------------
-// JPEG pixel format FOURCC constants
+// esp_jpeg_common.h
+//
+// Everything below mirrors the headers shipped with the library (0.6.x). The
+// layout assertions after each struct are checked against the ESP32-S3 C ABI,
+// so a library upgrade that moves a field fails the build instead of writing
+// past the end of a Rust struct.
 // ---------------------------------------------------------------------------
 
+/// `JPEG_FOURCC(a, b, c, d)`
 const fn fourcc(a: u8, b: u8, c: u8, d: u8) -> u32 {
     (a as u32) | ((b as u32) << 8) | ((c as u32) << 16) | ((d as u32) << 24)
 }
 
+// jpeg_pixel_format_t
+
+/// One luminance byte per pixel.
+pub const JPEG_PIXEL_FORMAT_GRAY: u32 = fourcc(b'G', b'R', b'E', b'Y');
+/// `R G B`, three bytes per pixel.
+pub const JPEG_PIXEL_FORMAT_RGB888: u32 = fourcc(b'R', b'G', b'B', b'3');
+/// `R G B A`, four bytes per pixel.
+pub const JPEG_PIXEL_FORMAT_RGBA: u32 = fourcc(b'R', b'G', b'B', b'A');
+/// RGB565, most significant byte first.
+pub const JPEG_PIXEL_FORMAT_RGB565_BE: u32 = fourcc(b'R', b'G', b'B', b'B');
+/// RGB565, least significant byte first.
 pub const JPEG_PIXEL_FORMAT_RGB565_LE: u32 = fourcc(b'R', b'G', b'B', b'L');
-
-// ---------------------------------------------------------------------------
-// Repr(C) structs matching the C header definitions
-// ---------------------------------------------------------------------------
-
+/// Packed YUV422, `Cb Y Cr Y` per pair of pixels.
+pub const JPEG_PIXEL_FORMAT_CBYCRY: u32 = fourcc(b'U', b'Y', b'V', b'Y');
+/// Packed YUV422, `Y Cb Y Cr` per pair of pixels.
+pub const JPEG_PIXEL_FORMAT_YCBYCR: u32 = fourcc(b'Y', b'U', b'Y', b'V');
+/// Packed YUV420, `Y Cb Y Y Cr Y` per pair of rows.
+pub const JPEG_PIXEL_FORMAT_YCBY2YCRY2: u32 = fourcc(b'O', b'U', b'Y', b'Y');
+
+// jpeg_subsampling_t
+
+pub const JPEG_SUBSAMPLE_GRAY: u32 = 0;
+pub const JPEG_SUBSAMPLE_444: u32 = 1;
+pub const JPEG_SUBSAMPLE_422: u32 = 2;
+pub const JPEG_SUBSAMPLE_420: u32 = 3;
+
+// jpeg_rotate_t, clockwise
+
+pub const JPEG_ROTATE_0D: u32 = 0;
+pub const JPEG_ROTATE_90D: u32 = 1;
+pub const JPEG_ROTATE_180D: u32 = 2;
+pub const JPEG_ROTATE_270D: u32 = 3;
+
+// jpeg_error_t
+
+pub const JPEG_ERR_OK: i32 = 0;
+pub const JPEG_ERR_FAIL: i32 = -1;
+pub const JPEG_ERR_NO_MEM: i32 = -2;
+pub const JPEG_ERR_NO_MORE_DATA: i32 = -3;
+pub const JPEG_ERR_INVALID_PARAM: i32 = -4;
+pub const JPEG_ERR_BAD_DATA: i32 = -5;
+pub const JPEG_ERR_UNSUPPORT_FMT: i32 = -6;
+pub const JPEG_ERR_UNSUPPORT_STD: i32 = -7;
+
+/// `jpeg_resolution_t`
 #[repr(C)]
 pub struct JpegResolution {
     pub width: u16,
     pub height: u16,
 }
 
+const _: () = {
+    assert!(size_of::<JpegResolution>() == 4);
+    assert!(offset_of!(JpegResolution, height) == 2);
+};
+
+// ---------------------------------------------------------------------------
+// esp_jpeg_dec.h
+// ---------------------------------------------------------------------------
+
+/// `jpeg_dec_config_t`
 #[repr(C)]
 pub struct JpegDecConfig {
+    /// A `jpeg_pixel_format_t`: RGB888, RGB565 or CbYCrY.
     pub output_type: u32,
+    /// Zero keeps the original size; otherwise multiples of 8.
     pub scale: JpegResolution,
+    /// Zero keeps the scaled size; otherwise multiples of 8.
     pub clipper: JpegResolution,
+    /// A `jpeg_rotate_t`.
     pub rotate: u32,
+    /// Decode one MCU row per `jpeg_dec_process` call.
     pub block_enable: bool,
 }
 
+const _: () = {
+    assert!(size_of::<JpegDecConfig>() == 20);
+    assert!(offset_of!(JpegDecConfig, scale) == 4);
+    assert!(offset_of!(JpegDecConfig, clipper) == 8);
+    assert!(offset_of!(JpegDecConfig, rotate) == 12);
+    assert!(offset_of!(JpegDecConfig, block_enable) == 16);
+};
+
+/// `jpeg_dec_io_t`
 #[repr(C)]
 pub struct JpegDecIo {
     pub inbuf: *mut u8,
     pub inbuf_len: i32,
+    /// Set by the library: input bytes not consumed yet.
     pub inbuf_remain: i32,
+    /// At least `jpeg_dec_get_outbuf_len` bytes, 16-byte aligned.
     pub outbuf: *mut u8,
+    /// Set by the library: bytes written to `outbuf` by the last call.
     pub out_size: i32,
 }
 
+#[cfg(target_pointer_width = "32")]
+const _: () = {
+    assert!(size_of::<JpegDecIo>() == 20);
+    assert!(offset_of!(JpegDecIo, inbuf_len) == 4);
+    assert!(offset_of!(JpegDecIo, inbuf_remain) == 8);
+    assert!(offset_of!(JpegDecIo, outbuf) == 12);
+    assert!(offset_of!(JpegDecIo, out_size) == 16);
+};
+
+/// `jpeg_dec_header_info_t`, filled in by `jpeg_dec_parse_header`. The
+/// tables take most of its 2.6 KiB.
 #[repr(C)]
 pub struct JpegDecHeaderInfo {
     pub width: u16,
     pub height: u16,
+    pub component_num: u8,
+    pub component_id: [u8; 3],
+    /// Horizontal sampling factor of each component.
+    pub x_factor: [u8; 3],
+    /// Vertical sampling factor of each component.
+    pub y_factor: [u8; 3],
+    /// Huffman code counts per length, `[table][dc, ac][length - 1]`.
+    pub huffbits: [[[u8; 16]; 2]; 2],
+    /// Huffman symbols, `[table][dc, ac][symbol]`.
+    pub huffcode: [[[u16; 256]; 2]; 2],
+    pub qt_tbl_num: u8,
+    /// Quantization tables in zigzag order.
+    pub qt_tbl: [[u16; 64]; 4],
+}
+
+const _: () = {
+    assert!(size_of::<JpegDecHeaderInfo>() == 2640);
+    assert!(offset_of!(JpegDecHeaderInfo, component_num) == 4);
+    assert!(offset_of!(JpegDecHeaderInfo, component_id) == 5);
+    assert!(offset_of!(JpegDecHeaderInfo, x_factor) == 8);
+    assert!(offset_of!(JpegDecHeaderInfo, y_factor) == 11);
+    assert!(offset_of!(JpegDecHeaderInfo, huffbits) == 14);
+    assert!(offset_of!(JpegDecHeaderInfo, huffcode) == 78);
+    assert!(offset_of!(JpegDecHeaderInfo, qt_tbl_num) == 2126);
+    assert!(offset_of!(JpegDecHeaderInfo, qt_tbl) == 2128);
+};
+
+impl Default for JpegDecHeaderInfo {
+    fn default() -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            component_num: 0,
+            component_id: [0; 3],
+            x_factor: [0; 3],
+            y_factor: [0; 3],
+            huffbits: [[[0; 16]; 2]; 2],
+            huffcode: [[[0; 256]; 2]; 2],
+            qt_tbl_num: 0,
+            qt_tbl: [[0; 64]; 4],
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// esp_jpeg_enc.h
+// ---------------------------------------------------------------------------
+
+/// `jpeg_enc_config_t`
+#[repr(C)]
+pub struct JpegEncConfig {
+    pub width: i32,
+    pub height: i32,
+    /// A `jpeg_pixel_format_t`.
+    pub src_type: u32,
+    /// A `jpeg_subsampling_t`.
+    pub subsampling: u32,
+    /// 1..=100.
+    pub quality: u8,
+    /// A `jpeg_rotate_t`.
+    pub rotate: u32,
+    /// Nonzero runs Huffman coding in a second task.
+    pub task_enable: i32,
+    pub hfm_task_priority: i32,
+    pub hfm_task_core: i32,
 }
 
+const _: () = {
+    assert!(size_of::<JpegEncConfig>() == 36);
+    assert!(offset_of!(JpegEncConfig, src_type) == 8);
+    assert!(offset_of!(JpegEncConfig, subsampling) == 12);
+    assert!(offset_of!(JpegEncConfig, quality) == 16);
+    assert!(offset_of!(JpegEncConfig, rotate) == 20);
+    assert!(offset_of!(JpegEncConfig, task_enable) == 24);
+    assert!(offset_of!(JpegEncConfig, hfm_task_core) == 32);
+};
+
 // ---------------------------------------------------------------------------
 // FFI declarations
 // ---------------------------------------------------------------------------
 
+#[cfg(feature = "esp-new-jpeg")]
 type JpegDecHandle = *mut c_void;
 
+#[cfg(feature = "esp-new-jpeg")]
 unsafe extern "C" {
     fn jpeg_dec_open(config: *mut JpegDecConfig, handle: *mut JpegDecHandle) -> i32;
     fn jpeg_dec_parse_header(
@@ -71,287 +261,661 @@ unsafe extern "C" {
 }
 
 // ---------------------------------------------------------------------------
-// ESP-IDF heap_caps stubs — the .a library calls these internally
-//
-// Strategy: allocate with Rust's global allocator using Layout(size, align=1),
-// store (raw_ptr, alloc_size) in a header just before the returned pointer.
+// Errors
 // ---------------------------------------------------------------------------
 
-const META_WORDS: usize = 2; // raw_ptr + alloc_size
-const META_BYTES: usize = META_WORDS * core::mem::size_of::<usize>();
-
-unsafe fn caps_alloc_inner(count: usize, size: usize, align: usize) -> *mut u8 {
-    let payload = match count.checked_mul(size) {
-        Some(0) | None => return ptr::null_mut(),
-        Some(p) => p,
-    };
-    let align = align.max(core::mem::size_of::<usize>());
-    let alloc_size = payload + META_BYTES + align;
-    unsafe {
-        let layout = Layout::from_size_align_unchecked(alloc_size, 1);
-        let raw = alloc(layout);
-        if raw.is_null() {
-            return ptr::null_mut();
+/// Errors reported by the C library (`jpeg_error_t`) or by this wrapper.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JpegError {
+    /// `JPEG_ERR_FAIL`: unspecified library failure.
+    Fail,
+    /// `JPEG_ERR_NO_MEM`: an allocation failed.
+    NoMemory,
+    /// `JPEG_ERR_NO_MORE_DATA`: the input ended before the image did.
+    NoMoreData,
+    /// `JPEG_ERR_INVALID_PARAM`: the library rejected an argument.
+    InvalidParam,
+    /// `JPEG_ERR_BAD_DATA`: the bitstream is corrupt.
+    BadData,
+    /// `JPEG_ERR_UNSUPPORT_FMT`: the pixel format is not supported.
+    UnsupportedFormat,
+    /// `JPEG_ERR_UNSUPPORT_STD`: the JPEG profile is not supported (e.g. progressive).
+    UnsupportedStandard,
+    /// A code outside the documented `jpeg_error_t` range.
+    Unknown(i32),
+    /// The configuration was rejected before reaching the library.
+    Config(JpegConfigError),
+    /// The input buffer is longer than the library's `int` length field.
+    InputTooLarge,
+    /// The marker walker rejected the frame before it reached the library.
+    InvalidStream(markers::MarkerError),
+    /// `decode_next_block` or `encode_block` was called after the last block.
+    SessionExhausted,
+    /// An encode session was finished before every block was encoded.
+    SessionIncomplete,
+    /// A caller-supplied buffer is too small for the image.
+    BufferTooSmall,
+}
+
+impl JpegError {
+    /// Map a raw `jpeg_error_t` code. `JPEG_ERR_OK` is not an error and maps
+    /// to `Unknown(0)`; use [`check`] to turn return codes into `Result`s.
+    pub const fn from_code(code: i32) -> Self {
+        match code {
+            JPEG_ERR_FAIL => Self::Fail,
+            JPEG_ERR_NO_MEM => Self::NoMemory,
+            JPEG_ERR_NO_MORE_DATA => Self::NoMoreData,
+            JPEG_ERR_INVALID_PARAM => Self::InvalidParam,
+            JPEG_ERR_BAD_DATA => Self::BadData,
+            JPEG_ERR_UNSUPPORT_FMT => Self::UnsupportedFormat,
+            JPEG_ERR_UNSUPPORT_STD => Self::UnsupportedStandard,
+            other => Self::Unknown(other),
         }
-        raw.write_bytes(0, alloc_size);
-        // Aligned user pointer with room for header
-        let base = raw as usize + META_BYTES;
-        let user_addr = (base + align - 1) & !(align - 1);
-        let meta = user_addr as *mut usize;
-        meta.sub(1).write(raw as usize);
-        meta.sub(2).write(alloc_size);
-        user_addr as *mut u8
     }
-}
 
-unsafe fn caps_free_inner(ptr: *mut u8) {
-    if ptr.is_null() {
-        return;
+    /// The frame itself is broken or unsupported; skipping it and decoding the
+    /// next one is the right recovery.
+    pub const fn is_bad_frame(&self) -> bool {
+        matches!(
+            self,
+            Self::NoMoreData
+                | Self::BadData
+                | Self::UnsupportedStandard
+                | Self::InputTooLarge
+                | Self::InvalidStream(_)
+        )
     }
-    unsafe {
-        let meta = ptr as *mut usize;
-        let raw = meta.sub(1).read() as *mut u8;
-        let alloc_size = meta.sub(2).read();
-        let layout = Layout::from_size_align_unchecked(alloc_size, 1);
-        dealloc(raw, layout);
+
+    /// The decoder ran out of memory; the frame may be fine.
+    pub const fn is_out_of_memory(&self) -> bool {
+        matches!(self, Self::NoMemory)
     }
 }
 
-/// `void *heap_caps_calloc_prefer(size_t n, size_t size, size_t num, ...)`
-/// The variadic caps arguments are ignored — we just allocate from Rust's heap.
-#[unsafe(no_mangle)]
-pub extern "C" fn heap_caps_calloc_prefer(n: usize, size: usize, _num: usize) -> *mut c_void {
-    unsafe { caps_alloc_inner(n, size, 4) as *mut c_void }
+impl From<markers::MarkerError> for JpegError {
+    fn from(e: markers::MarkerError) -> Self {
+        Self::InvalidStream(e)
+    }
 }
 
-/// `void heap_caps_free(void *ptr)`
-#[unsafe(no_mangle)]
-pub extern "C" fn heap_caps_free(ptr: *mut c_void) {
-    unsafe { caps_free_inner(ptr as *mut u8) }
+impl From<JpegConfigError> for JpegError {
+    fn from(e: JpegConfigError) -> Self {
+        Self::Config(e)
+    }
+}
+
+impl core::fmt::Display for JpegError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Fail => f.write_str("JPEG library failure"),
+            Self::NoMemory => f.write_str("out of memory"),
+            Self::NoMoreData => f.write_str("JPEG data ended unexpectedly"),
+            Self::InvalidParam => f.write_str("invalid parameter"),
+            Self::BadData => f.write_str("corrupt JPEG data"),
+            Self::UnsupportedFormat => f.write_str("unsupported pixel format"),
+            Self::UnsupportedStandard => f.write_str("unsupported JPEG standard"),
+            Self::Unknown(code) => write!(f, "unknown JPEG error {}", code),
+            Self::Config(e) => write!(f, "invalid codec config: {}", e),
+            Self::InputTooLarge => f.write_str("JPEG input too large"),
+            Self::InvalidStream(e) => write!(f, "invalid JPEG stream: {}", e),
+            Self::SessionExhausted => f.write_str("no more blocks in session"),
+            Self::SessionIncomplete => f.write_str("encode session finished early"),
+            Self::BufferTooSmall => f.write_str("buffer too small for image"),
+        }
+    }
 }
 
-/// `void *heap_caps_aligned_calloc(size_t alignment, size_t n, size_t size, uint32_t caps)`
-#[unsafe(no_mangle)]
-pub extern "C" fn heap_caps_aligned_calloc(
-    alignment: usize,
-    n: usize,
-    size: usize,
-    _caps: u32,
-) -> *mut c_void {
-    unsafe { caps_alloc_inner(n, size, alignment) as *mut c_void }
+impl core::error::Error for JpegError {}
+
+/// Convert a `jpeg_error_t` return value into a `Result`.
+pub fn check(code: i32) -> Result<(), JpegError> {
+    if code == JPEG_ERR_OK {
+        Ok(())
+    } else {
+        Err(JpegError::from_code(code))
+    }
 }
 
 // ---------------------------------------------------------------------------
-// ESP-IDF logging stubs — the .a library calls these for diagnostics
+// Decoder configuration
 // ---------------------------------------------------------------------------
 
-/// `void esp_log_write(esp_log_level_t level, const char *tag, const char *format, ...)`
-#[unsafe(no_mangle)]
-pub extern "C" fn esp_log_write(_level: u32, _tag: *const u8, _fmt: *const u8) {
-    // no-op: variadic printf formatting is not practical in no_std
+/// Pixel formats the decoder can output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JpegPixelFormat {
+    Rgb888,
+    Rgb565Be,
+    Rgb565Le,
+    CbYCrY,
+}
+
+impl JpegPixelFormat {
+    pub const fn fourcc(self) -> u32 {
+        match self {
+            Self::Rgb888 => JPEG_PIXEL_FORMAT_RGB888,
+            Self::Rgb565Be => JPEG_PIXEL_FORMAT_RGB565_BE,
+            Self::Rgb565Le => JPEG_PIXEL_FORMAT_RGB565_LE,
+            Self::CbYCrY => JPEG_PIXEL_FORMAT_CBYCRY,
+        }
+    }
+
+    /// Bytes per output pixel. CbYCrY packs two pixels into four bytes.
+    pub const fn bytes_per_pixel(self) -> usize {
+        match self {
+            Self::Rgb888 => 3,
+            Self::Rgb565Be | Self::Rgb565Le | Self::CbYCrY => 2,
+        }
+    }
+}
+
+/// Clockwise rotation applied after scale and clipper.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JpegRotation {
+    #[default]
+    Deg0,
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
+impl JpegRotation {
+    pub const fn raw(self) -> u32 {
+        match self {
+            Self::Deg0 => JPEG_ROTATE_0D,
+            Self::Deg90 => JPEG_ROTATE_90D,
+            Self::Deg180 => JPEG_ROTATE_180D,
+            Self::Deg270 => JPEG_ROTATE_270D,
+        }
+    }
+
+    const fn swaps_axes(self) -> bool {
+        matches!(self, Self::Deg90 | Self::Deg270)
+    }
+}
+
+/// Chroma subsampling of a JPEG image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JpegSubsampling {
+    Gray,
+    Yuv444,
+    Yuv422,
+    Yuv420,
 }
 
-/// `void esp_log_level_set(const char *tag, esp_log_level_t level)`
-#[unsafe(no_mangle)]
-pub extern "C" fn esp_log_level_set(_tag: *const u8, _level: u32) {}
+impl JpegSubsampling {
+    pub const fn raw(self) -> u32 {
+        match self {
+            Self::Gray => JPEG_SUBSAMPLE_GRAY,
+            Self::Yuv444 => JPEG_SUBSAMPLE_444,
+            Self::Yuv422 => JPEG_SUBSAMPLE_422,
+            Self::Yuv420 => JPEG_SUBSAMPLE_420,
+        }
+    }
+
+    /// Rows per block-mode strip: 16 for YUV420, 8 otherwise.
+    pub const fn block_height(self) -> u16 {
+        match self {
+            Self::Yuv420 => 16,
+            Self::Gray | Self::Yuv444 | Self::Yuv422 => 8,
+        }
+    }
+}
+
+/// Configuration combinations rejected by the C library.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JpegConfigError {
+    /// A scale or clipper dimension is not a multiple of 8.
+    NotMultipleOf8,
+    /// The clipper is larger than the scaled image on at least one axis.
+    ClipperExceedsScale,
+    /// Scale, clipper and rotate cannot be combined with block mode.
+    ExtendedFeatureInBlockMode,
+    /// Encoder width or height is zero or does not fit the library's `int`.
+    InvalidDimensions,
+    /// Encoder quality must be in 1..=100.
+    QualityOutOfRange,
+    /// The source format cannot be encoded with the requested subsampling.
+    UnsupportedSubsampling,
+    /// Encoder rotation needs YCbYCr input with YUV420 or gray subsampling
+    /// and a size that is a multiple of the MCU.
+    UnsupportedRotation,
+    /// Block-mode encoding needs a height that is a multiple of the block height.
+    HeightNotMultipleOfBlock,
+}
+
+impl core::fmt::Display for JpegConfigError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Self::NotMultipleOf8 => "scale and clipper sizes must be multiples of 8",
+            Self::ClipperExceedsScale => "clipper is larger than the scaled image",
+            Self::ExtendedFeatureInBlockMode => {
+                "scale, clipper and rotate are not available in block mode"
+            }
+            Self::InvalidDimensions => "image size out of range",
+            Self::QualityOutOfRange => "quality must be between 1 and 100",
+            Self::UnsupportedSubsampling => "subsampling not supported for this source format",
+            Self::UnsupportedRotation => "rotation not supported for this configuration",
+            Self::HeightNotMultipleOfBlock => "height must be a multiple of the block height",
+        })
+    }
+}
+
+impl core::error::Error for JpegConfigError {}
+
+/// Validated decoder configuration. Build one with [`JpegDecoderConfig::builder`].
+///
+/// A scale or clipper dimension of 0 leaves that axis unchanged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JpegDecoderConfig {
+    output_format: JpegPixelFormat,
+    scale: (u16, u16),
+    clipper: (u16, u16),
+    rotation: JpegRotation,
+    block_mode: bool,
+}
+
+impl Default for JpegDecoderConfig {
+    /// Block-mode RGB565_LE output with no scale, clipper or rotation.
+    fn default() -> Self {
+        Self {
+            output_format: JpegPixelFormat::Rgb565Le,
+            scale: (0, 0),
+            clipper: (0, 0),
+            rotation: JpegRotation::Deg0,
+            block_mode: true,
+        }
+    }
+}
+
+impl JpegDecoderConfig {
+    pub fn builder() -> JpegDecoderConfigBuilder {
+        JpegDecoderConfigBuilder {
+            config: Self::default(),
+        }
+    }
+
+    pub fn output_format(&self) -> JpegPixelFormat {
+        self.output_format
+    }
+
+    pub fn scale(&self) -> (u16, u16) {
+        self.scale
+    }
+
+    pub fn clipper(&self) -> (u16, u16) {
+        self.clipper
+    }
+
+    pub fn rotation(&self) -> JpegRotation {
+        self.rotation
+    }
+
+    pub fn block_mode(&self) -> bool {
+        self.block_mode
+    }
+
+    /// Output dimensions for a `width`×`height` source, following the
+    /// library's scale → clip → rotate order.
+    pub fn output_size(&self, width: u16, height: u16) -> (u16, u16) {
+        let pick = |requested: u16, current: u16| if requested == 0 { current } else { requested };
+        let w = pick(self.clipper.0, pick(self.scale.0, width));
+        let h = pick(self.clipper.1, pick(self.scale.1, height));
+        if self.rotation.swaps_axes() {
+            (h, w)
+        } else {
+            (w, h)
+        }
+    }
+
+    #[cfg(feature = "esp-new-jpeg")]
+    fn to_raw(self) -> JpegDecConfig {
+        JpegDecConfig {
+            output_type: self.output_format.fourcc(),
+            scale: JpegResolution {
+                width: self.scale.0,
+                height: self.scale.1,
+            },
+            clipper: JpegResolution {
+                width: self.clipper.0,
+                height: self.clipper.1,
+            },
+            rotate: self.rotation.raw(),
+            block_enable: self.block_mode,
+        }
+    }
+}
+
+pub struct JpegDecoderConfigBuilder {
+    config: JpegDecoderConfig,
+}
+
+impl JpegDecoderConfigBuilder {
+    pub fn with_output_format(mut self, format: JpegPixelFormat) -> Self {
+        self.config.output_format = format;
+        self
+    }
+
+    /// Scale the decoded image down (at most 1/8) to `width`×`height`.
+    pub fn with_scale(mut self, width: u16, height: u16) -> Self {
+        self.config.scale = (width, height);
+        self
+    }
+
+    /// Clip the (scaled) image to `width`×`height` from the top-left corner.
+    pub fn with_clipper(mut self, width: u16, height: u16) -> Self {
+        self.config.clipper = (width, height);
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: JpegRotation) -> Self {
+        self.config.rotation = rotation;
+        self
+    }
+
+    pub fn with_block_mode(mut self, enable: bool) -> Self {
+        self.config.block_mode = enable;
+        self
+    }
+
+    pub fn build(self) -> Result<JpegDecoderConfig, JpegConfigError> {
+        let c = self.config;
+        let (sw, sh) = c.scale;
+        let (cw, ch) = c.clipper;
 
-/// `uint32_t esp_log_timestamp(void)`
-#[unsafe(no_mangle)]
-pub extern "C" fn esp_log_timestamp() -> u32 {
-    0
+        if [sw, sh, cw, ch].iter().any(|d| !d.is_multiple_of(8)) {
+            return Err(JpegConfigError::NotMultipleOf8);
+        }
+        if (sw != 0 && cw > sw) || (sh != 0 && ch > sh) {
+            return Err(JpegConfigError::ClipperExceedsScale);
+        }
+        let extended = c.scale != (0, 0) || c.clipper != (0, 0) || c.rotation != JpegRotation::Deg0;
+        if c.block_mode && extended {
+            return Err(JpegConfigError::ExtendedFeatureInBlockMode);
+        }
+        Ok(c)
+    }
 }
 
 // ---------------------------------------------------------------------------
 // Safe wrapper
 // ---------------------------------------------------------------------------
 
+#[cfg(feature = "esp-new-jpeg")]
 pub struct JpegDecoder {
     handle: JpegDecHandle,
+    config: JpegDecoderConfig,
 }
 
+#[derive(Clone, Copy, Debug)]
 pub struct JpegFrameInfo {
     pub width: u16,
     pub height: u16,
 }
 
+/// Header details returned by [`JpegDecoder::inspect`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JpegImageInfo {
+    /// Source image width.
+    pub width: u16,
+    /// Source image height.
+    pub height: u16,
+    /// Number of colour components (1 for grayscale, 3 for YCbCr).
+    pub components: u8,
+    pub subsampling: JpegSubsampling,
+    /// Rows per decoded block: 8 or 16 in block mode, the full output height otherwise.
+    pub block_height: u16,
+    /// Number of `jpeg_dec_process` calls (blocks) for this image.
+    pub process_count: usize,
+    /// Output width after scale, clipper and rotation.
+    pub output_width: u16,
+    /// Output height after scale, clipper and rotation.
+    pub output_height: u16,
+    /// Bytes the output buffer needs for one process call.
+    pub outbuf_len: usize,
+}
+
+#[cfg(feature = "esp-new-jpeg")]
 impl JpegDecoder {
     /// Create a new block-mode JPEG decoder with RGB565_LE output.
-    pub fn new() -> Result<Self, i32> {
-        let mut config = JpegDecConfig {
-            output_type: JPEG_PIXEL_FORMAT_RGB565_LE,
-            scale: JpegResolution {
-                width: 0,
-                height: 0,
-            },
-            clipper: JpegResolution {
-                width: 0,
-                height: 0,
-            },
-            rotate: 0, // JPEG_ROTATE_0D
-            block_enable: true,
-        };
+    pub fn new() -> Result<Self, JpegError> {
+        Self::with_config(JpegDecoderConfig::default())
+    }
+
+    /// Create a decoder from a validated configuration.
+    pub fn with_config(config: JpegDecoderConfig) -> Result<Self, JpegError> {
+        let mut raw = config.to_raw();
         let mut handle: JpegDecHandle = ptr::null_mut();
-        let ret = unsafe { jpeg_dec_open(&mut config, &mut handle) };
-        if ret != 0 {
-            return Err(ret);
+        check(unsafe { jpeg_dec_open(&mut raw, &mut handle) })?;
+        Ok(Self { handle, config })
+    }
+
+    /// Reopen the library handle with the same configuration, releasing
+    /// everything the previous handle allocated. The new handle is opened
+    /// first, so if that fails the decoder keeps the one it had.
+    pub fn reset(&mut self) -> Result<(), JpegError> {
+        let mut raw = self.config.to_raw();
+        let mut handle: JpegDecHandle = ptr::null_mut();
+        check(unsafe { jpeg_dec_open(&mut raw, &mut handle) })?;
+        self.close();
+        self.handle = handle;
+        Ok(())
+    }
+
+    /// Switch to `config`. The old handle is closed first, so the two never
+    /// hold memory at once; if the new one can't be opened, the old
+    /// configuration is opened again and the error returned.
+    pub fn reconfigure(&mut self, config: JpegDecoderConfig) -> Result<(), JpegError> {
+        let old = self.config;
+        self.close();
+        self.config = config;
+        if let Err(e) = self.reset() {
+            self.config = old;
+            // Should this fail too, the next decode tries again
+            let _ = self.reset();
+            return Err(e);
         }
-        Ok(Self { handle })
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        if !self.handle.is_null() {
+            unsafe { jpeg_dec_close(self.handle) };
+            self.handle = ptr::null_mut();
+        }
+    }
+
+    pub fn config(&self) -> &JpegDecoderConfig {
+        &self.config
     }
 
     /// Decode a complete JPEG frame, calling `on_block` for each decoded strip.
     ///
-    /// `on_block(block_index, width, height, rgb565_le_data)` receives the block
-    /// index, dimensions of the decoded strip, and the raw RGB565-LE pixel bytes.
+    /// `on_block(block_index, width, height, pixel_data)` receives the block
+    /// index, dimensions of the decoded strip, and the raw pixel bytes in the
+    /// configured output format.
     pub fn decode<F>(
         &mut self,
         jpeg_data: &mut [u8],
         mut on_block: F,
-    ) -> Result<JpegFrameInfo, i32>
+    ) -> Result<JpegFrameInfo, JpegError>
     where
         F: FnMut(usize, u16, u16, &[u8]),
     {
-        let mut io = JpegDecIo {
-            inbuf: jpeg_data.as_mut_ptr(),
-            inbuf_len: jpeg_data.len() as i32,
-            inbuf_remain: 0,
-            outbuf: ptr::null_mut(),
-            out_size: 0,
-        };
-
-        // Parse header
-        let mut header = JpegDecHeaderInfo {
-            width: 0,
-            height: 0,
-        };
-        let ret = unsafe { jpeg_dec_parse_header(self.handle, &mut io, &mut header) };
-        if ret != 0 {
-            return Err(ret);
-        }
-
-        let info = JpegFrameInfo {
-            width: header.width,
-            height: header.height,
-        };
-
-        // Get output buffer size for one block
-        let mut outbuf_len: i32 = 0;
-        let ret = unsafe { jpeg_dec_get_outbuf_len(self.handle, &mut outbuf_len) };
-        if ret != 0 {
-            return Err(ret);
-        }
-
-        // Allocate 16-byte aligned output buffer via the library's own allocator
-        let outbuf = unsafe { jpeg_calloc_align(outbuf_len as usize, 16) };
-        if outbuf.is_null() {
-            return Err(-2); // JPEG_ERR_NO_MEM
-        }
-        io.outbuf = outbuf as *mut u8;
-
-        // Get number of blocks to process
-        let mut process_count: i32 = 0;
-        let ret = unsafe { jpeg_dec_get_process_count(self.handle, &mut process_count) };
-        if ret != 0 {
-            unsafe { jpeg_free_align(outbuf) };
-            return Err(ret);
-        }
-
-        // Decode block by block
-        for i in 0..process_count as usize {
-            io.out_size = 0;
-            let ret = unsafe { jpeg_dec_process(self.handle, &mut io) };
-            if ret != 0 {
-                unsafe { jpeg_free_align(outbuf) };
-                return Err(ret);
-            }
-
-            let block_data =
-                unsafe { core::slice::from_raw_parts(io.outbuf, io.out_size as usize) };
-
-            // Calculate block height: out_size / (width * 2 bytes per pixel)
-            let block_width = header.width;
-            let block_height = if block_width > 0 {
-                (io.out_size as u16) / (block_width * 2)
-            } else {
-                0
-            };
-
-            on_block(i, block_width, block_height, block_data);
+        let mut session = self.start_decode(jpeg_data)?;
+        for i in 0..session.block_count() {
+            let (block_width, block_height) = session.decode_next_block()?;
+            on_block(i, block_width, block_height, session.block_data());
         }
-
-        unsafe { jpeg_free_align(outbuf) };
-        Ok(info)
+        Ok(*session.info())
     }
 }
 
 /// Step-by-step decode session. Allows yielding to the async executor between
 /// blocks so the network task can process TCP ACKs during display writes.
+///
+/// The library keeps reading the input through `io.inbuf` while the session
+/// lives, so the session mutably borrows both the decoder and the JPEG data.
+/// The input cannot be touched until the session is dropped:
+///
+/// ```compile_fail,E0506
+/// # use rumble_rs::jpeg::JpegDecoder;
+/// # let mut decoder = JpegDecoder::new().unwrap();
+/// let mut frame_buf = [0u8; 64];
+/// let mut session = decoder.start_decode(&mut frame_buf).unwrap();
+/// frame_buf[0] = 0; // input is still borrowed by the session
+/// session.decode_next_block().unwrap();
+/// ```
+///
+/// ```compile_fail,E0597
+/// # use rumble_rs::jpeg::JpegDecoder;
+/// # let mut decoder = JpegDecoder::new().unwrap();
+/// let mut session = {
+///     let mut frame_buf = [0u8; 64];
+///     decoder.start_decode(&mut frame_buf).unwrap()
+/// }; // input dropped while the session still points at it
+/// session.decode_next_block().unwrap();
+/// ```
+///
+/// Decoded blocks are borrowed from the session's output buffer, which the
+/// next `decode_next_block()` overwrites:
+///
+/// ```compile_fail,E0502
+/// # use rumble_rs::jpeg::JpegDecoder;
+/// # let mut decoder = JpegDecoder::new().unwrap();
+/// # let mut frame_buf = [0u8; 64];
+/// let mut session = decoder.start_decode(&mut frame_buf).unwrap();
+/// session.decode_next_block().unwrap();
+/// let first = session.block_data();
+/// session.decode_next_block().unwrap();
+/// let _ = first[0];
+/// ```
+#[cfg(feature = "esp-new-jpeg")]
 pub struct DecodeSession<'a> {
     decoder: &'a mut JpegDecoder,
+    _input: PhantomData<&'a mut [u8]>,
     io: JpegDecIo,
     info: JpegFrameInfo,
     outbuf: *mut c_void,
     block_count: usize,
     current_block: usize,
-    header_width: u16,
+    output_width: u16,
+    bytes_per_pixel: usize,
 }
 
+#[cfg(feature = "esp-new-jpeg")]
 impl JpegDecoder {
     /// Begin decoding a JPEG frame. Returns a session that yields one block at a time.
+    ///
+    /// `jpeg_data` stays borrowed until the session is dropped. The frame is
+    /// validated by [`markers::parse`] first; bytes after EOI are ignored.
     pub fn start_decode<'a>(
         &'a mut self,
-        jpeg_data: &mut [u8],
-    ) -> Result<DecodeSession<'a>, i32> {
+        jpeg_data: &'a mut [u8],
+    ) -> Result<DecodeSession<'a>, JpegError> {
+        let summary = markers::parse(jpeg_data)?;
+        let inbuf_len = i32::try_from(summary.len).map_err(|_| JpegError::InputTooLarge)?;
         let mut io = JpegDecIo {
             inbuf: jpeg_data.as_mut_ptr(),
-            inbuf_len: jpeg_data.len() as i32,
+            inbuf_len,
             inbuf_remain: 0,
             outbuf: ptr::null_mut(),
             out_size: 0,
         };
 
-        let mut header = JpegDecHeaderInfo {
-            width: 0,
-            height: 0,
-        };
-        let ret = unsafe { jpeg_dec_parse_header(self.handle, &mut io, &mut header) };
-        if ret != 0 {
-            return Err(ret);
-        }
-
-        let mut outbuf_len: i32 = 0;
-        let ret = unsafe { jpeg_dec_get_outbuf_len(self.handle, &mut outbuf_len) };
-        if ret != 0 {
-            return Err(ret);
-        }
+        let (info, outbuf_len, process_count) = self.parse_header(&mut io)?;
 
-        let outbuf = unsafe { jpeg_calloc_align(outbuf_len as usize, 16) };
+        let outbuf = unsafe { jpeg_calloc_align(outbuf_len, 16) };
         if outbuf.is_null() {
-            return Err(-2);
+            return Err(JpegError::NoMemory);
         }
         io.outbuf = outbuf as *mut u8;
 
-        let mut process_count: i32 = 0;
-        let ret = unsafe { jpeg_dec_get_process_count(self.handle, &mut process_count) };
-        if ret != 0 {
-            unsafe { jpeg_free_align(outbuf) };
-            return Err(ret);
-        }
+        let (output_width, _) = self.config.output_size(info.width, info.height);
+        let bytes_per_pixel = self.config.output_format.bytes_per_pixel();
 
         Ok(DecodeSession {
             decoder: self,
+            _input: PhantomData,
             io,
-            info: JpegFrameInfo {
-                width: header.width,
-                height: header.height,
-            },
+            info,
             outbuf,
-            block_count: process_count as usize,
+            block_count: process_count,
             current_block: 0,
-            header_width: header.width,
+            output_width,
+            bytes_per_pixel,
         })
     }
 }
 
+#[cfg(feature = "esp-new-jpeg")]
+impl JpegDecoder {
+    /// Parse the header of `jpeg_data` and report its geometry without
+    /// allocating an output buffer or starting a decode.
+    pub fn inspect(&mut self, jpeg_data: &[u8]) -> Result<JpegImageInfo, JpegError> {
+        let summary = markers::parse(jpeg_data)?;
+        let subsampling = summary.subsampling().ok_or(JpegError::UnsupportedFormat)?;
+        let components = summary.components().len() as u8;
+
+        let inbuf_len = i32::try_from(summary.len).map_err(|_| JpegError::InputTooLarge)?;
+        let mut io = JpegDecIo {
+            // The library only reads the input while parsing the header.
+            inbuf: jpeg_data.as_ptr() as *mut u8,
+            inbuf_len,
+            inbuf_remain: 0,
+            outbuf: ptr::null_mut(),
+            out_size: 0,
+        };
+        let (info, outbuf_len, process_count) = self.parse_header(&mut io)?;
+
+        let (output_width, output_height) = self.config.output_size(info.width, info.height);
+        let block_height = if self.config.block_mode {
+            subsampling.block_height()
+        } else {
+            output_height
+        };
+
+        Ok(JpegImageInfo {
+            width: info.width,
+            height: info.height,
+            components,
+            subsampling,
+            block_height,
+            process_count,
+            output_width,
+            output_height,
+            outbuf_len,
+        })
+    }
+
+    /// Run `jpeg_dec_parse_header` and return the image size together with
+    /// the per-call output buffer length and the number of process calls.
+    fn parse_header(
+        &mut self,
+        io: &mut JpegDecIo,
+    ) -> Result<(JpegFrameInfo, usize, usize), JpegError> {
+        // Never hand the library a null handle; failing to reopen is an
+        // error the caller can recover from
+        if self.handle.is_null() {
+            self.reset()?;
+        }
+        let mut header = JpegDecHeaderInfo::default();
+        check(unsafe { jpeg_dec_parse_header(self.handle, io, &mut header) })?;
+        let info = JpegFrameInfo {
+            width: header.width,
+            height: header.height,
+        };
+
+        let mut outbuf_len: i32 = 0;
+        check(unsafe { jpeg_dec_get_outbuf_len(self.handle, &mut outbuf_len) })?;
+
+        let mut process_count: i32 = 0;
+        check(unsafe { jpeg_dec_get_process_count(self.handle, &mut process_count) })?;
+
+        Ok((info, outbuf_len as usize, process_count as usize))
+    }
+}
+
+#[cfg(feature = "esp-new-jpeg")]
 impl<'a> DecodeSession<'a> {
     pub fn info(&self) -> &JpegFrameInfo {
         &self.info
@@ -361,34 +925,42 @@ impl<'a> DecodeSession<'a> {
         self.block_count
     }
 
+    /// Width of every decoded block after scale, clipper and rotation.
+    pub fn output_width(&self) -> u16 {
+        self.output_width
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bytes_per_pixel
+    }
+
     /// Decode the next block. Returns `(block_width, block_height)`.
-    /// The decoded pixel data is available via `block_data()` until the next call.
-    pub fn decode_next_block(&mut self) -> Result<(u16, u16), i32> {
+    /// The decoded pixel data is available via `block_data()` until the next call;
+    /// the borrow checker rejects holding it across this call.
+    pub fn decode_next_block(&mut self) -> Result<(u16, u16), JpegError> {
         if self.current_block >= self.block_count {
-            return Err(-3); // no more blocks
+            return Err(JpegError::SessionExhausted);
         }
         self.io.out_size = 0;
-        let ret = unsafe { jpeg_dec_process(self.decoder.handle, &mut self.io) };
-        if ret != 0 {
-            return Err(ret);
-        }
+        check(unsafe { jpeg_dec_process(self.decoder.handle, &mut self.io) })?;
         self.current_block += 1;
 
-        let block_width = self.header_width;
-        let block_height = if block_width > 0 {
-            (self.io.out_size as u16) / (block_width * 2)
-        } else {
-            0
-        };
+        let block_width = self.output_width;
+        let row_bytes = block_width as usize * self.bytes_per_pixel;
+        let block_height = (self.io.out_size as usize)
+            .checked_div(row_bytes)
+            .unwrap_or(0) as u16;
         Ok((block_width, block_height))
     }
 
-    /// Raw RGB565-LE pixel data for the most recently decoded block.
+    /// Raw pixel data, in the configured output format, for the most recently
+    /// decoded block.
     pub fn block_data(&self) -> &[u8] {
         unsafe { core::slice::from_raw_parts(self.io.outbuf, self.io.out_size as usize) }
     }
 }
 
+#[cfg(feature = "esp-new-jpeg")]
 impl<'a> Drop for DecodeSession<'a> {
     fn drop(&mut self) {
         if !self.outbuf.is_null() {
@@ -397,10 +969,9 @@ impl<'a> Drop for DecodeSession<'a> {
     }
 }
 
+#[cfg(feature = "esp-new-jpeg")]
 impl Drop for JpegDecoder {
     fn drop(&mut self) {
-        if !self.handle.is_null() {
-            unsafe { jpeg_dec_close(self.handle) };
-        }
+        self.close();
     }
 }
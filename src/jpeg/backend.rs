@@ -0,0 +1,139 @@
+//! The block-by-block decoding API, independent of the decoder behind it.
+//!
+//! [`JpegBackend`] and [`JpegSession`] mirror [`JpegDecoder`] and
+//! [`DecodeSession`], so code written against them runs on ESP_NEW_JPEG, the
+//! ROM's [`RomJpegDecoder`] or the pure-Rust [`BaselineDecoder`], whichever
+//! features are enabled.
+//!
+//! [`JpegDecoder`]: super::JpegDecoder
+//! [`DecodeSession`]: super::DecodeSession
+//! [`RomJpegDecoder`]: super::rom::RomJpegDecoder
+//! [`BaselineDecoder`]: super::baseline::BaselineDecoder
+
+use super::{JpegDecoderConfig, JpegError, JpegFrameInfo};
+
+/// A JPEG decoder that produces one strip of the image at a time.
+pub trait JpegBackend: Sized {
+    type Session<'a>: JpegSession
+    where
+        Self: 'a;
+
+    /// Create a decoder from a validated configuration.
+    fn with_config(config: JpegDecoderConfig) -> Result<Self, JpegError>;
+
+    fn config(&self) -> &JpegDecoderConfig;
+
+    /// Release everything the decoder allocated, keeping its configuration.
+    fn reset(&mut self) -> Result<(), JpegError>;
+
+    /// Switch to `config`, releasing what the old configuration allocated
+    /// first. If the decoder can't take it, it keeps the old one.
+    fn reconfigure(&mut self, config: JpegDecoderConfig) -> Result<(), JpegError> {
+        *self = Self::with_config(config)?;
+        Ok(())
+    }
+
+    /// Begin decoding a JPEG frame. `jpeg_data` stays borrowed until the
+    /// session is dropped.
+    fn start_decode<'a>(
+        &'a mut self,
+        jpeg_data: &'a mut [u8],
+    ) -> Result<Self::Session<'a>, JpegError>;
+
+    /// Decode a complete JPEG frame, calling `on_block(block_index, width,
+    /// height, pixel_data)` for each decoded strip.
+    fn decode<F>(
+        &mut self,
+        jpeg_data: &mut [u8],
+        mut on_block: F,
+    ) -> Result<JpegFrameInfo, JpegError>
+    where
+        F: FnMut(usize, u16, u16, &[u8]),
+    {
+        let mut session = self.start_decode(jpeg_data)?;
+        for i in 0..session.block_count() {
+            let (block_width, block_height) = session.decode_next_block()?;
+            on_block(i, block_width, block_height, session.block_data());
+        }
+        Ok(*session.info())
+    }
+}
+
+/// One frame being decoded, see [`JpegBackend::start_decode`].
+pub trait JpegSession {
+    fn info(&self) -> &JpegFrameInfo;
+
+    fn block_count(&self) -> usize;
+
+    /// Width of every decoded block after scale, clipper and rotation.
+    fn output_width(&self) -> u16;
+
+    fn bytes_per_pixel(&self) -> usize;
+
+    /// Decode the next block. Returns `(block_width, block_height)`, or
+    /// [`JpegError::SessionExhausted`] after the last one.
+    fn decode_next_block(&mut self) -> Result<(u16, u16), JpegError>;
+
+    /// Pixel data, in the configured output format, for the most recently
+    /// decoded block.
+    fn block_data(&self) -> &[u8];
+}
+
+#[cfg(feature = "esp-new-jpeg")]
+mod esp_new_jpeg {
+    use super::{JpegBackend, JpegSession};
+    use crate::jpeg::{DecodeSession, JpegDecoder, JpegDecoderConfig, JpegError, JpegFrameInfo};
+
+    impl JpegBackend for JpegDecoder {
+        type Session<'a> = DecodeSession<'a>;
+
+        fn with_config(config: JpegDecoderConfig) -> Result<Self, JpegError> {
+            JpegDecoder::with_config(config)
+        }
+
+        fn config(&self) -> &JpegDecoderConfig {
+            JpegDecoder::config(self)
+        }
+
+        fn reset(&mut self) -> Result<(), JpegError> {
+            JpegDecoder::reset(self)
+        }
+
+        fn reconfigure(&mut self, config: JpegDecoderConfig) -> Result<(), JpegError> {
+            JpegDecoder::reconfigure(self, config)
+        }
+
+        fn start_decode<'a>(
+            &'a mut self,
+            jpeg_data: &'a mut [u8],
+        ) -> Result<DecodeSession<'a>, JpegError> {
+            JpegDecoder::start_decode(self, jpeg_data)
+        }
+    }
+
+    impl JpegSession for DecodeSession<'_> {
+        fn info(&self) -> &JpegFrameInfo {
+            DecodeSession::info(self)
+        }
+
+        fn block_count(&self) -> usize {
+            DecodeSession::block_count(self)
+        }
+
+        fn output_width(&self) -> u16 {
+            DecodeSession::output_width(self)
+        }
+
+        fn bytes_per_pixel(&self) -> usize {
+            DecodeSession::bytes_per_pixel(self)
+        }
+
+        fn decode_next_block(&mut self) -> Result<(u16, u16), JpegError> {
+            DecodeSession::decode_next_block(self)
+        }
+
+        fn block_data(&self) -> &[u8] {
+            DecodeSession::block_data(self)
+        }
+    }
+}
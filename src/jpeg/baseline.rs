@@ -0,0 +1,1208 @@
+//! A pure-Rust baseline JPEG decoder.
+//!
+//! [`BaselineDecoder`] decodes the frames ESP_NEW_JPEG takes (8-bit Huffman
+//! SOF0/SOF1, one scan, grayscale or YCbCr 4:4:4, 4:2:2 and 4:2:0) on any
+//! target, including frames without DHT that rely on the standard tables.
+//! In block mode it produces one MCU row per block, 8 rows high or 16 for
+//! 4:2:0, like the C library. Chroma is upsampled by repeating samples and
+//! scaling picks the nearest pixel, so it trades some quality for speed;
+//! rotation is not supported.
+//!
+//! Unlike the C library, it doesn't need the whole frame up front: through
+//! a [`BaselineStream`] it starts on a frame once its headers are in, and
+//! finishes each block as soon as that block's entropy-coded data arrives.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::num::Wrapping;
+
+use super::backend::{JpegBackend, JpegSession};
+use super::markers::{self, DHT, DQT, FrameSummary, MarkerError, RST0, RST7, SOI, SOS, TEM};
+use super::{JpegDecoderConfig, JpegError, JpegFrameInfo, JpegPixelFormat, JpegRotation};
+
+// ---------------------------------------------------------------------------
+// Tables
+// ---------------------------------------------------------------------------
+
+/// Position in the 8×8 block of each coefficient, in zigzag order.
+const ZIGZAG: [u8; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+// The standard Huffman tables (ITU T.81 K.3), which MJPEG without DHT uses.
+
+const DC_LUMA_COUNTS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const DC_CHROMA_COUNTS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+
+const AC_LUMA_COUNTS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7D];
+const AC_LUMA_VALUES: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xA1, 0x08, 0x23, 0x42, 0xB1, 0xC1, 0x15, 0x52, 0xD1, 0xF0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0A, 0x16, 0x17, 0x18, 0x19, 0x1A, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2A, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3A, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4A, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5A, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6A, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7A, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8A, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9A, 0xA2, 0xA3, 0xA4, 0xA5, 0xA6, 0xA7,
+    0xA8, 0xA9, 0xAA, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6, 0xB7, 0xB8, 0xB9, 0xBA, 0xC2, 0xC3, 0xC4, 0xC5,
+    0xC6, 0xC7, 0xC8, 0xC9, 0xCA, 0xD2, 0xD3, 0xD4, 0xD5, 0xD6, 0xD7, 0xD8, 0xD9, 0xDA, 0xE1, 0xE2,
+    0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8, 0xE9, 0xEA, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8,
+    0xF9, 0xFA,
+];
+
+const AC_CHROMA_COUNTS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+const AC_CHROMA_VALUES: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xA1, 0xB1, 0xC1, 0x09, 0x23, 0x33, 0x52, 0xF0,
+    0x15, 0x62, 0x72, 0xD1, 0x0A, 0x16, 0x24, 0x34, 0xE1, 0x25, 0xF1, 0x17, 0x18, 0x19, 0x1A, 0x26,
+    0x27, 0x28, 0x29, 0x2A, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3A, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4A, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5A, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6A, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7A, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8A, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9A, 0xA2, 0xA3, 0xA4, 0xA5,
+    0xA6, 0xA7, 0xA8, 0xA9, 0xAA, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6, 0xB7, 0xB8, 0xB9, 0xBA, 0xC2, 0xC3,
+    0xC4, 0xC5, 0xC6, 0xC7, 0xC8, 0xC9, 0xCA, 0xD2, 0xD3, 0xD4, 0xD5, 0xD6, 0xD7, 0xD8, 0xD9, 0xDA,
+    0xE2, 0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8, 0xE9, 0xEA, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8,
+    0xF9, 0xFA,
+];
+
+/// Codes up to this long are decoded with a single table lookup.
+const LOOKUP_BITS: u32 = 9;
+
+/// A canonical Huffman table.
+#[derive(Clone)]
+struct Huffman {
+    /// Indexed by the next `LOOKUP_BITS` bits: code length << 8 | value,
+    /// or 0 for longer codes.
+    lookup: [u16; 1 << LOOKUP_BITS],
+    /// Largest code of each length, -1 if there are none.
+    maxcode: [i32; 17],
+    /// Added to a code of each length to find its index in `values`.
+    valptr: [i32; 17],
+    values: [u8; 256],
+}
+
+impl Huffman {
+    /// A table that decodes nothing, for slots the frame never defined.
+    const EMPTY: Self = Self {
+        lookup: [0; 1 << LOOKUP_BITS],
+        maxcode: [-1; 17],
+        valptr: [0; 17],
+        values: [0; 256],
+    };
+
+    /// Build the table from a DHT entry: the number of codes of each length
+    /// 1..=16, then the values in code order.
+    fn build(&mut self, counts: &[u8; 16], values: &[u8]) -> Result<(), JpegError> {
+        *self = Self::EMPTY;
+        self.values[..values.len()].copy_from_slice(values);
+        let mut code = 0i32;
+        let mut k = 0usize;
+        for len in 1..=16u32 {
+            let n = counts[len as usize - 1] as usize;
+            // More codes than fit in `len` bits would run off the lookup
+            if code + n as i32 > 1 << len {
+                return Err(JpegError::BadData);
+            }
+            self.valptr[len as usize] = k as i32 - code;
+            for &value in &values[k..k + n] {
+                if len <= LOOKUP_BITS {
+                    let shift = LOOKUP_BITS - len;
+                    let first = (code << shift) as usize;
+                    self.lookup[first..first + (1 << shift)].fill((len << 8) as u16 | value as u16);
+                }
+                code += 1;
+            }
+            k += n;
+            if n > 0 {
+                self.maxcode[len as usize] = code - 1;
+            }
+            code <<= 1;
+        }
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Entropy-coded data
+// ---------------------------------------------------------------------------
+
+/// Reads the bits of a scan, removing stuffed zero bytes. At a marker it
+/// stops and feeds zeros, as the C library does.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    acc: u32,
+    /// Unread bits at the bottom of `acc`.
+    count: u32,
+    at_marker: bool,
+    /// More of the scan may still arrive after `data`. Running out of it then
+    /// sets `starved` and feeds zeros, instead of ending the scan.
+    more: bool,
+    starved: bool,
+}
+
+/// Where a [`BitReader`] is in its data, to go back to or to carry on from
+/// once more has arrived.
+#[derive(Clone, Copy, Default)]
+struct Position {
+    pos: usize,
+    acc: u32,
+    count: u32,
+    at_marker: bool,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            acc: 0,
+            count: 0,
+            at_marker: false,
+            more: false,
+            starved: false,
+        }
+    }
+
+    /// A reader of the part of a scan that has arrived so far, picking up
+    /// at `position`.
+    fn resume(data: &'a [u8], position: Position) -> Self {
+        let mut reader = Self {
+            more: true,
+            ..Self::new(data)
+        };
+        reader.seek(position);
+        reader
+    }
+
+    fn position(&self) -> Position {
+        Position {
+            pos: self.pos,
+            acc: self.acc,
+            count: self.count,
+            at_marker: self.at_marker,
+        }
+    }
+
+    /// Go back to `position`, forgetting that the data ran out since.
+    fn seek(&mut self, position: Position) {
+        self.pos = position.pos;
+        self.acc = position.acc;
+        self.count = position.count;
+        self.at_marker = position.at_marker;
+        self.starved = false;
+    }
+
+    /// The data ran out where the scan goes on: stop at a marker, or note
+    /// that more must arrive.
+    fn end_of_data(&mut self) {
+        if self.more {
+            self.starved = true;
+        } else {
+            self.at_marker = true;
+        }
+    }
+
+    /// Top up `acc` to at least 25 bits.
+    fn fill(&mut self) {
+        while self.count <= 24 {
+            let mut byte = 0;
+            if !self.at_marker {
+                match self.data.get(self.pos) {
+                    Some(0xFF) => match self.data.get(self.pos + 1) {
+                        Some(0x00) => {
+                            byte = 0xFF;
+                            self.pos += 2;
+                        }
+                        // Fill byte before a marker
+                        Some(0xFF) => {
+                            self.pos += 1;
+                            continue;
+                        }
+                        Some(_) => self.at_marker = true,
+                        None => self.end_of_data(),
+                    },
+                    Some(&b) => {
+                        byte = b;
+                        self.pos += 1;
+                    }
+                    None => self.end_of_data(),
+                }
+            }
+            self.acc = (self.acc << 8) | byte as u32;
+            self.count += 8;
+        }
+    }
+
+    /// The next `n` (1..=16) bits, which must already be in `acc`.
+    fn peek(&self, n: u32) -> u32 {
+        (self.acc >> (self.count - n)) & ((1 << n) - 1)
+    }
+
+    fn decode(&mut self, table: &Huffman) -> Result<u8, JpegError> {
+        self.fill();
+        let entry = table.lookup[self.peek(LOOKUP_BITS) as usize];
+        if entry != 0 {
+            self.count -= (entry >> 8) as u32;
+            return Ok(entry as u8);
+        }
+        for len in LOOKUP_BITS + 1..=16 {
+            let code = self.peek(len) as i32;
+            if code <= table.maxcode[len as usize] {
+                self.count -= len;
+                return Ok(table.values[(code + table.valptr[len as usize]) as usize]);
+            }
+        }
+        Err(JpegError::BadData)
+    }
+
+    /// Read an `s`-bit (0..=16) coefficient and sign-extend it.
+    fn receive_extend(&mut self, s: u8) -> Result<i32, JpegError> {
+        if s == 0 {
+            return Ok(0);
+        }
+        if s > 16 {
+            return Err(JpegError::BadData);
+        }
+        self.fill();
+        let s = s as u32;
+        let v = self.peek(s) as i32;
+        self.count -= s;
+        Ok(if v < 1 << (s - 1) {
+            v - (1 << s) + 1
+        } else {
+            v
+        })
+    }
+
+    /// Drop the padding bits of the current interval and move past the
+    /// restart marker that ends it.
+    fn restart(&mut self) -> Result<(), JpegError> {
+        self.acc = 0;
+        self.count = 0;
+        self.at_marker = false;
+        while let Some(&b) = self.data.get(self.pos) {
+            self.pos += 1;
+            if b == 0xFF && matches!(self.data.get(self.pos), Some(RST0..=RST7)) {
+                self.pos += 1;
+                return Ok(());
+            }
+        }
+        if self.more {
+            self.starved = true;
+            return Ok(());
+        }
+        Err(JpegError::BadData)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Decoder
+// ---------------------------------------------------------------------------
+
+/// Pure-Rust counterpart of [`JpegDecoder`](super::JpegDecoder), through
+/// [`JpegBackend`].
+///
+/// The tables and buffers a frame needs are kept for the next one, until
+/// [`JpegBackend::reset`].
+pub struct BaselineDecoder {
+    config: JpegDecoderConfig,
+    /// DC tables 0..=3, then AC tables 0..=3.
+    huffman: Vec<Huffman>,
+    /// In zigzag order, as in DQT.
+    quant: Vec<[u16; 64]>,
+    /// Samples of the MCU row being decoded, one plane per component.
+    planes: Vec<u8>,
+    /// Output of the current block.
+    out: Vec<u8>,
+    /// Source column of each output column.
+    columns: Vec<u16>,
+}
+
+impl BaselineDecoder {
+    /// Create a new block-mode decoder with RGB565_LE output.
+    pub fn new() -> Result<Self, JpegError> {
+        Self::with_config(JpegDecoderConfig::default())
+    }
+
+    /// Begin decoding the next frame of `stream` before all of it has
+    /// arrived. Returns [`JpegError::NoMoreData`] until its headers are in,
+    /// as [`BaselineStream::header`] does; a frame that can't be decoded is
+    /// skipped.
+    pub fn start_stream<'a>(
+        &'a mut self,
+        stream: &'a mut BaselineStream,
+    ) -> Result<StreamSession<'a>, JpegError> {
+        let summary = stream.summary()?;
+        let header = &stream.buf[stream.start..stream.start + summary.scan_offset];
+        match Frame::new(self, &summary, header) {
+            Ok(frame) => {
+                stream.start += summary.scan_offset;
+                stream.header = None;
+                Ok(StreamSession {
+                    decoder: self,
+                    stream,
+                    frame,
+                    position: Position::default(),
+                })
+            }
+            Err(e) => {
+                stream.skip_frame();
+                Err(e)
+            }
+        }
+    }
+}
+
+impl JpegBackend for BaselineDecoder {
+    type Session<'a> = BaselineSession<'a>;
+
+    /// Create a decoder from a validated configuration. Rotation is refused
+    /// with [`JpegError::InvalidParam`].
+    fn with_config(config: JpegDecoderConfig) -> Result<Self, JpegError> {
+        if config.rotation() != JpegRotation::Deg0 {
+            return Err(JpegError::InvalidParam);
+        }
+        Ok(Self {
+            config,
+            huffman: Vec::new(),
+            quant: Vec::new(),
+            planes: Vec::new(),
+            out: Vec::new(),
+            columns: Vec::new(),
+        })
+    }
+
+    fn config(&self) -> &JpegDecoderConfig {
+        &self.config
+    }
+
+    fn reset(&mut self) -> Result<(), JpegError> {
+        *self = Self::with_config(self.config)?;
+        Ok(())
+    }
+
+    fn start_decode<'a>(
+        &'a mut self,
+        jpeg_data: &'a mut [u8],
+    ) -> Result<BaselineSession<'a>, JpegError> {
+        BaselineSession::new(self, jpeg_data)
+    }
+}
+
+/// One component of the scan.
+#[derive(Clone, Copy, Default)]
+struct Component {
+    /// Blocks per MCU across and down.
+    h: u8,
+    v: u8,
+    /// 1 if the component has half the resolution of the frame on that axis.
+    hshift: u8,
+    vshift: u8,
+    quant: usize,
+    dc: usize,
+    ac: usize,
+    pred: i32,
+    /// Where its plane starts in `planes`, and the plane's row length.
+    offset: usize,
+    stride: usize,
+}
+
+/// How far a frame has got, wherever its bytes come from.
+struct Frame {
+    info: JpegFrameInfo,
+    components: [Component; 3],
+    component_count: usize,
+    mcus_x: u16,
+    /// Source rows per MCU row.
+    mcu_height: u16,
+    next_mcu_row: u16,
+    /// The next MCU of the row being decoded.
+    next_mcu: u16,
+    restart_interval: u16,
+    restarts_left: u16,
+    format: JpegPixelFormat,
+    output_width: u16,
+    output_height: u16,
+    /// Scaled height of the source, before clipping.
+    scaled_height: u16,
+    next_output_row: u16,
+    block_count: usize,
+    current_block: usize,
+    /// The current block is partly decoded, `block_rows` rows so far.
+    in_block: bool,
+    block_rows: u16,
+    out_len: usize,
+}
+
+impl Frame {
+    /// Get `decoder` ready for the frame `summary` describes, loading the
+    /// tables from `header`, which runs from SOI through SOS at least.
+    fn new(
+        decoder: &mut BaselineDecoder,
+        summary: &FrameSummary,
+        header: &[u8],
+    ) -> Result<Self, JpegError> {
+        summary.subsampling().ok_or(JpegError::UnsupportedFormat)?;
+
+        if decoder.huffman.is_empty() {
+            decoder.huffman = vec![Huffman::EMPTY; 8];
+            decoder.quant = vec![[0; 64]; 4];
+        }
+        decoder.huffman[0].build(&DC_LUMA_COUNTS, &DC_VALUES)?;
+        decoder.huffman[1].build(&DC_CHROMA_COUNTS, &DC_VALUES)?;
+        decoder.huffman[4].build(&AC_LUMA_COUNTS, &AC_LUMA_VALUES)?;
+        decoder.huffman[5].build(&AC_CHROMA_COUNTS, &AC_CHROMA_VALUES)?;
+        let scan = read_tables(header, &mut decoder.huffman, &mut decoder.quant)?;
+
+        // The scan must cover every component, in frame order
+        let frame = summary.components();
+        let count = scan[0] as usize;
+        if count != frame.len()
+            || scan[1..1 + 2 * count]
+                .chunks_exact(2)
+                .zip(frame)
+                .any(|(s, c)| s[0] != c.id)
+        {
+            return Err(JpegError::UnsupportedStandard);
+        }
+        let mut components = [Component::default(); 3];
+        for ((c, s), f) in components
+            .iter_mut()
+            .zip(scan[1..].chunks_exact(2))
+            .zip(frame)
+        {
+            // A single component is never interleaved, whatever its factors
+            let (h, v) = if count == 1 { (1, 1) } else { (f.h, f.v) };
+            *c = Component {
+                h,
+                v,
+                quant: f.tq as usize,
+                dc: (s[1] >> 4) as usize,
+                ac: 4 + (s[1] & 0x0F) as usize,
+                ..Component::default()
+            };
+        }
+        let components_used = &mut components[..count];
+        let hmax = components_used.iter().map(|c| c.h).max().unwrap_or(1);
+        let vmax = components_used.iter().map(|c| c.v).max().unwrap_or(1);
+
+        let (width, height) = (summary.width, summary.height);
+        let mcus_x = width.div_ceil(8 * hmax as u16);
+        let mcu_height = 8 * vmax as u16;
+        let mut planes_len = 0;
+        for c in components_used.iter_mut() {
+            c.hshift = (c.h < hmax) as u8;
+            c.vshift = (c.v < vmax) as u8;
+            c.offset = planes_len;
+            c.stride = mcus_x as usize * c.h as usize * 8;
+            planes_len += c.stride * c.v as usize * 8;
+        }
+        if decoder.planes.len() < planes_len {
+            decoder.planes.resize(planes_len, 0);
+        }
+
+        // Scale down to the nearest pixel, then clip from the top left
+        let config = decoder.config;
+        let pick = |requested: u16, current: u16| if requested == 0 { current } else { requested };
+        let (scaled_width, scaled_height) = (
+            pick(config.scale().0, width),
+            pick(config.scale().1, height),
+        );
+        let (output_width, output_height) = config.output_size(width, height);
+        if scaled_width > width
+            || scaled_height > height
+            || output_width > scaled_width
+            || output_height > scaled_height
+        {
+            return Err(JpegError::InvalidParam);
+        }
+        decoder.columns.clear();
+        decoder.columns.extend(
+            (0..output_width as u32).map(|x| (x * width as u32 / scaled_width as u32) as u16),
+        );
+
+        let (block_count, block_height) = if config.block_mode() {
+            (height.div_ceil(mcu_height) as usize, mcu_height)
+        } else {
+            (1, output_height)
+        };
+        let format = config.output_format();
+        let out_len = output_width as usize * block_height as usize * format.bytes_per_pixel();
+        if decoder.out.len() < out_len {
+            decoder.out.resize(out_len, 0);
+        }
+
+        Ok(Self {
+            info: JpegFrameInfo { width, height },
+            components,
+            component_count: count,
+            mcus_x,
+            mcu_height,
+            next_mcu_row: 0,
+            next_mcu: 0,
+            restart_interval: summary.restart_interval,
+            restarts_left: summary.restart_interval,
+            format,
+            output_width,
+            output_height,
+            scaled_height,
+            next_output_row: 0,
+            block_count,
+            current_block: 0,
+            in_block: false,
+            block_rows: 0,
+            out_len: 0,
+        })
+    }
+
+    /// Decode the next block, or carry on with the one the data ran out in.
+    fn decode_next_block(
+        &mut self,
+        decoder: &mut BaselineDecoder,
+        reader: &mut BitReader,
+    ) -> Result<(u16, u16), JpegError> {
+        if self.current_block >= self.block_count {
+            return Err(JpegError::SessionExhausted);
+        }
+        if !self.in_block {
+            self.in_block = true;
+            self.block_rows = 0;
+            self.out_len = 0;
+        }
+        // Outside block mode the only block is the whole output; stop as soon
+        // as it's complete, even if the clipper left rows undecoded.
+        while self.next_output_row < self.output_height {
+            let top = self.next_mcu_row * self.mcu_height;
+            self.decode_mcu_row(decoder, reader)?;
+            self.next_mcu_row += 1;
+            let bottom = (top + self.mcu_height).min(self.info.height);
+            while self.next_output_row < self.output_height {
+                let y = (self.next_output_row as u32 * self.info.height as u32
+                    / self.scaled_height as u32) as u16;
+                if y >= bottom {
+                    break;
+                }
+                self.write_row(decoder, y - top);
+                self.next_output_row += 1;
+                self.block_rows += 1;
+            }
+            if decoder.config.block_mode() {
+                break;
+            }
+        }
+        self.in_block = false;
+        self.current_block += 1;
+        Ok((self.output_width, self.block_rows))
+    }
+
+    /// Decode the rest of the current row of MCUs into the planes. If the
+    /// reader runs out of data, the MCU it was in is undone and
+    /// [`JpegError::NoMoreData`] returned, to carry on from there.
+    fn decode_mcu_row(
+        &mut self,
+        decoder: &mut BaselineDecoder,
+        reader: &mut BitReader,
+    ) -> Result<(), JpegError> {
+        while self.next_mcu < self.mcus_x {
+            let (position, components, restarts_left) =
+                (reader.position(), self.components, self.restarts_left);
+            let decoded = self.decode_mcu(decoder, reader);
+            if reader.starved {
+                reader.seek(position);
+                self.components = components;
+                self.restarts_left = restarts_left;
+                return Err(JpegError::NoMoreData);
+            }
+            decoded?;
+            self.next_mcu += 1;
+        }
+        self.next_mcu = 0;
+        Ok(())
+    }
+
+    /// Decode the next MCU of the row into the planes.
+    fn decode_mcu(
+        &mut self,
+        decoder: &mut BaselineDecoder,
+        reader: &mut BitReader,
+    ) -> Result<(), JpegError> {
+        let BaselineDecoder {
+            huffman,
+            quant,
+            planes,
+            ..
+        } = decoder;
+        let components = &mut self.components[..self.component_count];
+        if self.restart_interval != 0 {
+            if self.restarts_left == 0 {
+                reader.restart()?;
+                components.iter_mut().for_each(|c| c.pred = 0);
+                self.restarts_left = self.restart_interval;
+            }
+            self.restarts_left -= 1;
+        }
+        let mx = self.next_mcu as usize;
+        for c in components.iter_mut() {
+            for by in 0..c.v as usize {
+                for bx in 0..c.h as usize {
+                    let mut coefficients = [0i32; 64];
+                    decode_block(
+                        reader,
+                        c,
+                        &huffman[c.dc],
+                        &huffman[c.ac],
+                        &quant[c.quant],
+                        &mut coefficients,
+                    )?;
+                    let x = (mx * c.h as usize + bx) * 8;
+                    let at = c.offset + by * 8 * c.stride + x;
+                    idct(&coefficients, &mut planes[at..], c.stride);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Convert row `y` of the current MCU row to the output format and
+    /// append it to the block.
+    fn write_row(&mut self, decoder: &mut BaselineDecoder, y: u16) {
+        let BaselineDecoder {
+            planes,
+            out,
+            columns,
+            ..
+        } = decoder;
+        let row_len = self.output_width as usize * self.format.bytes_per_pixel();
+        let row = &mut out[self.out_len..self.out_len + row_len];
+        self.out_len += row_len;
+
+        let components = &self.components[..self.component_count];
+        let y = y as usize;
+        let sample = |c: &Component, x: u16| {
+            planes[c.offset + (y >> c.vshift) * c.stride + (x as usize >> c.hshift)]
+        };
+        let ycc = |x: u16| match components {
+            [luma, cb, cr] => [sample(luma, x), sample(cb, x), sample(cr, x)],
+            [luma] => [sample(luma, x), 128, 128],
+            _ => [0, 128, 128],
+        };
+
+        match self.format {
+            JpegPixelFormat::Rgb565Be | JpegPixelFormat::Rgb565Le => {
+                let be = self.format == JpegPixelFormat::Rgb565Be;
+                for (px, &x) in row.chunks_exact_mut(2).zip(columns.iter()) {
+                    let [r, g, b] = rgb(ycc(x));
+                    let v = ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3);
+                    px.copy_from_slice(&if be { v.to_be_bytes() } else { v.to_le_bytes() });
+                }
+            }
+            JpegPixelFormat::Rgb888 => {
+                for (px, &x) in row.chunks_exact_mut(3).zip(columns.iter()) {
+                    px.copy_from_slice(&rgb(ycc(x)));
+                }
+            }
+            JpegPixelFormat::CbYCrY => {
+                // Each pair of pixels shares the chroma of the first
+                for (px, pair) in row.chunks_mut(4).zip(columns.chunks(2)) {
+                    let [y0, cb, cr] = ycc(pair[0]);
+                    let y1 = pair.get(1).map_or(y0, |&x| ycc(x)[0]);
+                    let pixels = [cb, y0, cr, y1];
+                    px.copy_from_slice(&pixels[..px.len()]);
+                }
+            }
+        }
+    }
+}
+
+/// One frame being decoded by a [`BaselineDecoder`].
+///
+/// The session reads the frame as it goes, so it borrows the JPEG data as
+/// well as the decoder, and neither can be touched until it is dropped:
+///
+/// ```compile_fail,E0506
+/// # use rumble_rs::jpeg::JpegBackend;
+/// # use rumble_rs::jpeg::baseline::BaselineDecoder;
+/// # let mut decoder = BaselineDecoder::new().unwrap();
+/// let mut frame_buf = [0u8; 64];
+/// let mut session = decoder.start_decode(&mut frame_buf).unwrap();
+/// frame_buf[0] = 0; // input is still borrowed by the session
+/// session.decode_next_block().unwrap();
+/// ```
+///
+/// ```compile_fail,E0597
+/// # use rumble_rs::jpeg::JpegBackend;
+/// # use rumble_rs::jpeg::baseline::BaselineDecoder;
+/// # let mut decoder = BaselineDecoder::new().unwrap();
+/// let mut session = {
+///     let mut frame_buf = [0u8; 64];
+///     decoder.start_decode(&mut frame_buf).unwrap()
+/// }; // input dropped while the session still points at it
+/// session.decode_next_block().unwrap();
+/// ```
+///
+/// Decoded blocks are borrowed from the decoder's output buffer, which the
+/// next `decode_next_block()` overwrites:
+///
+/// ```compile_fail,E0502
+/// # use rumble_rs::jpeg::JpegBackend;
+/// # use rumble_rs::jpeg::baseline::BaselineDecoder;
+/// # let mut decoder = BaselineDecoder::new().unwrap();
+/// # let mut frame_buf = [0u8; 64];
+/// let mut session = decoder.start_decode(&mut frame_buf).unwrap();
+/// session.decode_next_block().unwrap();
+/// let first = session.block_data();
+/// session.decode_next_block().unwrap();
+/// let _ = first[0];
+/// ```
+pub struct BaselineSession<'a> {
+    decoder: &'a mut BaselineDecoder,
+    reader: BitReader<'a>,
+    frame: Frame,
+}
+
+impl<'a> BaselineSession<'a> {
+    fn new(decoder: &'a mut BaselineDecoder, data: &'a [u8]) -> Result<Self, JpegError> {
+        let summary = markers::parse(data)?;
+        if summary.scans != 1 {
+            return Err(JpegError::UnsupportedStandard);
+        }
+        let frame = Frame::new(decoder, &summary, data)?;
+        Ok(Self {
+            decoder,
+            reader: BitReader::new(&data[summary.scan_offset..summary.len]),
+            frame,
+        })
+    }
+
+    pub fn info(&self) -> &JpegFrameInfo {
+        &self.frame.info
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.frame.block_count
+    }
+
+    /// Width of every decoded block after scale and clipper.
+    pub fn output_width(&self) -> u16 {
+        self.frame.output_width
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.frame.format.bytes_per_pixel()
+    }
+
+    /// Decode the next block. Returns `(block_width, block_height)`.
+    pub fn decode_next_block(&mut self) -> Result<(u16, u16), JpegError> {
+        self.frame.decode_next_block(self.decoder, &mut self.reader)
+    }
+
+    /// Pixel data, in the configured output format, for the most recently
+    /// decoded block.
+    pub fn block_data(&self) -> &[u8] {
+        &self.decoder.out[..self.frame.out_len]
+    }
+}
+
+impl JpegSession for BaselineSession<'_> {
+    fn info(&self) -> &JpegFrameInfo {
+        BaselineSession::info(self)
+    }
+
+    fn block_count(&self) -> usize {
+        BaselineSession::block_count(self)
+    }
+
+    fn output_width(&self) -> u16 {
+        BaselineSession::output_width(self)
+    }
+
+    fn bytes_per_pixel(&self) -> usize {
+        BaselineSession::bytes_per_pixel(self)
+    }
+
+    fn decode_next_block(&mut self) -> Result<(u16, u16), JpegError> {
+        BaselineSession::decode_next_block(self)
+    }
+
+    fn block_data(&self) -> &[u8] {
+        BaselineSession::block_data(self)
+    }
+}
+
+/// Load the DQT and DHT tables ahead of the scan, and return the payload of
+/// its SOS segment. [`markers::parse`] has already checked the structure.
+fn read_tables<'d>(
+    data: &'d [u8],
+    huffman: &mut [Huffman],
+    quant: &mut [[u16; 64]],
+) -> Result<&'d [u8], JpegError> {
+    let mut pos = 2;
+    loop {
+        while data.get(pos) == Some(&0xFF) {
+            pos += 1;
+        }
+        let marker = *data.get(pos).ok_or(JpegError::BadData)?;
+        pos += 1;
+        if matches!(marker, TEM | RST0..=RST7) {
+            continue;
+        }
+        let len_bytes = data.get(pos..pos + 2).ok_or(JpegError::BadData)?;
+        let len = u16::from_be_bytes([len_bytes[0], len_bytes[1]]) as usize;
+        let mut seg = data.get(pos + 2..pos + len).ok_or(JpegError::BadData)?;
+        pos += len;
+
+        match marker {
+            SOS => return Ok(seg),
+            DQT => {
+                while let Some((&pq_tq, rest)) = seg.split_first() {
+                    let table = &mut quant[(pq_tq & 0x03) as usize];
+                    if pq_tq >> 4 == 0 {
+                        table.iter_mut().zip(rest).for_each(|(q, &b)| *q = b as u16);
+                        seg = &rest[64..];
+                    } else {
+                        let words = rest.chunks_exact(2);
+                        table
+                            .iter_mut()
+                            .zip(words)
+                            .for_each(|(q, b)| *q = u16::from_be_bytes([b[0], b[1]]));
+                        seg = &rest[128..];
+                    }
+                }
+            }
+            DHT => {
+                while let Some((&tc_th, rest)) = seg.split_first() {
+                    let counts: &[u8; 16] =
+                        rest[..16].try_into().map_err(|_| JpegError::BadData)?;
+                    let n: usize = counts.iter().map(|&n| n as usize).sum();
+                    let slot = (tc_th >> 4) as usize * 4 + (tc_th & 0x03) as usize;
+                    huffman[slot].build(counts, &rest[16..16 + n])?;
+                    seg = &rest[16 + n..];
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Streaming
+// ---------------------------------------------------------------------------
+
+/// A stream of JPEG frames, decoded with [`BaselineDecoder::start_stream`]
+/// while its bytes are still arriving.
+///
+/// It holds a frame's headers until its scan starts, then only what has
+/// arrived and isn't decoded yet, so it can be much smaller than a frame.
+/// Anything between frames is skipped.
+pub struct BaselineStream {
+    buf: Vec<u8>,
+    /// `buf[start..end]` is the input not used up yet.
+    start: usize,
+    end: usize,
+    /// The next frame's headers, once they are all in.
+    header: Option<FrameSummary>,
+}
+
+impl BaselineStream {
+    /// A stream that holds up to `capacity` bytes of input. The headers of a
+    /// frame, SOI through SOS, must fit.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            buf: vec![0; capacity],
+            start: 0,
+            end: 0,
+            header: None,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Room for more input, made by dropping what's used up. Write into it
+    /// and [`commit`](Self::commit) what was written; it's empty when the
+    /// stream is full.
+    pub fn spare(&mut self) -> &mut [u8] {
+        self.buf.copy_within(self.start..self.end, 0);
+        self.end -= self.start;
+        self.start = 0;
+        &mut self.buf[self.end..]
+    }
+
+    /// Add the first `n` bytes of [`spare`](Self::spare) to the input.
+    pub fn commit(&mut self, n: usize) {
+        self.end = (self.end + n).min(self.buf.len());
+    }
+
+    /// Add as much of `input` as fits and return how much that was.
+    pub fn push(&mut self, input: &[u8]) -> usize {
+        let spare = self.spare();
+        let n = spare.len().min(input.len());
+        spare[..n].copy_from_slice(&input[..n]);
+        self.commit(n);
+        n
+    }
+
+    /// The size of the next frame. Skips to its SOI, then returns
+    /// [`JpegError::NoMoreData`] until its headers have arrived.
+    ///
+    /// A frame with broken headers, or headers that don't fit, is skipped
+    /// with the error; call again to look for the next one.
+    pub fn header(&mut self) -> Result<JpegFrameInfo, JpegError> {
+        let summary = self.summary()?;
+        Ok(JpegFrameInfo {
+            width: summary.width,
+            height: summary.height,
+        })
+    }
+
+    /// Forget the input, e.g. after reconnecting.
+    pub fn reset(&mut self) {
+        self.start = 0;
+        self.end = 0;
+        self.header = None;
+    }
+
+    fn summary(&mut self) -> Result<FrameSummary, JpegError> {
+        if let Some(summary) = self.header {
+            return Ok(summary);
+        }
+        let data = &self.buf[self.start..self.end];
+        match data.windows(2).position(|w| w == [0xFF, SOI]) {
+            Some(soi) => self.start += soi,
+            None => {
+                // Keep a last 0xFF, the SOI may go on in the next read
+                self.start = self.end - (data.last() == Some(&0xFF)) as usize;
+                return Err(JpegError::NoMoreData);
+            }
+        }
+        match markers::parse_header(&self.buf[self.start..self.end]) {
+            Ok(summary) => {
+                self.header = Some(summary);
+                Ok(summary)
+            }
+            Err(MarkerError::Truncated) if !self.is_full() => Err(JpegError::NoMoreData),
+            Err(MarkerError::Truncated) => {
+                self.skip_frame();
+                Err(JpegError::BufferTooSmall)
+            }
+            Err(e) => {
+                self.skip_frame();
+                Err(e.into())
+            }
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.end - self.start == self.buf.len()
+    }
+
+    /// Move past the SOI of the current frame, so the next one is looked for.
+    fn skip_frame(&mut self) {
+        self.start += 2;
+        self.header = None;
+    }
+}
+
+/// A frame of a [`BaselineStream`] being decoded as it arrives.
+///
+/// When a block needs data that hasn't arrived yet,
+/// [`decode_next_block`](Self::decode_next_block) returns
+/// [`JpegError::NoMoreData`]: add more input and call it again. The MCU the
+/// data ran out in is decoded again from its start, nothing else.
+pub struct StreamSession<'a> {
+    decoder: &'a mut BaselineDecoder,
+    stream: &'a mut BaselineStream,
+    frame: Frame,
+    /// Where the scan goes on, from the first byte the stream holds.
+    position: Position,
+}
+
+impl StreamSession<'_> {
+    pub fn info(&self) -> &JpegFrameInfo {
+        &self.frame.info
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.frame.block_count
+    }
+
+    /// Width of every decoded block after scale and clipper.
+    pub fn output_width(&self) -> u16 {
+        self.frame.output_width
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.frame.format.bytes_per_pixel()
+    }
+
+    /// Decode the next block. Returns `(block_width, block_height)`,
+    /// [`JpegError::NoMoreData`] until enough of it has arrived, or
+    /// [`JpegError::BufferTooSmall`] if one MCU doesn't fit the stream.
+    pub fn decode_next_block(&mut self) -> Result<(u16, u16), JpegError> {
+        let stream = &mut *self.stream;
+        let mut reader = BitReader::resume(&stream.buf[stream.start..stream.end], self.position);
+        let decoded = self.frame.decode_next_block(self.decoder, &mut reader);
+        // Whatever the reader got past is used up
+        let position = reader.position();
+        stream.start += position.pos;
+        self.position = Position { pos: 0, ..position };
+        match decoded {
+            Err(JpegError::NoMoreData) if stream.is_full() => Err(JpegError::BufferTooSmall),
+            decoded => decoded,
+        }
+    }
+
+    /// Pixel data, in the configured output format, for the most recently
+    /// decoded block.
+    pub fn block_data(&self) -> &[u8] {
+        &self.decoder.out[..self.frame.out_len]
+    }
+
+    /// See [`BaselineStream::spare`].
+    pub fn spare(&mut self) -> &mut [u8] {
+        self.stream.spare()
+    }
+
+    /// See [`BaselineStream::commit`].
+    pub fn commit(&mut self, n: usize) {
+        self.stream.commit(n)
+    }
+
+    /// See [`BaselineStream::push`].
+    pub fn push(&mut self, input: &[u8]) -> usize {
+        self.stream.push(input)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Blocks
+// ---------------------------------------------------------------------------
+
+/// Decode one 8×8 block of `c` into dequantized coefficients, in natural
+/// order.
+fn decode_block(
+    reader: &mut BitReader,
+    c: &mut Component,
+    dc: &Huffman,
+    ac: &Huffman,
+    quant: &[u16; 64],
+    coefficients: &mut [i32; 64],
+) -> Result<(), JpegError> {
+    // Valid coefficients fit 16 bits; clamping keeps corrupt ones from
+    // overflowing in the IDCT.
+    let dequantize = |v: i32, q: u16| v.saturating_mul(q as i32).clamp(-32768, 32767);
+
+    let s = reader.decode(dc)?;
+    c.pred = c.pred.wrapping_add(reader.receive_extend(s)?);
+    coefficients[0] = dequantize(c.pred, quant[0]);
+    let mut k = 1;
+    while k < 64 {
+        let rs = reader.decode(ac)?;
+        let (run, size) = ((rs >> 4) as usize, rs & 0x0F);
+        if size == 0 {
+            if run != 15 {
+                // End of block
+                break;
+            }
+            k += 16;
+            continue;
+        }
+        k += run;
+        if k > 63 {
+            return Err(JpegError::BadData);
+        }
+        coefficients[ZIGZAG[k] as usize] = dequantize(reader.receive_extend(size)?, quant[k]);
+        k += 1;
+    }
+    Ok(())
+}
+
+/// Fixed-point constant with 12 fractional bits.
+const fn f2f(x: f64) -> Wrapping<i32> {
+    Wrapping((x * 4096.0 + 0.5) as i32)
+}
+
+/// The even and odd halves of a 1-D IDCT, scaled by 4096 (the integer
+/// IDCT from stb_image, itself derived from the IJG one).
+fn idct_1d(s: [Wrapping<i32>; 8]) -> ([Wrapping<i32>; 4], [Wrapping<i32>; 4]) {
+    let p2 = s[2];
+    let p3 = s[6];
+    let p1 = (p2 + p3) * f2f(0.5411961);
+    let t2 = p1 + p3 * f2f(-1.847759065);
+    let t3 = p1 + p2 * f2f(0.765366865);
+    let t0 = (s[0] + s[4]) << 12;
+    let t1 = (s[0] - s[4]) << 12;
+    let even = [t0 + t3, t1 + t2, t1 - t2, t0 - t3];
+
+    let (mut t0, mut t1, mut t2, mut t3) = (s[7], s[5], s[3], s[1]);
+    let p3 = t0 + t2;
+    let p4 = t1 + t3;
+    let p1 = t0 + t3;
+    let p2 = t1 + t2;
+    let p5 = (p3 + p4) * f2f(1.175875602);
+    t0 *= f2f(0.298631336);
+    t1 *= f2f(2.053119869);
+    t2 *= f2f(3.072711026);
+    t3 *= f2f(1.501321110);
+    let p1 = p5 + p1 * f2f(-0.899976223);
+    let p2 = p5 + p2 * f2f(-2.562915447);
+    let p3 = p3 * f2f(-1.961570560);
+    let p4 = p4 * f2f(-0.390180644);
+    let odd = [t3 + p1 + p4, t2 + p2 + p3, t1 + p2 + p4, t0 + p1 + p3];
+    (even, odd)
+}
+
+/// Inverse DCT of one block into 8 rows of `out`, `stride` apart, level
+/// shifted and clamped to 0..=255.
+fn idct(coefficients: &[i32; 64], out: &mut [u8], stride: usize) {
+    let mut v = [Wrapping(0i32); 64];
+
+    // Columns, keeping 2 extra bits
+    for x in 0..8 {
+        let column: [Wrapping<i32>; 8] =
+            core::array::from_fn(|i| Wrapping(coefficients[x + i * 8]));
+        if column[1..].iter().all(|c| c.0 == 0) {
+            // Only DC: every output is the same
+            let dc = column[0] << 2;
+            (0..8).for_each(|i| v[x + i * 8] = dc);
+            continue;
+        }
+        let (even, odd) = idct_1d(column);
+        let round = Wrapping(512);
+        for i in 0..4 {
+            v[x + i * 8] = (even[i] + round + odd[i]) >> 10;
+            v[x + (7 - i) * 8] = (even[i] + round - odd[i]) >> 10;
+        }
+    }
+
+    // Rows, dropping the 2 + 3 bits of scale, 12 of fixed point, and
+    // adding the level shift of 128
+    let round = Wrapping(65536 + (128 << 17));
+    for (y, row) in v.chunks_exact(8).enumerate() {
+        let (even, odd) = idct_1d(row.try_into().unwrap());
+        let out = &mut out[y * stride..y * stride + 8];
+        for i in 0..4 {
+            out[i] = clamp((even[i] + round + odd[i]) >> 17);
+            out[7 - i] = clamp((even[i] + round - odd[i]) >> 17);
+        }
+    }
+}
+
+fn clamp(v: Wrapping<i32>) -> u8 {
+    v.0.clamp(0, 255) as u8
+}
+
+/// YCbCr to RGB with the JFIF coefficients, 16 fractional bits.
+fn rgb([y, cb, cr]: [u8; 3]) -> [u8; 3] {
+    let y = ((y as i32) << 16) + 32768;
+    let cb = cb as i32 - 128;
+    let cr = cr as i32 - 128;
+    let r = y + 91881 * cr;
+    let g = y - 22554 * cb - 46802 * cr;
+    let b = y + 116130 * cb;
+    [r, g, b].map(|c| (c >> 16).clamp(0, 255) as u8)
+}
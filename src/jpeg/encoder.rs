@@ -0,0 +1,379 @@
+//! FFI bindings and safe wrapper for the esp_new_jpeg encoder.
+//!
+//! Mirrors the decoder side: [`JpegEncoder`] owns the library handle, and
+//! [`EncodeSession`] feeds it one block-mode strip at a time.
+
+use core::ffi::c_void;
+use core::ptr;
+
+use super::{
+    JPEG_ERR_OK, JPEG_PIXEL_FORMAT_CBYCRY, JPEG_PIXEL_FORMAT_GRAY, JPEG_PIXEL_FORMAT_RGB565_BE,
+    JPEG_PIXEL_FORMAT_RGB565_LE, JPEG_PIXEL_FORMAT_RGB888, JPEG_PIXEL_FORMAT_RGBA,
+    JPEG_PIXEL_FORMAT_YCBY2YCRY2, JPEG_PIXEL_FORMAT_YCBYCR, JpegConfigError, JpegEncConfig,
+    JpegError, JpegRotation, JpegSubsampling, check, jpeg_calloc_align, jpeg_free_align,
+};
+
+// ---------------------------------------------------------------------------
+// FFI declarations
+// ---------------------------------------------------------------------------
+
+type JpegEncHandle = *mut c_void;
+
+unsafe extern "C" {
+    fn jpeg_enc_open(config: *mut JpegEncConfig, handle: *mut JpegEncHandle) -> i32;
+    fn jpeg_enc_process(
+        handle: JpegEncHandle,
+        inbuf: *const u8,
+        inbuf_size: i32,
+        outbuf: *mut u8,
+        outbuf_size: i32,
+        out_size: *mut i32,
+    ) -> i32;
+    fn jpeg_enc_get_block_size(handle: JpegEncHandle) -> i32;
+    fn jpeg_enc_process_with_block(
+        handle: JpegEncHandle,
+        inbuf: *const u8,
+        inbuf_size: i32,
+        outbuf: *mut u8,
+        outbuf_size: i32,
+        out_size: *mut i32,
+    ) -> i32;
+    fn jpeg_enc_close(handle: JpegEncHandle) -> i32;
+}
+
+// ---------------------------------------------------------------------------
+// Encoder configuration
+// ---------------------------------------------------------------------------
+
+/// Pixel formats the encoder accepts as input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JpegSourceFormat {
+    Gray,
+    Rgb888,
+    Rgba,
+    Rgb565Be,
+    Rgb565Le,
+    YCbYCr,
+    /// Packed YUV420 (`Y Cb Y Y Cr Y` per pair of rows).
+    YCbY2YCrY2,
+    CbYCrY,
+}
+
+impl JpegSourceFormat {
+    pub const fn fourcc(self) -> u32 {
+        match self {
+            Self::Gray => JPEG_PIXEL_FORMAT_GRAY,
+            Self::Rgb888 => JPEG_PIXEL_FORMAT_RGB888,
+            Self::Rgba => JPEG_PIXEL_FORMAT_RGBA,
+            Self::Rgb565Be => JPEG_PIXEL_FORMAT_RGB565_BE,
+            Self::Rgb565Le => JPEG_PIXEL_FORMAT_RGB565_LE,
+            Self::YCbYCr => JPEG_PIXEL_FORMAT_YCBYCR,
+            Self::YCbY2YCrY2 => JPEG_PIXEL_FORMAT_YCBY2YCRY2,
+            Self::CbYCrY => JPEG_PIXEL_FORMAT_CBYCRY,
+        }
+    }
+
+    /// Bits per source pixel. YCbY2YCrY2 averages 12 bits.
+    pub const fn bits_per_pixel(self) -> usize {
+        match self {
+            Self::Gray => 8,
+            Self::YCbY2YCrY2 => 12,
+            Self::Rgb565Be | Self::Rgb565Le | Self::YCbYCr | Self::CbYCrY => 16,
+            Self::Rgb888 => 24,
+            Self::Rgba => 32,
+        }
+    }
+}
+
+/// Validated encoder configuration. Build one with [`JpegEncoderConfig::builder`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JpegEncoderConfig {
+    width: u16,
+    height: u16,
+    source_format: JpegSourceFormat,
+    subsampling: JpegSubsampling,
+    quality: u8,
+    rotation: JpegRotation,
+}
+
+impl JpegEncoderConfig {
+    /// Start from the library defaults (RGB888 input, YUV420, quality 40)
+    /// for a `width`×`height` source image.
+    pub fn builder(width: u16, height: u16) -> JpegEncoderConfigBuilder {
+        JpegEncoderConfigBuilder {
+            config: Self {
+                width,
+                height,
+                source_format: JpegSourceFormat::Rgb888,
+                subsampling: JpegSubsampling::Yuv420,
+                quality: 40,
+                rotation: JpegRotation::Deg0,
+            },
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    pub fn source_format(&self) -> JpegSourceFormat {
+        self.source_format
+    }
+
+    pub fn subsampling(&self) -> JpegSubsampling {
+        self.subsampling
+    }
+
+    pub fn quality(&self) -> u8 {
+        self.quality
+    }
+
+    pub fn rotation(&self) -> JpegRotation {
+        self.rotation
+    }
+
+    /// Size in bytes of one complete source image.
+    pub fn image_size(&self) -> usize {
+        self.width as usize * self.height as usize * self.source_format.bits_per_pixel() / 8
+    }
+
+    fn to_raw(self) -> JpegEncConfig {
+        JpegEncConfig {
+            width: self.width as i32,
+            height: self.height as i32,
+            src_type: self.source_format.fourcc(),
+            subsampling: self.subsampling.raw(),
+            quality: self.quality,
+            rotate: self.rotation.raw(),
+            // Dual-task encoding spawns a FreeRTOS task, which we don't have.
+            task_enable: 0,
+            hfm_task_priority: 13,
+            hfm_task_core: 1,
+        }
+    }
+}
+
+pub struct JpegEncoderConfigBuilder {
+    config: JpegEncoderConfig,
+}
+
+impl JpegEncoderConfigBuilder {
+    pub fn with_source_format(mut self, format: JpegSourceFormat) -> Self {
+        self.config.source_format = format;
+        self
+    }
+
+    pub fn with_subsampling(mut self, subsampling: JpegSubsampling) -> Self {
+        self.config.subsampling = subsampling;
+        self
+    }
+
+    /// Quality from 1 (smallest) to 100 (best).
+    pub fn with_quality(mut self, quality: u8) -> Self {
+        self.config.quality = quality;
+        self
+    }
+
+    /// Clockwise rotation of the encoded image.
+    pub fn with_rotation(mut self, rotation: JpegRotation) -> Self {
+        self.config.rotation = rotation;
+        self
+    }
+
+    pub fn build(self) -> Result<JpegEncoderConfig, JpegConfigError> {
+        let c = self.config;
+
+        if c.width == 0 || c.height == 0 {
+            return Err(JpegConfigError::InvalidDimensions);
+        }
+        if !(1..=100).contains(&c.quality) {
+            return Err(JpegConfigError::QualityOutOfRange);
+        }
+        if c.source_format == JpegSourceFormat::YCbY2YCrY2
+            && !matches!(
+                c.subsampling,
+                JpegSubsampling::Yuv420 | JpegSubsampling::Gray
+            )
+        {
+            return Err(JpegConfigError::UnsupportedSubsampling);
+        }
+        if c.rotation != JpegRotation::Deg0 {
+            let mcu = match c.subsampling {
+                JpegSubsampling::Yuv420 => 16,
+                JpegSubsampling::Gray => 8,
+                _ => return Err(JpegConfigError::UnsupportedRotation),
+            };
+            if c.source_format != JpegSourceFormat::YCbYCr
+                || !c.width.is_multiple_of(mcu)
+                || !c.height.is_multiple_of(mcu)
+            {
+                return Err(JpegConfigError::UnsupportedRotation);
+            }
+        }
+        Ok(c)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Safe wrapper
+// ---------------------------------------------------------------------------
+
+pub struct JpegEncoder {
+    handle: JpegEncHandle,
+    config: JpegEncoderConfig,
+}
+
+impl JpegEncoder {
+    /// Create an encoder from a validated configuration.
+    pub fn new(config: JpegEncoderConfig) -> Result<Self, JpegError> {
+        let mut raw = config.to_raw();
+        let mut handle: JpegEncHandle = ptr::null_mut();
+        check(unsafe { jpeg_enc_open(&mut raw, &mut handle) })?;
+        Ok(Self { handle, config })
+    }
+
+    pub fn config(&self) -> &JpegEncoderConfig {
+        &self.config
+    }
+
+    /// Encode a complete source image into `out`. Returns the JPEG length.
+    pub fn encode(&mut self, image: &[u8], out: &mut [u8]) -> Result<usize, JpegError> {
+        let image_size = self.config.image_size();
+        if image.len() < image_size {
+            return Err(JpegError::BufferTooSmall);
+        }
+        let outbuf_size = i32::try_from(out.len()).unwrap_or(i32::MAX);
+        let mut out_size: i32 = 0;
+        check(unsafe {
+            jpeg_enc_process(
+                self.handle,
+                image.as_ptr(),
+                image_size as i32,
+                out.as_mut_ptr(),
+                outbuf_size,
+                &mut out_size,
+            )
+        })?;
+        Ok(out_size as usize)
+    }
+
+    /// Begin a block-mode encode into `out`. The session owns an aligned
+    /// input buffer for one strip of source rows; fill it through
+    /// [`EncodeSession::block_buffer`] before each [`EncodeSession::encode_block`].
+    pub fn start_encode<'a>(
+        &'a mut self,
+        out: &'a mut [u8],
+    ) -> Result<EncodeSession<'a>, JpegError> {
+        let block_height = self.config.subsampling.block_height();
+        if !self.config.height.is_multiple_of(block_height) {
+            return Err(JpegConfigError::HeightNotMultipleOfBlock.into());
+        }
+
+        let block_size = unsafe { jpeg_enc_get_block_size(self.handle) };
+        if block_size < JPEG_ERR_OK {
+            return Err(JpegError::from_code(block_size));
+        }
+        if block_size == 0 {
+            return Err(JpegError::Fail);
+        }
+        let block_size = block_size as usize;
+
+        let inbuf = unsafe { jpeg_calloc_align(block_size, 16) };
+        if inbuf.is_null() {
+            return Err(JpegError::NoMemory);
+        }
+
+        let block_count = self.config.image_size() / block_size;
+        Ok(EncodeSession {
+            encoder: self,
+            out,
+            inbuf,
+            block_size,
+            block_count,
+            current_block: 0,
+            out_len: 0,
+        })
+    }
+}
+
+/// Step-by-step block-mode encode. Each block is `block_size()` bytes of
+/// source pixels covering the full image width.
+pub struct EncodeSession<'a> {
+    encoder: &'a mut JpegEncoder,
+    out: &'a mut [u8],
+    inbuf: *mut c_void,
+    block_size: usize,
+    block_count: usize,
+    current_block: usize,
+    out_len: usize,
+}
+
+impl<'a> EncodeSession<'a> {
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.block_count
+    }
+
+    /// Input buffer for the next block. Its contents are consumed by
+    /// `encode_block()`.
+    pub fn block_buffer(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.inbuf as *mut u8, self.block_size) }
+    }
+
+    /// Encode the strip currently held in `block_buffer()`.
+    pub fn encode_block(&mut self) -> Result<(), JpegError> {
+        if self.current_block >= self.block_count {
+            return Err(JpegError::SessionExhausted);
+        }
+        let outbuf_size = i32::try_from(self.out.len()).unwrap_or(i32::MAX);
+        let mut out_size: i32 = 0;
+        let ret = unsafe {
+            jpeg_enc_process_with_block(
+                self.encoder.handle,
+                self.inbuf as *const u8,
+                self.block_size as i32,
+                self.out.as_mut_ptr(),
+                outbuf_size,
+                &mut out_size,
+            )
+        };
+        if ret < JPEG_ERR_OK {
+            return Err(JpegError::from_code(ret));
+        }
+        self.current_block += 1;
+        self.out_len = out_size.max(0) as usize;
+        Ok(())
+    }
+
+    /// Finish the session and return the encoded JPEG.
+    pub fn finish(mut self) -> Result<&'a [u8], JpegError> {
+        if self.current_block < self.block_count {
+            return Err(JpegError::SessionIncomplete);
+        }
+        let out = core::mem::take(&mut self.out);
+        Ok(&out[..self.out_len.min(out.len())])
+    }
+}
+
+impl<'a> Drop for EncodeSession<'a> {
+    fn drop(&mut self) {
+        if !self.inbuf.is_null() {
+            unsafe { jpeg_free_align(self.inbuf) };
+        }
+    }
+}
+
+impl Drop for JpegEncoder {
+    fn drop(&mut self) {
+        if !self.handle.is_null() {
+            unsafe { jpeg_enc_close(self.handle) };
+        }
+    }
+}
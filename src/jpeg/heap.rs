@@ -0,0 +1,288 @@
+//! Caps-aware allocation behind the C codec's `heap_caps_*` calls.
+//!
+//! ESP_NEW_JPEG asks ESP-IDF's heap for memory by capability (`MALLOC_CAP_*`
+//! masks): DMA-capable memory for the output buffer, internal RAM for its
+//! tables, PSRAM where it can live with it. [`calloc`] turns each mask into a
+//! [`Region`], tries them in the caller's order of preference and hands the
+//! request to the [`RegionAllocator`] installed with [`set_allocator`], which
+//! is Rust's global allocator until then. A PSRAM request falls back to the
+//! default heap when there is no PSRAM, or no room in it.
+//!
+//! Every block starts with a small header that lets [`free`] rebuild its
+//! layout and catch pointers it never handed out; [`stats`] counts what's
+//! live.
+
+use alloc::boxed::Box;
+use core::alloc::Layout;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+
+// ---------------------------------------------------------------------------
+// Capabilities (esp_heap_caps.h)
+// ---------------------------------------------------------------------------
+
+pub const MALLOC_CAP_EXEC: u32 = 1 << 0;
+pub const MALLOC_CAP_32BIT: u32 = 1 << 1;
+pub const MALLOC_CAP_8BIT: u32 = 1 << 2;
+pub const MALLOC_CAP_DMA: u32 = 1 << 3;
+pub const MALLOC_CAP_SPIRAM: u32 = 1 << 10;
+pub const MALLOC_CAP_INTERNAL: u32 = 1 << 11;
+pub const MALLOC_CAP_DEFAULT: u32 = 1 << 12;
+
+/// Where a block may be placed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Region {
+    /// Internal RAM the DMA engines can reach.
+    Dma,
+    /// Internal RAM.
+    Internal,
+    /// PSRAM.
+    External,
+    /// Wherever the heap likes.
+    Default,
+}
+
+impl Region {
+    /// The region a `MALLOC_CAP_*` mask asks for. DMA beats internal, which
+    /// beats PSRAM; the width and default bits don't say where.
+    pub const fn from_caps(caps: u32) -> Self {
+        if caps & MALLOC_CAP_DMA != 0 {
+            Self::Dma
+        } else if caps & MALLOC_CAP_INTERNAL != 0 {
+            Self::Internal
+        } else if caps & MALLOC_CAP_SPIRAM != 0 {
+            Self::External
+        } else {
+            Self::Default
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Backing allocator
+// ---------------------------------------------------------------------------
+
+/// The heap behind the codec's allocations.
+pub trait RegionAllocator: Sync {
+    /// Allocate `layout` from `region`, or return null if it has no room.
+    ///
+    /// # Safety
+    ///
+    /// As [`GlobalAlloc::alloc`](core::alloc::GlobalAlloc::alloc).
+    unsafe fn alloc(&self, region: Region, layout: Layout) -> *mut u8;
+
+    /// Free a block returned by [`RegionAllocator::alloc`].
+    ///
+    /// # Safety
+    ///
+    /// As [`GlobalAlloc::dealloc`](core::alloc::GlobalAlloc::dealloc).
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout);
+}
+
+/// Rust's global allocator, whatever the region.
+struct Global;
+
+impl RegionAllocator for Global {
+    unsafe fn alloc(&self, _region: Region, layout: Layout) -> *mut u8 {
+        unsafe { alloc::alloc::alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { alloc::alloc::dealloc(ptr, layout) }
+    }
+}
+
+static ALLOCATOR: AtomicPtr<&'static dyn RegionAllocator> = AtomicPtr::new(ptr::null_mut());
+
+/// Send the codec's allocations to `allocator`. Install it before the first
+/// decoder or encoder opens: blocks go back to whichever allocator is
+/// installed when they are freed.
+pub fn set_allocator(allocator: &'static dyn RegionAllocator) {
+    ALLOCATOR.store(Box::leak(Box::new(allocator)), Ordering::Release);
+}
+
+fn allocator() -> &'static dyn RegionAllocator {
+    let installed = ALLOCATOR.load(Ordering::Acquire);
+    if installed.is_null() {
+        &Global
+    } else {
+        unsafe { *installed }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Accounting
+// ---------------------------------------------------------------------------
+
+static LIVE: AtomicU32 = AtomicU32::new(0);
+static LIVE_BYTES: AtomicU32 = AtomicU32::new(0);
+static PEAK_BYTES: AtomicU32 = AtomicU32::new(0);
+static FAILED: AtomicU32 = AtomicU32::new(0);
+static BAD_FREES: AtomicU32 = AtomicU32::new(0);
+
+/// The codec's allocations so far, see [`stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Blocks handed out and not freed yet.
+    pub live: u32,
+    /// Bytes the codec asked for in those blocks.
+    pub live_bytes: u32,
+    /// The most bytes live at once.
+    pub peak_bytes: u32,
+    /// Requests that no region had room for.
+    pub failed: u32,
+    /// Frees of pointers without a valid header. Those are leaked rather
+    /// than handed to the heap.
+    pub bad_frees: u32,
+}
+
+pub fn stats() -> HeapStats {
+    HeapStats {
+        live: LIVE.load(Ordering::Relaxed),
+        live_bytes: LIVE_BYTES.load(Ordering::Relaxed),
+        peak_bytes: PEAK_BYTES.load(Ordering::Relaxed),
+        failed: FAILED.load(Ordering::Relaxed),
+        bad_frees: BAD_FREES.load(Ordering::Relaxed),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Blocks
+// ---------------------------------------------------------------------------
+
+/// Sits right before the pointer handed out.
+#[repr(C)]
+struct Header {
+    size: usize,
+    align: usize,
+    /// [`MAGIC`] xor the block's address, zeroed once it is freed.
+    check: usize,
+}
+
+const MAGIC: usize = 0x4a50_4547;
+
+/// The layout of a block holding `size` bytes aligned to `align`, and where
+/// those bytes start in it.
+fn block_layout(size: usize, align: usize) -> Option<(Layout, usize)> {
+    let align = align.max(align_of::<Header>());
+    if !align.is_power_of_two() {
+        return None;
+    }
+    let offset = size_of::<Header>().next_multiple_of(align);
+    let layout = Layout::from_size_align(offset.checked_add(size)?, align).ok()?;
+    Some((layout, offset))
+}
+
+/// Allocate `n * size` zeroed bytes aligned to `align` (a power of two) from
+/// the first of `caps` with room. Returns null for an empty request or when
+/// no region can take it.
+pub fn calloc(n: usize, size: usize, align: usize, caps: &[u32]) -> *mut u8 {
+    let Some(size) = n.checked_mul(size) else {
+        return failed();
+    };
+    if size == 0 {
+        return ptr::null_mut();
+    }
+    let Some((layout, offset)) = block_layout(size, align) else {
+        return failed();
+    };
+
+    let allocator = allocator();
+    for &caps in caps {
+        let region = Region::from_caps(caps);
+        let mut raw = unsafe { allocator.alloc(region, layout) };
+        if raw.is_null() && region == Region::External {
+            raw = unsafe { allocator.alloc(Region::Default, layout) };
+        }
+        if raw.is_null() {
+            continue;
+        }
+        unsafe {
+            let block = raw.add(offset);
+            block.write_bytes(0, size);
+            (block as *mut Header).sub(1).write(Header {
+                size,
+                align: layout.align(),
+                check: MAGIC ^ block as usize,
+            });
+            LIVE.fetch_add(1, Ordering::Relaxed);
+            let live = LIVE_BYTES.fetch_add(size as u32, Ordering::Relaxed) + size as u32;
+            PEAK_BYTES.fetch_max(live, Ordering::Relaxed);
+            return block;
+        }
+    }
+    failed()
+}
+
+fn failed() -> *mut u8 {
+    FAILED.fetch_add(1, Ordering::Relaxed);
+    ptr::null_mut()
+}
+
+/// Free a block from [`calloc`]. Null is ignored.
+///
+/// # Safety
+///
+/// `block` is null or was returned by [`calloc`]. Freeing it twice is caught
+/// as long as the heap hasn't reused the memory.
+pub unsafe fn free(block: *mut u8) {
+    if block.is_null() {
+        return;
+    }
+    let header = unsafe { (block as *mut Header).sub(1) };
+    let Header { size, align, check } = unsafe { header.read() };
+    let layout = block_layout(size, align).filter(|_| check == MAGIC ^ block as usize);
+    let Some((layout, offset)) = layout else {
+        BAD_FREES.fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+    unsafe {
+        (*header).check = 0;
+        LIVE.fetch_sub(1, Ordering::Relaxed);
+        LIVE_BYTES.fetch_sub(size as u32, Ordering::Relaxed);
+        allocator().dealloc(block.sub(offset), layout);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// ESP-IDF heap_caps stubs — the .a library calls these internally
+// ---------------------------------------------------------------------------
+
+/// `void *heap_caps_calloc_prefer(size_t n, size_t size, size_t num, ...)`
+///
+/// The `num` caps masks, most preferred first, are C varargs. The Xtensa and
+/// RISC-V ABIs pass the first six integer arguments in registers either way,
+/// so the first three masks can be read as plain arguments; any more are
+/// ignored.
+#[cfg(feature = "esp-new-jpeg")]
+#[unsafe(no_mangle)]
+pub extern "C" fn heap_caps_calloc_prefer(
+    n: usize,
+    size: usize,
+    num: usize,
+    caps0: u32,
+    caps1: u32,
+    caps2: u32,
+) -> *mut core::ffi::c_void {
+    let caps = [caps0, caps1, caps2];
+    calloc(n, size, 4, &caps[..num.min(caps.len())]) as _
+}
+
+/// `void *heap_caps_aligned_calloc(size_t alignment, size_t n, size_t size, uint32_t caps)`
+#[cfg(feature = "esp-new-jpeg")]
+#[unsafe(no_mangle)]
+pub extern "C" fn heap_caps_aligned_calloc(
+    alignment: usize,
+    n: usize,
+    size: usize,
+    caps: u32,
+) -> *mut core::ffi::c_void {
+    calloc(n, size, alignment, &[caps]) as _
+}
+
+/// `void heap_caps_free(void *ptr)`
+#[cfg(feature = "esp-new-jpeg")]
+#[unsafe(no_mangle)]
+pub extern "C" fn heap_caps_free(ptr: *mut core::ffi::c_void) {
+    unsafe { free(ptr as *mut u8) }
+}
@@ -0,0 +1,566 @@
+//! ESP-IDF logging for the C codec, sent to the firmware log.
+//!
+//! The library logs through `esp_log_write` with a `printf` format string.
+//! ESP-IDF's `ESP_LOGx` macros have already folded the level letter, the
+//! timestamp and the tag into that string, so a message only needs
+//! formatting and passing on. [`format`] handles the subset of `printf` the
+//! codec uses: `%d %i %u %x %X %o %c %s %p %%` with the `-0+ #` flags, a
+//! width and precision (or `*`), and the usual length modifiers.
+//!
+//! Messages below the level set for their tag with [`set_level`] (or the
+//! C `esp_log_level_set`) are dropped; the rest go to the function installed
+//! with [`set_output`]. [`set_clock`] supplies `esp_log_timestamp`.
+
+use core::ffi::CStr;
+use core::fmt::{self, Write};
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+
+// ---------------------------------------------------------------------------
+// Levels
+// ---------------------------------------------------------------------------
+
+/// `esp_log_level_t`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    None,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Verbose,
+}
+
+impl Level {
+    pub const fn from_raw(raw: u32) -> Self {
+        match raw {
+            0 => Self::None,
+            1 => Self::Error,
+            2 => Self::Warn,
+            3 => Self::Info,
+            4 => Self::Debug,
+            _ => Self::Verbose,
+        }
+    }
+
+    pub const fn raw(self) -> u32 {
+        self as u32
+    }
+}
+
+/// Distinct tags that can have their own level; further tags follow the
+/// default.
+const TAG_SLOTS: usize = 16;
+
+static DEFAULT_LEVEL: AtomicU32 = AtomicU32::new(Level::Info as u32);
+/// Tag hashes, 0 for a free slot, and the level of each.
+static TAGS: [AtomicU32; TAG_SLOTS] = [const { AtomicU32::new(0) }; TAG_SLOTS];
+static LEVELS: [AtomicU32; TAG_SLOTS] = [const { AtomicU32::new(0) }; TAG_SLOTS];
+
+/// FNV-1a, never 0.
+fn tag_hash(tag: &[u8]) -> u32 {
+    let hash = tag.iter().fold(0x811c_9dc5u32, |hash, &b| {
+        (hash ^ b as u32).wrapping_mul(0x0100_0193)
+    });
+    hash.max(1)
+}
+
+/// Log `tag` at `level` and above. `*` sets the level of every tag that has
+/// none of its own, as in ESP-IDF.
+pub fn set_level(tag: &[u8], level: Level) {
+    if tag == b"*" {
+        DEFAULT_LEVEL.store(level.raw(), Ordering::Relaxed);
+        return;
+    }
+    let hash = tag_hash(tag);
+    for (slot, levels) in TAGS.iter().zip(&LEVELS) {
+        let owner = slot
+            .compare_exchange(0, hash, Ordering::AcqRel, Ordering::Acquire)
+            .unwrap_or_else(|owner| owner);
+        if owner == 0 || owner == hash {
+            levels.store(level.raw(), Ordering::Relaxed);
+            return;
+        }
+    }
+}
+
+/// The level `tag` logs at.
+pub fn level(tag: &[u8]) -> Level {
+    let hash = tag_hash(tag);
+    let raw = TAGS
+        .iter()
+        .position(|slot| slot.load(Ordering::Acquire) == hash)
+        .map(|i| LEVELS[i].load(Ordering::Relaxed))
+        .unwrap_or_else(|| DEFAULT_LEVEL.load(Ordering::Relaxed));
+    Level::from_raw(raw)
+}
+
+// ---------------------------------------------------------------------------
+// Output and clock
+// ---------------------------------------------------------------------------
+
+static OUTPUT: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+static CLOCK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Send log messages to `output`. Until then they are dropped.
+pub fn set_output(output: fn(&str)) {
+    OUTPUT.store(output as *mut (), Ordering::Release);
+}
+
+/// Take `esp_log_timestamp` from `clock`, milliseconds since boot. Until then
+/// it is 0.
+pub fn set_clock(clock: fn() -> u32) {
+    CLOCK.store(clock as *mut (), Ordering::Release);
+}
+
+pub fn timestamp() -> u32 {
+    let clock = CLOCK.load(Ordering::Acquire);
+    if clock.is_null() {
+        return 0;
+    }
+    let clock: fn() -> u32 = unsafe { core::mem::transmute(clock) };
+    clock()
+}
+
+/// How much of a message is collected before it goes out.
+const LINE_LEN: usize = 128;
+
+/// Collects a message so the output sees it in as few pieces as fit.
+struct Line {
+    buf: [u8; LINE_LEN],
+    len: usize,
+    output: fn(&str),
+}
+
+impl Line {
+    fn flush(&mut self) {
+        if self.len > 0 {
+            (self.output)(core::str::from_utf8(&self.buf[..self.len]).unwrap_or(""));
+            self.len = 0;
+        }
+    }
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if s.len() > LINE_LEN - self.len {
+            self.flush();
+            if s.len() > LINE_LEN {
+                (self.output)(s);
+                return Ok(());
+            }
+        }
+        self.buf[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+        self.len += s.len();
+        Ok(())
+    }
+}
+
+/// Format one message and send it to the output, unless `tag` doesn't log at
+/// `level`.
+///
+/// # Safety
+///
+/// As [`format`].
+pub unsafe fn write(level: Level, tag: &[u8], format: &[u8], mut args: Args) {
+    let output = OUTPUT.load(Ordering::Acquire);
+    if output.is_null() || level == Level::None || level > self::level(tag) {
+        return;
+    }
+    let output: fn(&str) = unsafe { core::mem::transmute(output) };
+    let mut line = Line {
+        buf: [0; LINE_LEN],
+        len: 0,
+        output,
+    };
+    let _ = unsafe { self::format(&mut line, format, &mut args) };
+    line.flush();
+}
+
+// ---------------------------------------------------------------------------
+// printf
+// ---------------------------------------------------------------------------
+
+/// The arguments after a format string, one register-sized word each, in
+/// the order they were passed.
+pub struct Args<'a> {
+    words: &'a [usize],
+    next: usize,
+    /// How many argument words came before `words[0]` in the call.
+    position: usize,
+}
+
+impl<'a> Args<'a> {
+    pub fn new(words: &'a [usize]) -> Self {
+        Self::after(0, words)
+    }
+
+    /// Arguments that follow `position` other words in the call. 64-bit
+    /// arguments on 32-bit targets start at an even word of the call.
+    pub fn after(position: usize, words: &'a [usize]) -> Self {
+        Self {
+            words,
+            next: 0,
+            position,
+        }
+    }
+
+    fn word(&mut self) -> Option<usize> {
+        let word = self.words.get(self.next).copied();
+        self.next += 1;
+        word
+    }
+
+    fn wide(&mut self) -> Option<u64> {
+        if usize::BITS >= 64 {
+            return self.word().map(|w| w as u64);
+        }
+        self.next += (self.position + self.next) % 2;
+        let low = self.word()? as u64;
+        let high = self.word()? as u64;
+        Some(low | high << 32)
+    }
+}
+
+/// `hh`, `h`, none, `l`/`z`/`t`, `ll`/`j`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Length {
+    Char,
+    Short,
+    Int,
+    Long,
+    Wide,
+}
+
+/// One `%` conversion.
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+    length: Length,
+}
+
+/// Format `format` like C's `printf`, taking arguments from `args`. An
+/// argument past the end of `args` prints as `?`, and conversions outside the
+/// subset are copied as they are.
+///
+/// # Safety
+///
+/// Every `%s` argument is null or points to a string that is NUL-terminated
+/// (or at least as long as the precision), as in C.
+pub unsafe fn format(out: &mut impl Write, format: &[u8], args: &mut Args) -> fmt::Result {
+    let mut rest = format;
+    while let Some(percent) = rest.iter().position(|&b| b == b'%') {
+        write_bytes(out, &rest[..percent])?;
+        let start = &rest[percent..];
+        let (spec, used) = parse_spec(&start[1..], args);
+        let Some(&conversion) = start.get(1 + used) else {
+            return write_bytes(out, start);
+        };
+        rest = &start[2 + used..];
+        match conversion {
+            b'%' => out.write_char('%')?,
+            b'd' | b'i' | b'u' | b'x' | b'X' | b'o' | b'c' | b'p' | b's' => {
+                let value = if spec.length == Length::Wide {
+                    args.wide()
+                } else {
+                    args.word().map(|w| w as u64)
+                };
+                match value {
+                    Some(value) => unsafe { write_arg(out, &spec, conversion, value)? },
+                    None => out.write_char('?')?,
+                }
+            }
+            _ => write_bytes(out, &start[..2 + used])?,
+        }
+    }
+    write_bytes(out, rest)
+}
+
+/// Read the flags, width, precision and length after a `%`, returning them
+/// and how many bytes they took.
+fn parse_spec(bytes: &[u8], args: &mut Args) -> (Spec, usize) {
+    let mut spec = Spec {
+        left: false,
+        zero: false,
+        plus: false,
+        space: false,
+        alternate: false,
+        width: 0,
+        precision: None,
+        length: Length::Int,
+    };
+    let mut i = 0;
+    while let Some(&b) = bytes.get(i) {
+        match b {
+            b'-' => spec.left = true,
+            b'0' => spec.zero = true,
+            b'+' => spec.plus = true,
+            b' ' => spec.space = true,
+            b'#' => spec.alternate = true,
+            _ => break,
+        }
+        i += 1;
+    }
+
+    let mut number = |i: &mut usize| {
+        if bytes.get(*i) == Some(&b'*') {
+            *i += 1;
+            return args.word().map(|w| w as u32 as i32);
+        }
+        let mut n = 0i32;
+        while let Some(digit) = bytes.get(*i).filter(|b| b.is_ascii_digit()) {
+            n = n.saturating_mul(10).saturating_add((digit - b'0') as i32);
+            *i += 1;
+        }
+        Some(n)
+    };
+    let width = number(&mut i).unwrap_or(0);
+    spec.left |= width < 0;
+    spec.width = width.unsigned_abs() as usize;
+    if bytes.get(i) == Some(&b'.') {
+        i += 1;
+        // A negative precision counts as none
+        spec.precision = number(&mut i)
+            .and_then(|p| usize::try_from(p).ok())
+            .or(Some(0));
+    }
+
+    let (length, used) = match (bytes.get(i), bytes.get(i + 1)) {
+        (Some(b'h'), Some(b'h')) => (Length::Char, 2),
+        (Some(b'h'), _) => (Length::Short, 1),
+        (Some(b'l'), Some(b'l')) => (Length::Wide, 2),
+        (Some(b'l' | b'z' | b't'), _) => (Length::Long, 1),
+        (Some(b'j'), _) => (Length::Wide, 1),
+        _ => (Length::Int, 0),
+    };
+    spec.length = length;
+    (spec, i + used)
+}
+
+/// Write one argument, `value` holding its word (or two).
+unsafe fn write_arg(out: &mut impl Write, spec: &Spec, conversion: u8, value: u64) -> fmt::Result {
+    let mut digits = Digits::default();
+    let mut prefix = "";
+    match conversion {
+        b'd' | b'i' => {
+            let value = match spec.length {
+                Length::Char => value as i8 as i64,
+                Length::Short => value as i16 as i64,
+                Length::Int => value as u32 as i32 as i64,
+                Length::Long => value as usize as isize as i64,
+                Length::Wide => value as i64,
+            };
+            prefix = if value < 0 {
+                "-"
+            } else if spec.plus {
+                "+"
+            } else if spec.space {
+                " "
+            } else {
+                ""
+            };
+            write!(digits, "{}", value.unsigned_abs())?;
+        }
+        b'u' | b'x' | b'X' | b'o' => {
+            let value = match spec.length {
+                Length::Char => value as u8 as u64,
+                Length::Short => value as u16 as u64,
+                Length::Int => value as u32 as u64,
+                Length::Long => value as usize as u64,
+                Length::Wide => value,
+            };
+            match conversion {
+                b'u' => write!(digits, "{}", value)?,
+                b'x' => write!(digits, "{:x}", value)?,
+                b'X' => write!(digits, "{:X}", value)?,
+                _ => write!(digits, "{:o}", value)?,
+            }
+            if spec.alternate && value != 0 {
+                prefix = match conversion {
+                    b'x' => "0x",
+                    b'X' => "0X",
+                    b'o' => "0",
+                    _ => "",
+                };
+            }
+        }
+        b'p' => {
+            prefix = "0x";
+            write!(digits, "{:x}", value as usize)?;
+        }
+        b'c' => return pad(out, spec, "", 0, &[value as u8], false),
+        _ => {
+            let ptr = value as usize as *const u8;
+            if ptr.is_null() {
+                return pad(out, spec, "", 0, b"(null)", false);
+            }
+            let bytes = match spec.precision {
+                Some(max) => {
+                    let mut len = 0;
+                    while len < max && unsafe { *ptr.add(len) } != 0 {
+                        len += 1;
+                    }
+                    unsafe { core::slice::from_raw_parts(ptr, len) }
+                }
+                None => unsafe { CStr::from_ptr(ptr.cast()) }.to_bytes(),
+            };
+            return pad(out, spec, "", 0, bytes, false);
+        }
+    }
+
+    // The precision of an integer is its fewest digits, and `%.0d` of zero
+    // prints none
+    let body = digits.as_bytes();
+    match spec.precision {
+        Some(0) if body == b"0" => pad(out, spec, prefix, 0, &[], false),
+        Some(precision) => {
+            let zeros = precision.saturating_sub(body.len());
+            pad(out, spec, prefix, zeros, body, false)
+        }
+        None => pad(out, spec, prefix, 0, body, spec.zero),
+    }
+}
+
+/// Write `prefix`, `zeros` zeros and `body`, padded to the width with spaces,
+/// or with more zeros after the prefix if `zero_fill` and right-aligned.
+fn pad(
+    out: &mut impl Write,
+    spec: &Spec,
+    prefix: &str,
+    mut zeros: usize,
+    body: &[u8],
+    zero_fill: bool,
+) -> fmt::Result {
+    let mut fill = spec.width.saturating_sub(prefix.len() + zeros + body.len());
+    if zero_fill && !spec.left {
+        zeros += fill;
+        fill = 0;
+    }
+    if !spec.left {
+        repeat(out, ' ', fill)?;
+    }
+    out.write_str(prefix)?;
+    repeat(out, '0', zeros)?;
+    write_bytes(out, body)?;
+    if spec.left {
+        repeat(out, ' ', fill)?;
+    }
+    Ok(())
+}
+
+fn repeat(out: &mut impl Write, c: char, count: usize) -> fmt::Result {
+    (0..count).try_for_each(|_| out.write_char(c))
+}
+
+/// Write C bytes, replacing anything that isn't UTF-8.
+fn write_bytes(out: &mut impl Write, bytes: &[u8]) -> fmt::Result {
+    for chunk in bytes.utf8_chunks() {
+        out.write_str(chunk.valid())?;
+        if !chunk.invalid().is_empty() {
+            out.write_char(char::REPLACEMENT_CHARACTER)?;
+        }
+    }
+    Ok(())
+}
+
+/// The digits of one number, at most 22 (octal, 64 bits).
+#[derive(Default)]
+struct Digits {
+    buf: [u8; 22],
+    len: usize,
+}
+
+impl Digits {
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl Write for Digits {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// ESP-IDF logging stubs — the .a library calls these for diagnostics
+// ---------------------------------------------------------------------------
+
+/// Format arguments `esp_log_write` reads.
+#[cfg(feature = "esp-new-jpeg")]
+const VARARGS: usize = 8;
+
+/// `void esp_log_write(esp_log_level_t level, const char *tag, const char *format, ...)`
+///
+/// The arguments after `format` are C varargs. The Xtensa and RISC-V ABIs
+/// pass them in registers and then on the stack exactly like named ones, so
+/// they can be read as plain word-sized arguments. Words past the ones the
+/// caller passed hold whatever was there; only the format decides how many
+/// are used.
+///
+/// # Safety
+///
+/// `tag` and `format` are null or NUL-terminated, and the arguments match
+/// `format`, as for `printf`.
+#[cfg(feature = "esp-new-jpeg")]
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn esp_log_write(
+    level: u32,
+    tag: *const core::ffi::c_char,
+    format: *const core::ffi::c_char,
+    a0: usize,
+    a1: usize,
+    a2: usize,
+    a3: usize,
+    a4: usize,
+    a5: usize,
+    a6: usize,
+    a7: usize,
+) {
+    if format.is_null() {
+        return;
+    }
+    let tag = if tag.is_null() {
+        &[][..]
+    } else {
+        unsafe { CStr::from_ptr(tag) }.to_bytes()
+    };
+    let format = unsafe { CStr::from_ptr(format) }.to_bytes();
+    let words: [usize; VARARGS] = [a0, a1, a2, a3, a4, a5, a6, a7];
+    unsafe { write(Level::from_raw(level), tag, format, Args::after(3, &words)) }
+}
+
+/// `void esp_log_level_set(const char *tag, esp_log_level_t level)`
+///
+/// # Safety
+///
+/// `tag` is null or NUL-terminated.
+#[cfg(feature = "esp-new-jpeg")]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn esp_log_level_set(tag: *const core::ffi::c_char, level: u32) {
+    if !tag.is_null() {
+        set_level(
+            unsafe { CStr::from_ptr(tag) }.to_bytes(),
+            Level::from_raw(level),
+        );
+    }
+}
+
+/// `uint32_t esp_log_timestamp(void)`
+#[cfg(feature = "esp-new-jpeg")]
+#[unsafe(no_mangle)]
+pub extern "C" fn esp_log_timestamp() -> u32 {
+    timestamp()
+}
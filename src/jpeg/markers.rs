@@ -0,0 +1,416 @@
+//! Pure-Rust JPEG marker/segment walker.
+//!
+//! Validates the segment structure of a frame (SOI through EOI) before the
+//! bytes reach the closed-source decoder, and rejects streams the decoder
+//! can't handle: progressive, lossless, hierarchical, arithmetic-coded and
+//! 12-bit images.
+
+use super::JpegSubsampling;
+
+pub const TEM: u8 = 0x01;
+pub const SOF0: u8 = 0xC0;
+pub const SOF1: u8 = 0xC1;
+pub const SOF2: u8 = 0xC2;
+pub const SOF3: u8 = 0xC3;
+pub const DHT: u8 = 0xC4;
+pub const JPG: u8 = 0xC8;
+pub const DAC: u8 = 0xCC;
+pub const RST0: u8 = 0xD0;
+pub const RST7: u8 = 0xD7;
+pub const SOI: u8 = 0xD8;
+pub const EOI: u8 = 0xD9;
+pub const SOS: u8 = 0xDA;
+pub const DQT: u8 = 0xDB;
+pub const DNL: u8 = 0xDC;
+pub const DRI: u8 = 0xDD;
+pub const APP0: u8 = 0xE0;
+pub const APP15: u8 = 0xEF;
+pub const COM: u8 = 0xFE;
+
+/// Maximum number of components in a frame.
+pub const MAX_COMPONENTS: usize = 4;
+
+/// Why a frame was rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarkerError {
+    /// The data does not start with SOI.
+    MissingSoi,
+    /// The data ended before EOI.
+    Truncated,
+    /// A byte other than `0xFF` where a marker was expected, or a reserved marker.
+    InvalidMarker(u8),
+    /// The length field of the segment with this marker is inconsistent with its contents.
+    BadSegmentLength(u8),
+    /// SOF2/SOF6/SOF10/SOF14: progressive DCT.
+    Progressive,
+    /// SOF3/SOF7/SOF11/SOF15: lossless.
+    Lossless,
+    /// SOF5/SOF6/SOF7 or DNL: hierarchical or deferred-height frames.
+    Hierarchical,
+    /// SOF9..SOF15 or DAC: arithmetic coding.
+    Arithmetic,
+    /// Sample precision other than 8 bits.
+    UnsupportedPrecision(u8),
+    /// Only one- and three-component images can be decoded.
+    UnsupportedComponents(u8),
+    /// Zero width or height in the frame header.
+    ZeroSize,
+    /// More than one frame header.
+    DuplicateFrameHeader,
+    /// SOS before any frame header.
+    ScanBeforeFrame,
+    /// The scan header references a component not in the frame.
+    UnknownComponent(u8),
+    /// A component references a quantization table that was never defined.
+    MissingQuantTable(u8),
+    /// A table selector or sampling factor outside the allowed range.
+    BadTableSpec(u8),
+    /// EOI without any scan.
+    MissingScan,
+}
+
+impl core::fmt::Display for MarkerError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::MissingSoi => f.write_str("missing SOI"),
+            Self::Truncated => f.write_str("truncated before EOI"),
+            Self::InvalidMarker(m) => write!(f, "invalid marker 0x{:02X}", m),
+            Self::BadSegmentLength(m) => write!(f, "bad length in segment 0x{:02X}", m),
+            Self::Progressive => f.write_str("progressive JPEG"),
+            Self::Lossless => f.write_str("lossless JPEG"),
+            Self::Hierarchical => f.write_str("hierarchical JPEG"),
+            Self::Arithmetic => f.write_str("arithmetic-coded JPEG"),
+            Self::UnsupportedPrecision(p) => write!(f, "{}-bit precision", p),
+            Self::UnsupportedComponents(n) => write!(f, "{} components", n),
+            Self::ZeroSize => f.write_str("zero image size"),
+            Self::DuplicateFrameHeader => f.write_str("duplicate frame header"),
+            Self::ScanBeforeFrame => f.write_str("scan before frame header"),
+            Self::UnknownComponent(id) => write!(f, "scan references unknown component {}", id),
+            Self::MissingQuantTable(t) => write!(f, "quantization table {} not defined", t),
+            Self::BadTableSpec(m) => write!(f, "bad table spec in segment 0x{:02X}", m),
+            Self::MissingScan => f.write_str("no scan before EOI"),
+        }
+    }
+}
+
+impl core::error::Error for MarkerError {}
+
+/// One frame component from the SOF header.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Component {
+    pub id: u8,
+    /// Horizontal sampling factor (1..=4).
+    pub h: u8,
+    /// Vertical sampling factor (1..=4).
+    pub v: u8,
+    /// Quantization table selector.
+    pub tq: u8,
+}
+
+/// What [`parse`] learned about a valid frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameSummary {
+    pub width: u16,
+    pub height: u16,
+    /// The SOFn marker of the frame (SOF0 or SOF1).
+    pub sof: u8,
+    pub precision: u8,
+    component_count: u8,
+    components: [Component; MAX_COMPONENTS],
+    /// MCUs between restart markers, 0 if restart markers are disabled.
+    pub restart_interval: u16,
+    /// Bit `n` set if quantization table `n` was defined.
+    pub quant_tables: u8,
+    /// Bits 0..=3: DC tables 0..=3 defined; bits 4..=7: AC tables 0..=3 defined.
+    pub huffman_tables: u8,
+    /// Number of SOS segments.
+    pub scans: u8,
+    /// Offset of the first entropy-coded byte.
+    pub scan_offset: usize,
+    /// Offset just past EOI; anything after it is not part of the frame.
+    pub len: usize,
+}
+
+impl FrameSummary {
+    pub fn components(&self) -> &[Component] {
+        &self.components[..self.component_count as usize]
+    }
+
+    /// Chroma subsampling, or `None` for layouts the decoder doesn't know
+    /// (e.g. 4:1:1 or subsampled luma).
+    pub fn subsampling(&self) -> Option<JpegSubsampling> {
+        match self.components() {
+            [_] => Some(JpegSubsampling::Gray),
+            [y, cb, cr] if (cb.h, cb.v, cr.h, cr.v) == (1, 1, 1, 1) => match (y.h, y.v) {
+                (1, 1) => Some(JpegSubsampling::Yuv444),
+                (2, 1) => Some(JpegSubsampling::Yuv422),
+                (2, 2) => Some(JpegSubsampling::Yuv420),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// True if the file supplied its own Huffman tables. MJPEG from some
+    /// cameras omits DHT and relies on the standard tables.
+    pub fn has_huffman_tables(&self) -> bool {
+        self.huffman_tables != 0
+    }
+}
+
+/// Walk every segment of the frame in `data` and return its summary.
+///
+/// Trailing bytes after EOI are ignored and excluded from `len`.
+pub fn parse(data: &[u8]) -> Result<FrameSummary, MarkerError> {
+    walk(data, false)
+}
+
+/// Walk the segments of a frame that is still arriving, up to and including
+/// the first SOS, and return what they say. [`MarkerError::Truncated`] means
+/// the headers aren't all there yet.
+///
+/// Nothing past `scan_offset` is looked at: `scans` is 1 and `len` is 0.
+pub fn parse_header(data: &[u8]) -> Result<FrameSummary, MarkerError> {
+    walk(data, true)
+}
+
+fn walk(data: &[u8], header_only: bool) -> Result<FrameSummary, MarkerError> {
+    if data.len() < 2 || data[0] != 0xFF || data[1] != SOI {
+        return Err(MarkerError::MissingSoi);
+    }
+
+    let mut summary = FrameSummary {
+        width: 0,
+        height: 0,
+        sof: 0,
+        precision: 0,
+        component_count: 0,
+        components: [Component::default(); MAX_COMPONENTS],
+        restart_interval: 0,
+        quant_tables: 0,
+        huffman_tables: 0,
+        scans: 0,
+        scan_offset: 0,
+        len: 0,
+    };
+
+    let mut pos = 2;
+    loop {
+        let marker = next_marker(data, &mut pos)?;
+        match marker {
+            EOI => {
+                if summary.scans == 0 {
+                    return Err(MarkerError::MissingScan);
+                }
+                summary.len = pos;
+                return Ok(summary);
+            }
+            // Standalone markers carry no length field
+            TEM | RST0..=RST7 => continue,
+            SOI => return Err(MarkerError::InvalidMarker(marker)),
+            _ => {}
+        }
+
+        let segment = segment(data, pos, marker)?;
+        pos += 2 + segment.len();
+
+        match marker {
+            SOF0 | SOF1 => parse_sof(&mut summary, marker, segment)?,
+            SOF2 => return Err(MarkerError::Progressive),
+            SOF3 => return Err(MarkerError::Lossless),
+            // SOF5..SOF7: differential (hierarchical) Huffman
+            0xC5..=0xC7 => return Err(MarkerError::Hierarchical),
+            // SOF9..SOF15 and DAC: arithmetic coding
+            0xC9..=0xCB | 0xCD..=0xCF | DAC => return Err(MarkerError::Arithmetic),
+            DNL => return Err(MarkerError::Hierarchical),
+            DHT => parse_dht(&mut summary, segment)?,
+            DQT => parse_dqt(&mut summary, segment)?,
+            DRI => {
+                if segment.len() != 2 {
+                    return Err(MarkerError::BadSegmentLength(marker));
+                }
+                summary.restart_interval = u16::from_be_bytes([segment[0], segment[1]]);
+            }
+            SOS => {
+                parse_sos(&summary, segment)?;
+                if summary.scans == 0 {
+                    summary.scan_offset = pos;
+                }
+                summary.scans = summary.scans.saturating_add(1);
+                if header_only {
+                    return Ok(summary);
+                }
+                pos = skip_entropy_data(data, pos)?;
+            }
+            APP0..=APP15 | COM => {}
+            // JPG and JPGn extensions, reserved markers
+            _ => return Err(MarkerError::InvalidMarker(marker)),
+        }
+    }
+}
+
+/// Read the marker at `pos` (skipping fill bytes) and advance past it.
+fn next_marker(data: &[u8], pos: &mut usize) -> Result<u8, MarkerError> {
+    let first = *data.get(*pos).ok_or(MarkerError::Truncated)?;
+    if first != 0xFF {
+        return Err(MarkerError::InvalidMarker(first));
+    }
+    *pos += 1;
+    loop {
+        let b = *data.get(*pos).ok_or(MarkerError::Truncated)?;
+        *pos += 1;
+        match b {
+            0xFF => continue,
+            0x00 => return Err(MarkerError::InvalidMarker(b)),
+            _ => return Ok(b),
+        }
+    }
+}
+
+/// Return the payload of the segment whose length field starts at `pos`.
+fn segment(data: &[u8], pos: usize, marker: u8) -> Result<&[u8], MarkerError> {
+    let len_bytes = data.get(pos..pos + 2).ok_or(MarkerError::Truncated)?;
+    let len = u16::from_be_bytes([len_bytes[0], len_bytes[1]]) as usize;
+    if len < 2 {
+        return Err(MarkerError::BadSegmentLength(marker));
+    }
+    data.get(pos + 2..pos + len).ok_or(MarkerError::Truncated)
+}
+
+/// Advance past entropy-coded data, stopping at the next real marker.
+fn skip_entropy_data(data: &[u8], mut pos: usize) -> Result<usize, MarkerError> {
+    loop {
+        let ff = data[pos..]
+            .iter()
+            .position(|&b| b == 0xFF)
+            .ok_or(MarkerError::Truncated)?;
+        pos += ff;
+        match data.get(pos + 1) {
+            None => return Err(MarkerError::Truncated),
+            // Stuffed byte or restart marker: still inside the scan
+            Some(0x00) | Some(RST0..=RST7) => pos += 2,
+            // Fill byte; the marker follows
+            Some(0xFF) => pos += 1,
+            Some(_) => return Ok(pos),
+        }
+    }
+}
+
+fn parse_sof(summary: &mut FrameSummary, marker: u8, seg: &[u8]) -> Result<(), MarkerError> {
+    if summary.component_count != 0 {
+        return Err(MarkerError::DuplicateFrameHeader);
+    }
+    if seg.len() < 6 {
+        return Err(MarkerError::BadSegmentLength(marker));
+    }
+    let precision = seg[0];
+    let height = u16::from_be_bytes([seg[1], seg[2]]);
+    let width = u16::from_be_bytes([seg[3], seg[4]]);
+    let count = seg[5];
+    if seg.len() != 6 + 3 * count as usize {
+        return Err(MarkerError::BadSegmentLength(marker));
+    }
+    if precision != 8 {
+        return Err(MarkerError::UnsupportedPrecision(precision));
+    }
+    if height == 0 {
+        // Height deferred to a DNL segment
+        return Err(MarkerError::Hierarchical);
+    }
+    if width == 0 {
+        return Err(MarkerError::ZeroSize);
+    }
+    if count != 1 && count != 3 {
+        return Err(MarkerError::UnsupportedComponents(count));
+    }
+
+    for (i, c) in seg[6..].chunks_exact(3).enumerate() {
+        let (h, v, tq) = (c[1] >> 4, c[1] & 0x0F, c[2]);
+        if !(1..=4).contains(&h) || !(1..=4).contains(&v) || tq > 3 {
+            return Err(MarkerError::BadTableSpec(marker));
+        }
+        summary.components[i] = Component { id: c[0], h, v, tq };
+    }
+    summary.sof = marker;
+    summary.precision = precision;
+    summary.width = width;
+    summary.height = height;
+    summary.component_count = count;
+    Ok(())
+}
+
+fn parse_dqt(summary: &mut FrameSummary, mut seg: &[u8]) -> Result<(), MarkerError> {
+    if seg.is_empty() {
+        return Err(MarkerError::BadSegmentLength(DQT));
+    }
+    while let Some((&pq_tq, rest)) = seg.split_first() {
+        let (pq, tq) = (pq_tq >> 4, pq_tq & 0x0F);
+        if pq > 1 || tq > 3 {
+            return Err(MarkerError::BadTableSpec(DQT));
+        }
+        let size = if pq == 0 { 64 } else { 128 };
+        seg = rest.get(size..).ok_or(MarkerError::BadSegmentLength(DQT))?;
+        summary.quant_tables |= 1 << tq;
+    }
+    Ok(())
+}
+
+fn parse_dht(summary: &mut FrameSummary, mut seg: &[u8]) -> Result<(), MarkerError> {
+    if seg.is_empty() {
+        return Err(MarkerError::BadSegmentLength(DHT));
+    }
+    while let Some((&tc_th, rest)) = seg.split_first() {
+        let (tc, th) = (tc_th >> 4, tc_th & 0x0F);
+        if tc > 1 || th > 3 {
+            return Err(MarkerError::BadTableSpec(DHT));
+        }
+        let counts = rest.get(..16).ok_or(MarkerError::BadSegmentLength(DHT))?;
+        let values: usize = counts.iter().map(|&n| n as usize).sum();
+        if values > 256 || !fits_code_space(counts) {
+            return Err(MarkerError::BadTableSpec(DHT));
+        }
+        seg = rest
+            .get(16 + values..)
+            .ok_or(MarkerError::BadSegmentLength(DHT))?;
+        summary.huffman_tables |= 1 << (tc * 4 + th);
+    }
+    Ok(())
+}
+
+/// Whether canonical codes with `counts[i]` codes of length `i + 1` all fit
+/// in their lengths.
+fn fits_code_space(counts: &[u8]) -> bool {
+    let mut code = 0u32;
+    for (len, &n) in (1..).zip(counts) {
+        code += n as u32;
+        if code > 1 << len {
+            return false;
+        }
+        code <<= 1;
+    }
+    true
+}
+
+fn parse_sos(summary: &FrameSummary, seg: &[u8]) -> Result<(), MarkerError> {
+    if summary.component_count == 0 {
+        return Err(MarkerError::ScanBeforeFrame);
+    }
+    let count = *seg.first().ok_or(MarkerError::BadSegmentLength(SOS))? as usize;
+    if count == 0 || count > MAX_COMPONENTS || seg.len() != 4 + 2 * count {
+        return Err(MarkerError::BadSegmentLength(SOS));
+    }
+    for c in seg[1..1 + 2 * count].chunks_exact(2) {
+        let component = summary
+            .components()
+            .iter()
+            .find(|comp| comp.id == c[0])
+            .ok_or(MarkerError::UnknownComponent(c[0]))?;
+        if summary.quant_tables & (1 << component.tq) == 0 {
+            return Err(MarkerError::MissingQuantTable(component.tq));
+        }
+        if c[1] >> 4 > 3 || c[1] & 0x0F > 3 {
+            return Err(MarkerError::BadTableSpec(SOS));
+        }
+    }
+    Ok(())
+}
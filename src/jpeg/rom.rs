@@ -0,0 +1,444 @@
+//! The TJpgDec decoder in the chip's mask ROM (ESP_JPEG's ROM build).
+//!
+//! ESP32, ESP32-S3, ESP32-C3 and ESP32-C6 carry Tiny JPEG Decompressor
+//! R0.01 in ROM, so [`RomJpegDecoder`] costs almost no flash, unlike the
+//! prebuilt ESP_NEW_JPEG. It is smaller in other ways too: YCbCr only (no
+//! grayscale), DHT required, RGB output, no rotation, and power-of-two
+//! scaling (anything finer picks the nearest pixel).
+//!
+//! TJpgDec hands out one MCU at a time through a callback and can't stop
+//! and resume a frame. Outside block mode a block is the whole output, one
+//! pass. In block mode every block runs the decoder again from the top and
+//! keeps one MCU row, so a frame costs about rows²/2 MCU rows: use it when
+//! flash is tighter than time.
+//!
+//! `jd_prepare` and `jd_decomp` are resolved by the ROM linker scripts
+//! (`esp32s3.rom.ld`, group tjpgd).
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use core::mem::MaybeUninit;
+
+use super::backend::{JpegBackend, JpegSession};
+use super::markers;
+use super::{JpegDecoderConfig, JpegError, JpegFrameInfo, JpegPixelFormat, JpegRotation};
+
+// ---------------------------------------------------------------------------
+// FFI declarations (rom/tjpgd.h)
+// ---------------------------------------------------------------------------
+
+/// `JRESULT`
+const JDR_OK: i32 = 0;
+/// Interrupted by the output function.
+const JDR_INTR: i32 = 1;
+/// Device error or wrong termination of the input stream.
+const JDR_INP: i32 = 2;
+/// Memory pool too small for the image.
+const JDR_MEM1: i32 = 3;
+/// Stream input buffer too small.
+const JDR_MEM2: i32 = 4;
+const JDR_PAR: i32 = 5;
+/// Data format error (may be damaged data).
+const JDR_FMT1: i32 = 6;
+/// Right format but not supported.
+const JDR_FMT2: i32 = 7;
+/// Not supported JPEG standard.
+const JDR_FMT3: i32 = 8;
+
+/// Enough for 4:2:0 with four quantization tables, as ESP_JPEG's 3100 bytes
+/// plus some room.
+const POOL_SIZE: usize = 4096;
+
+type InFunc = unsafe extern "C" fn(jd: *mut Jdec, buf: *mut u8, len: u32) -> u32;
+type OutFunc = unsafe extern "C" fn(jd: *mut Jdec, bitmap: *mut c_void, rect: *mut JRect) -> u32;
+
+/// `JRECT`: the pixels of one MCU, inclusive, after scaling.
+#[repr(C)]
+struct JRect {
+    left: u16,
+    right: u16,
+    top: u16,
+    bottom: u16,
+}
+
+/// `JDEC`, the decompressor state. `jd_prepare` fills it in.
+#[repr(C)]
+struct Jdec {
+    dctr: u32,
+    dptr: *mut u8,
+    inbuf: *mut u8,
+    dmsk: u8,
+    scale: u8,
+    /// MCU size in blocks.
+    msx: u8,
+    msy: u8,
+    qtid: [u8; 3],
+    dcv: [i16; 3],
+    nrst: u16,
+    width: u32,
+    height: u32,
+    huffbits: [[*mut u8; 2]; 2],
+    huffcode: [[*mut u16; 2]; 2],
+    huffdata: [[*mut u8; 2]; 2],
+    qttbl: [*mut i32; 4],
+    workbuf: *mut c_void,
+    mcubuf: *mut u8,
+    pool: *mut c_void,
+    sz_pool: u32,
+    infunc: Option<InFunc>,
+    /// Passed through to the callbacks; points at a [`Pass`].
+    device: *mut c_void,
+}
+
+unsafe extern "C" {
+    fn jd_prepare(
+        jd: *mut Jdec,
+        infunc: InFunc,
+        pool: *mut c_void,
+        sz_pool: u32,
+        device: *mut c_void,
+    ) -> i32;
+    fn jd_decomp(jd: *mut Jdec, outfunc: OutFunc, scale: u8) -> i32;
+}
+
+fn check(code: i32) -> Result<(), JpegError> {
+    match code {
+        JDR_OK => Ok(()),
+        JDR_INP => Err(JpegError::NoMoreData),
+        JDR_MEM1 | JDR_MEM2 => Err(JpegError::NoMemory),
+        JDR_PAR => Err(JpegError::InvalidParam),
+        JDR_FMT1 => Err(JpegError::BadData),
+        JDR_FMT2 => Err(JpegError::UnsupportedFormat),
+        JDR_FMT3 => Err(JpegError::UnsupportedStandard),
+        _ => Err(JpegError::Unknown(code)),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Callbacks
+// ---------------------------------------------------------------------------
+
+/// What one run of the decoder reads and writes, behind `JDEC.device`.
+struct Pass<'p> {
+    input: &'p [u8],
+    pos: usize,
+    format: JpegPixelFormat,
+    /// Decoded column of each output column, and row of each output row.
+    columns: &'p [u16],
+    rows: &'p [u16],
+    /// The output rows this pass fills, starting at the top of `out`.
+    first_row: usize,
+    out: &'p mut [u8],
+}
+
+impl Pass<'_> {
+    /// Run the decoder once, stopping after the last row this pass needs.
+    fn run(&mut self, pool: &mut [u32], scale: u8) -> Result<Jdec, JpegError> {
+        let mut jd = MaybeUninit::<Jdec>::uninit();
+        let device = self as *mut Self as *mut c_void;
+        unsafe {
+            check(jd_prepare(
+                jd.as_mut_ptr(),
+                read_input,
+                pool.as_mut_ptr() as *mut c_void,
+                (pool.len() * 4) as u32,
+                device,
+            ))?;
+            if self.rows.len() > self.first_row {
+                match jd_decomp(jd.as_mut_ptr(), write_output, scale) {
+                    JDR_INTR => {}
+                    code => check(code)?,
+                }
+            }
+            Ok(jd.assume_init())
+        }
+    }
+}
+
+/// `infunc`: copy (or, without `buf`, skip) up to `len` bytes of input.
+unsafe extern "C" fn read_input(jd: *mut Jdec, buf: *mut u8, len: u32) -> u32 {
+    let pass = unsafe { &mut *((*jd).device as *mut Pass) };
+    let rest = &pass.input[pass.pos..];
+    let n = rest.len().min(len as usize);
+    if !buf.is_null() {
+        unsafe { core::ptr::copy_nonoverlapping(rest.as_ptr(), buf, n) };
+    }
+    pass.pos += n;
+    n as u32
+}
+
+/// `outfunc`: copy the output pixels that fall in this MCU. Returns 0 to
+/// stop the decoder once the MCU starts below the pass's last row.
+unsafe extern "C" fn write_output(jd: *mut Jdec, bitmap: *mut c_void, rect: *mut JRect) -> u32 {
+    let pass = unsafe { &mut *((*jd).device as *mut Pass) };
+    let rect = unsafe { &*rect };
+    if rect.right < rect.left || rect.bottom < rect.top {
+        return 1;
+    }
+    let rect_width = (rect.right - rect.left + 1) as usize;
+    let rect_height = (rect.bottom - rect.top + 1) as usize;
+    let bitmap =
+        unsafe { core::slice::from_raw_parts(bitmap as *const u8, rect_width * rect_height * 3) };
+
+    let rows = &pass.rows[pass.first_row..];
+    if rows.last().is_some_and(|&last| rect.top > last) {
+        return 0;
+    }
+    let in_rect = |range: &[u16], low: u16, high: u16| {
+        range.partition_point(|&v| v < low)..range.partition_point(|&v| v <= high)
+    };
+    let bpp = pass.format.bytes_per_pixel();
+    let row_len = pass.columns.len() * bpp;
+    let columns = in_rect(pass.columns, rect.left, rect.right);
+    for y in in_rect(rows, rect.top, rect.bottom) {
+        let src_row = (rows[y] - rect.top) as usize * rect_width;
+        for x in columns.clone() {
+            let at = (src_row + (pass.columns[x] - rect.left) as usize) * 3;
+            let [r, g, b] = [bitmap[at], bitmap[at + 1], bitmap[at + 2]];
+            let px = &mut pass.out[y * row_len + x * bpp..][..bpp];
+            match pass.format {
+                JpegPixelFormat::Rgb888 => px.copy_from_slice(&[r, g, b]),
+                format => {
+                    let v = ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3);
+                    px.copy_from_slice(&if format == JpegPixelFormat::Rgb565Le {
+                        v.to_le_bytes()
+                    } else {
+                        v.to_be_bytes()
+                    });
+                }
+            }
+        }
+    }
+    1
+}
+
+// ---------------------------------------------------------------------------
+// Decoder
+// ---------------------------------------------------------------------------
+
+/// TJpgDec from ROM behind [`JpegBackend`].
+pub struct RomJpegDecoder {
+    config: JpegDecoderConfig,
+    /// TJpgDec's memory pool, word aligned.
+    pool: Vec<u32>,
+    out: Vec<u8>,
+    columns: Vec<u16>,
+    rows: Vec<u16>,
+}
+
+impl RomJpegDecoder {
+    /// Create a new block-mode decoder with RGB565_LE output.
+    pub fn new() -> Result<Self, JpegError> {
+        Self::with_config(JpegDecoderConfig::default())
+    }
+}
+
+impl JpegBackend for RomJpegDecoder {
+    type Session<'a> = RomSession<'a>;
+
+    /// Create a decoder from a validated configuration. CbYCrY output and
+    /// rotation are refused.
+    fn with_config(config: JpegDecoderConfig) -> Result<Self, JpegError> {
+        if config.output_format() == JpegPixelFormat::CbYCrY {
+            return Err(JpegError::UnsupportedFormat);
+        }
+        if config.rotation() != JpegRotation::Deg0 {
+            return Err(JpegError::InvalidParam);
+        }
+        Ok(Self {
+            config,
+            pool: Vec::new(),
+            out: Vec::new(),
+            columns: Vec::new(),
+            rows: Vec::new(),
+        })
+    }
+
+    fn config(&self) -> &JpegDecoderConfig {
+        &self.config
+    }
+
+    fn reset(&mut self) -> Result<(), JpegError> {
+        *self = Self::with_config(self.config)?;
+        Ok(())
+    }
+
+    fn start_decode<'a>(
+        &'a mut self,
+        jpeg_data: &'a mut [u8],
+    ) -> Result<RomSession<'a>, JpegError> {
+        RomSession::new(self, jpeg_data)
+    }
+}
+
+/// One frame being decoded by a [`RomJpegDecoder`].
+pub struct RomSession<'a> {
+    decoder: &'a mut RomJpegDecoder,
+    input: &'a [u8],
+    info: JpegFrameInfo,
+    /// TJpgDec's scale, 1/2^n.
+    scale: u8,
+    output_width: u16,
+    /// Output rows per block.
+    block_height: u16,
+    block_count: usize,
+    current_block: usize,
+    out_len: usize,
+}
+
+impl<'a> RomSession<'a> {
+    fn new(decoder: &'a mut RomJpegDecoder, data: &'a [u8]) -> Result<Self, JpegError> {
+        let summary = markers::parse(data)?;
+        let input = &data[..summary.len];
+        let (width, height) = (summary.width, summary.height);
+
+        // TJpgDec scales by 1/2, 1/4 or 1/8; pick the strongest one that
+        // stays at least as large as the request, then the nearest pixels
+        let config = decoder.config;
+        let pick = |requested: u16, current: u16| if requested == 0 { current } else { requested };
+        let (scaled_width, scaled_height) = (
+            pick(config.scale().0, width),
+            pick(config.scale().1, height),
+        );
+        let (output_width, output_height) = config.output_size(width, height);
+        if scaled_width > width
+            || scaled_height > height
+            || output_width > scaled_width
+            || output_height > scaled_height
+        {
+            return Err(JpegError::InvalidParam);
+        }
+        let scale = (0..=3u8)
+            .rev()
+            .find(|&s| width >> s >= scaled_width && height >> s >= scaled_height)
+            .unwrap_or(0);
+        let (decoded_width, decoded_height) = (width >> scale, height >> scale);
+        let map = |n: u16, decoded: u16, scaled: u16| {
+            (0..n as u32).map(move |i| (i * decoded as u32 / scaled as u32) as u16)
+        };
+        decoder.columns.clear();
+        decoder
+            .columns
+            .extend(map(output_width, decoded_width, scaled_width));
+        decoder.rows.clear();
+        decoder
+            .rows
+            .extend(map(output_height, decoded_height, scaled_height));
+
+        if decoder.pool.is_empty() {
+            decoder.pool = vec![0; POOL_SIZE / 4];
+        }
+        // Only the header, for the MCU size
+        let jd = Pass {
+            input,
+            pos: 0,
+            format: config.output_format(),
+            columns: &decoder.columns,
+            rows: &decoder.rows,
+            first_row: decoder.rows.len(),
+            out: &mut [],
+        }
+        .run(&mut decoder.pool, scale)?;
+
+        let (block_count, block_height) = if config.block_mode() {
+            let mcu_height = 8 * jd.msy as u16;
+            (height.div_ceil(mcu_height) as usize, mcu_height)
+        } else {
+            (1, output_height)
+        };
+        let out_len = output_width as usize
+            * block_height as usize
+            * config.output_format().bytes_per_pixel();
+        if decoder.out.len() < out_len {
+            decoder.out.resize(out_len, 0);
+        }
+
+        Ok(Self {
+            decoder,
+            input,
+            info: JpegFrameInfo { width, height },
+            scale,
+            output_width,
+            block_height,
+            block_count,
+            current_block: 0,
+            out_len: 0,
+        })
+    }
+
+    pub fn info(&self) -> &JpegFrameInfo {
+        &self.info
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.block_count
+    }
+
+    /// Width of every decoded block after scale and clipper.
+    pub fn output_width(&self) -> u16 {
+        self.output_width
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.decoder.config.output_format().bytes_per_pixel()
+    }
+
+    /// Decode the next block. Returns `(block_width, block_height)`.
+    pub fn decode_next_block(&mut self) -> Result<(u16, u16), JpegError> {
+        if self.current_block >= self.block_count {
+            return Err(JpegError::SessionExhausted);
+        }
+        let bytes_per_pixel = self.bytes_per_pixel();
+        let decoder = &mut *self.decoder;
+        let first_row = self.current_block * self.block_height as usize;
+        let last_row = (first_row + self.block_height as usize).min(decoder.rows.len());
+        let rows = (last_row - first_row) as u16;
+        self.out_len = self.output_width as usize * rows as usize * bytes_per_pixel;
+        self.current_block += 1;
+
+        Pass {
+            input: self.input,
+            pos: 0,
+            format: decoder.config.output_format(),
+            columns: &decoder.columns,
+            rows: &decoder.rows[..last_row],
+            first_row,
+            out: &mut decoder.out[..self.out_len],
+        }
+        .run(&mut decoder.pool, self.scale)?;
+        Ok((self.output_width, rows))
+    }
+
+    /// Pixel data, in the configured output format, for the most recently
+    /// decoded block.
+    pub fn block_data(&self) -> &[u8] {
+        &self.decoder.out[..self.out_len]
+    }
+}
+
+impl JpegSession for RomSession<'_> {
+    fn info(&self) -> &JpegFrameInfo {
+        RomSession::info(self)
+    }
+
+    fn block_count(&self) -> usize {
+        RomSession::block_count(self)
+    }
+
+    fn output_width(&self) -> u16 {
+        RomSession::output_width(self)
+    }
+
+    fn bytes_per_pixel(&self) -> usize {
+        RomSession::bytes_per_pixel(self)
+    }
+
+    fn decode_next_block(&mut self) -> Result<(u16, u16), JpegError> {
+        RomSession::decode_next_block(self)
+    }
+
+    fn block_data(&self) -> &[u8] {
+        RomSession::block_data(self)
+    }
+}
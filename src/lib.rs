@@ -1,4 +1,15 @@
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
 extern crate alloc;
 
+pub mod config;
+pub mod display;
+pub mod fit;
+pub mod http;
 pub mod jpeg;
+pub mod mdns;
+pub mod mjpeg;
+pub mod pipeline;
+pub mod portal;
+pub mod rtp;
+pub mod shell;
+pub mod wifi;
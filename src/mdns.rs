@@ -0,0 +1,288 @@
+//! mDNS/DNS-SD service browser (RFC 6762, RFC 6763).
+//!
+//! [`Browser`] finds the first instance of any of a list of service types,
+//! e.g. `_rumble._tcp.local`, and follows its PTR, SRV and A records to an
+//! address and port. It only builds queries and parses responses; sending
+//! them over a UDP socket bound to [`MDNS_PORT`] is up to the caller.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+
+pub const MDNS_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub const MDNS_PORT: u16 = 5353;
+
+const HEADER_LEN: usize = 12;
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+/// Question class bit asking for a unicast response.
+const UNICAST_RESPONSE: u16 = 0x8000;
+/// Longest encoded name RFC 1035 allows.
+const MAX_NAME: usize = 255;
+/// Compression pointers followed before a name is considered a loop.
+const MAX_JUMPS: usize = 16;
+/// A records remembered while waiting for the SRV that names their host.
+const MAX_ADDRESSES: usize = 8;
+
+/// A domain name in uncompressed wire format, e.g. `\x04host\x05local\x00`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Name(Vec<u8>);
+
+impl Name {
+    /// Encode a dotted name. Returns `None` for empty or overlong labels.
+    fn from_dotted(name: &str) -> Option<Self> {
+        let mut wire = Vec::with_capacity(name.len() + 2);
+        for label in name.trim_end_matches('.').split('.') {
+            if label.is_empty() || label.len() > 63 {
+                return None;
+            }
+            wire.push(label.len() as u8);
+            wire.extend_from_slice(label.as_bytes());
+        }
+        wire.push(0);
+        (wire.len() <= MAX_NAME).then_some(Self(wire))
+    }
+
+    /// Read a possibly compressed name at `pos`. Returns it and the offset
+    /// just past it.
+    fn read(packet: &[u8], mut pos: usize) -> Option<(Self, usize)> {
+        let mut wire = Vec::new();
+        let mut end = None;
+        let mut jumps = 0;
+        loop {
+            let len = *packet.get(pos)? as usize;
+            match len {
+                0 => {
+                    wire.push(0);
+                    return Some((Self(wire), end.unwrap_or(pos + 1)));
+                }
+                1..=63 => {
+                    wire.extend_from_slice(packet.get(pos..pos + 1 + len)?);
+                    if wire.len() >= MAX_NAME {
+                        return None;
+                    }
+                    pos += 1 + len;
+                }
+                0xC0.. => {
+                    let low = *packet.get(pos + 1)? as usize;
+                    end.get_or_insert(pos + 2);
+                    jumps += 1;
+                    if jumps > MAX_JUMPS {
+                        return None;
+                    }
+                    pos = (len & 0x3F) << 8 | low;
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    /// DNS names compare case-insensitively. Length bytes are at most 63 and
+    /// so never change under ASCII case folding.
+    fn matches(&self, other: &Self) -> bool {
+        self.0.eq_ignore_ascii_case(&other.0)
+    }
+
+    /// The name in dotted form, without the trailing dot.
+    fn to_dotted(&self) -> String {
+        let mut dotted = String::new();
+        let mut pos = 0;
+        while let Some(&len) = self.0.get(pos)
+            && len != 0
+        {
+            if !dotted.is_empty() {
+                dotted.push('.');
+            }
+            let label = &self.0[pos + 1..pos + 1 + len as usize];
+            dotted.push_str(&String::from_utf8_lossy(label));
+            pos += 1 + len as usize;
+        }
+        dotted
+    }
+}
+
+/// A resolved service instance.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Service {
+    /// Instance name, e.g. `My Laptop._rumble._tcp.local`.
+    pub instance: String,
+    /// Host name from the SRV record, e.g. `my-laptop.local`.
+    pub host: String,
+    pub addr: Ipv4Addr,
+    pub port: u16,
+}
+
+/// One resource record of interest.
+enum Record {
+    Ptr { owner: Name, target: Name },
+    Srv { owner: Name, port: u16, target: Name },
+    A { owner: Name, addr: Ipv4Addr },
+}
+
+/// Follows PTR → SRV → A for the first instance of any of `services`.
+pub struct Browser {
+    services: Vec<Name>,
+    instance: Option<Name>,
+    target: Option<(Name, u16)>,
+    addresses: Vec<(Name, Ipv4Addr)>,
+}
+
+impl Browser {
+    /// Browse for the given service types, e.g. `"_rumble._tcp.local"`.
+    /// Names that can't be encoded are skipped.
+    pub fn new(services: &[&str]) -> Self {
+        Self {
+            services: services
+                .iter()
+                .filter_map(|s| Name::from_dotted(s))
+                .collect(),
+            instance: None,
+            target: None,
+            addresses: Vec::new(),
+        }
+    }
+
+    /// Write the query for whatever is still unknown into `buf`: the
+    /// service PTRs, then the instance SRV, then the host's A record.
+    /// Returns the query length, or `None` if `buf` is too small.
+    pub fn write_query(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut questions: Vec<(&Name, u16)> = Vec::new();
+        match (&self.instance, &self.target) {
+            (None, _) => questions.extend(self.services.iter().map(|s| (s, TYPE_PTR))),
+            (Some(instance), None) => questions.push((instance, TYPE_SRV)),
+            (Some(_), Some((target, _))) => questions.push((target, TYPE_A)),
+        }
+
+        let len = HEADER_LEN
+            + questions
+                .iter()
+                .map(|(name, _)| name.0.len() + 4)
+                .sum::<usize>();
+        let buf = buf.get_mut(..len)?;
+        buf[..HEADER_LEN].fill(0);
+        buf[4..6].copy_from_slice(&(questions.len() as u16).to_be_bytes());
+        let mut pos = HEADER_LEN;
+        for (name, qtype) in questions {
+            buf[pos..pos + name.0.len()].copy_from_slice(&name.0);
+            pos += name.0.len();
+            buf[pos..pos + 2].copy_from_slice(&qtype.to_be_bytes());
+            buf[pos + 2..pos + 4].copy_from_slice(&(CLASS_IN | UNICAST_RESPONSE).to_be_bytes());
+            pos += 4;
+        }
+        Some(len)
+    }
+
+    /// Take what's useful from one mDNS packet. Returns the service once its
+    /// address is known.
+    pub fn handle(&mut self, packet: &[u8]) -> Option<Service> {
+        let records = parse_response(packet)?;
+
+        // Records in one packet may come in any order, so look at PTRs first.
+        for record in &records {
+            if let Record::Ptr { owner, target } = record
+                && self.instance.is_none()
+                && self.services.iter().any(|s| s.matches(owner))
+            {
+                self.instance = Some(target.clone());
+            }
+        }
+        for record in records {
+            match record {
+                Record::Srv {
+                    owner,
+                    port,
+                    target,
+                } if self.target.is_none()
+                    && self.instance.as_ref().is_some_and(|i| i.matches(&owner)) =>
+                {
+                    self.target = Some((target, port));
+                }
+                Record::A { owner, addr } => {
+                    if self.addresses.len() == MAX_ADDRESSES {
+                        self.addresses.remove(0);
+                    }
+                    self.addresses.push((owner, addr));
+                }
+                _ => {}
+            }
+        }
+
+        let instance = self.instance.as_ref()?;
+        let (target, port) = self.target.as_ref()?;
+        let (_, addr) = self
+            .addresses
+            .iter()
+            .rev()
+            .find(|(owner, _)| owner.matches(target))?;
+        Some(Service {
+            instance: instance.to_dotted(),
+            host: target.to_dotted(),
+            addr: *addr,
+            port: *port,
+        })
+    }
+}
+
+/// Extract PTR, SRV and A records from every section of a response.
+fn parse_response(packet: &[u8]) -> Option<Vec<Record>> {
+    let header = packet.get(..HEADER_LEN)?;
+    // Only responses (QR set) with a standard query opcode
+    if header[2] & 0xF8 != 0x80 {
+        return None;
+    }
+    let count = |i: usize| u16::from_be_bytes([header[i], header[i + 1]]) as usize;
+    let questions = count(4);
+    let records = count(6) + count(8) + count(10);
+
+    let mut pos = HEADER_LEN;
+    for _ in 0..questions {
+        let (_, end) = Name::read(packet, pos)?;
+        pos = end + 4;
+    }
+
+    let mut found = Vec::new();
+    for _ in 0..records {
+        let (owner, end) = Name::read(packet, pos)?;
+        let fixed = packet.get(end..end + 10)?;
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        // The top bit is the cache-flush flag
+        let class = u16::from_be_bytes([fixed[2], fixed[3]]) & 0x7FFF;
+        let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+        let rdlen = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let rdata_pos = end + 10;
+        let rdata = packet.get(rdata_pos..rdata_pos + rdlen)?;
+        pos = rdata_pos + rdlen;
+        // Names in the data may point anywhere, but must end inside it
+        let read_name = |at: usize| Name::read(packet, at).filter(|&(_, end)| end <= pos);
+
+        // A zero TTL announces that the record is going away
+        if class != CLASS_IN || ttl == 0 {
+            continue;
+        }
+        match rtype {
+            TYPE_PTR => {
+                if let Some((target, _)) = read_name(rdata_pos) {
+                    found.push(Record::Ptr { owner, target });
+                }
+            }
+            TYPE_SRV if rdlen > 6 => {
+                let port = u16::from_be_bytes([rdata[4], rdata[5]]);
+                if let Some((target, _)) = read_name(rdata_pos + 6) {
+                    found.push(Record::Srv {
+                        owner,
+                        port,
+                        target,
+                    });
+                }
+            }
+            TYPE_A if rdlen == 4 => {
+                let addr = Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]);
+                found.push(Record::A { owner, addr });
+            }
+            _ => {}
+        }
+    }
+    Some(found)
+}
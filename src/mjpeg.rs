@@ -0,0 +1,324 @@
+//! MJPEG frame assembly from an arbitrary byte stream.
+//!
+//! [`FrameAssembler`] follows the JPEG segment structure instead of searching
+//! for raw `FF D8`/`FF D9` pairs, so markers split across reads and EOI bytes
+//! inside APPn payloads (e.g. EXIF thumbnails) don't end a frame early.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::jpeg::markers::{EOI, RST0, RST7, SOI, SOS, TEM};
+
+/// Outcome of one [`FrameSource::push`] call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// All input was consumed without completing a frame.
+    Incomplete,
+    /// A frame completed after `consumed` input bytes; it is available from
+    /// [`FrameSource::frame_mut`] until the next push.
+    Frame { consumed: usize },
+    /// A frame was discarded after `consumed` input bytes.
+    Dropped { consumed: usize, reason: DropReason },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropReason {
+    /// The frame (`size` bytes, SOI through EOI) did not fit in the buffer.
+    TooLarge { size: usize },
+    /// The next frame started before this one was complete, e.g. a new SOI
+    /// before the current frame's EOI.
+    Interrupted,
+    /// Bytes that cannot appear at this point of a JPEG stream.
+    Corrupt,
+}
+
+/// Something that turns a byte stream into complete JPEG frames.
+pub trait FrameSource {
+    /// Feed stream bytes. Stops as soon as a frame completes or is dropped,
+    /// so call again with the unconsumed rest of `input`.
+    fn push(&mut self, input: &[u8]) -> Status;
+
+    /// The frame completed by the last `push`, SOI through EOI.
+    fn frame_mut(&mut self) -> Option<&mut [u8]>;
+
+    /// Forget any partial frame, e.g. after reconnecting.
+    fn reset(&mut self);
+}
+
+/// Running totals since the assembler was created.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub frames: u32,
+    pub too_large: u32,
+    pub interrupted: u32,
+    pub corrupt: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Looking for SOI; `prev_ff` if the last byte seen was `0xFF`.
+    Search { prev_ff: bool },
+    /// Expecting the next marker between segments.
+    Marker { prev_ff: bool },
+    /// Reading the two-byte length of the segment started by `marker`.
+    Length { marker: u8, high: Option<u8> },
+    /// Inside a segment payload with `remaining` bytes left.
+    Segment { remaining: usize, scan_follows: bool },
+    /// Inside entropy-coded data.
+    Entropy { prev_ff: bool },
+}
+
+/// Reassembles complete JPEG frames from chunks of a concatenated MJPEG stream.
+pub struct FrameAssembler {
+    buf: Vec<u8>,
+    len: usize,
+    /// Size of the current frame including bytes that didn't fit.
+    size: usize,
+    state: State,
+    ready: bool,
+    stats: Stats,
+}
+
+impl FrameAssembler {
+    /// Create an assembler that holds frames of up to `capacity` bytes.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            buf: vec![0u8; capacity],
+            len: 0,
+            size: 0,
+            state: State::Search { prev_ff: false },
+            ready: false,
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Handle marker byte `b` (the `0xFF` before it is already stored).
+    /// Returns a status if the frame ended.
+    fn on_marker(&mut self, b: u8, pos: usize) -> Option<Status> {
+        match b {
+            0xFF => {
+                self.store(&[b]);
+                None
+            }
+            SOI => {
+                // The sender restarted mid-frame; start over with the new frame.
+                self.stats.interrupted += 1;
+                self.begin_frame();
+                Some(Status::Dropped {
+                    consumed: pos,
+                    reason: DropReason::Interrupted,
+                })
+            }
+            EOI => {
+                self.store(&[b]);
+                self.state = State::Search { prev_ff: false };
+                Some(self.finish_frame(pos))
+            }
+            // Standalone markers without a length field
+            TEM | RST0..=RST7 => {
+                self.store(&[b]);
+                self.state = State::Marker { prev_ff: false };
+                None
+            }
+            0x00 => Some(self.drop_corrupt(pos)),
+            _ => {
+                self.store(&[b]);
+                self.state = State::Length {
+                    marker: b,
+                    high: None,
+                };
+                None
+            }
+        }
+    }
+
+    fn begin_frame(&mut self) {
+        self.len = 0;
+        self.size = 0;
+        self.store(&[0xFF, SOI]);
+        self.state = State::Marker { prev_ff: false };
+    }
+
+    fn finish_frame(&mut self, consumed: usize) -> Status {
+        if self.size > self.buf.len() {
+            self.stats.too_large += 1;
+            let size = self.size;
+            self.len = 0;
+            self.size = 0;
+            return Status::Dropped {
+                consumed,
+                reason: DropReason::TooLarge { size },
+            };
+        }
+        self.stats.frames += 1;
+        self.ready = true;
+        Status::Frame { consumed }
+    }
+
+    fn drop_corrupt(&mut self, consumed: usize) -> Status {
+        self.stats.corrupt += 1;
+        self.len = 0;
+        self.size = 0;
+        self.state = State::Search { prev_ff: false };
+        Status::Dropped {
+            consumed,
+            reason: DropReason::Corrupt,
+        }
+    }
+
+    /// Append to the frame buffer. Once a frame outgrows the buffer the rest
+    /// is only counted, so its EOI is still found and reported.
+    fn store(&mut self, bytes: &[u8]) {
+        self.size += bytes.len();
+        if self.size <= self.buf.len() {
+            self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+            self.len += bytes.len();
+        }
+    }
+}
+
+impl FrameSource for FrameAssembler {
+    fn push(&mut self, input: &[u8]) -> Status {
+        if self.ready {
+            self.ready = false;
+            self.len = 0;
+            self.size = 0;
+        }
+
+        let mut pos = 0;
+        while pos < input.len() {
+            match self.state {
+                State::Search { prev_ff } => {
+                    // Fast path: jump to the next 0xFF
+                    if !prev_ff {
+                        match input[pos..].iter().position(|&b| b == 0xFF) {
+                            Some(ff) => pos += ff + 1,
+                            None => return Status::Incomplete,
+                        }
+                        self.state = State::Search { prev_ff: true };
+                        continue;
+                    }
+                    let b = input[pos];
+                    pos += 1;
+                    match b {
+                        SOI => self.begin_frame(),
+                        0xFF => {}
+                        _ => self.state = State::Search { prev_ff: false },
+                    }
+                }
+                State::Marker { prev_ff } => {
+                    let b = input[pos];
+                    pos += 1;
+                    if !prev_ff {
+                        if b != 0xFF {
+                            return self.drop_corrupt(pos);
+                        }
+                        self.store(&[b]);
+                        self.state = State::Marker { prev_ff: true };
+                        continue;
+                    }
+                    if let Some(status) = self.on_marker(b, pos) {
+                        return status;
+                    }
+                }
+                State::Length { marker, high } => {
+                    let b = input[pos];
+                    pos += 1;
+                    self.store(&[b]);
+                    let Some(high) = high else {
+                        self.state = State::Length {
+                            marker,
+                            high: Some(b),
+                        };
+                        continue;
+                    };
+                    let len = u16::from_be_bytes([high, b]) as usize;
+                    if len < 2 {
+                        return self.drop_corrupt(pos);
+                    }
+                    self.state = State::Segment {
+                        remaining: len - 2,
+                        scan_follows: marker == SOS,
+                    };
+                }
+                State::Segment {
+                    remaining,
+                    scan_follows,
+                } => {
+                    let take = remaining.min(input.len() - pos);
+                    self.store(&input[pos..pos + take]);
+                    pos += take;
+                    let remaining = remaining - take;
+                    self.state = if remaining > 0 {
+                        State::Segment {
+                            remaining,
+                            scan_follows,
+                        }
+                    } else if scan_follows {
+                        State::Entropy { prev_ff: false }
+                    } else {
+                        State::Marker { prev_ff: false }
+                    };
+                }
+                State::Entropy { prev_ff } => {
+                    if !prev_ff {
+                        // Bulk copy up to and including the next 0xFF
+                        let rest = &input[pos..];
+                        match rest.iter().position(|&b| b == 0xFF) {
+                            Some(ff) => {
+                                self.store(&rest[..=ff]);
+                                pos += ff + 1;
+                                self.state = State::Entropy { prev_ff: true };
+                            }
+                            None => {
+                                self.store(rest);
+                                return Status::Incomplete;
+                            }
+                        }
+                        continue;
+                    }
+                    let b = input[pos];
+                    pos += 1;
+                    match b {
+                        // Stuffed zero or restart marker: still in the scan
+                        0x00 | RST0..=RST7 => {
+                            self.store(&[b]);
+                            self.state = State::Entropy { prev_ff: false };
+                        }
+                        // Fill byte
+                        0xFF => self.store(&[b]),
+                        _ => {
+                            if let Some(status) = self.on_marker(b, pos) {
+                                return status;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Status::Incomplete
+    }
+
+    fn frame_mut(&mut self) -> Option<&mut [u8]> {
+        if self.ready {
+            Some(&mut self.buf[..self.len])
+        } else {
+            None
+        }
+    }
+
+    fn reset(&mut self) {
+        self.len = 0;
+        self.size = 0;
+        self.state = State::Search { prev_ff: false };
+        self.ready = false;
+    }
+}
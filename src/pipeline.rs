@@ -0,0 +1,296 @@
+//! Drawing a frame block by block while the previous block is being sent.
+//!
+//! [`Pipeline::draw`] resamples each decoded block into one of two buffers,
+//! in the sink's RGB565 byte order, and hands it to a [`DisplaySink`]. The
+//! next block is decoded into the other buffer while the sink's transfer
+//! runs, so decoding and SPI DMA overlap instead of taking turns. Waiting
+//! for a transfer is also what lets the network task run between blocks.
+//!
+//! The blocks may come from a frame that is still arriving: [`Incoming`]
+//! reads more of it from the network whenever the decoder runs dry.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use embassy_futures::join::join;
+
+use crate::display::DisplaySink;
+use crate::fit::{Layout, Rect};
+#[cfg(feature = "baseline-jpeg")]
+use crate::jpeg::JpegFrameInfo;
+#[cfg(feature = "baseline-jpeg")]
+use crate::jpeg::baseline::StreamSession;
+use crate::jpeg::{JpegError, JpegSession};
+
+/// Decoded blocks of one image, top to bottom.
+pub trait BlockSource {
+    type Error;
+    /// Decode the next block and return its width and height, or `None`
+    /// after the last one. It may have to wait for the block's data.
+    fn next_block(&mut self) -> impl Future<Output = Option<Result<(u16, u16), Self::Error>>>;
+    /// The last decoded block, two bytes per pixel.
+    fn block(&self) -> &[u8];
+}
+
+impl<S: JpegSession> BlockSource for S {
+    type Error = JpegError;
+
+    async fn next_block(&mut self) -> Option<Result<(u16, u16), JpegError>> {
+        match self.decode_next_block() {
+            Err(JpegError::SessionExhausted) => None,
+            result => Some(result),
+        }
+    }
+
+    fn block(&self) -> &[u8] {
+        self.block_data()
+    }
+}
+
+/// The blocks of a frame that is still arriving: whenever the session needs
+/// data that isn't there yet, more is read from `input`.
+#[cfg(feature = "baseline-jpeg")]
+pub struct Incoming<'a, R> {
+    session: StreamSession<'a>,
+    input: &'a mut R,
+    received: usize,
+}
+
+#[cfg(feature = "baseline-jpeg")]
+impl<'a, R: embedded_io_async::Read> Incoming<'a, R> {
+    pub fn new(session: StreamSession<'a>, input: &'a mut R) -> Self {
+        Self {
+            session,
+            input,
+            received: 0,
+        }
+    }
+
+    pub fn info(&self) -> &JpegFrameInfo {
+        self.session.info()
+    }
+
+    /// Bytes read from `input` so far.
+    pub fn received(&self) -> usize {
+        self.received
+    }
+}
+
+#[cfg(feature = "baseline-jpeg")]
+impl<R: embedded_io_async::Read> BlockSource for Incoming<'_, R> {
+    type Error = IncomingError<R::Error>;
+
+    async fn next_block(&mut self) -> Option<Result<(u16, u16), Self::Error>> {
+        loop {
+            match self.session.decode_next_block() {
+                Err(JpegError::NoMoreData) => {}
+                Err(JpegError::SessionExhausted) => return None,
+                decoded => return Some(decoded.map_err(IncomingError::Decode)),
+            }
+            match self.input.read(self.session.spare()).await {
+                Ok(0) => return Some(Err(IncomingError::Closed)),
+                Ok(n) => {
+                    self.session.commit(n);
+                    self.received += n;
+                }
+                Err(e) => return Some(Err(IncomingError::Read(e))),
+            }
+        }
+    }
+
+    fn block(&self) -> &[u8] {
+        self.session.block_data()
+    }
+}
+
+/// Why an [`Incoming`] frame stopped short.
+#[derive(Debug)]
+pub enum IncomingError<E> {
+    Decode(JpegError),
+    Read(E),
+    /// The connection closed in the middle of the frame.
+    Closed,
+}
+
+impl<E: core::fmt::Debug> core::fmt::Display for IncomingError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Decode(e) => write!(f, "{}", e),
+            Self::Read(e) => write!(f, "read error: {:?}", e),
+            Self::Closed => f.write_str("connection closed"),
+        }
+    }
+}
+
+impl<E: core::fmt::Debug> core::error::Error for IncomingError<E> {}
+
+#[derive(Debug)]
+pub enum DrawError<D, P> {
+    Decode(D),
+    Sink(P),
+}
+
+impl<D: core::fmt::Display, P: core::fmt::Debug> core::fmt::Display for DrawError<D, P> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Decode(e) => write!(f, "decode error: {}", e),
+            Self::Sink(e) => write!(f, "display error: {:?}", e),
+        }
+    }
+}
+
+impl<D: core::fmt::Display + core::fmt::Debug, P: core::fmt::Debug> core::error::Error
+    for DrawError<D, P>
+{
+}
+
+/// The two block buffers.
+pub struct Pipeline {
+    buffers: [Vec<u8>; 2],
+}
+
+/// What filling a buffer produced.
+enum Step {
+    /// A strip of the panel, ready to send.
+    Draw(Rect),
+    /// A block that isn't shown.
+    Skip,
+    Done,
+}
+
+impl Pipeline {
+    /// Buffers of `len` bytes each. Blocks that resample to more rows than
+    /// fit are sent in several strips.
+    pub fn new(len: usize) -> Self {
+        Self {
+            buffers: [vec![0; len], vec![0; len]],
+        }
+    }
+
+    /// Decode the image in `blocks` and draw it on `sink` as `layout` says,
+    /// then flush the sink.
+    pub async fn draw<B: BlockSource, S: DisplaySink>(
+        &mut self,
+        layout: &Layout,
+        blocks: &mut B,
+        sink: &mut S,
+    ) -> Result<(), DrawError<B::Error, S::Error>> {
+        let max_rows = self.rows_per_buffer(layout.target.width);
+        let mut strips = Strips {
+            layout,
+            blocks,
+            top: 0,
+            block_top: 0,
+            block_width: 0,
+            rows: 0..0,
+        };
+        let [mut front, mut back] = self.buffers.each_mut();
+        // A strip in `back`, waiting to be sent
+        let mut pending: Option<Rect> = None;
+        loop {
+            let step = match pending.take() {
+                Some(window) => {
+                    let pixels = &back[..window.width as usize * window.height as usize * 2];
+                    sink.set_window(window).await.map_err(DrawError::Sink)?;
+                    // Only the pixels are sent while the next block decodes
+                    let (sent, step) =
+                        join(sink.write_pixels(pixels), strips.fill(front, max_rows)).await;
+                    sent.map_err(DrawError::Sink)?;
+                    step
+                }
+                None => strips.fill(front, max_rows).await,
+            };
+            match step.map_err(DrawError::Decode)? {
+                Step::Draw(window) => {
+                    core::mem::swap(&mut front, &mut back);
+                    pending = Some(window);
+                }
+                // Nothing was sent meanwhile, so give other tasks a turn
+                Step::Skip => embassy_futures::yield_now().await,
+                Step::Done => return sink.flush().await.map_err(DrawError::Sink),
+            }
+        }
+    }
+
+    /// Paint `rects` black (zero in either byte order).
+    pub async fn clear<S: DisplaySink>(
+        &mut self,
+        rects: impl IntoIterator<Item = Rect>,
+        sink: &mut S,
+    ) -> Result<(), S::Error> {
+        for rect in rects {
+            let max_rows = self.rows_per_buffer(rect.width);
+            let buffer = &mut self.buffers[0];
+            buffer.fill(0);
+            let mut y = rect.y;
+            while y < rect.bottom() {
+                let rows = max_rows.min(rect.bottom() - y);
+                let len = rect.width as usize * rows as usize * 2;
+                sink.set_window(Rect::new(rect.x, y, rect.width, rows))
+                    .await?;
+                sink.write_pixels(&buffer[..len]).await?;
+                y += rows;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rows of `width` pixels that fit a buffer, growing the buffers to at
+    /// least one row.
+    fn rows_per_buffer(&mut self, width: u16) -> u16 {
+        let row_len = (width as usize * 2).max(2);
+        for buffer in &mut self.buffers {
+            if buffer.len() < row_len {
+                buffer.resize(row_len, 0);
+            }
+        }
+        (self.buffers[0].len() / row_len).min(u16::MAX as usize) as u16
+    }
+}
+
+/// Turns decoded blocks into panel strips.
+struct Strips<'a, B> {
+    layout: &'a Layout,
+    blocks: &'a mut B,
+    /// First row of the next block.
+    top: u16,
+    /// First row of the current block.
+    block_top: u16,
+    block_width: u16,
+    /// Panel rows of the current block not yet filled.
+    rows: core::ops::Range<u16>,
+}
+
+impl<B: BlockSource> Strips<'_, B> {
+    /// Fill `buffer` with up to `max_rows` panel rows, decoding a block
+    /// first if the current one is used up.
+    async fn fill(&mut self, buffer: &mut [u8], max_rows: u16) -> Result<Step, B::Error> {
+        if self.rows.is_empty() {
+            let Some(block) = self.blocks.next_block().await else {
+                return Ok(Step::Done);
+            };
+            let (width, height) = block?;
+            self.block_top = self.top;
+            self.block_width = width;
+            self.top = self.top.saturating_add(height);
+            self.rows = self.layout.rows(self.block_top, height);
+            if self.rows.is_empty() {
+                return Ok(Step::Skip);
+            }
+        }
+        let end = self.rows.end.min(self.rows.start.saturating_add(max_rows));
+        let rows = self.rows.start..end;
+        self.rows.start = end;
+
+        let target = self.layout.target;
+        let window = Rect::new(target.x, rows.start, target.width, rows.len() as u16);
+        self.layout.copy_rows(
+            rows,
+            self.block_top,
+            self.block_width,
+            self.blocks.block(),
+            buffer,
+        );
+        Ok(Step::Draw(window))
+    }
+}
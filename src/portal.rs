@@ -0,0 +1,18 @@
+//! Captive-portal provisioning over a SoftAP.
+//!
+//! When the badge can't join its network it opens an access point and runs
+//! three small servers on it: [`dhcp`] hands out addresses, [`dns`] answers
+//! every lookup with the badge's own address so phones pop up their
+//! captive-portal sheet, and [`web`] serves the page that picks a network and
+//! a stream. The handlers only see byte buffers and `embedded_io_async`
+//! streams, so binding the sockets is up to the caller.
+
+pub mod dhcp;
+pub mod dns;
+pub mod web;
+
+use core::net::Ipv4Addr;
+
+/// The badge's address on its own access point.
+pub const PORTAL_ADDR: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 1);
+pub const PORTAL_PREFIX_LEN: u8 = 24;
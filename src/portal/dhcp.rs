@@ -0,0 +1,258 @@
+//! Minimal DHCPv4 server (RFC 2131) for the portal's access point.
+//!
+//! Leases are tracked per client MAC address in a small pool. Nothing
+//! expires: when the pool is full the least recently seen client loses its
+//! address, which is plenty for the one or two phones that join a badge.
+
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+
+pub const SERVER_PORT: u16 = 67;
+/// Replies go to the broadcast address on this port, since the client has
+/// no address yet.
+pub const CLIENT_PORT: u16 = 68;
+
+/// Lease time handed to clients, in seconds.
+const LEASE_SECS: u32 = 3600;
+/// Fixed BOOTP header up to and including the magic cookie.
+const HEADER_LEN: usize = 240;
+/// Replies are padded to the minimum BOOTP message size.
+const MIN_REPLY: usize = 300;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_END: u8 = 255;
+
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPDECLINE: u8 = 4;
+const DHCPACK: u8 = 5;
+const DHCPNAK: u8 = 6;
+const DHCPRELEASE: u8 = 7;
+const DHCPINFORM: u8 = 8;
+
+/// The fields of a client message the server looks at.
+struct Message<'a> {
+    xid: &'a [u8],
+    flags: &'a [u8],
+    ciaddr: Ipv4Addr,
+    giaddr: &'a [u8],
+    chaddr: &'a [u8],
+    mac: [u8; 6],
+    kind: u8,
+    requested: Option<Ipv4Addr>,
+    server_id: Option<Ipv4Addr>,
+}
+
+impl<'a> Message<'a> {
+    fn parse(packet: &'a [u8]) -> Option<Self> {
+        let header = packet.get(..HEADER_LEN)?;
+        if header[0] != BOOTREQUEST
+            || header[1] != HTYPE_ETHERNET
+            || header[2] != 6
+            || header[236..240] != MAGIC_COOKIE
+        {
+            return None;
+        }
+
+        let mut kind = None;
+        let mut requested = None;
+        let mut server_id = None;
+        let mut pos = HEADER_LEN;
+        while let Some(&code) = packet.get(pos) {
+            match code {
+                OPT_PAD => {
+                    pos += 1;
+                    continue;
+                }
+                OPT_END => break,
+                _ => {}
+            }
+            let len = *packet.get(pos + 1)? as usize;
+            let value = packet.get(pos + 2..pos + 2 + len)?;
+            match (code, value) {
+                (OPT_MESSAGE_TYPE, &[t]) => kind = Some(t),
+                (OPT_REQUESTED_IP, &[a, b, c, d]) => requested = Some(Ipv4Addr::new(a, b, c, d)),
+                (OPT_SERVER_ID, &[a, b, c, d]) => server_id = Some(Ipv4Addr::new(a, b, c, d)),
+                _ => {}
+            }
+            pos += 2 + len;
+        }
+
+        Some(Self {
+            xid: &header[4..8],
+            flags: &header[10..12],
+            ciaddr: Ipv4Addr::new(header[12], header[13], header[14], header[15]),
+            giaddr: &header[24..28],
+            chaddr: &header[28..44],
+            mac: header[28..34].try_into().ok()?,
+            kind: kind?,
+            requested,
+            server_id,
+        })
+    }
+}
+
+/// Hands out addresses from `pool_start` onwards on a /24 network.
+pub struct DhcpServer {
+    addr: Ipv4Addr,
+    pool_start: Ipv4Addr,
+    pool_size: u8,
+    /// Client MACs and their pool index, least recently seen first.
+    leases: Vec<([u8; 6], u8)>,
+}
+
+impl DhcpServer {
+    /// Serve `pool_size` addresses from `pool_start`, stopping short of the
+    /// broadcast address. `addr` is the server itself, announced as router
+    /// and DNS server. Returns `None` if `pool_start` is the broadcast
+    /// address, leaving no room for a pool.
+    pub fn new(addr: Ipv4Addr, pool_start: Ipv4Addr, pool_size: u8) -> Option<Self> {
+        let room = 255 - pool_start.octets()[3];
+        (room > 0).then(|| Self {
+            addr,
+            pool_start,
+            pool_size: pool_size.clamp(1, room),
+            leases: Vec::new(),
+        })
+    }
+
+    /// Handle one client message and write the reply into `response`.
+    /// Returns the reply length, or `None` if there is nothing to send (or
+    /// `response` is too small).
+    pub fn handle(&mut self, request: &[u8], response: &mut [u8]) -> Option<usize> {
+        let msg = Message::parse(request)?;
+        match msg.kind {
+            DHCPDISCOVER => {
+                let addr = self.lease(msg.mac, msg.requested);
+                write_reply(response, &msg, DHCPOFFER, addr, self.addr)
+            }
+            DHCPREQUEST => {
+                // The client picked another server's offer
+                if msg.server_id.is_some_and(|id| id != self.addr) {
+                    self.release(msg.mac);
+                    return None;
+                }
+                let wanted = msg.requested.unwrap_or(msg.ciaddr);
+                match self.index(wanted) {
+                    Some(index) if self.is_free_for(index, msg.mac) => {
+                        self.assign(msg.mac, index);
+                        write_reply(response, &msg, DHCPACK, wanted, self.addr)
+                    }
+                    _ => write_reply(response, &msg, DHCPNAK, Ipv4Addr::UNSPECIFIED, self.addr),
+                }
+            }
+            DHCPINFORM => write_reply(response, &msg, DHCPACK, Ipv4Addr::UNSPECIFIED, self.addr),
+            DHCPRELEASE | DHCPDECLINE => {
+                self.release(msg.mac);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Pick an address for `mac`: its current lease, else the one it asks
+    /// for if that's free, else the first free one, else the oldest lease.
+    fn lease(&mut self, mac: [u8; 6], requested: Option<Ipv4Addr>) -> Ipv4Addr {
+        let index = self
+            .leases
+            .iter()
+            .find(|(m, _)| *m == mac)
+            .map(|&(_, i)| i)
+            .or_else(|| {
+                requested
+                    .and_then(|a| self.index(a))
+                    .filter(|&i| self.is_free_for(i, mac))
+            })
+            .or_else(|| (0..self.pool_size).find(|&i| self.is_free_for(i, mac)))
+            .unwrap_or_else(|| self.leases[0].1);
+        self.assign(mac, index);
+        self.address(index)
+    }
+
+    fn assign(&mut self, mac: [u8; 6], index: u8) {
+        self.leases.retain(|&(m, i)| m != mac && i != index);
+        self.leases.push((mac, index));
+    }
+
+    fn release(&mut self, mac: [u8; 6]) {
+        self.leases.retain(|&(m, _)| m != mac);
+    }
+
+    fn is_free_for(&self, index: u8, mac: [u8; 6]) -> bool {
+        self.leases.iter().all(|&(m, i)| i != index || m == mac)
+    }
+
+    fn address(&self, index: u8) -> Ipv4Addr {
+        let mut octets = self.pool_start.octets();
+        octets[3] += index;
+        Ipv4Addr::from(octets)
+    }
+
+    /// Pool index of `addr`, if it's in the pool.
+    fn index(&self, addr: Ipv4Addr) -> Option<u8> {
+        let (start, addr) = (self.pool_start.octets(), addr.octets());
+        if start[..3] != addr[..3] {
+            return None;
+        }
+        let index = addr[3].checked_sub(start[3])?;
+        (index < self.pool_size).then_some(index)
+    }
+}
+
+fn write_reply(
+    buf: &mut [u8],
+    msg: &Message,
+    kind: u8,
+    yiaddr: Ipv4Addr,
+    server: Ipv4Addr,
+) -> Option<usize> {
+    let buf = buf.get_mut(..MIN_REPLY)?;
+    buf.fill(0);
+    buf[0] = BOOTREPLY;
+    buf[1] = HTYPE_ETHERNET;
+    buf[2] = 6;
+    buf[4..8].copy_from_slice(msg.xid);
+    buf[10..12].copy_from_slice(msg.flags);
+    if kind == DHCPACK && yiaddr.is_unspecified() {
+        // INFORM: the client already has an address
+        buf[12..16].copy_from_slice(&msg.ciaddr.octets());
+    }
+    buf[16..20].copy_from_slice(&yiaddr.octets());
+    buf[20..24].copy_from_slice(&server.octets());
+    buf[24..28].copy_from_slice(msg.giaddr);
+    buf[28..44].copy_from_slice(msg.chaddr);
+    buf[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+    let mut pos = HEADER_LEN;
+    let mut option = |code: u8, value: &[u8]| {
+        buf[pos] = code;
+        buf[pos + 1] = value.len() as u8;
+        buf[pos + 2..pos + 2 + value.len()].copy_from_slice(value);
+        pos += 2 + value.len();
+    };
+    option(OPT_MESSAGE_TYPE, &[kind]);
+    option(OPT_SERVER_ID, &server.octets());
+    if kind != DHCPNAK {
+        if !yiaddr.is_unspecified() {
+            option(OPT_LEASE_TIME, &LEASE_SECS.to_be_bytes());
+        }
+        option(OPT_SUBNET_MASK, &[255, 255, 255, 0]);
+        option(OPT_ROUTER, &server.octets());
+        option(OPT_DNS, &server.octets());
+    }
+    buf[pos] = OPT_END;
+    Some(MIN_REPLY)
+}
@@ -0,0 +1,62 @@
+//! Catch-all DNS responder.
+//!
+//! Every A query is answered with one address, so whatever a phone looks up
+//! (its captive-portal probe included) leads to the portal. Other query
+//! types get an empty answer, which makes clients fall back to IPv4.
+
+use core::net::Ipv4Addr;
+
+pub const DNS_PORT: u16 = 53;
+
+const HEADER_LEN: usize = 12;
+const TYPE_A: u16 = 1;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Short, so clients stop using the portal's address soon after leaving.
+const TTL_SECS: u32 = 60;
+
+/// Answer `query` with `addr` into `response`. Returns the response length,
+/// or `None` if the packet isn't a standard query or doesn't fit.
+pub fn answer(query: &[u8], addr: Ipv4Addr, response: &mut [u8]) -> Option<usize> {
+    let header = query.get(..HEADER_LEN)?;
+    // QR clear, opcode QUERY, at least one question
+    if header[2] & 0xF8 != 0 || u16::from_be_bytes([header[4], header[5]]) == 0 {
+        return None;
+    }
+
+    // Only the first question is answered. Queries don't compress names.
+    let mut pos = HEADER_LEN;
+    loop {
+        match *query.get(pos)? {
+            0 => break,
+            len @ 1..=63 => pos += 1 + len as usize,
+            _ => return None,
+        }
+    }
+    let question_end = pos + 5;
+    let fixed = query.get(pos + 1..question_end)?;
+    let qtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+    let qclass = u16::from_be_bytes([fixed[2], fixed[3]]) & 0x7FFF;
+    let answers = u16::from(qclass == CLASS_IN && matches!(qtype, TYPE_A | TYPE_ANY));
+
+    let len = question_end + usize::from(answers) * 16;
+    let response = response.get_mut(..len)?;
+    response[..question_end].copy_from_slice(&query[..question_end]);
+    // QR and AA set, RD copied; RA clear, NOERROR
+    response[2] = 0x84 | (header[2] & 0x01);
+    response[3] = 0;
+    response[4..6].copy_from_slice(&1u16.to_be_bytes());
+    response[6..8].copy_from_slice(&answers.to_be_bytes());
+    response[8..12].fill(0);
+    if answers > 0 {
+        let record = &mut response[question_end..];
+        // A pointer back to the question's name
+        record[0..2].copy_from_slice(&(0xC000 | HEADER_LEN as u16).to_be_bytes());
+        record[2..4].copy_from_slice(&TYPE_A.to_be_bytes());
+        record[4..6].copy_from_slice(&CLASS_IN.to_be_bytes());
+        record[6..10].copy_from_slice(&TTL_SECS.to_be_bytes());
+        record[10..12].copy_from_slice(&4u16.to_be_bytes());
+        record[12..16].copy_from_slice(&addr.octets());
+    }
+    Some(len)
+}
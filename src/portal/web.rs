@@ -0,0 +1,307 @@
+//! The portal's web page: pick a network, enter its password and a stream
+//! URL, save.
+//!
+//! [`read_request`] reads one HTTP/1.1 request from any `embedded_io_async`
+//! stream and [`route`] decides what to do with it. Scanning and saving need
+//! the radio and the flash, so the caller does those before answering with
+//! [`write_page`], [`write_saved`] or [`write_redirect`]. Every response
+//! closes the connection.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write as _;
+use core::net::Ipv4Addr;
+
+use embedded_io_async::{Read, Write};
+
+use crate::config::{Config, WifiConfig};
+use crate::wifi::Network;
+
+pub const HTTP_PORT: u16 = 80;
+
+#[derive(Debug)]
+pub enum WebError<E> {
+    Io(E),
+    /// The connection closed before the request was complete.
+    Closed,
+    /// The request did not fit in the buffer.
+    TooLarge,
+    /// The request line or headers could not be parsed.
+    BadRequest,
+}
+
+impl<E: core::fmt::Debug> core::fmt::Display for WebError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {:?}", e),
+            Self::Closed => f.write_str("connection closed"),
+            Self::TooLarge => f.write_str("request too large"),
+            Self::BadRequest => f.write_str("malformed HTTP request"),
+        }
+    }
+}
+
+impl<E: core::fmt::Debug> core::error::Error for WebError<E> {}
+
+/// One request, borrowed from the buffer it was read into.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Request<'a> {
+    pub method: &'a str,
+    /// The target without its query string.
+    pub path: &'a str,
+    pub body: &'a [u8],
+}
+
+/// What to do about a request.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Route {
+    /// Show the form.
+    Page,
+    /// Scan for networks, then show the form.
+    Scan,
+    /// The form was submitted; save this and restart.
+    Save(Config),
+    /// The form was submitted with a mistake; show it again with this.
+    Invalid(&'static str),
+    /// Anything else, e.g. an OS connectivity check: send it to the portal.
+    Redirect,
+}
+
+/// Read one request into `buf`, body included.
+pub async fn read_request<'b, S: Read>(
+    socket: &mut S,
+    buf: &'b mut [u8],
+) -> Result<Request<'b>, WebError<S::Error>> {
+    let mut filled = 0;
+    let mut head_len = None;
+    let mut total = usize::MAX;
+    while filled < total {
+        if head_len.is_none()
+            && let Some(end) = buf[..filled].windows(4).position(|w| w == b"\r\n\r\n")
+        {
+            let body_len = content_length(&buf[..end])?;
+            head_len = Some(end + 4);
+            total = (end + 4).checked_add(body_len).ok_or(WebError::TooLarge)?;
+            continue;
+        }
+        if filled == buf.len() {
+            return Err(WebError::TooLarge);
+        }
+        match socket
+            .read(&mut buf[filled..])
+            .await
+            .map_err(WebError::Io)?
+        {
+            0 => return Err(WebError::Closed),
+            n => filled += n,
+        }
+    }
+    let head_len = head_len.ok_or(WebError::BadRequest)?;
+
+    let buf: &'b [u8] = buf;
+    let head = core::str::from_utf8(&buf[..head_len]).map_err(|_| WebError::BadRequest)?;
+    let mut fields = head.lines().next().unwrap_or("").split(' ');
+    let (Some(method), Some(target), Some(version)) = (fields.next(), fields.next(), fields.next())
+    else {
+        return Err(WebError::BadRequest);
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(WebError::BadRequest);
+    }
+    let path = target.split_once('?').map_or(target, |(path, _)| path);
+    Ok(Request {
+        method,
+        path,
+        body: &buf[head_len..total],
+    })
+}
+
+/// The `Content-Length` of a request head, 0 if it has none.
+fn content_length<E>(head: &[u8]) -> Result<usize, WebError<E>> {
+    let head = core::str::from_utf8(head).map_err(|_| WebError::BadRequest)?;
+    for line in head.lines().skip(1) {
+        if let Some((name, value)) = line.split_once(':')
+            && name.trim().eq_ignore_ascii_case("content-length")
+        {
+            return value.trim().parse().map_err(|_| WebError::BadRequest);
+        }
+    }
+    Ok(0)
+}
+
+/// Decide what `request` asks for. A submitted form is applied on top of
+/// `current`.
+pub fn route(request: &Request, current: &Config) -> Route {
+    match (request.method, request.path) {
+        ("GET", "/") => Route::Page,
+        ("GET", "/scan") => Route::Scan,
+        ("POST", "/save") => match parse_form(request.body, current) {
+            Ok(config) => Route::Save(config),
+            Err(message) => Route::Invalid(message),
+        },
+        _ => Route::Redirect,
+    }
+}
+
+fn parse_form(body: &[u8], current: &Config) -> Result<Config, &'static str> {
+    let ssid = form_value(body, "ssid").unwrap_or_default();
+    let password = form_value(body, "password").unwrap_or_default();
+    let stream = form_value(body, "stream").unwrap_or_default();
+
+    let wifi = WifiConfig::new(ssid, password)?;
+    let mut config = current
+        .clone()
+        .with_stream_url(&stream)
+        .ok_or("The stream URL is not valid.")?;
+    config.add_preferred_network(wifi)?;
+    Ok(config)
+}
+
+/// Look up and decode a field of an `application/x-www-form-urlencoded`
+/// body.
+fn form_value(body: &[u8], name: &str) -> Option<String> {
+    body.split(|&b| b == b'&').find_map(|pair| {
+        let (key, value) = match pair.iter().position(|&b| b == b'=') {
+            Some(i) => (&pair[..i], &pair[i + 1..]),
+            None => (pair, &[][..]),
+        };
+        (key == name.as_bytes()).then(|| percent_decode(value))
+    })
+}
+
+fn percent_decode(value: &[u8]) -> String {
+    let mut out = Vec::with_capacity(value.len());
+    let mut i = 0;
+    while i < value.len() {
+        let escaped = value
+            .get(i + 1..i + 3)
+            .and_then(|hex| core::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (value[i], escaped) {
+            (b'+', _) => out.push(b' '),
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 2;
+            }
+            (b, _) => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Send the form, filled in from `config`, with `networks` to pick from and
+/// an optional `notice` above it.
+pub async fn write_page<S: Write>(
+    socket: &mut S,
+    config: &Config,
+    networks: &[Network],
+    notice: Option<&str>,
+) -> Result<(), S::Error> {
+    // Strongest first, each name once
+    let mut sorted: Vec<&Network> = networks.iter().filter(|n| !n.ssid.is_empty()).collect();
+    sorted.sort_by_key(|n| core::cmp::Reverse(n.rssi));
+    let mut seen: Vec<&str> = Vec::new();
+    sorted.retain(|n| {
+        let new = !seen.contains(&n.ssid.as_str());
+        seen.push(&n.ssid);
+        new
+    });
+
+    let mut body = String::new();
+    body.push_str(PAGE_HEAD);
+    if let Some(notice) = notice {
+        let _ = write!(body, "<p class=notice>{}</p>", Escaped(notice));
+    }
+    body.push_str("<form method=post action=/save><label>Network<input name=ssid list=networks required maxlength=32 value=\"");
+    let ssid = config.preferred_network().map_or("", |n| n.ssid.as_str());
+    let _ = write!(body, "{}", Escaped(ssid));
+    body.push_str("\"></label><datalist id=networks>");
+    for network in &sorted {
+        let _ = write!(
+            body,
+            "<option value=\"{}\">{} dBm{}</option>",
+            Escaped(&network.ssid),
+            network.rssi,
+            if network.secure { "" } else { ", open" },
+        );
+    }
+    body.push_str(
+        "</datalist><a href=/scan>Scan again</a>\
+         <label>Password<input name=password type=password maxlength=63></label>\
+         <label>Stream<input name=stream placeholder=\"tcp://host:3000\" value=\"",
+    );
+    let _ = write!(body, "{}", Escaped(&config.stream_url()));
+    body.push_str(
+        "\"></label><p class=hint>tcp://host:port, http://host:port/path, \
+         tcp://@:port to accept pushed streams or rtp://@:port for RTP. \
+         Leave it empty to find the server with mDNS.</p>\
+         <button>Save and restart</button></form></body></html>",
+    );
+    write_response(socket, "200 OK", "", &body).await
+}
+
+/// Tell the browser the settings were saved.
+pub async fn write_saved<S: Write>(socket: &mut S, ssid: &str) -> Result<(), S::Error> {
+    let mut body = String::from(PAGE_HEAD);
+    let _ = write!(
+        body,
+        "<p>Saved. The badge restarts and joins <b>{}</b>.</p></body></html>",
+        Escaped(ssid)
+    );
+    write_response(socket, "200 OK", "", &body).await
+}
+
+/// Send the browser to the portal page at `addr`.
+pub async fn write_redirect<S: Write>(socket: &mut S, addr: Ipv4Addr) -> Result<(), S::Error> {
+    let mut location = String::new();
+    let _ = write!(location, "Location: http://{}/\r\n", addr);
+    write_response(socket, "302 Found", &location, "").await
+}
+
+async fn write_response<S: Write>(
+    socket: &mut S,
+    status: &str,
+    headers: &str,
+    body: &str,
+) -> Result<(), S::Error> {
+    let mut head = String::new();
+    let _ = write!(
+        head,
+        "HTTP/1.1 {}\r\n{}Content-Type: text/html; charset=utf-8\r\n\
+         Content-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        headers,
+        body.len()
+    );
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(body.as_bytes()).await?;
+    socket.flush().await
+}
+
+const PAGE_HEAD: &str = "<!DOCTYPE html><html><head><meta charset=utf-8>\
+<meta name=viewport content=\"width=device-width,initial-scale=1\"><title>rumble-rs</title>\
+<style>body{font-family:sans-serif;max-width:24em;margin:1em auto;padding:0 1em}\
+label{display:block;margin-top:1em}input{display:block;width:100%;box-sizing:border-box;\
+padding:.4em;font-size:1em}button{margin-top:1.5em;padding:.6em 1em;font-size:1em}\
+.hint{color:#666;font-size:.85em}.notice{color:#b00}</style></head>\
+<body><h1>rumble-rs</h1>";
+
+/// Displays a string with HTML special characters escaped.
+struct Escaped<'a>(&'a str);
+
+impl core::fmt::Display for Escaped<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '&' => f.write_str("&amp;")?,
+                '<' => f.write_str("&lt;")?,
+                '>' => f.write_str("&gt;")?,
+                '"' => f.write_str("&quot;")?,
+                '\'' => f.write_str("&#39;")?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,581 @@
+//! RTP/JPEG (RFC 2435) depacketizer.
+//!
+//! RTP/JPEG leaves the JPEG headers out and sends only the entropy-coded scan
+//! data, plus a small per-packet header with the image type, size and
+//! quantization. [`Depacketizer`] rebuilds a complete baseline JPEG from that,
+//! so frames from `ffmpeg -f rtp` or GStreamer `rtpjpegpay` can go straight to
+//! [`JpegDecoder`](crate::jpeg::JpegDecoder). Feed it one UDP datagram per
+//! [`FrameSource::push`].
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::jpeg::markers::{DHT, DQT, DRI, EOI, SOF0, SOI, SOS};
+use crate::mjpeg::{DropReason, FrameSource, Status};
+
+const RTP_VERSION: u8 = 2;
+const RTP_HEADER_LEN: usize = 12;
+const JPEG_HEADER_LEN: usize = 8;
+const RESTART_HEADER_LEN: usize = 4;
+const QTABLE_HEADER_LEN: usize = 4;
+
+/// Types 64..=127 are types 0..=63 with a restart marker header.
+const TYPE_RESTART: u8 = 64;
+/// Q values from 128 up carry their quantization tables in-band.
+const Q_INBAND: u8 = 128;
+
+/// Offsets of the two quantization tables in the generated header: after
+/// SOI, the DQT marker and length, and each table's Pq/Tq byte.
+const LUMA_TABLE: usize = 7;
+const CHROMA_TABLE: usize = LUMA_TABLE + 65;
+
+/// Gaps a frame may have while fragments arrive out of order.
+const MAX_RANGES: usize = 32;
+
+/// JPEG Annex K.1 luminance quantization table, natural order.
+const LUMA_QUANTIZER: [u8; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, //
+    12, 12, 14, 19, 26, 58, 60, 55, //
+    14, 13, 16, 24, 40, 57, 69, 56, //
+    14, 17, 22, 29, 51, 87, 80, 62, //
+    18, 22, 37, 56, 68, 109, 103, 77, //
+    24, 35, 55, 64, 81, 104, 113, 92, //
+    49, 64, 78, 87, 103, 121, 120, 101, //
+    72, 92, 95, 98, 112, 100, 103, 99, //
+];
+
+/// JPEG Annex K.2 chrominance quantization table, natural order.
+const CHROMA_QUANTIZER: [u8; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, //
+    18, 21, 26, 66, 99, 99, 99, 99, //
+    24, 26, 56, 99, 99, 99, 99, 99, //
+    47, 66, 99, 99, 99, 99, 99, 99, //
+    99, 99, 99, 99, 99, 99, 99, 99, //
+    99, 99, 99, 99, 99, 99, 99, 99, //
+    99, 99, 99, 99, 99, 99, 99, 99, //
+    99, 99, 99, 99, 99, 99, 99, 99, //
+];
+
+/// Natural-order index of each zigzag position.
+const ZIGZAG: [u8; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, //
+    12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13, 6, 7, 14, 21, 28, //
+    35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, //
+    58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63, //
+];
+
+// Annex K.3 Huffman tables, which RFC 2435 senders must use.
+const LUMA_DC_BITS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const LUMA_DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+const CHROMA_DC_BITS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const CHROMA_DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+const LUMA_AC_BITS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+const LUMA_AC_VALUES: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+    0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+    0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+const CHROMA_AC_BITS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+const CHROMA_AC_VALUES: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+    0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+    0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+    0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+    0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+    0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+/// Quantization tables for `q` in 1..=99, in zigzag order (RFC 2435
+/// appendix A).
+pub fn make_tables(q: u8) -> ([u8; 64], [u8; 64]) {
+    let factor = q.clamp(1, 99) as u32;
+    let scale = if factor < 50 {
+        5000 / factor
+    } else {
+        200 - factor * 2
+    };
+    let quantize = |base: u8| ((base as u32 * scale + 50) / 100).clamp(1, 255) as u8;
+
+    let mut luma = [0u8; 64];
+    let mut chroma = [0u8; 64];
+    for (i, &natural) in ZIGZAG.iter().enumerate() {
+        luma[i] = quantize(LUMA_QUANTIZER[natural as usize]);
+        chroma[i] = quantize(CHROMA_QUANTIZER[natural as usize]);
+    }
+    (luma, chroma)
+}
+
+/// The fields of one RTP/JPEG packet.
+#[derive(Clone, Copy, Debug)]
+struct Packet<'a> {
+    ssrc: u32,
+    timestamp: u32,
+    /// Set on the last packet of a frame.
+    marker: bool,
+    type_specific: u8,
+    offset: usize,
+    kind: u8,
+    q: u8,
+    width: u16,
+    height: u16,
+    restart_interval: u16,
+    /// Quantization table header precision and tables, present when `q` is
+    /// in-band and `offset` is 0.
+    tables: Option<(u8, &'a [u8])>,
+    data: &'a [u8],
+}
+
+impl<'a> Packet<'a> {
+    /// Parse an RTP packet carrying a JPEG payload. Returns `None` for
+    /// anything else, including RTCP sharing the port.
+    fn parse(packet: &'a [u8]) -> Option<Self> {
+        if packet.len() < RTP_HEADER_LEN || packet[0] >> 6 != RTP_VERSION {
+            return None;
+        }
+        let padding = packet[0] & 0x20 != 0;
+        let extension = packet[0] & 0x10 != 0;
+        let csrc_count = (packet[0] & 0x0f) as usize;
+        let marker = packet[1] & 0x80 != 0;
+        // RTCP sender/receiver reports etc. (RFC 5761 section 4)
+        if (72..=76).contains(&(packet[1] & 0x7f)) {
+            return None;
+        }
+        let timestamp = u32::from_be_bytes(packet[4..8].try_into().ok()?);
+        let ssrc = u32::from_be_bytes(packet[8..12].try_into().ok()?);
+
+        let mut end = packet.len();
+        if padding {
+            end = end.checked_sub(*packet.last()? as usize)?;
+        }
+        let mut payload = packet.get(RTP_HEADER_LEN + 4 * csrc_count..end)?;
+        if extension {
+            let words = u16::from_be_bytes([*payload.get(2)?, *payload.get(3)?]) as usize;
+            payload = payload.get(4 + 4 * words..)?;
+        }
+
+        let header = payload.get(..JPEG_HEADER_LEN)?;
+        let mut rest = &payload[JPEG_HEADER_LEN..];
+        let offset = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        let kind = header[4];
+        let q = header[5];
+
+        let mut restart_interval = 0;
+        if kind >= TYPE_RESTART {
+            let restart = rest.get(..RESTART_HEADER_LEN)?;
+            restart_interval = u16::from_be_bytes([restart[0], restart[1]]);
+            rest = &rest[RESTART_HEADER_LEN..];
+        }
+
+        let mut tables = None;
+        if q >= Q_INBAND && offset == 0 {
+            let header = rest.get(..QTABLE_HEADER_LEN)?;
+            let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+            tables = Some((header[1], rest.get(QTABLE_HEADER_LEN..QTABLE_HEADER_LEN + len)?));
+            rest = &rest[QTABLE_HEADER_LEN + len..];
+        }
+
+        Some(Self {
+            ssrc,
+            timestamp,
+            marker,
+            type_specific: header[0],
+            offset,
+            kind,
+            q,
+            width: header[6] as u16 * 8,
+            height: header[7] as u16 * 8,
+            restart_interval,
+            tables,
+            data: rest,
+        })
+    }
+
+    /// The fields that must not change between fragments of one frame.
+    fn params(&self) -> [u8; 4] {
+        [
+            self.kind,
+            self.q,
+            (self.width / 8) as u8,
+            (self.height / 8) as u8,
+        ]
+    }
+}
+
+/// Byte ranges of the scan data received so far, merged as they touch.
+#[derive(Clone, Copy, Debug)]
+struct Coverage {
+    ranges: [(usize, usize); MAX_RANGES],
+    len: usize,
+}
+
+impl Coverage {
+    const EMPTY: Self = Self {
+        ranges: [(0, 0); MAX_RANGES],
+        len: 0,
+    };
+
+    /// Add `start..end`; false if there are too many gaps to track.
+    fn insert(&mut self, mut start: usize, mut end: usize) -> bool {
+        let mut kept = 0;
+        for i in 0..self.len {
+            let (s, e) = self.ranges[i];
+            if e < start || s > end {
+                self.ranges[kept] = (s, e);
+                kept += 1;
+            } else {
+                start = start.min(s);
+                end = end.max(e);
+            }
+        }
+        if kept == MAX_RANGES {
+            return false;
+        }
+        self.ranges[kept] = (start, end);
+        self.len = kept + 1;
+        true
+    }
+
+    fn covers(&self, total: usize) -> bool {
+        self.len == 1 && self.ranges[0] == (0, total)
+    }
+}
+
+/// The frame being reassembled.
+struct Frame {
+    ssrc: u32,
+    timestamp: u32,
+    params: [u8; 4],
+    /// Length of the generated headers in front of the scan data.
+    header_len: usize,
+    /// Whether the quantization tables are in the header yet.
+    has_tables: bool,
+    coverage: Coverage,
+    /// Scan data length, known once the packet with the marker bit arrives.
+    total: Option<usize>,
+}
+
+/// Reassembles JPEG frames from RTP/JPEG packets.
+///
+/// Fragments may arrive in any order. A frame that is still missing data when
+/// a packet of a newer frame arrives is dropped as
+/// [`DropReason::Interrupted`]; packets of older frames are ignored.
+pub struct Depacketizer {
+    buf: Vec<u8>,
+    len: usize,
+    frame: Option<Frame>,
+    /// SSRC and timestamp of the last frame completed or dropped.
+    last: Option<(u32, u32)>,
+    /// Last in-band tables, for senders that only send them when they change.
+    cached_tables: Option<(u8, [u8; 128])>,
+    ready: bool,
+}
+
+impl Depacketizer {
+    /// Create a depacketizer that holds frames of up to `capacity` bytes,
+    /// including the regenerated headers.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            buf: vec![0u8; capacity],
+            len: 0,
+            frame: None,
+            last: None,
+            cached_tables: None,
+            ready: false,
+        }
+    }
+
+    /// Start a frame from any of its packets and write its headers. Returns
+    /// `None` for types this decoder can't handle.
+    fn begin_frame(&mut self, packet: &Packet) -> Option<Frame> {
+        // RFC 2435 only defines 4:2:2 (type 0) and 4:2:0 (type 1)
+        let sampling = match packet.kind & !TYPE_RESTART {
+            0 => 0x21,
+            1 => 0x22,
+            _ => return None,
+        };
+        if packet.type_specific != 0
+            || packet.width == 0
+            || packet.height == 0
+            || packet.q == 0
+            || (100..Q_INBAND).contains(&packet.q)
+        {
+            return None;
+        }
+
+        let mut header = Writer {
+            buf: &mut self.buf,
+            len: 0,
+        };
+        header.marker(SOI);
+
+        header.marker(DQT);
+        header.u16(2 + 2 * 65);
+        let (luma, chroma) = make_tables(packet.q);
+        header.u8(0x00);
+        header.bytes(&luma);
+        header.u8(0x01);
+        header.bytes(&chroma);
+
+        header.marker(SOF0);
+        header.u16(8 + 3 * 3);
+        header.u8(8);
+        header.u16(packet.height);
+        header.u16(packet.width);
+        header.u8(3);
+        header.bytes(&[1, sampling, 0]);
+        header.bytes(&[2, 0x11, 1]);
+        header.bytes(&[3, 0x11, 1]);
+
+        header.marker(DHT);
+        header.u16(2 + 4 * 17 + 2 * 12 + 2 * 162);
+        header.huffman(0x00, &LUMA_DC_BITS, &LUMA_DC_VALUES);
+        header.huffman(0x10, &LUMA_AC_BITS, &LUMA_AC_VALUES);
+        header.huffman(0x01, &CHROMA_DC_BITS, &CHROMA_DC_VALUES);
+        header.huffman(0x11, &CHROMA_AC_BITS, &CHROMA_AC_VALUES);
+
+        if packet.restart_interval != 0 {
+            header.marker(DRI);
+            header.u16(4);
+            header.u16(packet.restart_interval);
+        }
+
+        header.marker(SOS);
+        header.u16(6 + 3 * 2);
+        header.u8(3);
+        header.bytes(&[1, 0x00]);
+        header.bytes(&[2, 0x11]);
+        header.bytes(&[3, 0x11]);
+        header.bytes(&[0, 63, 0]);
+
+        let header_len = header.len;
+        Some(Frame {
+            ssrc: packet.ssrc,
+            timestamp: packet.timestamp,
+            params: packet.params(),
+            header_len,
+            has_tables: packet.q < Q_INBAND,
+            coverage: Coverage::EMPTY,
+            total: None,
+        })
+    }
+
+    /// Put in-band tables into the header. The RFC lets senders send an
+    /// empty table header when the tables didn't change since the last
+    /// frame with the same Q.
+    fn set_tables(&mut self, q: u8, precision: u8, tables: &[u8]) -> bool {
+        // 16-bit tables are only valid in extended-sequential JPEG
+        if precision != 0 {
+            return false;
+        }
+        let tables = match tables.len() {
+            0 => match &self.cached_tables {
+                Some((cached_q, cached)) if *cached_q == q => *cached,
+                _ => return false,
+            },
+            // One table shared by all components
+            64 => {
+                let mut both = [0u8; 128];
+                both[..64].copy_from_slice(tables);
+                both[64..].copy_from_slice(tables);
+                both
+            }
+            128 => tables.try_into().unwrap(),
+            _ => return false,
+        };
+        self.cached_tables = Some((q, tables));
+        if let Some(dst) = self.buf.get_mut(LUMA_TABLE..LUMA_TABLE + 64) {
+            dst.copy_from_slice(&tables[..64]);
+        }
+        if let Some(dst) = self.buf.get_mut(CHROMA_TABLE..CHROMA_TABLE + 64) {
+            dst.copy_from_slice(&tables[64..]);
+        }
+        true
+    }
+
+    /// Add one packet of `frame`. Returns a status once the frame ended.
+    fn add(&mut self, frame: &mut Frame, packet: &Packet, consumed: usize) -> Option<Status> {
+        if packet.params() != frame.params {
+            return Some(self.drop_corrupt(consumed));
+        }
+        if let Some((precision, tables)) = packet.tables {
+            if !self.set_tables(packet.q, precision, tables) {
+                return Some(self.drop_corrupt(consumed));
+            }
+            frame.has_tables = true;
+        }
+
+        let start = packet.offset;
+        let end = start + packet.data.len();
+        if !frame.coverage.insert(start, end) {
+            return Some(self.drop_corrupt(consumed));
+        }
+        if let Some(dst) = self
+            .buf
+            .get_mut(frame.header_len + start..frame.header_len + end)
+        {
+            dst.copy_from_slice(packet.data);
+        }
+        if packet.marker {
+            frame.total = Some(end);
+        }
+
+        let total = frame.total?;
+        if !frame.has_tables || !frame.coverage.covers(total) {
+            return None;
+        }
+
+        // Some senders keep the EOI on the last fragment
+        let mut len = frame.header_len + total;
+        if !self.buf[..len.min(self.buf.len())].ends_with(&[0xFF, EOI]) {
+            len += 2;
+            if let Some(dst) = self.buf.get_mut(len - 2..len) {
+                dst.copy_from_slice(&[0xFF, EOI]);
+            }
+        }
+        if len > self.buf.len() {
+            return Some(Status::Dropped {
+                consumed,
+                reason: DropReason::TooLarge { size: len },
+            });
+        }
+        self.len = len;
+        self.ready = true;
+        Some(Status::Frame { consumed })
+    }
+
+    fn drop_corrupt(&mut self, consumed: usize) -> Status {
+        Status::Dropped {
+            consumed,
+            reason: DropReason::Corrupt,
+        }
+    }
+}
+
+impl FrameSource for Depacketizer {
+    fn push(&mut self, input: &[u8]) -> Status {
+        if self.ready {
+            self.ready = false;
+            self.len = 0;
+        }
+
+        let Some(packet) = Packet::parse(input) else {
+            return Status::Incomplete;
+        };
+
+        if let Some(frame) = &self.frame
+            && (frame.ssrc, frame.timestamp) != (packet.ssrc, packet.timestamp)
+        {
+            if frame.ssrc == packet.ssrc && !is_newer(packet.timestamp, frame.timestamp) {
+                // Straggler from an older frame
+                return Status::Incomplete;
+            }
+            // A newer frame started, so this one lost packets. Report it
+            // without consuming the packet; the next push starts the new frame.
+            self.last = Some((frame.ssrc, frame.timestamp));
+            self.frame = None;
+            return Status::Dropped {
+                consumed: 0,
+                reason: DropReason::Interrupted,
+            };
+        }
+
+        let mut frame = match self.frame.take() {
+            Some(frame) => frame,
+            None => {
+                if let Some((ssrc, timestamp)) = self.last
+                    && ssrc == packet.ssrc
+                    && !is_newer(packet.timestamp, timestamp)
+                {
+                    // Late packet of a frame that is already done
+                    return Status::Incomplete;
+                }
+                match self.begin_frame(&packet) {
+                    Some(frame) => frame,
+                    None => {
+                        self.last = Some((packet.ssrc, packet.timestamp));
+                        return self.drop_corrupt(input.len());
+                    }
+                }
+            }
+        };
+
+        match self.add(&mut frame, &packet, input.len()) {
+            Some(status) => {
+                self.last = Some((frame.ssrc, frame.timestamp));
+                status
+            }
+            None => {
+                self.frame = Some(frame);
+                Status::Incomplete
+            }
+        }
+    }
+
+    fn frame_mut(&mut self) -> Option<&mut [u8]> {
+        if self.ready {
+            Some(&mut self.buf[..self.len])
+        } else {
+            None
+        }
+    }
+
+    fn reset(&mut self) {
+        self.len = 0;
+        self.frame = None;
+        self.last = None;
+        self.cached_tables = None;
+        self.ready = false;
+    }
+}
+
+/// RTP timestamp comparison with wraparound (RFC 3550 section 5.1).
+fn is_newer(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+/// Writes the JPEG header at the start of the frame buffer. Bytes past the
+/// end of the buffer are only counted; such a frame is reported too large.
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, bytes: &[u8]) {
+        if let Some(dst) = self.buf.get_mut(self.len..self.len + bytes.len()) {
+            dst.copy_from_slice(bytes);
+        }
+        self.len += bytes.len();
+    }
+
+    fn u8(&mut self, v: u8) {
+        self.bytes(&[v]);
+    }
+
+    fn u16(&mut self, v: u16) {
+        self.bytes(&v.to_be_bytes());
+    }
+
+    fn marker(&mut self, marker: u8) {
+        self.bytes(&[0xFF, marker]);
+    }
+
+    fn huffman(&mut self, class_id: u8, bits: &[u8; 16], values: &[u8]) {
+        self.u8(class_id);
+        self.bytes(bits);
+        self.bytes(values);
+    }
+}
@@ -0,0 +1,553 @@
+//! Line-oriented command shell for the serial console.
+//!
+//! [`LineBuffer`] turns console bytes into lines, [`Command::parse`] reads a
+//! command from one, and [`execute`] runs it against a [`Target`], which the
+//! firmware implements on top of its flash store and tasks. The stream loop
+//! counts what it does in a shared [`Stats`].
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::net::Ipv4Addr;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::config::{Config, WifiConfig};
+use crate::fit::FitMode;
+use crate::jpeg::JpegDecoderConfig;
+use crate::jpeg::heap::HeapStats;
+use crate::wifi::Candidate;
+
+/// Longest line kept; further input is dropped until the line ends.
+const MAX_LINE: usize = 256;
+
+const HELP: &str = "\
+commands:
+  wifi show                    network, address and saved networks
+  wifi set <ssid> [password]   save a network as the preferred one and join it
+  wifi add <ssid> [password] [priority]
+                               save a network to join when in range; higher
+                               priorities are tried first
+  wifi remove <ssid>           forget a saved network
+  stream show                  stream URL
+  stream set [url]             save a stream URL, applied after reboot;
+                               without one, find the server with mDNS
+  display fit <mode>           fit, fill, center or stretch streams of another
+                               size, applied after reboot
+  stats                        frame and network counters
+  decoder info                 JPEG decoder settings and last frame
+  config dump                  everything that is saved
+  config reset                 forget saved settings, applied after reboot
+  reboot
+Quote arguments that contain spaces, e.g. wifi set \"My Network\" secret123
+";
+
+// ---------------------------------------------------------------------------
+// Line input
+// ---------------------------------------------------------------------------
+
+/// What a byte of input did to the line.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Edit {
+    /// Nothing to show.
+    None,
+    /// Echo this byte.
+    Echo(u8),
+    /// Erase the last character on screen.
+    Erase,
+    /// Enter was pressed.
+    Line(String),
+}
+
+/// Accumulates console input into lines, handling backspace.
+#[derive(Default)]
+pub struct LineBuffer {
+    line: Vec<u8>,
+    /// The previous byte was CR, so a following LF belongs to it.
+    after_cr: bool,
+}
+
+impl LineBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, byte: u8) -> Edit {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        match byte {
+            b'\n' if after_cr => Edit::None,
+            b'\r' | b'\n' => {
+                let line = String::from_utf8_lossy(&self.line).into_owned();
+                self.line.clear();
+                Edit::Line(line)
+            }
+            // Backspace and DEL, removing a whole UTF-8 character
+            0x08 | 0x7F => {
+                while let Some(b) = self.line.pop() {
+                    if b & 0xC0 != 0x80 {
+                        return Edit::Erase;
+                    }
+                }
+                Edit::None
+            }
+            // Ctrl-C drops the line
+            0x03 => {
+                self.line.clear();
+                Edit::Line(String::new())
+            }
+            b if b < 0x20 => Edit::None,
+            b if self.line.len() < MAX_LINE => {
+                self.line.push(b);
+                Edit::Echo(b)
+            }
+            _ => Edit::None,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Commands
+// ---------------------------------------------------------------------------
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Help,
+    WifiShow,
+    WifiSet(WifiConfig),
+    WifiAdd(WifiConfig),
+    WifiRemove(String),
+    StreamShow,
+    StreamSet(String),
+    DisplayFit(FitMode),
+    Stats,
+    DecoderInfo,
+    ConfigDump,
+    ConfigReset,
+    Reboot,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// Blank line.
+    Empty,
+    UnterminatedQuote,
+    UnknownCommand(String),
+    /// Known command, wrong arguments; holds the expected usage.
+    Usage(&'static str),
+    /// The arguments parsed but aren't acceptable.
+    Invalid(&'static str),
+}
+
+impl core::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Empty => Ok(()),
+            Self::UnterminatedQuote => f.write_str("unterminated quote"),
+            Self::UnknownCommand(c) => write!(f, "unknown command '{}', try 'help'", c),
+            Self::Usage(usage) => write!(f, "usage: {}", usage),
+            Self::Invalid(reason) => f.write_str(reason),
+        }
+    }
+}
+
+impl core::error::Error for ParseError {}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, ParseError> {
+        let args = split_args(line)?;
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        match args.as_slice() {
+            [] => Err(ParseError::Empty),
+            ["help" | "?"] => Ok(Self::Help),
+            ["wifi"] | ["wifi", "show"] => Ok(Self::WifiShow),
+            ["wifi", "set", ssid] => wifi_config(ssid, "").map(Self::WifiSet),
+            ["wifi", "set", ssid, password] => wifi_config(ssid, password).map(Self::WifiSet),
+            ["wifi", "add", ssid] => wifi_config(ssid, "").map(Self::WifiAdd),
+            ["wifi", "add", ssid, password] => wifi_config(ssid, password).map(Self::WifiAdd),
+            ["wifi", "add", ssid, password, priority] => {
+                let priority = priority
+                    .parse()
+                    .map_err(|_| ParseError::Invalid("The priority must be 0 to 255."))?;
+                let wifi = wifi_config(ssid, password)?;
+                Ok(Self::WifiAdd(wifi.with_priority(priority)))
+            }
+            ["wifi", "remove", ssid] => Ok(Self::WifiRemove(ssid.to_string())),
+            ["wifi", ..] => Err(ParseError::Usage(
+                "wifi show | wifi set <ssid> [password] | \
+                 wifi add <ssid> [password] [priority] | wifi remove <ssid>",
+            )),
+            ["stream"] | ["stream", "show"] => Ok(Self::StreamShow),
+            ["stream", "set"] => Ok(Self::StreamSet(String::new())),
+            ["stream", "set", url] => Ok(Self::StreamSet(url.to_string())),
+            ["stream", ..] => Err(ParseError::Usage("stream show | stream set [url]")),
+            ["display", "fit", mode] => {
+                FitMode::parse(mode)
+                    .map(Self::DisplayFit)
+                    .ok_or(ParseError::Invalid(
+                        "The mode must be fit, fill, center or stretch.",
+                    ))
+            }
+            ["display", ..] => Err(ParseError::Usage("display fit <mode>")),
+            ["stats"] => Ok(Self::Stats),
+            ["decoder"] | ["decoder", "info"] => Ok(Self::DecoderInfo),
+            ["decoder", ..] => Err(ParseError::Usage("decoder info")),
+            ["config"] | ["config", "dump"] => Ok(Self::ConfigDump),
+            ["config", "reset"] => Ok(Self::ConfigReset),
+            ["config", ..] => Err(ParseError::Usage("config dump | config reset")),
+            ["reboot"] => Ok(Self::Reboot),
+            [command, ..] => Err(ParseError::UnknownCommand(command.to_string())),
+        }
+    }
+}
+
+fn wifi_config(ssid: &str, password: &str) -> Result<WifiConfig, ParseError> {
+    WifiConfig::new(ssid.to_string(), password.to_string()).map_err(ParseError::Invalid)
+}
+
+/// Split on whitespace, keeping `"quoted strings"` together. A backslash
+/// escapes the next character.
+fn split_args(line: &str) -> Result<Vec<String>, ParseError> {
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    let mut quoted = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let escaped = chars.next().unwrap_or('\\');
+                current.get_or_insert_default().push(escaped);
+            }
+            '"' => {
+                quoted = !quoted;
+                current.get_or_insert_default();
+            }
+            c if c.is_whitespace() && !quoted => args.extend(current.take()),
+            c => current.get_or_insert_default().push(c),
+        }
+    }
+    if quoted {
+        return Err(ParseError::UnterminatedQuote);
+    }
+    args.extend(current);
+    Ok(args)
+}
+
+// ---------------------------------------------------------------------------
+// Statistics
+// ---------------------------------------------------------------------------
+
+/// Counters shared between the stream loop and the shell. Everything wraps
+/// at `u32::MAX`.
+pub struct Stats {
+    frames: AtomicU32,
+    dropped: AtomicU32,
+    decode_errors: AtomicU32,
+    decoder_resets: AtomicU32,
+    bytes: AtomicU32,
+    connections: AtomicU32,
+    /// Width in the high half, height in the low half.
+    last_size: AtomicU32,
+    last_frame_us: AtomicU32,
+    max_frame_us: AtomicU32,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stats {
+    pub const fn new() -> Self {
+        Self {
+            frames: AtomicU32::new(0),
+            dropped: AtomicU32::new(0),
+            decode_errors: AtomicU32::new(0),
+            decoder_resets: AtomicU32::new(0),
+            bytes: AtomicU32::new(0),
+            connections: AtomicU32::new(0),
+            last_size: AtomicU32::new(0),
+            last_frame_us: AtomicU32::new(0),
+            max_frame_us: AtomicU32::new(0),
+        }
+    }
+
+    /// A frame of `width` × `height` took `frame_us` to decode and draw.
+    pub fn frame_shown(&self, width: u16, height: u16, frame_us: u32) {
+        self.frames.fetch_add(1, Ordering::Relaxed);
+        let size = (width as u32) << 16 | height as u32;
+        self.last_size.store(size, Ordering::Relaxed);
+        self.last_frame_us.store(frame_us, Ordering::Relaxed);
+        self.max_frame_us.fetch_max(frame_us, Ordering::Relaxed);
+    }
+
+    pub fn frame_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn decode_failed(&self) {
+        self.decode_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn decoder_reset(&self) {
+        self.decoder_resets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn received(&self, bytes: usize) {
+        self.bytes.fetch_add(bytes as u32, Ordering::Relaxed);
+    }
+
+    pub fn connected(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn get(counter: &AtomicU32) -> u32 {
+        counter.load(Ordering::Relaxed)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Dispatch
+// ---------------------------------------------------------------------------
+
+/// What the shell can see and do in the firmware.
+pub trait Target {
+    /// The configuration as saved.
+    fn config(&self) -> &Config;
+    /// Write `config` to flash and make it the one [`Target::config`] shows.
+    fn save(&mut self, config: Config) -> Result<(), String>;
+    /// Erase the saved configuration.
+    fn reset_config(&mut self) -> Result<(), String>;
+    /// Hand the saved networks to the Wi-Fi task. With `reconnect` it leaves
+    /// its network and picks again.
+    fn networks_changed(&mut self, reconnect: bool);
+    /// The network we're on, with its signal strength when it was picked.
+    fn network(&self) -> Option<Candidate>;
+    /// Our IPv4 address, if we have one.
+    fn address(&self) -> Option<Ipv4Addr>;
+    fn stats(&self) -> &Stats;
+    /// The settings the decoder runs with now.
+    fn decoder_config(&self) -> JpegDecoderConfig;
+    /// Heap bytes in use and free.
+    fn heap(&self) -> (usize, usize);
+    /// What the C codec has allocated through its `heap_caps_*` calls.
+    fn codec_heap(&self) -> HeapStats;
+    fn uptime_ms(&self) -> u64;
+    fn reboot(&mut self);
+}
+
+/// Run `command`, writing its output to `out`.
+pub fn execute(
+    command: Command,
+    target: &mut impl Target,
+    out: &mut impl Write,
+) -> core::fmt::Result {
+    match command {
+        Command::Help => out.write_str(HELP),
+        Command::WifiShow => write_wifi(target, out),
+        Command::WifiSet(wifi) => {
+            let ssid = wifi.ssid.clone();
+            let mut config = target.config().clone();
+            if let Err(e) = config.add_preferred_network(wifi) {
+                return writeln!(out, "{}", e);
+            }
+            if !save(target, config, out)? {
+                return Ok(());
+            }
+            writeln!(out, "joining {}", ssid)?;
+            target.networks_changed(true);
+            Ok(())
+        }
+        Command::WifiAdd(wifi) => {
+            let mut config = target.config().clone();
+            if let Err(e) = config.add_network(wifi) {
+                return writeln!(out, "{}", e);
+            }
+            if save(target, config, out)? {
+                target.networks_changed(false);
+            }
+            Ok(())
+        }
+        Command::WifiRemove(ssid) => {
+            let mut config = target.config().clone();
+            if !config.remove_network(&ssid) {
+                return writeln!(out, "unknown network: {}", ssid);
+            }
+            if save(target, config, out)? {
+                target.networks_changed(false);
+            }
+            Ok(())
+        }
+        Command::StreamShow => {
+            let config = target.config();
+            match config.stream_url().as_str() {
+                "" => writeln!(out, "discover with mDNS ({})", config.transport),
+                url => writeln!(out, "{}", url),
+            }
+        }
+        Command::StreamSet(url) => {
+            let Some(config) = target.config().clone().with_stream_url(&url) else {
+                return writeln!(out, "invalid stream URL: {}", url);
+            };
+            if save(target, config, out)? {
+                writeln!(out, "reboot to apply")?;
+            }
+            Ok(())
+        }
+        Command::DisplayFit(fit) => {
+            let mut config = target.config().clone();
+            config.display.fit = fit;
+            if save(target, config, out)? {
+                writeln!(out, "reboot to apply")?;
+            }
+            Ok(())
+        }
+        Command::Stats => write_stats(target, out),
+        Command::DecoderInfo => write_decoder_info(target, out),
+        Command::ConfigDump => write_config(target.config(), out),
+        Command::ConfigReset => match target.reset_config() {
+            Ok(()) => writeln!(out, "saved settings erased, reboot to apply"),
+            Err(e) => writeln!(out, "erase failed: {}", e),
+        },
+        Command::Reboot => {
+            writeln!(out, "rebooting")?;
+            target.reboot();
+            Ok(())
+        }
+    }
+}
+
+/// Save `config`, reporting how it went. Returns whether it was saved, so
+/// that nothing acts on a config that isn't in flash.
+fn save(
+    target: &mut impl Target,
+    config: Config,
+    out: &mut impl Write,
+) -> Result<bool, core::fmt::Error> {
+    match target.save(config) {
+        Ok(()) => writeln!(out, "saved").map(|()| true),
+        Err(e) => writeln!(out, "save failed: {}", e).map(|()| false),
+    }
+}
+
+fn write_wifi(target: &impl Target, out: &mut impl Write) -> core::fmt::Result {
+    match target.network() {
+        Some(Candidate {
+            network,
+            rssi: Some(rssi),
+        }) => writeln!(out, "ssid:    {} ({} dBm)", network.ssid, rssi)?,
+        Some(Candidate { network, .. }) => writeln!(out, "ssid:    {}", network.ssid)?,
+        None => writeln!(out, "ssid:    none")?,
+    }
+    match target.address() {
+        Some(addr) => writeln!(out, "address: {}", addr)?,
+        None => writeln!(out, "address: none")?,
+    }
+    let mut networks: Vec<&WifiConfig> = target.config().networks.iter().collect();
+    networks.sort_by_key(|n| core::cmp::Reverse(n.priority));
+    for (i, network) in networks.iter().enumerate() {
+        let label = if i == 0 { "saved:" } else { "" };
+        writeln!(
+            out,
+            "{:<8} {} (priority {})",
+            label, network.ssid, network.priority
+        )?;
+    }
+    Ok(())
+}
+
+fn write_stats(target: &impl Target, out: &mut impl Write) -> core::fmt::Result {
+    let stats = target.stats();
+    let uptime_ms = target.uptime_ms();
+    let frames = Stats::get(&stats.frames);
+    let (used, free) = target.heap();
+    writeln!(out, "uptime:      {} s", uptime_ms / 1000)?;
+    writeln!(
+        out,
+        "frames:      {} ({} fps average)",
+        frames,
+        (frames as u64 * 1000).checked_div(uptime_ms).unwrap_or(0)
+    )?;
+    writeln!(out, "dropped:     {}", Stats::get(&stats.dropped))?;
+    writeln!(out, "bad frames:  {}", Stats::get(&stats.decode_errors))?;
+    writeln!(out, "received:    {} KiB", Stats::get(&stats.bytes) / 1024)?;
+    writeln!(out, "connections: {}", Stats::get(&stats.connections))?;
+    writeln!(out, "heap:        {} used, {} free", used, free)
+}
+
+fn write_decoder_info(target: &impl Target, out: &mut impl Write) -> core::fmt::Result {
+    let config = target.decoder_config();
+    let stats = target.stats();
+    let (scale_w, scale_h) = config.scale();
+    let (clip_w, clip_h) = config.clipper();
+    writeln!(out, "output:      {:?}", config.output_format())?;
+    writeln!(out, "rotation:    {:?}", config.rotation())?;
+    writeln!(out, "block mode:  {}", config.block_mode())?;
+    writeln!(out, "scale:       {}x{}", scale_w, scale_h)?;
+    writeln!(out, "clipper:     {}x{}", clip_w, clip_h)?;
+    let size = Stats::get(&stats.last_size);
+    if size != 0 {
+        writeln!(out, "last frame:  {}x{}", size >> 16, size & 0xFFFF)?;
+    }
+    writeln!(
+        out,
+        "frame time:  {} us last, {} us max",
+        Stats::get(&stats.last_frame_us),
+        Stats::get(&stats.max_frame_us)
+    )?;
+    writeln!(out, "resets:      {}", Stats::get(&stats.decoder_resets))?;
+    let heap = target.codec_heap();
+    if heap.peak_bytes != 0 || heap.failed != 0 {
+        writeln!(
+            out,
+            "codec heap:  {} B in {} blocks, {} B peak, {} failed",
+            heap.live_bytes, heap.live, heap.peak_bytes, heap.failed
+        )?;
+    }
+    if heap.bad_frees != 0 {
+        writeln!(out, "bad frees:   {}", heap.bad_frees)?;
+    }
+    Ok(())
+}
+
+fn write_config(config: &Config, out: &mut impl Write) -> core::fmt::Result {
+    let display = &config.display;
+    let playback = &config.playback;
+    for (i, network) in config.networks.iter().enumerate() {
+        let password = if network.password.is_empty() {
+            "(none)"
+        } else {
+            "(set)"
+        };
+        writeln!(out, "wifi.{}.ssid:                {}", i, network.ssid)?;
+        writeln!(out, "wifi.{}.password:            {}", i, password)?;
+        writeln!(out, "wifi.{}.priority:            {}", i, network.priority)?;
+    }
+    writeln!(
+        out,
+        "server:                     {}",
+        config.server.as_deref().unwrap_or("(mDNS)")
+    )?;
+    writeln!(out, "transport:                  {}", config.transport)?;
+    writeln!(
+        out,
+        "display.size:               {}x{}",
+        display.width, display.height
+    )?;
+    writeln!(
+        out,
+        "display.offset:             {},{}",
+        display.offset_x, display.offset_y
+    )?;
+    writeln!(out, "display.invert_colors:      {}", display.invert_colors)?;
+    writeln!(out, "display.fit:                {}", display.fit)?;
+    writeln!(
+        out,
+        "playback.drop_stale_frames: {}",
+        playback.drop_stale_frames
+    )?;
+    writeln!(
+        out,
+        "playback.frame_capacity:    {}",
+        playback.frame_capacity
+    )
+}
@@ -0,0 +1,165 @@
+//! Choosing which of the known networks to join.
+//!
+//! [`Selector`] decides and the `connection` task acts: it scans, joins and
+//! waits as told, then reports back what happened. Networks seen in the scan
+//! are tried by priority, then signal strength; each gets a few attempts
+//! before the next one is tried. Known networks missing from the scan may
+//! have a hidden SSID, so they get one try at the end.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::config::WifiConfig;
+
+/// A network found by a scan.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Network {
+    pub ssid: String,
+    /// Signal strength in dBm.
+    pub rssi: i8,
+    /// Needs a password.
+    pub secure: bool,
+}
+
+/// A known network picked for joining.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    pub network: WifiConfig,
+    /// Signal strength in dBm when scanned, `None` if it wasn't seen.
+    pub rssi: Option<i8>,
+}
+
+/// What the `connection` task should do next.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Scan, then call [`Selector::scanned`].
+    Scan,
+    /// Try to join this, then call [`Selector::joined`] or
+    /// [`Selector::failed`].
+    Join(Candidate),
+    /// Connected: wait for a disconnect, then call
+    /// [`Selector::disconnected`].
+    Stay,
+    /// Nothing could be joined. Wait a while, then call [`Selector::start`].
+    Wait,
+}
+
+pub struct Selector {
+    networks: Vec<WifiConfig>,
+    /// Tries per network seen in the scan.
+    max_attempts: u8,
+    /// Left to try, best last.
+    candidates: Vec<Candidate>,
+    attempts: u8,
+    joined: Option<Candidate>,
+}
+
+impl Selector {
+    pub fn new(networks: Vec<WifiConfig>, max_attempts: u8) -> Self {
+        Self {
+            networks,
+            max_attempts: max_attempts.max(1),
+            candidates: Vec::new(),
+            attempts: 0,
+            joined: None,
+        }
+    }
+
+    /// The network we're connected to.
+    pub fn current(&self) -> Option<&Candidate> {
+        self.joined.as_ref()
+    }
+
+    /// Begin a round: scan, unless there is nothing to look for.
+    pub fn start(&mut self) -> Action {
+        self.candidates.clear();
+        self.joined = None;
+        if self.networks.is_empty() {
+            Action::Wait
+        } else {
+            Action::Scan
+        }
+    }
+
+    /// Replace the known networks. Stays connected if the current network is
+    /// still known and `reconnect` is false; otherwise starts over, and the
+    /// caller should leave its network.
+    pub fn set_networks(&mut self, networks: Vec<WifiConfig>, reconnect: bool) -> Action {
+        self.networks = networks;
+        let kept = self.joined.as_ref().and_then(|joined| {
+            let ssid = &joined.network.ssid;
+            self.networks.iter().find(|n| n.ssid == *ssid)
+        });
+        match kept {
+            Some(known) if !reconnect => {
+                let known = known.clone();
+                if let Some(joined) = &mut self.joined {
+                    joined.network = known;
+                }
+                Action::Stay
+            }
+            _ => self.start(),
+        }
+    }
+
+    pub fn scanned(&mut self, found: &[Network]) -> Action {
+        let mut seen = Vec::new();
+        let mut unseen = Vec::new();
+        for network in &self.networks {
+            // The strongest access point of the network
+            let rssi = found
+                .iter()
+                .filter(|f| f.ssid == network.ssid)
+                .map(|f| f.rssi)
+                .max();
+            let candidate = Candidate {
+                network: network.clone(),
+                rssi,
+            };
+            match rssi {
+                Some(_) => seen.push(candidate),
+                None => unseen.push(candidate),
+            }
+        }
+        seen.sort_by_key(|c| (c.network.priority, c.rssi));
+        unseen.sort_by_key(|c| c.network.priority);
+
+        // Popped from the end, so the unseen go first in the list
+        self.candidates = unseen;
+        self.candidates.append(&mut seen);
+        self.attempts = 0;
+        self.next()
+    }
+
+    pub fn joined(&mut self) -> Action {
+        self.joined = self.candidates.pop();
+        self.candidates.clear();
+        Action::Stay
+    }
+
+    pub fn failed(&mut self) -> Action {
+        self.attempts += 1;
+        let limit = match self.candidates.last() {
+            Some(Candidate { rssi: Some(_), .. }) => self.max_attempts,
+            _ => 1,
+        };
+        if self.attempts >= limit {
+            self.candidates.pop();
+            self.attempts = 0;
+        }
+        self.next()
+    }
+
+    /// Scan again rather than rejoin, as a stronger network may be in range
+    /// by now.
+    pub fn disconnected(&mut self) -> Action {
+        self.start()
+    }
+
+    fn next(&mut self) -> Action {
+        match self.candidates.last() {
+            Some(candidate) => Action::Join(candidate.clone()),
+            None => Action::Wait,
+        }
+    }
+}